@@ -0,0 +1,29 @@
+//! Embeds the git commit and build time, for `/about`.
+
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    // Builds without a git checkout, such as in containers, can pass the commit in
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    let commit = std::env::var("GIT_COMMIT").ok().or_else(|| {
+        let output = Command::new("git")
+            .args(["rev-parse", "--short", "HEAD"])
+            .output()
+            .ok()?;
+        let commit = String::from_utf8(output.stdout).ok()?;
+        output.status.success().then(|| commit.trim().to_string())
+    });
+    println!(
+        "cargo:rustc-env=GIT_COMMIT={}",
+        commit.as_deref().unwrap_or("unknown")
+    );
+
+    let built = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("the clock to be after 1970")
+        .as_secs();
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", built);
+}
@@ -0,0 +1,16 @@
+-- Everything but the roll history is kept as a single document, like the JSON file backend
+CREATE TABLE storage (
+    id SMALLINT PRIMARY KEY CHECK (id = 1),
+    document JSONB NOT NULL
+);
+
+CREATE TABLE rolls (
+    id BIGSERIAL PRIMARY KEY,
+    chat_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    timestamp TIMESTAMPTZ NOT NULL,
+    record JSONB NOT NULL
+);
+
+CREATE INDEX rolls_chat_id_user_id_timestamp ON rolls (chat_id, user_id, timestamp);
+CREATE INDEX rolls_chat_id_timestamp ON rolls (chat_id, timestamp);
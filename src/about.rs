@@ -0,0 +1,127 @@
+//! `/about` and `/ping`, to tell deployments of the bot apart and to see how fast they are, and
+//! `/donate`, for whoever pays to host the bot.
+
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+use crate::admin::{format_uptime, Admin};
+use crate::transport::{ChatTransport, Incoming};
+
+/// Crate version, git commit and build time, such as `0.1.0 (abc1234, built 2024-05-01 12:00 UTC)`
+pub fn version() -> String {
+    let built = env!("BUILD_TIMESTAMP")
+        .parse()
+        .ok()
+        .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+        .map(|built| built.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_else(|| "at an unknown time".to_string());
+    format!(
+        "{} ({}, built {})",
+        env!("CARGO_PKG_VERSION"),
+        env!("GIT_COMMIT"),
+        built
+    )
+}
+
+pub(crate) async fn handle_about(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    admin: &Admin,
+) -> anyhow::Result<()> {
+    let text = format!(
+        "🎲 <b>{}</b> {}\n⏱ Up for {}\n\n<a href=\"{repository}\">Source code</a> · <a href=\"{repository}/issues\">Report a problem</a>",
+        env!("CARGO_PKG_NAME"),
+        version(),
+        format_uptime(admin.started.elapsed()),
+        repository = env!("CARGO_PKG_REPOSITORY")
+    );
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+/// The text the operator configured, as they wrote it
+fn donate_text(donate: Option<&str>) -> String {
+    match donate {
+        Some(text) if !text.trim().is_empty() => text.to_string(),
+        _ => "The operator of this bot has not asked for donations. Thank you for thinking of it!"
+            .to_string(),
+    }
+}
+
+pub(crate) async fn handle_donate(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    admin: &Admin,
+) -> anyhow::Result<()> {
+    let text = donate_text(admin.settings().donate.as_deref());
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+fn ping_text(delivery_ms: i64, handling: Duration, round_trip: Duration) -> String {
+    format!(
+        "🏓 Pong!\n📨 Your message reached me after about {:.1} s\n⚙️ Handling it took {:.2} ms\n📡 Sending this reply took {} ms",
+        delivery_ms as f64 / 1000.0,
+        handling.as_secs_f64() * 1000.0,
+        round_trip.as_millis()
+    )
+}
+
+/// Reply, then edit the reply with how long the message took to arrive, to be handled since the
+/// dispatcher `received` it, and to send
+pub(crate) async fn handle_ping(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    received: Instant,
+) -> anyhow::Result<()> {
+    // Telegram only gives the time the message was sent in whole seconds
+    let delivery = (Utc::now() - msg.date).num_milliseconds().max(0);
+    let handling = received.elapsed();
+
+    let start = Instant::now();
+    let reply = bot.reply(msg, "🏓 Pong!".to_string(), vec![]).await?;
+    let round_trip = start.elapsed();
+
+    let text = ping_text(delivery, handling, round_trip);
+    bot.edit(msg.chat_id, reply, text, vec![]).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, FakeTransport, Sent};
+
+    #[test]
+    fn formats_the_latencies() {
+        assert_eq!(
+            ping_text(1500, Duration::from_micros(2250), Duration::from_millis(87)),
+            "🏓 Pong!\n📨 Your message reached me after about 1.5 s\n⚙️ Handling it took 2.25 ms\n📡 Sending this reply took 87 ms"
+        );
+    }
+
+    #[tokio::test]
+    async fn times_handling_from_when_the_update_arrived() {
+        let bot = FakeTransport::default();
+        let msg = testing::incoming(7);
+        let received = Instant::now() - Duration::from_millis(250);
+        handle_ping(&bot, &msg, received).await.unwrap();
+
+        let sent = bot.sent();
+        assert_eq!(bot.replies(), ["🏓 Pong!"]);
+        let Some(Sent::Edit {
+            message_id, text, ..
+        }) = sent.last()
+        else {
+            panic!("the reply to be edited: {:?}", sent);
+        };
+        assert_eq!(*message_id, 1000);
+        let handling = text
+            .lines()
+            .find_map(|line| line.strip_prefix("⚙️ Handling it took "))
+            .and_then(|line| line.strip_suffix(" ms"))
+            .and_then(|ms| ms.parse::<f64>().ok())
+            .unwrap();
+        assert!(handling >= 250.0, "{}", text);
+    }
+}
@@ -0,0 +1,374 @@
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
+
+use clap::ArgMatches;
+use teloxide::prelude::*;
+use teloxide::types::Chat;
+use teloxide::utils::html;
+
+use crate::backup::Backups;
+use crate::cli::RunArgs;
+use crate::ratelimit::RateLimiter;
+use crate::storage::Store;
+use crate::transport::{ChatTransport, Incoming};
+
+/// State of the commands for the bot operators
+#[derive(Debug)]
+pub struct Admin {
+    pub started: Instant,
+    pub backups: Option<Backups>,
+    pub limiter: Arc<RateLimiter>,
+    settings: RwLock<Settings>,
+    reload: Option<Reload>,
+}
+
+/// Settings that `/admin reload` can change while the bot runs
+#[derive(Debug, Default)]
+pub struct Settings {
+    /// Telegram user IDs of the operators
+    pub operators: Vec<i64>,
+    pub access: Access,
+    pub rate_limit: usize,
+    /// Answer to `/donate`
+    pub donate: Option<String>,
+}
+
+impl Settings {
+    pub fn from_args(args: &RunArgs) -> Self {
+        Settings {
+            operators: args.operators.clone(),
+            access: Access {
+                restricted: args.restrict_chats || !args.allowed_chats.is_empty(),
+                allowed_chats: args.allowed_chats.clone(),
+                denied_chats: args.denied_chats.clone(),
+            },
+            rate_limit: args.rate_limit,
+            donate: args.donate.clone(),
+        }
+    }
+}
+
+/// What is needed to apply the config file again
+#[derive(Debug)]
+pub struct Reload {
+    pub path: String,
+    pub args: RunArgs,
+    pub matches: ArgMatches,
+}
+
+impl Reload {
+    fn settings(&self) -> anyhow::Result<Settings> {
+        let mut args = self.args.clone();
+        crate::config::Config::load(&self.path)?.apply(&mut args, &self.matches);
+        Ok(Settings::from_args(&args))
+    }
+}
+
+/// Which group chats the bot may be used in. Private chats are always allowed.
+#[derive(Debug, Default)]
+pub struct Access {
+    /// Only allow the listed and approved group chats
+    pub restricted: bool,
+    pub allowed_chats: Vec<i64>,
+    pub denied_chats: Vec<i64>,
+}
+
+impl Admin {
+    pub fn new(
+        args: &RunArgs,
+        backups: Option<Backups>,
+        limiter: Arc<RateLimiter>,
+        reload: Option<Reload>,
+    ) -> Self {
+        Admin {
+            started: Instant::now(),
+            backups,
+            limiter,
+            settings: RwLock::new(Settings::from_args(args)),
+            reload,
+        }
+    }
+
+    pub fn settings(&self) -> RwLockReadGuard<'_, Settings> {
+        self.settings.read().expect("not to be poisoned")
+    }
+
+    pub fn is_operator(&self, msg: &Message) -> bool {
+        msg.from()
+            .is_some_and(|user| self.settings().operators.contains(&(user.id.0 as i64)))
+    }
+
+    pub async fn allows(&self, store: &Store, chat: &Chat) -> bool {
+        if chat.is_private() {
+            return true;
+        }
+        let chat_id = chat.id.0;
+        {
+            let access = &self.settings().access;
+            if access.denied_chats.contains(&chat_id) {
+                return false;
+            }
+            if !access.restricted || access.allowed_chats.contains(&chat_id) {
+                return true;
+            }
+        }
+        store
+            .read(|storage| storage.approved_chats.contains(&chat_id))
+            .await
+    }
+
+    fn reload(&self) -> anyhow::Result<()> {
+        let Some(ref reload) = self.reload else {
+            anyhow::bail!("the bot was not started with --config");
+        };
+        let settings = reload.settings()?;
+        self.limiter.set_limit(settings.rate_limit);
+        *self.settings.write().expect("not to be poisoned") = settings;
+        Ok(())
+    }
+}
+
+/// Remember the chats the bot is used in, for `/admin chats` and `/admin broadcast`
+pub(crate) async fn remember_chat(store: &Store, chat: &Chat) -> anyhow::Result<()> {
+    let chat_id = chat.id.0;
+    let known = store
+        .read(|storage| storage.known_chats.contains_key(&chat_id))
+        .await;
+    if !known {
+        let name = chat
+            .title()
+            .or_else(|| chat.username())
+            .or_else(|| chat.first_name())
+            .unwrap_or_default()
+            .to_string();
+        store
+            .update(|storage| storage.known_chats.insert(chat_id, name))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Whether the user was banned with `/admin ban`, which never applies to an operator
+pub(crate) async fn is_banned(store: &Store, admin: &Admin, user_id: i64) -> bool {
+    let is_operator = admin.settings().operators.contains(&user_id);
+    !is_operator
+        && store
+            .read(|storage| storage.banned_users.contains(&user_id))
+            .await
+}
+
+const USAGE: &str = "<code>/admin stats</code> shows how the bot is doing
+<code>/admin chats</code> lists the chats the bot is used in
+<code>/admin broadcast text</code> sends a message to every chat
+<code>/admin ban user_id</code> ignores a user, <code>/admin unban user_id</code> stops ignoring them
+<code>/admin approve chat_id</code> lets the bot be used in a group chat
+<code>/admin revoke chat_id</code> takes the approval back
+<code>/admin reload</code> reads the config file again
+<code>/admin backup</code> backs up the storage now";
+
+/// Say goodbye to a chat that the bot may not be used in, and tell the operators about it
+pub(crate) async fn leave_chat(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    admin: &Admin,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    log::warn!("Leaving chat {} that is not allowed", chat_id);
+    bot.send(
+        chat_id,
+        "Sorry, this is a private bot and I am not allowed in this chat. Goodbye!".to_string(),
+    )
+    .await?;
+    bot.leave(chat_id).await?;
+
+    let title = msg.chat_title.as_deref().unwrap_or_default();
+    let text = format!(
+        "I left {} ({}). To allow it, use <code>/admin approve {}</code>",
+        html::escape(title),
+        chat_id,
+        chat_id
+    );
+    let operators = admin.settings().operators.clone();
+    for operator in operators {
+        // Operators who never started a chat with the bot cannot be told
+        if let Err(e) = bot.send(operator, text.clone()).await {
+            log::warn!("Could not tell operator {} about leaving: {}", operator, e);
+        }
+    }
+    Ok(())
+}
+
+pub(crate) async fn refuse(bot: &impl ChatTransport, msg: &Incoming) -> anyhow::Result<()> {
+    bot.reply(
+        msg,
+        "Only the operator of the bot can do that.".to_string(),
+        vec![],
+    )
+    .await?;
+    Ok(())
+}
+
+/// Such as `3d 4h 5m`
+pub(crate) fn format_uptime(uptime: Duration) -> String {
+    let minutes = uptime.as_secs() / 60;
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+    match (days, hours) {
+        (0, 0) => format!("{}m", minutes),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h {}m", days, hours, minutes),
+    }
+}
+
+async fn stats(store: &Store, admin: &Admin) -> anyhow::Result<String> {
+    let rolls = store.backend().count_rolls().await?;
+    let text = store
+        .read(|storage| {
+            format!(
+                "⏱ Up for {}
+👥 {} players with characters
+💬 {} known chats, {} approved
+🎲 {} rolls in the history
+⏰ {} scheduled jobs
+🚫 {} banned users",
+                format_uptime(admin.started.elapsed()),
+                storage.users().count(),
+                storage.known_chats.len(),
+                storage.approved_chats.len(),
+                rolls,
+                storage.jobs.len(),
+                storage.banned_users.len()
+            )
+        })
+        .await;
+    Ok(text)
+}
+
+async fn broadcast(bot: &impl ChatTransport, store: &Store, text: &str) -> String {
+    let chats: Vec<i64> = store
+        .read(|storage| storage.known_chats.keys().copied().collect())
+        .await;
+    let mut failed = 0;
+    for &chat_id in &chats {
+        if let Err(e) = bot.send(chat_id, html::escape(text)).await {
+            log::warn!("Could not broadcast to chat {}: {}", chat_id, e);
+            failed += 1;
+        }
+    }
+    format!("Sent to {} of {} chats.", chats.len() - failed, chats.len())
+}
+
+/// Only reached by operators, see the dispatcher
+pub(crate) async fn handle_admin(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    admin: &Admin,
+    input: &str,
+) -> anyhow::Result<()> {
+    let (subcommand, argument) = input.trim().split_once(' ').unwrap_or((input.trim(), ""));
+    let argument = argument.trim();
+    let text = match subcommand {
+        "stats" => stats(&store, admin).await?,
+        "chats" => {
+            store
+                .read(|storage| {
+                    let chats: Vec<_> = storage
+                        .known_chats
+                        .iter()
+                        .map(|(id, name)| format!("<code>{}</code> {}", id, html::escape(name)))
+                        .collect();
+                    if chats.is_empty() {
+                        "I have not been used in any chat yet.".to_string()
+                    } else {
+                        chats.join("\n")
+                    }
+                })
+                .await
+        }
+        "broadcast" if !argument.is_empty() => broadcast(bot, &store, argument).await,
+        "ban" | "unban" => match argument.parse::<i64>() {
+            Err(_) => USAGE.to_string(),
+            Ok(user_id) => {
+                let ban = subcommand == "ban";
+                store
+                    .update(|storage| {
+                        if ban {
+                            storage.banned_users.insert(user_id);
+                        } else {
+                            storage.banned_users.remove(&user_id);
+                        }
+                    })
+                    .await?;
+                if ban {
+                    format!("I will ignore user {}.", user_id)
+                } else {
+                    format!("I will listen to user {} again.", user_id)
+                }
+            }
+        },
+        "approve" | "revoke" => match argument.parse::<i64>() {
+            Err(_) => USAGE.to_string(),
+            Ok(chat_id) => {
+                let approve = subcommand == "approve";
+                store
+                    .update(|storage| {
+                        if approve {
+                            storage.approved_chats.insert(chat_id);
+                        } else {
+                            storage.approved_chats.remove(&chat_id);
+                        }
+                    })
+                    .await?;
+                if approve {
+                    format!("Chat {} may use the bot now.", chat_id)
+                } else {
+                    format!("Chat {} is no longer approved.", chat_id)
+                }
+            }
+        },
+        "reload" => match admin.reload() {
+            Ok(()) => "Reloaded the config file.".to_string(),
+            Err(e) => format!(
+                "Could not reload the config file.\n\n<code>{}</code>",
+                html::escape(&format!("{:#}", e))
+            ),
+        },
+        "backup" => match admin.backups {
+            None => {
+                "Backups are not set up. Start the bot with <code>--backup-dir</code>.".to_string()
+            }
+            Some(ref backups) => match backups.backup(&store).await {
+                Ok(path) => format!(
+                    "Backed up to <code>{}</code>",
+                    html::escape(&path.display().to_string())
+                ),
+                Err(e) => {
+                    log::error!("Error backing up storage: {:#}", e);
+                    format!(
+                        "The backup failed.\n\n<code>{}</code>",
+                        html::escape(&format!("{:#}", e))
+                    )
+                }
+            },
+        },
+        _ => USAGE.to_string(),
+    };
+
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_uptime() {
+        assert_eq!(format_uptime(Duration::from_secs(59)), "0m");
+        assert_eq!(format_uptime(Duration::from_secs(3 * 3600 + 120)), "3h 2m");
+        assert_eq!(
+            format_uptime(Duration::from_secs(2 * 86400 + 3600 + 60)),
+            "2d 1h 1m"
+        );
+    }
+}
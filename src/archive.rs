@@ -0,0 +1,108 @@
+//! Versioned dump of all persisted state, for backups and moving between storage backends.
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use crate::history::RollRecord;
+use crate::storage::{Storage, StorageBackend};
+
+/// Bumped whenever an older bot could not make sense of a newer archive
+const VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct Archive {
+    pub version: u32,
+    pub storage: Storage,
+    #[serde(default)]
+    pub rolls: Vec<RollRecord>,
+}
+
+pub async fn export(backend: &dyn StorageBackend) -> anyhow::Result<Archive> {
+    Ok(Archive {
+        version: VERSION,
+        storage: backend.load().await?,
+        rolls: backend.all_rolls().await?,
+    })
+}
+
+/// Restore an archive, replacing the storage and the roll history. Unless forced, only into a
+/// backend without any data.
+pub async fn import(
+    backend: &dyn StorageBackend,
+    archive: Archive,
+    force: bool,
+) -> anyhow::Result<()> {
+    if archive.version > VERSION {
+        bail!(
+            "archive version {} is newer than the version {} that I understand",
+            archive.version,
+            VERSION
+        );
+    }
+    if !force {
+        let empty =
+            backend.load().await? == Storage::default() && backend.all_rolls().await?.is_empty();
+        if !empty {
+            bail!("the storage already holds data. Use --force to import anyway.");
+        }
+    }
+    backend.save(&archive.storage).await?;
+    backend.replace_rolls(&archive.rolls).await?;
+    log::info!(
+        "Imported storage and {} rolls from archive version {}",
+        archive.rolls.len(),
+        archive.version
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dice::{RollResults, RollSettings, RollType};
+    use crate::storage::JsonFile;
+
+    #[tokio::test]
+    async fn moves_state_between_backends() {
+        let directory = std::env::temp_dir();
+        let paths = ["from", "to"]
+            .map(|name| directory.join(format!("archive-{}-{}.json", name, std::process::id())));
+        let [from, to] = paths.clone().map(JsonFile::new);
+
+        let mut storage = Storage::default();
+        storage.user_mut(7).default_character = Some("Thorin".to_string());
+        from.save(&storage).await.unwrap();
+        let settings: RollSettings = "1d20".parse().unwrap();
+        let results = RollResults::new(&settings, &RollType::Straight);
+        let roll = RollRecord::new(1, 7, None, "1d20", &results);
+        from.record_roll(&roll).await.unwrap();
+
+        let archive = export(&from).await.unwrap();
+        import(
+            &to,
+            serde_json::from_value(serde_json::to_value(&archive).unwrap()).unwrap(),
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(export(&to).await.unwrap(), archive);
+        assert!(import(&to, export(&from).await.unwrap(), false)
+            .await
+            .is_err());
+
+        // Forcing replaces the history instead of adding to it
+        let other = RollRecord::new(1, 8, None, "1d20", &results);
+        to.record_roll(&other).await.unwrap();
+        import(&to, export(&from).await.unwrap(), true)
+            .await
+            .unwrap();
+        assert_eq!(export(&to).await.unwrap(), archive);
+
+        for path in paths {
+            std::fs::remove_file(&path).unwrap();
+            std::fs::remove_file(path.with_extension("json.history")).unwrap();
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::str::FromStr;
+
+use rand::Rng;
+use teloxide::utils::html;
+
+use crate::campaign::HouseRules;
+use crate::dice::{Flags, Roll, RollResults, RollSettings, RollType};
+use crate::dnd::Attack;
+use crate::storage::Store;
+use crate::transport::{ChatTransport, Incoming};
+
+/// Split a trailing `adv`/`dis` flag off the attack name
+pub(crate) fn parse_input(input: &str) -> (&str, RollType) {
+    let input = input.trim();
+    match input.rsplit_once(' ') {
+        Some((name, "adv" | "advantage")) => (name.trim(), RollType::Advantage),
+        Some((name, "dis" | "disadvantage")) => (name.trim(), RollType::Disadvantage),
+        _ => (input, RollType::Straight),
+    }
+}
+
+fn format_to_hit(results: &RollResults) -> String {
+    let roll = &results.result;
+    let dice = match results.roll_type {
+        RollType::Straight => roll.format_roll(Some(100)),
+        roll_type => format!("{} with <i>{}</i>", roll.format_roll(Some(100)), roll_type),
+    };
+    format!("To hit: {} = <b>{}</b>", dice, roll.total)
+}
+
+/// How an attack that did not miss critically went, for applying its damage
+pub(crate) struct Hit {
+    pub to_hit: i64,
+    pub crit: bool,
+    pub damage: i64,
+}
+
+pub(crate) fn roll_attack(
+    attacker: &str,
+    attack: &Attack,
+    roll_type: &RollType,
+    rules: &HouseRules,
+    rng: &mut impl Rng,
+) -> (String, Option<Hit>) {
+    let damage = match RollSettings::from_str(&attack.damage) {
+        Ok(damage) => damage,
+        Err(e) => {
+            let text = format!(
+                "The damage of {} is not a valid roll.\n\n💣 <code>{}</code> 💣",
+                html::escape(&attack.name),
+                e
+            );
+            return (text, None);
+        }
+    };
+    let to_hit = RollSettings {
+        number: 1,
+        sides: 20,
+        modifier: Some(attack.to_hit as i32),
+        label: None,
+        target: None,
+        keep: None,
+        explode: None,
+        success: None,
+        composite: false,
+        multiplier: None,
+        flags: Flags::NONE,
+    };
+    let to_hit = RollResults::with_rng(&to_hit, roll_type, rng);
+    // The one d20 that counts, of two with advantage
+    let natural = to_hit.result.natural().unwrap_or_default();
+
+    let mut text = format!(
+        "⚔️ <b>{}</b> attacks with <u>{}</u>\n{}",
+        html::escape(attacker),
+        html::escape(&attack.name),
+        format_to_hit(&to_hit)
+    );
+    let crit = crate::houserules::is_crit(rules, natural);
+    let damage = match natural {
+        _ if crit => {
+            text.push_str(" — <b>Critical hit!</b>");
+            crate::houserules::crit_damage(rules, &damage)
+        }
+        1 => {
+            text.push_str(" — <b>Critical miss!</b>");
+            return (text, None);
+        }
+        _ => damage,
+    };
+
+    let mut damage = Roll::with_rng(&damage, rng);
+    let notes = crate::houserules::apply_damage(rules, &mut damage, rng);
+    if crit {
+        crate::houserules::apply_crit(rules, &mut damage);
+    }
+    text.push_str(&format!(
+        "\nDamage: {} = <b>{}</b>",
+        damage.format_roll(Some(1000)),
+        damage.total
+    ));
+    if let Some(ref damage_type) = attack.damage_type {
+        text.push_str(&format!(" {}", html::escape(damage_type)));
+    }
+    for note in notes {
+        text.push_str(&format!("\n{}", note));
+    }
+    let hit = Hit {
+        to_hit: to_hit.result.total,
+        crit,
+        damage: damage.total,
+    };
+    (text, Some(hit))
+}
+
+pub(crate) async fn handle_attack(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let Some(user) = &msg.from else {
+        return Ok(());
+    };
+    let user_id = user.id;
+    let (name, roll_type) = parse_input(input);
+
+    let text = store
+        .read(|storage| {
+            let Some(character) = storage
+                .user(user_id)
+                .and_then(|user| user.character(msg.chat_id, None))
+            else {
+                return "You have no character. Upload one with /sheet upload.".to_string();
+            };
+            let rules = crate::houserules::for_chat(storage, msg.chat_id);
+            match character.attack(name) {
+                Some(attack) if !name.is_empty() => {
+                    roll_attack(
+                        &character.name,
+                        attack,
+                        &roll_type,
+                        &rules,
+                        &mut rand::thread_rng(),
+                    )
+                    .0
+                }
+                _ => {
+                    let attacks = character
+                        .attacks
+                        .iter()
+                        .map(|attack| format!("• {}", html::escape(&attack.name)))
+                        .collect::<Vec<_>>();
+                    if attacks.is_empty() {
+                        format!("{} has no attacks.", html::escape(&character.name))
+                    } else {
+                        format!(
+                            "Usage: <code>/attack name [adv|dis]</code>\n\n{} can attack with:\n{}",
+                            html::escape(&character.name),
+                            attacks.join("\n")
+                        )
+                    }
+                }
+            }
+        })
+        .await;
+
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_advantage_flags() {
+        assert_eq!(
+            parse_input("longsword adv"),
+            ("longsword", RollType::Advantage)
+        );
+        assert_eq!(
+            parse_input(" light crossbow  dis "),
+            ("light crossbow", RollType::Disadvantage)
+        );
+        assert_eq!(parse_input("hand axe"), ("hand axe", RollType::Straight));
+    }
+
+    #[test]
+    fn strikes_out_the_d20_that_does_not_count() {
+        let settings: RollSettings = "1d20 + 5".parse().unwrap();
+        let mut to_hit = RollResults::new(&settings, &RollType::Advantage);
+        to_hit.result.rolls = vec![3, 12];
+        to_hit.result.recount();
+        assert_eq!(
+            format_to_hit(&to_hit),
+            "To hit: (<s>3</s> + 12) + 5 with <i>Advantage</i> = <b>17</b>"
+        );
+    }
+
+    #[test]
+    fn rolls_the_same_attack_with_the_same_seed() {
+        use rand::SeedableRng;
+
+        let attack = Attack {
+            name: "Greataxe".to_string(),
+            to_hit: 5,
+            damage: "10d12 + 3".to_string(),
+            damage_type: None,
+        };
+        let rules = HouseRules {
+            reroll_damage_ones: true,
+            ..Default::default()
+        };
+        let roll = |seed| {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+            roll_attack("Grog", &attack, &RollType::Straight, &rules, &mut rng).0
+        };
+        assert_eq!(roll(11), roll(11));
+    }
+}
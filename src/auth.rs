@@ -0,0 +1,16 @@
+use crate::transport::{ChatTransport, Incoming};
+
+/// Whether the sender may manage chat-wide data such as tables.
+/// Everyone may in a private chat; in groups only administrators may.
+pub(crate) async fn is_chat_admin(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+) -> anyhow::Result<bool> {
+    if msg.private {
+        return Ok(true);
+    }
+    let Some(user_id) = msg.user_id() else {
+        return Ok(false);
+    };
+    bot.is_chat_admin(msg.chat_id, user_id).await
+}
@@ -0,0 +1,101 @@
+//! Periodic snapshots of the storage into a local directory.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::Utc;
+
+use crate::storage::Store;
+
+/// Where backups go and how many are kept
+#[derive(Clone, Debug)]
+pub struct Backups {
+    pub directory: PathBuf,
+    pub retention: usize,
+}
+
+impl Backups {
+    /// Write an archive of the storage and remove the oldest backups beyond the retention count,
+    /// always keeping the one just written
+    pub async fn backup(&self, store: &Store) -> anyhow::Result<PathBuf> {
+        let archive = crate::archive::export(store.backend()).await?;
+        let contents = serde_json::to_vec_pretty(&archive)?;
+
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .with_context(|| format!("error creating directory {:?}", self.directory))?;
+        let name = format!("backup-{}.json", Utc::now().format("%Y%m%dT%H%M%S%.3fZ"));
+        let path = self.directory.join(name);
+        tokio::fs::write(&path, contents)
+            .await
+            .with_context(|| format!("error writing file {:?}", path))?;
+
+        for old in self.backups()?.iter().rev().skip(self.retention.max(1)) {
+            tokio::fs::remove_file(old)
+                .await
+                .with_context(|| format!("error removing old backup {:?}", old))?;
+        }
+        Ok(path)
+    }
+
+    /// Existing backups, oldest first
+    fn backups(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut backups = vec![];
+        let entries = std::fs::read_dir(&self.directory)
+            .with_context(|| format!("error reading directory {:?}", self.directory))?;
+        for entry in entries {
+            let path = entry?.path();
+            let name = path.file_name().and_then(|name| name.to_str());
+            if name.is_some_and(|name| name.starts_with("backup-") && name.ends_with(".json")) {
+                backups.push(path);
+            }
+        }
+        backups.sort();
+        Ok(backups)
+    }
+}
+
+pub async fn run(store: Store, backups: Backups, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    // The first tick completes immediately, and there is nothing new to back up on startup
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        match backups.backup(&store).await {
+            Ok(path) => log::info!("Backed up storage to {}", path.display()),
+            Err(e) => log::error!("Error backing up storage: {:#}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::JsonFile;
+
+    #[tokio::test]
+    async fn keeps_only_the_newest_backups() {
+        let directory = std::env::temp_dir().join(format!("backups-{}", std::process::id()));
+        let storage = directory.join("storage.json");
+        let mut backups = Backups {
+            directory: directory.clone(),
+            retention: 2,
+        };
+        let store = Store::open(Box::new(JsonFile::new(&storage)))
+            .await
+            .unwrap();
+
+        let mut made = vec![];
+        for _ in 0..3 {
+            made.push(backups.backup(&store).await.unwrap());
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        assert_eq!(backups.backups().unwrap(), made[1..]);
+
+        backups.retention = 0;
+        let newest = backups.backup(&store).await.unwrap();
+        assert_eq!(backups.backups().unwrap(), [newest]);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,507 @@
+//! A campaign played in a group chat, with its GM, players and house rules.
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use teloxide::utils::html;
+
+use crate::secret::SecretKey;
+use crate::storage::{Storage, Store};
+use crate::transport::{ChatTransport, Incoming};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct Campaign {
+    pub name: String,
+    /// User ID of the GM
+    pub gm: i64,
+    /// User IDs of the players, who play their active character of the chat
+    #[serde(default)]
+    pub players: BTreeSet<i64>,
+    #[serde(default)]
+    pub house_rules: HouseRules,
+    /// Stat blocks uploaded by the GM with `/monster upload`
+    #[serde(default)]
+    pub monsters: Vec<crate::monster::Monster>,
+    /// Where the rolls of the campaign are posted, set with `/campaign webhook`
+    #[serde(default)]
+    pub webhook: Option<crate::webhook::Webhook>,
+    /// The dddice room that the rolls of the campaign are shown in, set with `/campaign dddice`
+    #[serde(default)]
+    pub dddice: Option<crate::dddice::Dddice>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct HouseRules {
+    /// The lowest natural roll of a d20 that is a critical hit
+    #[serde(default = "default_crit_range")]
+    pub crit_range: u32,
+    #[serde(default)]
+    pub crit_damage: CritDamage,
+    #[serde(default)]
+    pub rerolls: Rerolls,
+    /// Reroll damage dice that come up 1, once
+    #[serde(default)]
+    pub reroll_damage_ones: bool,
+    /// When a dice pool like `5d10>=8` botches
+    #[serde(default)]
+    pub botch: Botch,
+    /// How far past the DC a critical success or failure is
+    #[serde(default)]
+    pub degrees: crate::dice::Bands,
+}
+
+fn default_crit_range() -> u32 {
+    20
+}
+
+impl Default for HouseRules {
+    fn default() -> Self {
+        HouseRules {
+            crit_range: default_crit_range(),
+            crit_damage: Default::default(),
+            rerolls: Default::default(),
+            reroll_damage_ones: false,
+            botch: Default::default(),
+            degrees: Default::default(),
+        }
+    }
+}
+
+/// How the damage of a critical hit is rolled
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CritDamage {
+    /// Roll the damage dice twice
+    #[default]
+    Double,
+    /// The most the dice can roll, plus a roll of them
+    MaxPlusRoll,
+    /// The most the dice can roll
+    Max,
+}
+
+/// How 1s count against the successes of a dice pool
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Botch {
+    /// 1s are failures like any other
+    #[default]
+    Never,
+    /// Each 1 takes away a success, and more 1s than successes is a botch, as in classic World of
+    /// Darkness
+    OnesSubtract,
+    /// No successes and any 1 is a botch
+    NoSuccesses,
+}
+
+/// Who may use `/reroll` and the Reroll button
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Rerolls {
+    #[default]
+    Anyone,
+    Gm,
+    Nobody,
+}
+
+impl std::fmt::Display for HouseRules {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.crit_range {
+            20 => writeln!(f, "Crits on a natural 20")?,
+            range => writeln!(f, "Crits on a natural {} to 20", range)?,
+        }
+        match self.crit_damage {
+            CritDamage::Double => writeln!(f, "Crit damage rolls the dice twice")?,
+            CritDamage::MaxPlusRoll => {
+                writeln!(f, "Crit damage is the most the dice roll, plus a roll")?
+            }
+            CritDamage::Max => writeln!(f, "Crit damage is the most the dice roll")?,
+        }
+        if self.reroll_damage_ones {
+            writeln!(f, "Damage dice that come up 1 are rerolled once")?;
+        }
+        match self.botch {
+            Botch::Never => {}
+            Botch::OnesSubtract => writeln!(
+                f,
+                "In dice pools, 1s take away successes, and more 1s than successes is a botch"
+            )?,
+            Botch::NoSuccesses => writeln!(f, "In dice pools, no successes and any 1 is a botch")?,
+        }
+        if self.degrees != Default::default() {
+            writeln!(
+                f,
+                "Failing a DC by {} or more is a critical failure, and beating it by {} or more a critical success",
+                self.degrees.critical_failure, self.degrees.critical_success
+            )?;
+        }
+        match self.rerolls {
+            Rerolls::Anyone => write!(f, "Anyone may reroll"),
+            Rerolls::Gm => write!(f, "Only the GM may reroll"),
+            Rerolls::Nobody => write!(f, "Nobody may reroll"),
+        }
+    }
+}
+
+/// Whether the user may reroll in the chat
+pub(crate) fn may_reroll(storage: &Storage, chat_id: i64, user_id: i64) -> bool {
+    let Some(campaign) = storage
+        .chat(chat_id)
+        .and_then(|chat| chat.campaign.as_ref())
+    else {
+        return true;
+    };
+    match campaign.house_rules.rerolls {
+        Rerolls::Anyone => true,
+        Rerolls::Gm => campaign.gm == user_id,
+        Rerolls::Nobody => false,
+    }
+}
+
+fn mention(user_id: i64, name: &str) -> String {
+    format!(
+        "<a href=\"tg://user?id={}\">{}</a>",
+        user_id,
+        html::escape(name)
+    )
+}
+
+fn show(storage: &Storage, chat_id: i64) -> String {
+    let chat = storage.chat(chat_id);
+    let Some(campaign) = chat.and_then(|chat| chat.campaign.as_ref()) else {
+        return format!("This chat has no campaign.\n\n{}", USAGE);
+    };
+    let mut text = format!(
+        "🗺 <b>{}</b>\nGM: {}",
+        html::escape(&campaign.name),
+        mention(campaign.gm, "GM")
+    );
+    text.push_str("\n\n<b>Players</b>");
+    if campaign.players.is_empty() {
+        text.push_str("\nNobody yet. The GM invites players with /campaign invite.");
+    }
+    for player in &campaign.players {
+        let character = storage
+            .user(*player)
+            .and_then(|user| user.active_character(chat_id))
+            .unwrap_or("no character");
+        text.push_str(&format!("\n• {}", mention(*player, character)));
+    }
+    text.push_str(&format!("\n\n<b>House rules</b>\n{}", campaign.house_rules));
+    if campaign.webhook.is_some() {
+        text.push_str("\nRolls are posted to a webhook");
+    }
+    if let Some(ref dddice) = campaign.dddice {
+        text.push_str(&format!(
+            "\nRolls are shown in the dddice room {}",
+            html::escape(&dddice.room)
+        ));
+    }
+    let tables: Vec<_> = chat
+        .into_iter()
+        .flat_map(|chat| &chat.random_tables)
+        .map(|table| html::escape(&table.name))
+        .collect();
+    if !tables.is_empty() {
+        text.push_str(&format!("\n\n<b>Tables</b>\n{}", tables.join(", ")));
+    }
+    text
+}
+
+/// Change a house rule, returning what to answer
+fn change_rule(rules: &mut HouseRules, args: &[&str]) -> String {
+    match args {
+        [] => {}
+        ["crit", range] => match range.trim_end_matches("-20").parse() {
+            Ok(range @ 2..=20) => rules.crit_range = range,
+            _ => return "Crits can start at a natural 2 to 20.".to_string(),
+        },
+        ["critdamage", "double"] => rules.crit_damage = CritDamage::Double,
+        ["critdamage", "max"] => rules.crit_damage = CritDamage::Max,
+        ["critdamage", "maxplus"] => rules.crit_damage = CritDamage::MaxPlusRoll,
+        ["rerollones", toggle @ ("on" | "off")] => rules.reroll_damage_ones = *toggle == "on",
+        ["botch", "off"] => rules.botch = Botch::Never,
+        ["botch", "subtract"] => rules.botch = Botch::OnesSubtract,
+        ["botch", "nosuccess"] => rules.botch = Botch::NoSuccesses,
+        ["degrees", failure, success] => match (failure.parse(), success.parse()) {
+            (Ok(failure @ 1..), Ok(success @ 1..)) => {
+                rules.degrees.critical_failure = failure;
+                rules.degrees.critical_success = success;
+            }
+            _ => {
+                return "Critical failures and successes start at a margin of 1 or more."
+                    .to_string()
+            }
+        },
+        ["reroll", "anyone"] => rules.rerolls = Rerolls::Anyone,
+        ["reroll", "gm"] => rules.rerolls = Rerolls::Gm,
+        ["reroll", "nobody"] => rules.rerolls = Rerolls::Nobody,
+        _ => return SETTINGS_USAGE.to_string(),
+    }
+    format!("<b>House rules</b>\n{}", rules)
+}
+
+const USAGE: &str = "<code>/campaign create Curse of Strahd</code> starts a campaign in this chat, with you as the GM
+<code>/campaign</code> shows the campaign
+<code>/campaign invite</code> in reply to a player adds them to the campaign
+<code>/campaign remove</code> in reply to a player removes them
+<code>/campaign settings</code> shows or changes the house rules
+<code>/campaign webhook https://example.com/rolls</code> posts every roll of the campaign there as JSON, or <code>off</code> stops it
+<code>/campaign dddice room-slug api-key [theme]</code> shows every roll in a dddice room, and so on Foundry VTT through its dddice module. <code>/campaign dddice off</code> stops it.
+<code>/campaign end</code> ends the campaign";
+
+/// When the message with a dddice key could not be deleted
+const KEY_EXPOSED: &str = "⚠️ I could not delete the message with your dddice key, so everyone in this chat can see it. Revoke it in the developer settings of dddice and set a new one. Make me an admin who may delete messages first.";
+
+const NO_SECRET_KEY: &str =
+    "The operator of the bot has not set a secret key, so I cannot keep dddice keys safe.";
+
+const SETTINGS_USAGE: &str = "<code>/campaign settings crit 19</code> crits on a natural 19 or 20
+<code>/campaign settings critdamage max</code> crit damage is the most the dice roll, <code>maxplus</code> adds a roll to that, or <code>double</code> rolls the dice twice
+<code>/campaign settings rerollones on</code> rerolls damage dice that come up 1 once
+<code>/campaign settings botch subtract</code> has 1s take away the successes of dice pools like <code>5d10&gt;=8</code>, with more 1s than successes a botch. <code>nosuccess</code> makes only no successes and a 1 a botch, and <code>off</code> never botches.
+<code>/campaign settings degrees 5 10</code> makes failing a DC by 5 or more a critical failure, and beating it by 10 or more a critical success
+<code>/campaign settings reroll gm</code> lets only the GM reroll, or <code>anyone</code> or <code>nobody</code>";
+
+pub(crate) async fn handle_campaign(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    secret: Option<&SecretKey>,
+    input: &str,
+) -> anyhow::Result<()> {
+    let Some(user) = &msg.from else {
+        return Ok(());
+    };
+    let (chat_id, user_id) = (msg.chat_id, user.id);
+    let args: Vec<_> = input.split_whitespace().collect();
+    let gm = store
+        .read(|storage| {
+            storage
+                .chat(chat_id)
+                .and_then(|chat| chat.campaign.as_ref())
+                .map(|campaign| campaign.gm)
+        })
+        .await;
+    let replied = msg.reply_to.as_ref().and_then(|reply| reply.from.clone());
+
+    let text = match (args.as_slice(), gm) {
+        ([] | ["show"], _) => store.read(|storage| show(storage, chat_id)).await,
+        (["create", ..], Some(_)) => {
+            "This chat already has a campaign. End it first with /campaign end.".to_string()
+        }
+        (["create"], None) => USAGE.to_string(),
+        (["create", ..], None) if !crate::auth::is_chat_admin(bot, msg).await? => {
+            "Only administrators of this chat may start a campaign.".to_string()
+        }
+        (["create", name @ ..], None) => {
+            let campaign = Campaign {
+                name: name.join(" "),
+                gm: user_id,
+                players: BTreeSet::new(),
+                house_rules: HouseRules::default(),
+                monsters: vec![],
+                webhook: None,
+                dddice: None,
+            };
+            let text = format!(
+                "🗺 Started <b>{}</b>, with {} as the GM.",
+                html::escape(&campaign.name),
+                html::escape(&user.name)
+            );
+            store
+                .update(|storage| storage.chat_mut(chat_id).campaign = Some(campaign))
+                .await?;
+            text
+        }
+        (_, None) => format!("This chat has no campaign.\n\n{}", USAGE),
+        (_, Some(gm)) if gm != user_id => "Only the GM may change the campaign.".to_string(),
+        (["invite" | "remove"], _) if replied.is_none() => {
+            "Reply to a message of the player with this command.".to_string()
+        }
+        ([action @ ("invite" | "remove")], _) => {
+            let player = replied.expect("to be a reply");
+            let player_id = player.id;
+            let invite = *action == "invite";
+            store
+                .update(|storage| {
+                    let campaign = storage
+                        .chat_mut(chat_id)
+                        .campaign
+                        .as_mut()
+                        .expect("to have a campaign");
+                    match invite {
+                        true => campaign.players.insert(player_id),
+                        false => campaign.players.remove(&player_id),
+                    }
+                })
+                .await?;
+            let name = html::escape(&player.name);
+            match invite {
+                true => format!("🗺 {} joined the campaign.", name),
+                false => format!("{} left the campaign.", name),
+            }
+        }
+        (["settings", rule @ ..], _) => {
+            store
+                .update(|storage| {
+                    let campaign = storage
+                        .chat_mut(chat_id)
+                        .campaign
+                        .as_mut()
+                        .expect("to have a campaign");
+                    change_rule(&mut campaign.house_rules, rule)
+                })
+                .await?
+        }
+        (["webhook", url], _) => webhook(bot, msg, &store, url).await?,
+        (["dddice", "off"], _) => {
+            store
+                .update(|storage| {
+                    storage
+                        .chat_mut(chat_id)
+                        .campaign
+                        .as_mut()
+                        .expect("to have a campaign")
+                        .dddice = None
+                })
+                .await?;
+            "🗺 Rolls are no longer shown in dddice.".to_string()
+        }
+        (["dddice", room, api_key, theme @ ..], _) if theme.len() <= 1 => {
+            // Nobody else in the chat should see the key
+            if bot.delete(chat_id, msg.message_id).await.is_err() {
+                log::debug!("Could not delete a dddice key from chat {}", chat_id);
+                bot.send(chat_id, KEY_EXPOSED.to_string()).await?;
+            }
+            let Some(secret) = secret else {
+                bot.send(chat_id, NO_SECRET_KEY.to_string()).await?;
+                return Ok(());
+            };
+            let dddice = crate::dddice::Dddice {
+                room: room.to_string(),
+                api_key: secret.seal(api_key),
+                theme: theme.first().map(|theme| theme.to_string()),
+            };
+            store
+                .update(|storage| {
+                    storage
+                        .chat_mut(chat_id)
+                        .campaign
+                        .as_mut()
+                        .expect("to have a campaign")
+                        .dddice = Some(dddice)
+                })
+                .await?;
+            format!(
+                "🗺 Rolls are now shown in the dddice room {}.",
+                html::escape(room)
+            )
+        }
+        (["end"], _) => {
+            let campaign = store
+                .update(|storage| storage.chat_mut(chat_id).campaign.take())
+                .await?;
+            format!(
+                "🗺 <b>{}</b> has ended.",
+                html::escape(&campaign.map(|campaign| campaign.name).unwrap_or_default())
+            )
+        }
+        _ => USAGE.to_string(),
+    };
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+/// Set or drop the webhook, telling the GM the secret it signs with in a private chat
+async fn webhook(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: &Store,
+    url: &str,
+) -> anyhow::Result<String> {
+    let chat_id = msg.chat_id;
+    let webhook = match url {
+        "off" => None,
+        url => match url.parse::<reqwest::Url>() {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {
+                if let Err(reason) = crate::webhook::check_destination(&url).await {
+                    return Ok(reason.to_string());
+                }
+                Some(crate::webhook::Webhook::new(url))
+            }
+            _ => return Ok("That is not an http or https URL.".to_string()),
+        },
+    };
+    // The URL may hold a token, like those of Discord
+    if bot.delete(chat_id, msg.message_id).await.is_err() {
+        log::debug!("Could not delete a webhook URL from chat {}", chat_id);
+    }
+    let secret = webhook.as_ref().map(|webhook| webhook.secret.clone());
+    store
+        .update(|storage| {
+            storage
+                .chat_mut(chat_id)
+                .campaign
+                .as_mut()
+                .expect("to have a campaign")
+                .webhook = webhook
+        })
+        .await?;
+    let Some(secret) = secret else {
+        return Ok("🗺 Rolls are no longer posted to a webhook.".to_string());
+    };
+    let gm = msg.user_id().unwrap_or_default();
+    let private = format!(
+        "🔑 Rolls of {} are signed with the secret <code>{}</code>, as the HMAC-SHA256 of the body in the {} header.",
+        html::escape(msg.chat_title.as_deref().unwrap_or("your campaign")),
+        secret,
+        crate::webhook::SIGNATURE_HEADER
+    );
+    Ok(match bot.send(gm, private).await {
+        Ok(_) => "🗺 Rolls are now posted to the webhook. I sent the GM the secret they are signed with.",
+        Err(_) => "🗺 Rolls are now posted to the webhook, but I could not send the GM the secret they are signed with. Start a private chat with me and set the webhook again.",
+    }
+    .to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_house_rules() {
+        let mut rules = HouseRules::default();
+        assert_eq!(
+            change_rule(&mut rules, &["crit", "19-20"]),
+            "<b>House rules</b>\nCrits on a natural 19 to 20\n\
+             Crit damage rolls the dice twice\nAnyone may reroll"
+        );
+        assert!(change_rule(&mut rules, &["crit", "1"]).starts_with("Crits can start"));
+        change_rule(&mut rules, &["reroll", "gm"]);
+        assert_eq!(rules.rerolls, Rerolls::Gm);
+        assert!(change_rule(&mut rules, &["reroll", "sometimes"]).starts_with("<code>"));
+        assert!(change_rule(&mut rules, &["botch", "subtract"]).contains("1s take away"));
+        assert_eq!(rules.botch, Botch::OnesSubtract);
+        assert!(change_rule(&mut rules, &["degrees", "5", "10"]).contains("by 5 or more"));
+        assert_eq!(rules.degrees.critical_failure, 5);
+        assert!(change_rule(&mut rules, &["degrees", "0", "10"]).starts_with("Critical"));
+
+        let mut storage = Storage::default();
+        assert!(may_reroll(&storage, 1, 7));
+        storage.chat_mut(1).campaign = Some(Campaign {
+            name: "Curse of Strahd".to_string(),
+            gm: 3,
+            players: BTreeSet::from([7]),
+            house_rules: rules,
+            monsters: vec![],
+            webhook: None,
+            dddice: None,
+        });
+        assert!(!may_reroll(&storage, 1, 7));
+        assert!(may_reroll(&storage, 1, 3));
+        assert!(show(&storage, 1).contains("• <a href=\"tg://user?id=7\">no character</a>"));
+    }
+}
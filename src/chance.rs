@@ -0,0 +1,143 @@
+//! Randomness that is not about numbers: flipping coins and picking among options.
+
+use rand::distributions::{Distribution, WeightedIndex};
+use teloxide::utils::html;
+
+use crate::dice::{Flags, Roll, RollSettings};
+use crate::transport::{ChatTransport, Incoming};
+
+/// A coin is a two sided die with named faces
+const COIN: &[&str] = &["Heads", "Tails"];
+
+/// Same limit as the number of dice in a roll
+const MAX_FLIPS: u32 = 9999;
+
+fn flip(input: &str) -> String {
+    let number = match input.trim() {
+        "" => 1,
+        input => match input.parse::<u32>() {
+            Ok(number) if (1..=MAX_FLIPS).contains(&number) => number,
+            _ => {
+                return format!(
+                "<code>/flip</code> flips a coin, <code>/flip 10</code> flips up to {} of them.",
+                MAX_FLIPS
+            )
+            }
+        },
+    };
+    let settings = RollSettings {
+        number,
+        sides: COIN.len() as u32,
+        modifier: None,
+        label: None,
+        target: None,
+        keep: None,
+        explode: None,
+        success: None,
+        composite: false,
+        multiplier: None,
+        flags: Flags::NONE,
+    };
+    let roll = Roll::new(&settings);
+    if number == 1 {
+        return format!("🪙 <b>{}</b>", roll.format_faces(COIN, None));
+    }
+    let tally = roll
+        .tally()
+        .iter()
+        .zip(COIN)
+        .map(|(count, face)| format!("{} {}", face, count))
+        .collect::<Vec<_>>()
+        .join(" · ");
+    format!(
+        "Flips: {}\n🪙 <b>{}</b>",
+        roll.format_faces(COIN, Some(4000)),
+        tally
+    )
+}
+
+const CHOOSE_USAGE: &str = "<code>/choose pizza | sushi | tacos</code> picks one of the options
+<code>/choose pizza*2 | sushi</code> makes pizza twice as likely";
+
+/// Options and their weights, such as `pizza*2 | sushi`
+fn parse_options(input: &str) -> Option<Vec<(&str, u32)>> {
+    let options = input
+        .split('|')
+        .map(str::trim)
+        .filter(|option| !option.is_empty())
+        .map(|option| match option.rsplit_once('*') {
+            Some((name, weight)) => match weight.trim().parse::<u32>() {
+                Ok(weight) => Some((name.trim(), weight)),
+                Err(_) => None,
+            },
+            None => Some((option, 1)),
+        })
+        .collect::<Option<Vec<_>>>()?;
+    (!options.is_empty()).then_some(options)
+}
+
+fn choose(input: &str) -> String {
+    let Some(options) = parse_options(input) else {
+        return CHOOSE_USAGE.to_string();
+    };
+    // Fails when every option weighs 0
+    let Ok(index) = WeightedIndex::new(options.iter().map(|(_, weight)| *weight)) else {
+        return CHOOSE_USAGE.to_string();
+    };
+    let (choice, _) = options[index.sample(&mut rand::thread_rng())];
+    format!("🔮 <b>{}</b>", html::escape(choice))
+}
+
+pub(crate) async fn handle_flip(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    input: &str,
+) -> anyhow::Result<()> {
+    bot.reply(msg, flip(input), vec![]).await?;
+    Ok(())
+}
+
+pub(crate) async fn handle_choose(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    input: &str,
+) -> anyhow::Result<()> {
+    bot.reply(msg, choose(input), vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flips_coins() {
+        let text = flip("");
+        assert!(COIN
+            .iter()
+            .any(|face| text == format!("🪙 <b>{}</b>", face)));
+
+        let text = flip("20");
+        let tally = text.split("<b>").nth(1).unwrap();
+        let counted: usize = tally
+            .trim_end_matches("</b>")
+            .split(" · ")
+            .map(|face| face.rsplit(' ').next().unwrap().parse::<usize>().unwrap())
+            .sum();
+        assert_eq!(counted, 20);
+        assert!(flip("0").contains("/flip 10"));
+    }
+
+    #[test]
+    fn chooses_weighted_options() {
+        assert_eq!(
+            parse_options("pizza*2 | sushi |  | tacos * 0"),
+            Some(vec![("pizza", 2), ("sushi", 1), ("tacos", 0)])
+        );
+        assert_eq!(parse_options("pizza*lots"), None);
+        assert_eq!(parse_options(" | "), None);
+
+        assert_eq!(choose("a*0 | <b>*3"), "🔮 <b>&lt;b&gt;</b>");
+        assert_eq!(choose("a*0"), CHOOSE_USAGE);
+    }
+}
@@ -0,0 +1,230 @@
+//! Choosing which of a user's characters to play in a chat, with `/characters`.
+
+use teloxide::utils::html;
+
+use crate::storage::{Store, User};
+use crate::transport::{Button, ChatTransport, Incoming, Press};
+
+/// Telegram allows at most this many bytes of callback data
+const MAX_DATA: usize = 64;
+
+const PREFIX: &str = "c:";
+
+fn data(user_id: i64, name: &str) -> String {
+    format!("{}{}:{}", PREFIX, user_id, name)
+}
+
+/// The user and the character name of a button
+fn parse_data(data: &str) -> Option<(i64, &str)> {
+    let (user_id, name) = data.strip_prefix(PREFIX)?.split_once(':')?;
+    Some((user_id.parse().ok()?, name))
+}
+
+pub(crate) fn is_button(press: &Press) -> bool {
+    press.data.starts_with(PREFIX)
+}
+
+fn names(user: &User) -> Vec<&str> {
+    let mut names: Vec<_> = user.characters.keys().map(String::as_str).collect();
+    names.sort_unstable();
+    names
+}
+
+fn listing(user: Option<&User>, chat_id: i64) -> String {
+    let Some(user) = user.filter(|user| !user.characters.is_empty()) else {
+        return "You have no characters. Upload one with /sheet upload.".to_string();
+    };
+    let active = user.active_character(chat_id);
+    let lines: Vec<_> = names(user)
+        .into_iter()
+        .map(|name| match Some(name) == active {
+            true => format!("✅ <b>{}</b>, played in this chat", html::escape(name)),
+            false => format!("• {}", html::escape(name)),
+        })
+        .collect();
+    format!("🧙 Your characters\n{}", lines.join("\n"))
+}
+
+/// A button for each character whose name fits in the callback data
+fn keyboard(user: Option<&User>, chat_id: i64) -> Vec<Button> {
+    let Some(user) = user else {
+        return vec![];
+    };
+    let active = user.active_character(chat_id);
+    names(user)
+        .into_iter()
+        .map(|name| (name, data(user.id, name)))
+        .filter(|(_, data)| data.len() <= MAX_DATA)
+        .map(|(name, data)| Button {
+            label: match Some(name) == active {
+                true => format!("✅ {}", name),
+                false => name.to_string(),
+            },
+            data,
+        })
+        .collect()
+}
+
+/// Play the named character in the chat, answering whether it exists
+fn switch(user: &mut User, chat_id: i64, name: &str) -> Option<String> {
+    let name = user
+        .characters
+        .keys()
+        .find(|key| key.eq_ignore_ascii_case(name))?
+        .clone();
+    user.chat_characters.insert(chat_id, name.clone());
+    Some(name)
+}
+
+pub(crate) async fn handle_characters(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let Some(user) = &msg.from else {
+        return Ok(());
+    };
+    let (chat_id, user_id) = (msg.chat_id, user.id);
+    let name = input.trim();
+    if !name.is_empty() {
+        let switched = store
+            .update(|storage| match storage.user(user_id) {
+                Some(_) => switch(storage.user_mut(user_id), chat_id, name),
+                None => None,
+            })
+            .await?;
+        let text = match switched {
+            Some(name) => format!("🧙 You play {} in this chat.", html::escape(&name)),
+            None => "I could not find that character. See /characters.".to_string(),
+        };
+        bot.reply(msg, text, vec![]).await?;
+        return Ok(());
+    }
+
+    let (text, keyboard) = store
+        .read(|storage| {
+            let user = storage.user(user_id);
+            (listing(user, chat_id), keyboard(user, chat_id))
+        })
+        .await;
+    bot.reply(msg, text, keyboard).await?;
+    Ok(())
+}
+
+pub(crate) async fn handle_press(
+    bot: &impl ChatTransport,
+    press: &Press,
+    store: Store,
+) -> anyhow::Result<()> {
+    let (Some((user_id, name)), Some((chat_id, message_id))) =
+        (parse_data(&press.data), press.message)
+    else {
+        return Ok(());
+    };
+    if press.from.id != user_id {
+        let notice = "These are the characters of someone else.".to_string();
+        bot.answer_press(press, Some(notice)).await?;
+        return Ok(());
+    }
+    let (switched, text, keyboard) = store
+        .update(|storage| {
+            let switched = match storage.user(user_id) {
+                Some(_) => switch(storage.user_mut(user_id), chat_id, name),
+                None => None,
+            };
+            let user = storage.user(user_id);
+            (switched, listing(user, chat_id), keyboard(user, chat_id))
+        })
+        .await?;
+    let answer = match switched {
+        Some(name) => format!("You play {} in this chat.", name),
+        None => "That character is gone.".to_string(),
+    };
+    bot.answer_press(press, Some(answer)).await?;
+    bot.edit(chat_id, message_id, text, keyboard).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Storage;
+    use crate::testing::{self, FakeTransport, Sent};
+
+    fn add_characters(user: &mut User) {
+        user.default_character = Some("Thorin".to_string());
+        for name in ["Thorin", "Aria"] {
+            user.characters
+                .insert(name.to_string(), testing::character(name));
+        }
+    }
+
+    #[test]
+    fn switches_per_chat() {
+        let mut storage = Storage::default();
+        let user = storage.user_mut(7);
+        add_characters(user);
+
+        assert_eq!(switch(user, 1, "aria"), Some("Aria".to_string()));
+        assert_eq!(switch(user, 1, "Brom"), None);
+        assert_eq!(user.active_character(1), Some("Aria"));
+        assert_eq!(user.active_character(2), Some("Thorin"));
+        assert_eq!(
+            listing(Some(user), 1),
+            "🧙 Your characters\n✅ <b>Aria</b>, played in this chat\n• Thorin"
+        );
+        assert_eq!(parse_data(&data(7, "Aria")), Some((7, "Aria")));
+        assert!(listing(None, 1).starts_with("You have no characters"));
+    }
+
+    #[tokio::test]
+    async fn switches_with_a_button() {
+        let (bot, store) = (FakeTransport::default(), testing::store().await);
+        store
+            .update(|storage| add_characters(storage.user_mut(7)))
+            .await
+            .unwrap();
+
+        let press = testing::press(8, &data(7, "Aria"));
+        handle_press(&bot, &press, store.clone()).await.unwrap();
+        let notice = Some("These are the characters of someone else.".to_string());
+        assert_eq!(
+            bot.sent(),
+            vec![Sent::PressAnswer {
+                id: "press".to_string(),
+                notice
+            }]
+        );
+
+        let press = testing::press(7, &data(7, "Aria"));
+        handle_press(&bot, &press, store.clone()).await.unwrap();
+        let sent = bot.sent();
+        let notice = Some("You play Aria in this chat.".to_string());
+        assert_eq!(
+            sent[1],
+            Sent::PressAnswer {
+                id: "press".to_string(),
+                notice
+            }
+        );
+        let Sent::Edit {
+            message_id,
+            buttons,
+            ..
+        } = &sent[2]
+        else {
+            panic!("expected an edit, got {:?}", sent);
+        };
+        assert_eq!(*message_id, 1000);
+        assert_eq!(buttons[0].label, "✅ Aria");
+        let active = store
+            .read(|storage| {
+                storage
+                    .user(7)
+                    .and_then(|user| user.active_character(testing::CHAT_ID))
+                    .map(str::to_string)
+            })
+            .await;
+        assert_eq!(active.as_deref(), Some("Aria"));
+    }
+}
@@ -1,4 +1,4 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 
 /// Telegram bot to roll die!
 #[derive(Parser, Debug)]
@@ -7,44 +7,293 @@ pub struct Cli {
     #[command(subcommand)]
     pub command: Option<Command>,
 
+    /// Format of the log output
+    #[arg(long, env, global = true, value_enum, default_value_t)]
+    pub log_format: LogFormat,
+
+    /// OTLP gRPC endpoint to export traces to, such as http://localhost:4317
+    #[cfg(feature = "otel")]
+    #[arg(long, env, global = true)]
+    pub otel_endpoint: Option<String>,
+
+    /// Sentry DSN to report errors and panics to
+    #[cfg(feature = "sentry")]
+    #[arg(long, env, global = true)]
+    pub sentry_dsn: Option<String>,
+
     // https://github.com/clap-rs/clap/issues/3857#issuecomment-1239419407
     #[clap(flatten)]
     run: RunArgs,
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable, coloured lines
+    #[default]
+    Pretty,
+    /// One JSON object per line, for log collectors
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SimulationFormat {
+    /// Summary statistics and a histogram
+    #[default]
+    Text,
+    /// One line per total, with its count and probability
+    Csv,
+}
+
 /// Actions
 #[derive(Subcommand, Debug)]
 pub enum Command {
     /// Run bot
-    Run(RunArgs),
+    Run(Box<RunArgs>),
+
+    /// Check the config file, bot token, storage and character data that `run` would use, without starting the bot
+    Validate(Box<RunArgs>),
+
+    /// Roll dice without a bot, such as `roll 2d6+3 --adv`
+    Roll {
+        /// Dice to roll, in the same format as /roll
+        #[arg(required = true, allow_negative_numbers = true)]
+        expression: Vec<String>,
+
+        /// Roll with advantage
+        #[arg(long, conflicts_with = "dis")]
+        adv: bool,
+
+        /// Roll with disadvantage
+        #[arg(long)]
+        dis: bool,
+
+        /// Print the results as JSON instead, like /data
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Roll dice many times and show how the totals are distributed
+    Simulate {
+        /// Dice to roll, in the same format as /roll
+        #[arg(allow_negative_numbers = true)]
+        expression: String,
+
+        #[arg(long, short, default_value_t = 100_000)]
+        iterations: u64,
+
+        /// Roll with advantage
+        #[arg(long, conflicts_with = "dis")]
+        adv: bool,
+
+        /// Roll with disadvantage
+        #[arg(long)]
+        dis: bool,
+
+        #[arg(long, value_enum, default_value_t)]
+        format: SimulationFormat,
+    },
+
+    /// Measure how fast expressions are parsed and rolled. Only release builds give meaningful numbers.
+    Bench {
+        /// File with one expression per line. A built in corpus is used when this is not set.
+        #[arg(long)]
+        corpus: Option<String>,
+
+        /// Milliseconds to spend on parsing each expression, on rolling it with and without a shared rng, and on sampling each die
+        #[arg(long, default_value_t = 500)]
+        duration: u64,
+    },
+
+    /// Print a shell completion script, such as `completions bash > /etc/bash_completion.d/telegram-dice-maestro-oxide`
+    Completions { shell: clap_complete::Shell },
+
+    /// Print the man page in roff format
+    ManPage {
+        /// Write the man page to this file instead of standard output
+        #[arg(long, short)]
+        output: Option<String>,
+    },
 
     /// Load Character Data and validate it. Echoes the output back.
     LoadCharacterData {
         /// Path to a single file or a directory containing character data
         path: String,
     },
+
+    /// Convert a D&D Beyond character export into character data
+    ConvertDdb {
+        /// Path to the D&D Beyond JSON export
+        input: String,
+
+        /// Write the character data to this file instead of standard output
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+
+    /// Convert a Foundry VTT actor export into character data
+    ConvertFoundry {
+        /// Path to the Foundry VTT actor JSON export
+        input: String,
+
+        /// Write the character data to this file instead of standard output
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+
+    /// Dump all persisted state into a versioned JSON archive
+    ExportStorage {
+        #[clap(flatten)]
+        storage: StorageArgs,
+
+        /// Write the archive to this file instead of standard output
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+
+    /// Restore persisted state from an archive made by `export-storage`
+    ImportStorage {
+        #[clap(flatten)]
+        storage: StorageArgs,
+
+        /// Path to the archive
+        input: String,
+
+        /// Import even if the storage already holds data, replacing it. Roll history is appended.
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Print the JSON Schema of character data, using the canonical field names
+    Schema {
+        /// Write the schema to this file instead of standard output
+        #[arg(long, short)]
+        output: Option<String>,
+    },
 }
 
-#[derive(Parser, Debug, Default)]
+#[derive(Parser, Debug, Default, Clone)]
 pub struct RunArgs {
+    /// TOML file with any of these settings. Command line arguments and environment variables take precedence.
+    #[arg(long, env)]
+    pub config: Option<String>,
+
     /// Path to file containing Telegram Bot Token
-    #[arg(
-        long,
-        env,
-        conflicts_with("bot_token"),
-        required_unless_present("bot_token")
-    )]
+    #[arg(long, env, conflicts_with("bot_token"))]
     pub bot_token_file: Option<String>,
 
     /// Bot token. **Highly recommended that this is not set via command line, because it will show up in running processes.**
-    #[arg(long, env, required_unless_present("bot_token_file"))]
+    #[arg(long, env)]
     pub bot_token: Option<String>,
 
     /// Set bot commands on startup
     #[arg(long, env)]
     pub set_my_commands: bool,
 
+    #[clap(flatten)]
+    pub storage: StorageArgs,
+
+    #[clap(flatten)]
+    pub throttle: ThrottleArgs,
+
+    /// URL of the Bot API server, such as a self-hosted one at http://localhost:8081. For testing without the production API.
+    #[arg(long, env)]
+    pub api_url: Option<reqwest::Url>,
+
+    /// Proxy to reach the Telegram API through, such as http://proxy:3128 or socks5://proxy:1080
+    #[arg(long, env)]
+    pub proxy: Option<String>,
+
+    /// Telegram user IDs of the bot operators, who may use /admin
+    #[arg(long = "operator", env = "OPERATORS", value_delimiter = ',')]
+    pub operators: Vec<i64>,
+
+    /// Only allow group chats that are listed in --allowed-chats or approved with /admin approve
+    #[arg(long, env)]
+    pub restrict_chats: bool,
+
+    /// Group chat IDs that may use the bot. Implies --restrict-chats.
+    #[arg(long, env, value_delimiter = ',')]
+    pub allowed_chats: Vec<i64>,
+
+    /// Group chat IDs that may never use the bot
+    #[arg(long, env, value_delimiter = ',')]
+    pub denied_chats: Vec<i64>,
+
+    /// Commands a user may send per minute. 0 turns the limit off.
+    #[arg(long, env, default_value_t = 20)]
+    pub rate_limit: usize,
+
+    /// Directory to back up the storage to. There are no backups when this is not set.
+    #[arg(long, env)]
+    pub backup_dir: Option<String>,
+
+    /// Minutes between backups
+    #[arg(long, env, default_value_t = 60)]
+    pub backup_interval: u64,
+
+    /// Number of backups to keep
+    #[arg(long, env, default_value_t = 24)]
+    pub backup_retention: usize,
+
+    /// Chat to send error reports to, such as the user ID of an operator
+    #[arg(long, env)]
+    pub error_chat: Option<i64>,
+
+    /// Address to serve Prometheus metrics on, such as 0.0.0.0:9090
+    #[arg(long, env)]
+    pub metrics_address: Option<std::net::SocketAddr>,
+
+    /// Glob of character data files to give to their players, reloaded when they change
+    #[arg(long, env)]
+    pub character_data: Option<String>,
+
+    /// File to append every roll to as a line of JSON, or an http(s) URL to post each roll to
+    #[arg(long, env)]
+    pub roll_log: Option<String>,
+
+    /// File with the key that secrets of users, such as dddice keys, are encrypted with: 32 bytes in hex, from `openssl rand -hex 32`. The bot does not take such secrets without it.
+    #[arg(long, env)]
+    pub secret_key_file: Option<String>,
+
+    /// Answer to /donate, such as links to pay for hosting the bot. May be formatted with Telegram HTML.
+    #[arg(long, env)]
+    pub donate: Option<String>,
+}
+
+/// Where the storage is kept
+#[derive(Parser, Debug, Default, Clone)]
+pub struct StorageArgs {
     /// Path to data storage file
     #[arg(long, env, default_value("storage.db"))]
     pub storage_path: String,
+
+    /// PostgreSQL connection URL. When set, storage is kept there instead of the storage file.
+    #[cfg(feature = "postgres")]
+    #[arg(long, env)]
+    pub database_url: Option<String>,
+
+    /// Maximum number of connections to PostgreSQL
+    #[cfg(feature = "postgres")]
+    #[arg(long, env, default_value_t = 5)]
+    pub database_max_connections: u32,
+}
+
+/// How fast messages are sent, to stay under the limits of the Telegram API. Lower them if you see 429 errors.
+#[derive(Parser, Debug, Default, Clone)]
+pub struct ThrottleArgs {
+    /// Messages per second to a single chat
+    #[arg(long, env, default_value_t = 1)]
+    pub throttle_chat_per_second: u32,
+
+    /// Messages per minute to a single group chat
+    #[arg(long, env, default_value_t = 20)]
+    pub throttle_chat_per_minute: u32,
+
+    /// Messages per minute to a single channel
+    #[arg(long, env, default_value_t = 10)]
+    pub throttle_channel_per_minute: u32,
+
+    /// Messages per second to all chats together
+    #[arg(long, env, default_value_t = 30)]
+    pub throttle_overall_per_second: u32,
 }
@@ -0,0 +1,430 @@
+use serde::{Deserialize, Serialize};
+use teloxide::utils::html;
+
+use crate::dice::{Flags, RollResults, RollSettings, RollType};
+use crate::dnd::Character;
+use crate::monster::Monster;
+use crate::storage::Store;
+use crate::transport::{ChatTransport, Incoming};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct Combatant {
+    pub name: String,
+    pub initiative: i64,
+    #[serde(default)]
+    pub armor_class: Option<u8>,
+    #[serde(default)]
+    pub hit_points: Option<HitPoints>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct HitPoints {
+    pub current: i32,
+    pub max: u16,
+}
+
+fn roll_initiative(modifier: i32) -> i64 {
+    let settings = RollSettings {
+        number: 1,
+        sides: 20,
+        modifier: Some(modifier),
+        label: None,
+        target: None,
+        keep: None,
+        explode: None,
+        success: None,
+        composite: false,
+        multiplier: None,
+        flags: Flags::NONE,
+    };
+    RollResults::new(&settings, &RollType::Straight)
+        .result
+        .total
+}
+
+impl Combatant {
+    /// Roll initiative for a character, who starts at full hit points
+    pub fn from_character(character: &Character) -> Self {
+        Combatant {
+            name: character.name.clone(),
+            initiative: roll_initiative(character.initiative_modifier as i32),
+            armor_class: character.armor_class,
+            hit_points: character.max_hit_points.map(|max| HitPoints {
+                current: max as i32,
+                max,
+            }),
+        }
+    }
+}
+
+/// Monsters of `/combat start`, such as `goblin x4 +2`
+#[derive(Debug, PartialEq, Eq)]
+struct Monsters {
+    name: String,
+    count: u32,
+    /// The modifier of the stat block of the monster, when not given
+    initiative_modifier: Option<i32>,
+}
+
+const MAX_MONSTERS: u32 = 50;
+
+/// Comma separated monsters, each a name with an optional count and initiative modifier
+fn parse_monsters(input: &str) -> Result<Vec<Monsters>, String> {
+    let mut parsed = vec![];
+    for entry in input.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let mut monsters = Monsters {
+            name: String::new(),
+            count: 1,
+            initiative_modifier: None,
+        };
+        let mut name = vec![];
+        for word in entry.split_whitespace() {
+            if let Some(count) = word.strip_prefix(['x', 'X']).and_then(|c| c.parse().ok()) {
+                monsters.count = count;
+            } else if let Some(modifier) = word.strip_prefix('+').and_then(|m| m.parse().ok()) {
+                monsters.initiative_modifier = Some(modifier);
+            } else if let Ok(modifier @ ..=-1) = word.parse() {
+                monsters.initiative_modifier = Some(modifier);
+            } else {
+                name.push(word);
+            }
+        }
+        if name.is_empty() || !(1..=MAX_MONSTERS).contains(&monsters.count) {
+            return Err(format!(
+                "I do not understand <code>{}</code>. Give a name, and optionally up to x{} and an initiative modifier, such as <code>goblin x4 +2</code>.",
+                html::escape(entry),
+                MAX_MONSTERS
+            ));
+        }
+        monsters.name = name.join(" ");
+        parsed.push(monsters);
+    }
+    Ok(parsed)
+}
+
+/// A new combat, with initiative rolled for every character and monster.
+/// Monsters with a stat block in `stats` get its armor class and hit points.
+fn start(characters: &[&Character], monsters: &[Monsters], stats: &[Monster]) -> Tracker {
+    let mut tracker = Tracker::default();
+    for character in characters {
+        tracker.add(Combatant::from_character(character));
+    }
+    for monsters in monsters {
+        for number in 1..=monsters.count {
+            let name = match monsters.count {
+                1 => monsters.name.clone(),
+                _ => format!("{} {}", monsters.name, number),
+            };
+            let stats = crate::monster::find(stats, &monsters.name);
+            let modifier = monsters
+                .initiative_modifier
+                .or(stats.map(|stats| stats.initiative_modifier as i32))
+                .unwrap_or(0);
+            let initiative = roll_initiative(modifier);
+            tracker.add(match stats {
+                Some(stats) => stats.combatant(name, initiative),
+                None => Combatant {
+                    name,
+                    initiative,
+                    armor_class: None,
+                    hit_points: None,
+                },
+            });
+        }
+    }
+    // Everyone rolled at once, so the highest initiative goes first
+    tracker.turn = 0;
+    tracker
+}
+
+/// Lowercase without whitespace, so that `goblin2` names `Goblin 2`
+pub(crate) fn normalize(name: &str) -> String {
+    name.split_whitespace().collect::<String>().to_lowercase()
+}
+
+/// Initiative order of a chat's current combat
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct Tracker {
+    /// Sorted by initiative, highest first
+    combatants: Vec<Combatant>,
+    /// Index of the combatant whose turn it is
+    turn: usize,
+    round: u32,
+}
+
+impl Tracker {
+    pub fn is_empty(&self) -> bool {
+        self.combatants.is_empty()
+    }
+
+    /// Add a combatant in initiative order. Ties go after existing combatants.
+    pub fn add(&mut self, combatant: Combatant) {
+        if self.is_empty() {
+            self.round = 1;
+        }
+        let index = self
+            .combatants
+            .iter()
+            .position(|c| c.initiative < combatant.initiative)
+            .unwrap_or(self.combatants.len());
+        // Keep the turn with whoever currently has it
+        if index <= self.turn && !self.is_empty() {
+            self.turn += 1;
+        }
+        self.combatants.insert(index, combatant);
+    }
+
+    /// Move on to the next combatant, returning whose turn it is now
+    pub fn next_turn(&mut self) -> Option<&Combatant> {
+        if self.is_empty() {
+            return None;
+        }
+        self.turn += 1;
+        if self.turn >= self.combatants.len() {
+            self.turn = 0;
+            self.round += 1;
+        }
+        self.combatants.get(self.turn)
+    }
+
+    pub fn combatant(&self, name: &str) -> Option<&Combatant> {
+        let name = normalize(name);
+        self.combatants.iter().find(|c| normalize(&c.name) == name)
+    }
+
+    /// The combatant of a name like `goblin2` or `Goblin 2`
+    pub fn combatant_mut(&mut self, name: &str) -> Option<&mut Combatant> {
+        let name = normalize(name);
+        self.combatants
+            .iter_mut()
+            .find(|c| normalize(&c.name) == name)
+    }
+
+    pub fn clear(&mut self) {
+        *self = Default::default();
+    }
+}
+
+impl std::fmt::Display for Tracker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "There is no combat going on.");
+        }
+        write!(f, "⚔️ <b>Round {}</b>", self.round)?;
+        for (index, combatant) in self.combatants.iter().enumerate() {
+            let marker = if index == self.turn {
+                "▶️"
+            } else {
+                "▫️"
+            };
+            write!(
+                f,
+                "\n{} {} {}",
+                marker,
+                combatant.initiative,
+                html::escape(&combatant.name)
+            )?;
+            let mut stats = vec![];
+            if let Some(armor_class) = combatant.armor_class {
+                stats.push(format!("AC {}", armor_class));
+            }
+            if let Some(ref hit_points) = combatant.hit_points {
+                stats.push(format!("HP {}/{}", hit_points.current, hit_points.max));
+            }
+            if !stats.is_empty() {
+                write!(f, " ({})", stats.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+const USAGE: &str = "<code>/combat</code> shows the initiative order
+<code>/combat start goblin x4 +2, ogre -1</code> starts a combat, rolling initiative for every character in this chat and the monsters
+<code>/combat join [character]</code> rolls initiative for your character
+<code>/combat next</code> moves on to the next turn
+<code>/combat end</code> ends the combat";
+
+pub(crate) async fn handle_combat(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let input = input.trim();
+    let text = match input.split_once(' ').unwrap_or((input, "")) {
+        ("", _) => {
+            store
+                .read(|storage| {
+                    storage
+                        .chat(chat_id)
+                        .map(|chat| chat.combat.to_string())
+                        .unwrap_or_else(|| Tracker::default().to_string())
+                })
+                .await
+        }
+        ("join", name) => {
+            let Some(user) = &msg.from else {
+                return Ok(());
+            };
+            let user_id = user.id;
+            let name = Some(name.trim()).filter(|name| !name.is_empty());
+            store
+                .update(|storage| {
+                    let Some(character) = storage
+                        .user(user_id)
+                        .and_then(|user| user.character(chat_id, name))
+                        .cloned()
+                    else {
+                        return "I could not find that character. Upload one with /sheet upload."
+                            .to_string();
+                    };
+                    let tracker = &mut storage.chat_mut(chat_id).combat;
+                    tracker.add(Combatant::from_character(&character));
+                    tracker.to_string()
+                })
+                .await?
+        }
+        ("start", monsters) => {
+            let gm = store
+                .read(|storage| {
+                    storage
+                        .chat(chat_id)
+                        .and_then(|chat| chat.campaign.as_ref())
+                        .map(|campaign| campaign.gm)
+                })
+                .await;
+            let is_gm = gm.is_some() && gm == msg.user_id();
+            match parse_monsters(monsters) {
+                _ if !is_gm && !crate::auth::is_chat_admin(bot, msg).await? => {
+                    "Only the GM or chat administrators can start a combat.".to_string()
+                }
+                Err(e) => e,
+                Ok(monsters) => {
+                    store
+                        .update(|storage| {
+                            let characters = crate::groupcheck::characters(storage, chat_id, &[]);
+                            let stats = storage
+                                .chat(chat_id)
+                                .and_then(|chat| chat.campaign.as_ref())
+                                .map(|campaign| campaign.monsters.as_slice())
+                                .unwrap_or_default();
+                            let tracker = start(&characters, &monsters, stats);
+                            let text = tracker.to_string();
+                            storage.chat_mut(chat_id).combat = tracker;
+                            text
+                        })
+                        .await?
+                }
+            }
+        }
+        ("next", _) => {
+            store
+                .update(|storage| {
+                    let tracker = &mut storage.chat_mut(chat_id).combat;
+                    tracker.next_turn();
+                    tracker.to_string()
+                })
+                .await?
+        }
+        ("end", _) => {
+            if !crate::auth::is_chat_admin(bot, msg).await? {
+                "Only chat administrators can end the combat.".to_string()
+            } else {
+                store
+                    .update(|storage| storage.chat_mut(chat_id).combat.clear())
+                    .await?;
+                "Combat has ended.".to_string()
+            }
+        }
+        _ => USAGE.to_string(),
+    };
+
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn combatant(name: &str, initiative: i64) -> Combatant {
+        Combatant {
+            name: name.to_string(),
+            initiative,
+            armor_class: None,
+            hit_points: None,
+        }
+    }
+
+    #[test]
+    fn combatants_are_kept_in_initiative_order() {
+        let mut tracker = Tracker::default();
+        tracker.add(combatant("Goblin", 12));
+        tracker.add(combatant("Thorin", 18));
+        tracker.add(combatant("Wolf", 12));
+        tracker.add(combatant("Elara", 3));
+
+        let names: Vec<_> = tracker.combatants.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["Thorin", "Goblin", "Wolf", "Elara"]);
+    }
+
+    #[test]
+    fn starts_with_monsters() {
+        let monsters = parse_monsters("goblin x4 +2, Ogre Chieftain -1").unwrap();
+        assert_eq!(
+            monsters,
+            vec![
+                Monsters {
+                    name: "goblin".to_string(),
+                    count: 4,
+                    initiative_modifier: Some(2),
+                },
+                Monsters {
+                    name: "Ogre Chieftain".to_string(),
+                    count: 1,
+                    initiative_modifier: Some(-1),
+                },
+            ]
+        );
+        assert!(parse_monsters("x4 +2").is_err());
+        assert!(parse_monsters("goblin x0").is_err());
+        assert_eq!(parse_monsters(""), Ok(vec![]));
+
+        let tracker = start(&[], &monsters, &[]);
+        let mut names: Vec<_> = tracker.combatants.iter().map(|c| c.name.as_str()).collect();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            [
+                "Ogre Chieftain",
+                "goblin 1",
+                "goblin 2",
+                "goblin 3",
+                "goblin 4"
+            ]
+        );
+        assert_eq!((tracker.round, tracker.turn), (1, 0));
+    }
+
+    #[test]
+    fn turn_stays_with_combatant_when_someone_joins() {
+        let mut tracker = Tracker::default();
+        tracker.add(combatant("Goblin", 12));
+        tracker.add(combatant("Wolf", 8));
+        assert_eq!(tracker.next_turn().unwrap().name, "Wolf");
+
+        tracker.add(combatant("Thorin", 18));
+        assert_eq!(tracker.combatants[tracker.turn].name, "Wolf");
+
+        assert_eq!(tracker.next_turn().unwrap().name, "Thorin");
+        assert_eq!(tracker.round, 2);
+    }
+}
@@ -0,0 +1,189 @@
+//! Concentration on spells, with `/concentrate`, and the saves that damage calls for.
+
+use serde::{Deserialize, Serialize};
+use teloxide::utils::html;
+
+use crate::dice::{Flags, RollResults, RollSettings, RollType};
+use crate::storage::{Storage, Store};
+use crate::transport::{ChatTransport, Incoming};
+
+/// What happens when a concentrating character takes tracked damage
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Saves {
+    /// The bot rolls the save
+    #[default]
+    Roll,
+    /// The player is asked to roll the save
+    Prompt,
+}
+
+/// The DC of the Constitution save after taking damage
+fn dc(damage: i64) -> i64 {
+    (damage / 2).max(10)
+}
+
+/// The save that tracked damage to a combatant calls for, when it is a concentrating character of the chat
+pub(crate) fn on_damage(
+    storage: &mut Storage,
+    chat_id: i64,
+    name: &str,
+    damage: i64,
+) -> Option<String> {
+    if damage <= 0 {
+        return None;
+    }
+    let (user_id, character) = storage
+        .chat(chat_id)?
+        .members
+        .iter()
+        .filter_map(|id| storage.user(*id))
+        .find_map(|user| {
+            let character = user
+                .characters
+                .values()
+                .find(|character| character.name.eq_ignore_ascii_case(name))?;
+            Some((user.id, character.clone()))
+        })?;
+    let saves = storage
+        .chat(chat_id)
+        .map(|chat| chat.settings.concentration)
+        .unwrap_or_default();
+    let state = storage
+        .user_mut(user_id)
+        .states
+        .get_mut(&character.name)
+        .filter(|state| state.concentrating.is_some())?;
+    let spell = html::escape(state.concentrating.as_deref().unwrap_or_default());
+    let (dc, modifier) = (dc(damage), character.saving_throw_modifiers.constitution);
+
+    if saves == Saves::Prompt {
+        return Some(format!(
+            "🧠 {} is concentrating on {}. Roll <code>/roll 1d20{:+} con save vs {}</code> to keep it.",
+            html::escape(&character.name),
+            spell,
+            modifier,
+            dc
+        ));
+    }
+    let settings = RollSettings {
+        number: 1,
+        sides: 20,
+        modifier: Some(modifier as i32),
+        label: None,
+        target: Some(dc),
+        keep: None,
+        explode: None,
+        success: None,
+        composite: false,
+        multiplier: None,
+        flags: Flags::NONE,
+    };
+    let results = RollResults::new(&settings, &RollType::Straight);
+    let roll = &results.result;
+    let holds = results.degree().is_some_and(|degree| degree.passed());
+    if !holds {
+        state.concentrating = None;
+    }
+    Some(format!(
+        "🧠 Concentration on {}: Constitution save {} = <b>{}</b> vs DC {}, {}",
+        spell,
+        roll.format_roll(Some(100)),
+        roll.total,
+        dc,
+        match holds {
+            true => "it holds",
+            false => "it is broken",
+        }
+    ))
+}
+
+const USAGE: &str = "<code>/concentrate Bless</code> concentrates on a spell
+<code>/concentrate</code> shows what your character concentrates on
+<code>/concentrate end</code> stops concentrating
+Damage applied in /combat calls for the Constitution save, rolled or asked for as set with /settings.";
+
+pub(crate) async fn handle_concentrate(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let Some(user) = &msg.from else {
+        return Ok(());
+    };
+    let (chat_id, user_id) = (msg.chat_id, user.id);
+    let spell = input.trim().to_string();
+    let text = store
+        .update(|storage| {
+            let state = match storage.user(user_id) {
+                Some(_) => storage.user_mut(user_id).active_state_mut(chat_id),
+                None => None,
+            };
+            let Some((name, state)) = state else {
+                return "You have no character. Upload one with /sheet upload.".to_string();
+            };
+            let name = html::escape(&name);
+            match spell.as_str() {
+                "help" => USAGE.to_string(),
+                "" => match state.concentrating {
+                    Some(ref spell) => {
+                        format!("🧠 {} is concentrating on {}.", name, html::escape(spell))
+                    }
+                    None => format!("{} is not concentrating on anything.", name),
+                },
+                "end" | "stop" => match state.concentrating.take() {
+                    Some(spell) => {
+                        format!("{} stops concentrating on {}.", name, html::escape(&spell))
+                    }
+                    None => format!("{} was not concentrating on anything.", name),
+                },
+                _ => match state.concentrating.replace(spell.clone()) {
+                    Some(previous) => format!(
+                        "🧠 {} drops {} to concentrate on {}.",
+                        name,
+                        html::escape(&previous),
+                        html::escape(&spell)
+                    ),
+                    None => format!("🧠 {} concentrates on {}.", name, html::escape(&spell)),
+                },
+            }
+        })
+        .await?;
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_after_damage() {
+        assert_eq!(dc(7), 10);
+        assert_eq!(dc(31), 15);
+
+        let mut storage = Storage::default();
+        storage.chat_mut(1).members.insert(7);
+        let mut character = crate::testing::character("Aria");
+        character.saving_throw_modifiers.constitution = 50;
+        let user = storage.user_mut(7);
+        user.characters.insert("Aria".to_string(), character);
+        assert_eq!(on_damage(&mut storage, 1, "aria", 12), None);
+
+        let user = storage.user_mut(7);
+        user.states
+            .entry("Aria".to_string())
+            .or_default()
+            .concentrating = Some("Bless".to_string());
+        let text = on_damage(&mut storage, 1, "Aria", 12).unwrap();
+        assert!(text.ends_with("vs DC 10, it holds"), "{}", text);
+
+        storage.chat_mut(1).settings.concentration = Saves::Prompt;
+        assert_eq!(
+            on_damage(&mut storage, 1, "Aria", 40).unwrap(),
+            "🧠 Aria is concentrating on Bless. Roll <code>/roll 1d20+50 con save vs 20</code> to keep it."
+        );
+        assert_eq!(on_damage(&mut storage, 1, "Brom", 40), None);
+    }
+}
@@ -0,0 +1,255 @@
+//! Settings from a TOML file. Command line arguments and environment variables take precedence.
+
+use std::path::Path;
+
+use anyhow::Context;
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use serde::Deserialize;
+
+use crate::cli::RunArgs;
+
+#[derive(Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub bot_token_file: Option<String>,
+    pub set_my_commands: Option<bool>,
+    pub operators: Option<Vec<i64>>,
+    pub restrict_chats: Option<bool>,
+    pub allowed_chats: Option<Vec<i64>>,
+    pub denied_chats: Option<Vec<i64>>,
+    pub rate_limit: Option<usize>,
+    pub api_url: Option<reqwest::Url>,
+    pub proxy: Option<String>,
+    pub error_chat: Option<i64>,
+    pub metrics_address: Option<std::net::SocketAddr>,
+    pub character_data: Option<String>,
+    pub roll_log: Option<String>,
+    pub secret_key_file: Option<String>,
+    pub donate: Option<String>,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub backups: BackupConfig,
+    #[serde(default)]
+    pub throttle: ThrottleConfig,
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct StorageConfig {
+    pub path: Option<String>,
+    #[cfg(feature = "postgres")]
+    pub database_url: Option<String>,
+    #[cfg(feature = "postgres")]
+    pub database_max_connections: Option<u32>,
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct BackupConfig {
+    pub dir: Option<String>,
+    /// Minutes between backups
+    pub interval: Option<u64>,
+    pub retention: Option<usize>,
+}
+
+/// Messages the bot may send
+#[derive(Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct ThrottleConfig {
+    pub chat_per_second: Option<u32>,
+    pub chat_per_minute: Option<u32>,
+    pub channel_per_minute: Option<u32>,
+    pub overall_per_second: Option<u32>,
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("error reading config file {:?}", path))?;
+        toml::from_str(&contents).with_context(|| format!("error parsing config file {:?}", path))
+    }
+
+    /// Fill in the arguments that were not given on the command line or in the environment
+    pub fn apply(self, args: &mut RunArgs, matches: &ArgMatches) {
+        let unset = |id: &str| {
+            !matches!(
+                matches.value_source(id),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        };
+        fn set<T>(unset: bool, field: &mut T, value: Option<T>) {
+            if let (true, Some(value)) = (unset, value) {
+                *field = value;
+            }
+        }
+
+        // A token on the command line wins over a token file in the config
+        if unset("bot_token") {
+            set(
+                unset("bot_token_file"),
+                &mut args.bot_token_file,
+                self.bot_token_file.map(Some),
+            );
+        }
+        set(
+            unset("set_my_commands"),
+            &mut args.set_my_commands,
+            self.set_my_commands,
+        );
+        set(unset("operators"), &mut args.operators, self.operators);
+        set(
+            unset("restrict_chats"),
+            &mut args.restrict_chats,
+            self.restrict_chats,
+        );
+        set(
+            unset("allowed_chats"),
+            &mut args.allowed_chats,
+            self.allowed_chats,
+        );
+        set(
+            unset("denied_chats"),
+            &mut args.denied_chats,
+            self.denied_chats,
+        );
+        set(unset("rate_limit"), &mut args.rate_limit, self.rate_limit);
+        set(unset("api_url"), &mut args.api_url, self.api_url.map(Some));
+        set(unset("proxy"), &mut args.proxy, self.proxy.map(Some));
+        set(
+            unset("error_chat"),
+            &mut args.error_chat,
+            self.error_chat.map(Some),
+        );
+        set(
+            unset("metrics_address"),
+            &mut args.metrics_address,
+            self.metrics_address.map(Some),
+        );
+        set(
+            unset("character_data"),
+            &mut args.character_data,
+            self.character_data.map(Some),
+        );
+        set(
+            unset("roll_log"),
+            &mut args.roll_log,
+            self.roll_log.map(Some),
+        );
+        set(
+            unset("secret_key_file"),
+            &mut args.secret_key_file,
+            self.secret_key_file.map(Some),
+        );
+        set(unset("donate"), &mut args.donate, self.donate.map(Some));
+
+        let storage = &mut args.storage;
+        set(
+            unset("storage_path"),
+            &mut storage.storage_path,
+            self.storage.path,
+        );
+        #[cfg(feature = "postgres")]
+        {
+            set(
+                unset("database_url"),
+                &mut storage.database_url,
+                self.storage.database_url.map(Some),
+            );
+            set(
+                unset("database_max_connections"),
+                &mut storage.database_max_connections,
+                self.storage.database_max_connections,
+            );
+        }
+
+        set(
+            unset("backup_dir"),
+            &mut args.backup_dir,
+            self.backups.dir.map(Some),
+        );
+        set(
+            unset("backup_interval"),
+            &mut args.backup_interval,
+            self.backups.interval,
+        );
+        set(
+            unset("backup_retention"),
+            &mut args.backup_retention,
+            self.backups.retention,
+        );
+
+        let throttle = &mut args.throttle;
+        set(
+            unset("throttle_chat_per_second"),
+            &mut throttle.throttle_chat_per_second,
+            self.throttle.chat_per_second,
+        );
+        set(
+            unset("throttle_chat_per_minute"),
+            &mut throttle.throttle_chat_per_minute,
+            self.throttle.chat_per_minute,
+        );
+        set(
+            unset("throttle_channel_per_minute"),
+            &mut throttle.throttle_channel_per_minute,
+            self.throttle.channel_per_minute,
+        );
+        set(
+            unset("throttle_overall_per_second"),
+            &mut throttle.throttle_overall_per_second,
+            self.throttle.overall_per_second,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    #[test]
+    fn parses_example() {
+        let config: Config = toml::from_str(include_str!("../examples/bot.toml")).unwrap();
+        assert_eq!(config.backups.retention, Some(24));
+    }
+
+    #[test]
+    fn command_line_takes_precedence() {
+        let config: Config = toml::from_str(
+            r#"
+            bot_token_file = "token"
+            operators = [1, 2]
+            donate = "<a href=\"https://ko-fi.com/example\">Buy me a coffee</a>"
+
+            [storage]
+            path = "config.db"
+
+            [backups]
+            dir = "backups"
+            retention = 3
+            "#,
+        )
+        .unwrap();
+
+        let matches = RunArgs::command()
+            .try_get_matches_from(["run", "--storage-path", "cli.db", "--backup-retention", "5"])
+            .unwrap();
+        let mut args = RunArgs::from_arg_matches(&matches).unwrap();
+        config.apply(&mut args, &matches);
+
+        assert_eq!(args.bot_token_file.as_deref(), Some("token"));
+        assert_eq!(args.operators, [1, 2]);
+        assert!(args.donate.unwrap().contains("ko-fi"));
+        assert_eq!(args.storage.storage_path, "cli.db");
+        assert_eq!(args.backup_dir.as_deref(), Some("backups"));
+        assert_eq!(args.backup_retention, 5);
+        assert_eq!(args.backup_interval, 60);
+    }
+}
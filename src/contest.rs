@@ -0,0 +1,222 @@
+//! Opposed checks between two users, such as `/contest @alice athletics @bob acrobatics`.
+
+use std::cmp::Ordering;
+
+use teloxide::utils::html;
+
+use crate::dice::{Flags, RollResults, RollSettings, RollType};
+use crate::groupcheck::Check;
+use crate::storage::{Storage, Store};
+use crate::transport::{ChatTransport, Incoming};
+
+#[derive(Debug, PartialEq)]
+enum Contested {
+    /// A check with the modifier of the user's character
+    Check(Check, RollType),
+    /// A roll supplied in the command
+    Dice(RollSettings),
+}
+
+#[derive(Debug, PartialEq)]
+struct Side<'a> {
+    username: &'a str,
+    roll: Contested,
+}
+
+fn parse_side<'a>(tokens: &[&'a str]) -> Option<Side<'a>> {
+    let (who, rest) = tokens.split_first()?;
+    let username = who.strip_prefix('@').filter(|name| !name.is_empty())?;
+    let (first, flags) = rest.split_first()?;
+    let roll = match Check::parse(first) {
+        Some(check) => {
+            let roll_type = match flags {
+                [] => RollType::Straight,
+                ["adv" | "advantage"] => RollType::Advantage,
+                ["dis" | "disadvantage"] => RollType::Disadvantage,
+                _ => return None,
+            };
+            Contested::Check(check, roll_type)
+        }
+        None => Contested::Dice(rest.join(" ").parse().ok()?),
+    };
+    Some(Side { username, roll })
+}
+
+/// The two sides of the contest, each starting with an @mention
+fn parse_request(input: &str) -> Option<(Side<'_>, Side<'_>)> {
+    let tokens: Vec<_> = input.split_whitespace().collect();
+    let second = tokens
+        .iter()
+        .skip(1)
+        .position(|token| token.starts_with('@'))?
+        + 1;
+    let (first, second) = tokens.split_at(second);
+    Some((parse_side(first)?, parse_side(second)?))
+}
+
+#[derive(Debug)]
+struct Rolled {
+    name: String,
+    total: i64,
+    modifier: i64,
+    line: String,
+}
+
+fn roll_side(storage: &Storage, chat_id: i64, side: &Side) -> Result<Rolled, String> {
+    let members = storage.chat(chat_id).map(|chat| &chat.members);
+    let character = storage
+        .users()
+        .filter(|user| members.is_some_and(|members| members.contains(&user.id)))
+        .find(|user| {
+            user.username
+                .as_deref()
+                .is_some_and(|name| name.eq_ignore_ascii_case(side.username))
+        })
+        .and_then(|user| user.character(chat_id, None));
+    let name = match character {
+        Some(character) => html::escape(&character.name),
+        None => format!("@{}", html::escape(side.username)),
+    };
+
+    let (settings, roll_type, check) = match side.roll {
+        Contested::Check(check, ref roll_type) => {
+            let Some(character) = character else {
+                return Err(format!(
+                    "@{} has no character in this chat. Give them a roll instead, such as <code>@{} 1d20+3</code>.",
+                    html::escape(side.username),
+                    html::escape(side.username)
+                ));
+            };
+            let settings = RollSettings {
+                number: 1,
+                sides: 20,
+                modifier: Some(check.modifier(character) as i32),
+                label: None,
+                target: None,
+                keep: None,
+                explode: None,
+                success: None,
+                composite: false,
+                multiplier: None,
+                flags: Flags::NONE,
+            };
+            (settings, roll_type.clone(), check.name().to_string())
+        }
+        Contested::Dice(ref settings) => {
+            (settings.clone(), RollType::Straight, settings.to_string())
+        }
+    };
+    let results = RollResults::new(&settings, &roll_type);
+    let roll = &results.result;
+    let mut line = format!("{} — {}", name, html::escape(&check));
+    if roll_type != RollType::Straight {
+        line.push_str(&format!(" with <i>{}</i>", roll_type));
+    }
+    line.push_str(&format!(
+        ": {} = <b>{}</b>",
+        roll.format_roll(Some(100)),
+        roll.total
+    ));
+    Ok(Rolled {
+        name,
+        total: roll.total,
+        modifier: settings.modifier.unwrap_or(0) as i64,
+        line,
+    })
+}
+
+/// The higher total wins. A tie goes to the higher modifier, and otherwise nothing changes.
+fn winner(a: &Rolled, b: &Rolled) -> String {
+    match a.total.cmp(&b.total) {
+        Ordering::Greater => format!("🏆 <b>{}</b> wins", a.name),
+        Ordering::Less => format!("🏆 <b>{}</b> wins", b.name),
+        Ordering::Equal => match a.modifier.cmp(&b.modifier) {
+            Ordering::Greater => format!(
+                "🏆 A tie, won by <b>{}</b> with the higher modifier",
+                a.name
+            ),
+            Ordering::Less => format!(
+                "🏆 A tie, won by <b>{}</b> with the higher modifier",
+                b.name
+            ),
+            Ordering::Equal => "🤝 A tie. The situation stays as it was.".to_string(),
+        },
+    }
+}
+
+fn contest(storage: &Storage, chat_id: i64, sides: &(Side, Side)) -> String {
+    let rolled = roll_side(storage, chat_id, &sides.0)
+        .and_then(|a| Ok((a, roll_side(storage, chat_id, &sides.1)?)));
+    match rolled {
+        Ok((a, b)) => format!(
+            "🤼 <b>Contest</b>\n{}\n{}\n\n{}",
+            a.line,
+            b.line,
+            winner(&a, &b)
+        ),
+        Err(e) => e,
+    }
+}
+
+const USAGE: &str = "<code>/contest @alice athletics @bob acrobatics</code> rolls a contest between the characters of two users in this chat
+<code>/contest @alice athletics adv @bob 1d20+4</code> gives a roll to anyone without a character
+The higher total wins. A tie goes to the higher modifier, and otherwise the situation stays as it was.";
+
+pub(crate) async fn handle_contest(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let text = match parse_request(input) {
+        None => USAGE.to_string(),
+        Some(sides) => {
+            store
+                .read(|storage| contest(storage, chat_id, &sides))
+                .await
+        }
+    };
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dnd::Skill;
+
+    #[test]
+    fn parses_both_sides() {
+        let (alice, bob) = parse_request("@alice athletics adv @bob 1d20 + 4").unwrap();
+        assert_eq!(
+            alice,
+            Side {
+                username: "alice",
+                roll: Contested::Check(Check::Skill(Skill::Athletics), RollType::Advantage),
+            }
+        );
+        assert_eq!(bob.username, "bob");
+        assert_eq!(bob.roll, Contested::Dice("1d20 + 4".parse().unwrap()));
+        assert!(parse_request("@alice athletics").is_none());
+        assert!(parse_request("alice athletics @bob acrobatics").is_none());
+        assert!(parse_request("@alice juggling @bob acrobatics").is_none());
+    }
+
+    #[test]
+    fn breaks_ties_by_modifier() {
+        let rolled = |name: &str, total, modifier| Rolled {
+            name: name.to_string(),
+            total,
+            modifier,
+            line: String::new(),
+        };
+        let (aria, brom) = (rolled("Aria", 15, 5), rolled("Brom", 15, 2));
+        assert_eq!(
+            winner(&aria, &brom),
+            "🏆 A tie, won by <b>Aria</b> with the higher modifier"
+        );
+        assert_eq!(winner(&rolled("Aria", 9, 5), &brom), "🏆 <b>Brom</b> wins");
+        assert!(winner(&brom, &brom).starts_with("🤝 A tie"));
+    }
+}
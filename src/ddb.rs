@@ -0,0 +1,281 @@
+//! Conversion of D&D Beyond character exports into [`Character`].
+//! Only the parts of the export that we need are described here.
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::dnd::{
+    modifier_for_score, proficiency_bonus_for_level, Ability, AttributeModifiers, Character, Skill,
+    SkillProficiencies, Skills,
+};
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Export {
+    /// Response of the character service, with the character wrapped in `data`
+    Wrapped {
+        data: DdbCharacter,
+    },
+    Bare(DdbCharacter),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct DdbCharacter {
+    name: String,
+    stats: Vec<Stat>,
+    #[serde(default)]
+    bonus_stats: Vec<Stat>,
+    #[serde(default)]
+    override_stats: Vec<Stat>,
+    #[serde(default)]
+    classes: Vec<Class>,
+    #[serde(default)]
+    modifiers: HashMap<String, Vec<Modifier>>,
+    #[serde(default)]
+    base_hit_points: Option<i32>,
+    #[serde(default)]
+    bonus_hit_points: Option<i32>,
+    #[serde(default)]
+    override_hit_points: Option<i32>,
+    #[serde(default)]
+    race: Option<Race>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Stat {
+    id: u8,
+    value: Option<i32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Class {
+    level: u8,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Modifier {
+    #[serde(rename = "type")]
+    kind: String,
+    sub_type: String,
+    #[serde(default)]
+    value: Option<i32>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Race {
+    weight_speeds: Option<Speeds>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Speeds {
+    normal: Option<NormalSpeeds>,
+}
+
+#[derive(Deserialize, Debug)]
+struct NormalSpeeds {
+    walk: Option<u16>,
+}
+
+/// D&D Beyond identifies abilities by number
+fn ability_id(ability: Ability) -> u8 {
+    match ability {
+        Ability::Strength => 1,
+        Ability::Dexterity => 2,
+        Ability::Constitution => 3,
+        Ability::Intelligence => 4,
+        Ability::Wisdom => 5,
+        Ability::Charisma => 6,
+    }
+}
+
+/// `Sleight of Hand` becomes `sleight-of-hand`, as used in modifier sub types
+fn kebab_case(name: &str) -> String {
+    name.to_lowercase().replace(' ', "-")
+}
+
+impl DdbCharacter {
+    fn modifiers(&self) -> impl Iterator<Item = &Modifier> {
+        self.modifiers.values().flatten()
+    }
+
+    fn has_modifier(&self, kind: &str, sub_type: &str) -> bool {
+        self.modifiers()
+            .any(|modifier| modifier.kind == kind && modifier.sub_type == sub_type)
+    }
+
+    fn bonus(&self, sub_type: &str) -> i32 {
+        self.modifiers()
+            .filter(|modifier| modifier.kind == "bonus" && modifier.sub_type == sub_type)
+            .filter_map(|modifier| modifier.value)
+            .sum()
+    }
+
+    fn stat(stats: &[Stat], ability: Ability) -> Option<i32> {
+        stats
+            .iter()
+            .find(|stat| stat.id == ability_id(ability))
+            .and_then(|stat| stat.value)
+    }
+
+    fn score(&self, ability: Ability) -> anyhow::Result<i32> {
+        if let Some(score) = Self::stat(&self.override_stats, ability) {
+            return Ok(score);
+        }
+        let base = Self::stat(&self.stats, ability)
+            .with_context(|| format!("missing {} score", ability))?;
+        let bonus = Self::stat(&self.bonus_stats, ability).unwrap_or_default();
+        let sub_type = format!("{}-score", kebab_case(ability.name()));
+        Ok(base + bonus + self.bonus(&sub_type))
+    }
+
+    fn level(&self) -> u8 {
+        self.classes
+            .iter()
+            .fold(0u8, |level, class| level.saturating_add(class.level))
+            .clamp(1, 20)
+    }
+}
+
+/// Whether the JSON looks like a D&D Beyond export rather than our own format
+pub fn is_ddb_export(value: &serde_json::Value) -> bool {
+    let character = value.get("data").unwrap_or(value);
+    character.get("stats").is_some() && character.get("modifiers").is_some()
+}
+
+pub fn convert(json: &[u8]) -> anyhow::Result<Character> {
+    let export: Export =
+        serde_json::from_slice(json).context("not a D&D Beyond character export")?;
+    let ddb = match export {
+        Export::Wrapped { data } => data,
+        Export::Bare(character) => character,
+    };
+
+    let level = ddb.level();
+    let proficiency_bonus = proficiency_bonus_for_level(level);
+    let attributes = AttributeModifiers::try_from_fn(|ability| {
+        anyhow::Ok(modifier_for_score(ddb.score(ability)?))
+    })?;
+    let saves = AttributeModifiers::try_from_fn(|ability| {
+        let sub_type = format!("{}-saving-throws", kebab_case(ability.name()));
+        let proficient = ddb.has_modifier("proficiency", &sub_type);
+        anyhow::Ok(attributes.get(ability).saturating_add(if proficient {
+            proficiency_bonus
+        } else {
+            0
+        }))
+    })?;
+
+    let mut proficiencies = SkillProficiencies::default();
+    for skill in Skill::ALL {
+        let sub_type = kebab_case(skill.name());
+        if ddb.has_modifier("expertise", &sub_type) {
+            proficiencies.expertise.push(skill);
+        } else if ddb.has_modifier("proficiency", &sub_type) {
+            proficiencies.proficient.push(skill);
+        }
+    }
+
+    let max_hit_points = match ddb.override_hit_points {
+        Some(hit_points) => Some(hit_points),
+        None => ddb.base_hit_points.map(|base| {
+            base + ddb.bonus_hit_points.unwrap_or_default()
+                + *attributes.get(Ability::Constitution) as i32 * level as i32
+        }),
+    };
+    let speed = ddb
+        .race
+        .as_ref()
+        .and_then(|race| race.weight_speeds.as_ref())
+        .and_then(|speeds| speeds.normal.as_ref())
+        .and_then(|normal| normal.walk);
+
+    let mut character = Character {
+        name: ddb.name.clone(),
+        initiative_modifier: attributes
+            .get(Ability::Dexterity)
+            .saturating_add(ddb.bonus("initiative") as i8),
+        attribute_modifiers: attributes,
+        saving_throw_modifiers: saves,
+        skills: Skills::Proficiencies(proficiencies),
+        // Armor class depends on equipped items, which the export does not resolve
+        armor_class: None,
+        max_hit_points: max_hit_points.and_then(|hp| u16::try_from(hp).ok()),
+        speed,
+        proficiency_bonus: Some(proficiency_bonus),
+        passive_perception: None,
+        spell_save_dc: None,
+        attacks: vec![],
+        hit_dice: None,
+        spell_slots: vec![],
+        resources: vec![],
+        player: None,
+    };
+    let perception = character.skill_modifier(Skill::Perception) as i32 + 10;
+    character.passive_perception = u8::try_from(perception).ok();
+    Ok(character)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORT: &str = r#"{
+        "id": 1, "success": true,
+        "data": {
+            "name": "Elara",
+            "stats": [
+                {"id": 1, "value": 8}, {"id": 2, "value": 15}, {"id": 3, "value": 14},
+                {"id": 4, "value": 12}, {"id": 5, "value": 13}, {"id": 6, "value": 10}
+            ],
+            "bonusStats": [{"id": 1, "value": null}, {"id": 4, "value": 1}],
+            "overrideStats": [{"id": 6, "value": 18}],
+            "classes": [{"level": 4, "definition": {"name": "Rogue"}}, {"level": 1}],
+            "modifiers": {
+                "race": [
+                    {"type": "bonus", "subType": "dexterity-score", "value": 2},
+                    {"type": "proficiency", "subType": "perception", "value": null}
+                ],
+                "class": [
+                    {"type": "proficiency", "subType": "dexterity-saving-throws"},
+                    {"type": "proficiency", "subType": "stealth"},
+                    {"type": "expertise", "subType": "stealth"},
+                    {"type": "expertise", "subType": "sleight-of-hand"}
+                ],
+                "feat": [{"type": "bonus", "subType": "initiative", "value": 5}]
+            },
+            "baseHitPoints": 30,
+            "bonusHitPoints": null,
+            "race": {"weightSpeeds": {"normal": {"walk": 35, "fly": 0}}}
+        }
+    }"#;
+
+    #[test]
+    fn converts_ddb_export() {
+        let value: serde_json::Value = serde_json::from_str(EXPORT).unwrap();
+        assert!(is_ddb_export(&value));
+
+        let character = convert(EXPORT.as_bytes()).unwrap();
+        character.validate().unwrap();
+        assert_eq!(character.name, "Elara");
+        assert_eq!(character.attribute_modifiers.strength, -1);
+        assert_eq!(character.attribute_modifiers.dexterity, 3);
+        assert_eq!(character.attribute_modifiers.intelligence, 1);
+        assert_eq!(character.attribute_modifiers.charisma, 4);
+        assert_eq!(character.proficiency_bonus, Some(3));
+        assert_eq!(character.saving_throw_modifiers.dexterity, 6);
+        assert_eq!(character.saving_throw_modifiers.wisdom, 1);
+        assert_eq!(character.skill_modifier(Skill::Stealth), 9);
+        assert_eq!(character.skill_modifier(Skill::SleightOfHand), 9);
+        assert_eq!(character.skill_modifier(Skill::Perception), 4);
+        assert_eq!(character.skill_modifier(Skill::Athletics), -1);
+        assert_eq!(character.initiative_modifier, 8);
+        assert_eq!(character.max_hit_points, Some(40));
+        assert_eq!(character.speed, Some(35));
+        assert_eq!(character.passive_perception, Some(14));
+    }
+}
@@ -0,0 +1,134 @@
+//! Rolls of a campaign shown as 3D dice in a [dddice](https://dddice.com) room, which Foundry VTT
+//! and other virtual tabletops can show through their dddice modules. The dice land on the same
+//! numbers as they did in the chat.
+
+use serde::{Deserialize, Serialize};
+
+use crate::output::RollOutput;
+
+const API_URL: &str = "https://dddice.com/api/1.0/roll";
+
+const DEFAULT_THEME: &str = "dddice-standard";
+
+/// Dice that dddice has models for
+const SIDES: [u32; 6] = [4, 6, 8, 10, 12, 20];
+
+/// Rolls of more dice are not shown
+const MAX_DICE: usize = 50;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct Dddice {
+    /// Slug of the room, from its URL
+    pub room: String,
+    /// Key of the GM, from the developer settings of dddice, sealed with the secret key
+    pub api_key: String,
+    #[serde(default)]
+    pub theme: Option<String>,
+}
+
+/// Without the API key, so that it stays out of logs
+impl std::fmt::Debug for Dddice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Dddice")
+            .field("room", &self.room)
+            .field("api_key", &"[redacted]")
+            .field("theme", &self.theme)
+            .finish()
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+struct Die<'a> {
+    #[serde(rename = "type")]
+    kind: String,
+    theme: &'a str,
+    value: u32,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+struct Roll<'a> {
+    dice: Vec<Die<'a>>,
+    room: &'a str,
+}
+
+/// Every die rolled, or nothing if dddice cannot show them all
+fn roll<'a>(dddice: &'a Dddice, output: &RollOutput) -> Option<Roll<'a>> {
+    let theme = dddice.theme.as_deref().unwrap_or(DEFAULT_THEME);
+    let mut dice = vec![];
+    for group in &output.groups {
+        if !SIDES.contains(&group.sides) || group.rolls.is_empty() {
+            return None;
+        }
+        dice.extend(group.rolls.iter().map(|die| Die {
+            kind: format!("d{}", group.sides),
+            theme,
+            value: die.value,
+        }));
+    }
+    match dice.len() {
+        1..=MAX_DICE => Some(Roll {
+            dice,
+            room: &dddice.room,
+        }),
+        _ => None,
+    }
+}
+
+/// Show the roll in the room. Failing to do so is only logged.
+pub(crate) async fn show(
+    client: reqwest::Client,
+    dddice: Dddice,
+    api_key: String,
+    output: RollOutput,
+) {
+    let Some(roll) = roll(&dddice, &output) else {
+        return;
+    };
+    let sent = client
+        .post(API_URL)
+        .bearer_auth(&api_key)
+        .header(reqwest::header::ACCEPT, "application/json")
+        .json(&roll)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    if let Err(e) = sent {
+        log::warn!(
+            "Could not show a roll in dddice room {}: {}",
+            dddice.room,
+            e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dice::{RollResults, RollSettings, RollType};
+
+    #[test]
+    fn sends_the_dice_that_were_rolled() {
+        let dddice = Dddice {
+            room: "abc123".to_string(),
+            api_key: "secret".to_string(),
+            theme: None,
+        };
+        assert!(!format!("{:?}", dddice).contains("secret"));
+
+        let settings: RollSettings = "2d6 + 3".parse().unwrap();
+        let results = RollResults::new(&settings, &RollType::Advantage);
+        let output = RollOutput::new("2d6+3", &results);
+        let sent = roll(&dddice, &output).unwrap();
+        assert_eq!(sent.dice.len(), 4);
+        assert_eq!(sent.dice[0].value, output.groups[0].rolls[0].value);
+        let json = serde_json::to_value(&sent).unwrap();
+        assert_eq!(json["dice"][0]["type"], "d6");
+        assert_eq!(json["dice"][0]["theme"], DEFAULT_THEME);
+        assert_eq!(json["room"], "abc123");
+
+        let settings: RollSettings = "1d7".parse().unwrap();
+        let results = RollResults::new(&settings, &RollType::Straight);
+        assert_eq!(roll(&dddice, &RollOutput::new("1d7", &results)), None);
+    }
+}
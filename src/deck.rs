@@ -0,0 +1,510 @@
+//! Decks of cards that last across a session. Built in decks, or custom ones uploaded as JSON.
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use teloxide::utils::html;
+
+use crate::storage::{Chat, Store};
+use crate::transport::{ChatTransport, Incoming};
+
+/// Upper bound on the cards of a custom deck
+const MAX_CARDS: usize = 500;
+
+/// Upper bound on the cards laid out in one reading
+const MAX_READING: usize = 12;
+
+/// Deck used when no name is given and nothing was shuffled yet
+const DEFAULT_DECK: &str = "cards";
+
+const DECK_OF_MANY_THINGS: &[&str] = &[
+    "Balance", "Comet", "Donjon", "Euryale", "Fates", "Flames", "Fool", "Gem", "Idiot", "Jester",
+    "Key", "Knight", "Moon", "Rogue", "Ruin", "Skull", "Star", "Sun", "Talons", "Throne", "Vizier",
+    "The Void",
+];
+
+const MAJOR_ARCANA: &[&str] = &[
+    "The Fool",
+    "The Magician",
+    "The High Priestess",
+    "The Empress",
+    "The Emperor",
+    "The Hierophant",
+    "The Lovers",
+    "The Chariot",
+    "Strength",
+    "The Hermit",
+    "Wheel of Fortune",
+    "Justice",
+    "The Hanged Man",
+    "Death",
+    "Temperance",
+    "The Devil",
+    "The Tower",
+    "The Star",
+    "The Moon",
+    "The Sun",
+    "Judgement",
+    "The World",
+];
+
+/// A deck and the cards that are left in it, the top card last
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct Deck {
+    pub name: String,
+    pub cards: Vec<String>,
+    #[serde(default)]
+    pub remaining: Vec<String>,
+    /// What cards mean when they come up in a reading
+    #[serde(default)]
+    pub meanings: BTreeMap<String, String>,
+    /// Names of the places of a reading, such as `Past`, `Present` and `Future`
+    #[serde(default)]
+    pub positions: Vec<String>,
+}
+
+impl Deck {
+    fn new(name: &str, cards: Vec<String>) -> Self {
+        Deck {
+            name: name.to_string(),
+            cards,
+            remaining: vec![],
+            meanings: BTreeMap::new(),
+            positions: vec![],
+        }
+    }
+
+    /// Decks that every chat has without uploading anything
+    fn built_in(name: &str) -> Option<Self> {
+        let cards = match name {
+            "cards" | "jokers" => {
+                let mut cards: Vec<String> = ["♠", "♥", "♦", "♣"]
+                    .iter()
+                    .flat_map(|suit| {
+                        [
+                            "A", "2", "3", "4", "5", "6", "7", "8", "9", "10", "J", "Q", "K",
+                        ]
+                        .iter()
+                        .map(move |rank| format!("{}{}", rank, suit))
+                    })
+                    .collect();
+                if name == "jokers" {
+                    cards.extend(["🃏 Red Joker".to_string(), "🃏 Black Joker".to_string()]);
+                }
+                cards
+            }
+            "tarot" => {
+                let mut cards: Vec<String> = MAJOR_ARCANA.iter().map(ToString::to_string).collect();
+                for suit in ["Wands", "Cups", "Swords", "Pentacles"] {
+                    for rank in [
+                        "Ace", "Two", "Three", "Four", "Five", "Six", "Seven", "Eight", "Nine",
+                        "Ten", "Page", "Knight", "Queen", "King",
+                    ] {
+                        cards.push(format!("{} of {}", rank, suit));
+                    }
+                }
+                cards
+            }
+            "many" => DECK_OF_MANY_THINGS
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            _ => return None,
+        };
+        Some(Deck::new(name, cards))
+    }
+
+    fn shuffle(&mut self) {
+        self.remaining = self.cards.clone();
+        self.remaining.shuffle(&mut rand::thread_rng());
+    }
+
+    /// Take up to `number` cards off the top
+    fn draw(&mut self, number: usize) -> Vec<String> {
+        let split = self.remaining.len().saturating_sub(number);
+        let mut drawn = self.remaining.split_off(split);
+        drawn.reverse();
+        drawn
+    }
+}
+
+fn parse_deck(contents: &[u8]) -> anyhow::Result<Deck> {
+    let deck: Deck = serde_json::from_slice(contents)
+        .map_err(|e| anyhow!("not a valid deck, such as {{\"name\": \"runes\", \"cards\": [\"Fehu\", \"Uruz\"]}}: {}", e))?;
+    if deck.name.contains(char::is_whitespace) || deck.name.is_empty() {
+        bail!("the name of a deck is a single word");
+    }
+    if deck.cards.is_empty() || deck.cards.len() > MAX_CARDS {
+        bail!("a deck has between 1 and {} cards", MAX_CARDS);
+    }
+    if let Some(card) = deck.meanings.keys().find(|card| !deck.cards.contains(card)) {
+        bail!("{} has a meaning but is not one of the cards", card);
+    }
+    if deck.positions.len() > MAX_READING {
+        bail!("a reading has at most {} positions", MAX_READING);
+    }
+    Ok(deck)
+}
+
+/// The named deck of the chat, or the one shuffled last. Built in decks are added on first use.
+fn deck_mut<'a>(chat: &'a mut Chat, name: Option<&str>) -> Option<&'a mut Deck> {
+    let name = name
+        .or_else(|| chat.decks.first().map(|deck| deck.name.as_str()))
+        .unwrap_or(DEFAULT_DECK)
+        .to_lowercase();
+    let index = match chat.decks.iter().position(|deck| deck.name == name) {
+        Some(index) => index,
+        None => {
+            let mut deck = Deck::built_in(&name)?;
+            deck.shuffle();
+            chat.decks.push(deck);
+            chat.decks.len() - 1
+        }
+    };
+    // The front is the deck that /draw uses without a name
+    let deck = chat.decks.remove(index);
+    chat.decks.insert(0, deck);
+    chat.decks.first_mut()
+}
+
+fn unknown_deck(name: Option<&str>) -> String {
+    format!(
+        "There is no deck named {}. The built in decks are cards, jokers, tarot and many.",
+        html::escape(name.unwrap_or(DEFAULT_DECK))
+    )
+}
+
+fn shuffle(chat: &mut Chat, name: Option<&str>) -> String {
+    match deck_mut(chat, name) {
+        None => unknown_deck(name),
+        Some(deck) => {
+            deck.shuffle();
+            format!(
+                "🔀 Shuffled {} ({} cards)",
+                html::escape(&deck.name),
+                deck.remaining.len()
+            )
+        }
+    }
+}
+
+/// `/draw [n] [deck]`, in any order
+fn draw(chat: &mut Chat, input: &str) -> String {
+    let mut number = 1;
+    let mut name = None;
+    for arg in input.split_whitespace() {
+        match arg.parse::<usize>() {
+            Ok(n) => number = n.clamp(1, MAX_CARDS),
+            Err(_) => name = Some(arg),
+        }
+    }
+    let Some(deck) = deck_mut(chat, name) else {
+        return unknown_deck(name);
+    };
+    let drawn = deck.draw(number);
+    if drawn.is_empty() {
+        return format!(
+            "The {} deck is empty. Use <code>/shuffle {}</code> to start over.",
+            html::escape(&deck.name),
+            html::escape(&deck.name)
+        );
+    }
+    let mut text = format!(
+        "🃏 {}\n{} cards left in {}",
+        drawn
+            .iter()
+            .map(|card| format!("<b>{}</b>", html::escape(card)))
+            .collect::<Vec<_>>()
+            .join(", "),
+        deck.remaining.len(),
+        html::escape(&deck.name)
+    );
+    if drawn.len() < number {
+        text.push_str(&format!("\nOnly {} cards were left to draw.", drawn.len()));
+    }
+    text
+}
+
+/// `/reading [n] [deck]`: shuffle the deck and lay out cards with their meanings
+fn reading(chat: &mut Chat, input: &str) -> String {
+    let mut number = None;
+    let mut name = None;
+    for arg in input.split_whitespace() {
+        match arg.parse::<usize>() {
+            Ok(n) => number = Some(n.clamp(1, MAX_READING)),
+            Err(_) => name = Some(arg),
+        }
+    }
+    let Some(deck) = deck_mut(chat, name) else {
+        return unknown_deck(name);
+    };
+    let number = number.unwrap_or(match deck.positions.len() {
+        0 => 3,
+        positions => positions,
+    });
+    deck.shuffle();
+    let mut text = format!("🔮 A reading of {}", html::escape(&deck.name));
+    for (index, card) in deck.draw(number).iter().enumerate() {
+        let position = match deck.positions.get(index) {
+            Some(position) => html::escape(position),
+            None => (index + 1).to_string(),
+        };
+        text.push_str(&format!(
+            "
+
+{}. <b>{}</b>",
+            position,
+            html::escape(card)
+        ));
+        if let Some(meaning) = deck.meanings.get(card) {
+            text.push_str(&format!(
+                "
+<i>{}</i>",
+                html::escape(meaning)
+            ));
+        }
+    }
+    if deck.cards.len() < number {
+        text.push_str(&format!(
+            "
+
+The deck only has {} cards.",
+            deck.cards.len()
+        ));
+    }
+    text
+}
+
+const USAGE: &str = "<code>/deck</code> shows how many cards are left in the decks of this chat
+<code>/deck upload</code> as a reply to a JSON file adds or replaces a custom deck
+<code>/deck remove name</code> removes a deck
+<code>/shuffle [deck]</code> puts every card back and shuffles
+<code>/draw [n] [deck]</code> draws cards
+<code>/reading [n] [deck]</code> shuffles the deck and lays out cards with their meanings
+Custom decks can give cards a meaning and name the positions of a reading, such as {\"name\": \"fate\", \"cards\": [\"Raven\", \"Wolf\", \"Owl\"], \"meanings\": {\"Raven\": \"A message arrives\"}, \"positions\": [\"Past\", \"Future\"]}
+The built in decks are cards, jokers, tarot and many (the Deck of Many Things).";
+
+pub(crate) async fn handle_shuffle(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let name = input.split_whitespace().next();
+    let text = store
+        .update(|storage| shuffle(storage.chat_mut(chat_id), name))
+        .await?;
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+pub(crate) async fn handle_draw(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let text = store
+        .update(|storage| draw(storage.chat_mut(chat_id), input))
+        .await?;
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+pub(crate) async fn handle_reading(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let text = store
+        .update(|storage| reading(storage.chat_mut(chat_id), input))
+        .await?;
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+pub(crate) async fn handle_deck(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let mut args = input.split_whitespace();
+    let text = match args.next() {
+        None => {
+            store
+                .read(|storage| {
+                    let decks = storage
+                        .chat(chat_id)
+                        .map(|chat| chat.decks.as_slice())
+                        .unwrap_or_default();
+                    if decks.is_empty() {
+                        return format!("This chat has not used any deck yet.\n\n{}", USAGE);
+                    }
+                    decks
+                        .iter()
+                        .map(|deck| {
+                            format!(
+                                "• <b>{}</b>: {} of {} cards left",
+                                html::escape(&deck.name),
+                                deck.remaining.len(),
+                                deck.cards.len()
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                })
+                .await
+        }
+        Some("upload") if crate::auth::is_chat_admin(bot, msg).await? => {
+            match crate::upload::replied_document(bot, msg).await? {
+                Err(e) => e.to_string(),
+                Ok(contents) => match parse_deck(&contents) {
+                    Err(e) => format!("<code>{}</code>", html::escape(&format!("{:#}", e))),
+                    Ok(mut deck) => {
+                        deck.name = deck.name.to_lowercase();
+                        deck.shuffle();
+                        let text = format!(
+                            "Saved and shuffled the {} deck ({} cards)",
+                            html::escape(&deck.name),
+                            deck.cards.len()
+                        );
+                        store
+                            .update(|storage| {
+                                let decks = &mut storage.chat_mut(chat_id).decks;
+                                decks.retain(|existing| existing.name != deck.name);
+                                decks.insert(0, deck);
+                            })
+                            .await?;
+                        text
+                    }
+                },
+            }
+        }
+        Some("remove") if crate::auth::is_chat_admin(bot, msg).await? => {
+            let name = args.next().unwrap_or_default().to_lowercase();
+            let removed = store
+                .update(|storage| {
+                    let decks = &mut storage.chat_mut(chat_id).decks;
+                    let before = decks.len();
+                    decks.retain(|deck| deck.name != name);
+                    decks.len() != before
+                })
+                .await?;
+            if removed {
+                format!("Removed the {} deck", html::escape(&name))
+            } else {
+                format!("There is no deck named {}", html::escape(&name))
+            }
+        }
+        Some("upload") | Some("remove") => "Only chat administrators can change decks.".to_string(),
+        _ => USAGE.to_string(),
+    };
+
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, FakeTransport};
+    use crate::transport::{Attachment, Replied};
+
+    #[tokio::test]
+    async fn administrators_upload_decks() {
+        let runes = br#"{"name": "Runes", "cards": ["Fehu", "Uruz"]}"#;
+        let bot = FakeTransport::default()
+            .with_admin(7)
+            .with_file("runes", runes);
+        let store = testing::store().await;
+        let upload = |user_id| Incoming {
+            reply_to: Some(Replied {
+                from: Some(testing::sender(user_id)),
+                attachment: Some(Attachment::Document {
+                    file_id: "runes".to_string(),
+                    size: runes.len() as u32,
+                }),
+            }),
+            ..testing::incoming(user_id)
+        };
+
+        handle_deck(&bot, &upload(8), store.clone(), "upload")
+            .await
+            .unwrap();
+        handle_deck(&bot, &upload(7), store.clone(), "upload")
+            .await
+            .unwrap();
+        handle_deck(&bot, &testing::incoming(7), store.clone(), "upload")
+            .await
+            .unwrap();
+        assert_eq!(
+            bot.replies(),
+            [
+                "Only chat administrators can change decks.",
+                "Saved and shuffled the runes deck (2 cards)",
+                "Reply to a JSON file with this command to upload it.",
+            ]
+        );
+    }
+
+    #[test]
+    fn built_in_decks_have_every_card() {
+        assert_eq!(Deck::built_in("cards").unwrap().cards.len(), 52);
+        assert_eq!(Deck::built_in("jokers").unwrap().cards.len(), 54);
+        assert_eq!(Deck::built_in("tarot").unwrap().cards.len(), 78);
+        assert_eq!(Deck::built_in("many").unwrap().cards.len(), 22);
+        assert!(Deck::built_in("runes").is_none());
+    }
+
+    #[test]
+    fn draws_until_the_deck_is_empty() {
+        let mut chat = Chat::default();
+        let text = draw(&mut chat, "50");
+        assert!(text.ends_with("2 cards left in cards"), "{}", text);
+        let text = draw(&mut chat, "5");
+        assert!(text.contains("Only 2 cards were left to draw."));
+        assert!(draw(&mut chat, "").contains("is empty"));
+
+        // Without a name, the deck shuffled last is used
+        assert!(shuffle(&mut chat, Some("many")).contains("22 cards"));
+        assert!(draw(&mut chat, "").ends_with("21 cards left in many"));
+        assert!(draw(&mut chat, "cards").contains("is empty"));
+        assert!(draw(&mut chat, "runes").starts_with("There is no deck named runes"));
+
+        let deck = parse_deck(br#"{"name": "runes", "cards": ["Fehu", "Uruz"]}"#).unwrap();
+        assert_eq!(deck.cards.len(), 2);
+        assert!(parse_deck(br#"{"name": "runes", "cards": []}"#).is_err());
+    }
+
+    #[test]
+    fn lays_out_a_reading() {
+        let mut chat = Chat::default();
+        let deck = parse_deck(
+            br#"{"name": "fate", "cards": ["Raven", "Wolf"],
+                "meanings": {"Raven": "A message arrives", "Wolf": "A <wild> ally"},
+                "positions": ["Past", "Future"]}"#,
+        )
+        .unwrap();
+        chat.decks.push(deck);
+        let text = reading(&mut chat, "fate");
+        assert!(text.contains("\n\nPast. <b>"), "{}", text);
+        assert!(text.contains("<b>Wolf</b>\n<i>A &lt;wild&gt; ally</i>"));
+        assert!(reading(&mut chat, "5").ends_with("The deck only has 2 cards."));
+        assert!(reading(&mut chat, "tarot").contains("\n\n3. <b>"));
+
+        assert!(
+            parse_deck(br#"{"name": "fate", "cards": ["Raven"], "meanings": {"Owl": "?"}}"#)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,275 @@
+//! Validation reports for character data, pointing at the offending field.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use serde_path_to_error::Segment;
+
+use crate::dnd::{Character, Skill, SkillModifiers, SkillProficiencies};
+
+/// A single problem found in character data
+#[derive(Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Where the problem is, such as `attribute_modifiers.dex` or `line 3 column 5`
+    pub location: String,
+    pub message: String,
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)?;
+        if let Some(ref suggestion) = self.suggestion {
+            write!(f, "\n    help: {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of checking one character file
+#[derive(Debug)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub result: Result<Character, Vec<Diagnostic>>,
+}
+
+impl fmt::Display for FileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.result {
+            Ok(ref character) => write!(f, "{}: ok ({})", self.path.display(), character.name),
+            Err(ref diagnostics) => {
+                write!(f, "{}: invalid", self.path.display())?;
+                for diagnostic in diagnostics {
+                    write!(f, "\n  {}", diagnostic.to_string().replace('\n', "\n  "))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Check every file matching a glob pattern
+pub fn check_pattern(pattern: &str) -> anyhow::Result<Vec<FileReport>> {
+    glob::glob(pattern)
+        .with_context(|| format!("error figuring out path {}", pattern))?
+        .map(|entry| Ok(check_file(&entry.context("error handling file")?)))
+        .collect()
+}
+
+pub fn check_file(path: &Path) -> FileReport {
+    let result = match std::fs::read(path) {
+        Ok(contents) => check_json(&contents),
+        Err(e) => Err(vec![Diagnostic {
+            location: "file".to_string(),
+            message: e.to_string(),
+            suggestion: None,
+        }]),
+    };
+    FileReport {
+        path: path.to_path_buf(),
+        result,
+    }
+}
+
+/// Parse and validate character data, describing everything that is wrong with it
+pub fn check_json(json: &[u8]) -> Result<Character, Vec<Diagnostic>> {
+    let value: Value = serde_json::from_slice(json).map_err(|e| {
+        let message = e.to_string();
+        let message = match message.rfind(" at line ") {
+            Some(index) => message[..index].to_string(),
+            None => message,
+        };
+        vec![Diagnostic {
+            location: format!("line {} column {}", e.line(), e.column()),
+            message,
+            suggestion: None,
+        }]
+    })?;
+
+    let character: Character = deserialize(&value, "").map_err(|diagnostic| {
+        // Untagged enums hide why none of their variants matched, so look again
+        let diagnostic = match value.get("skill_modifiers") {
+            Some(skills) if diagnostic.location == "skill_modifiers" => {
+                let proficiencies =
+                    skills.get("proficient").is_some() || skills.get("expertise").is_some();
+                let inner = if proficiencies {
+                    deserialize::<SkillProficiencies>(skills, "skill_modifiers").err()
+                } else {
+                    deserialize::<SkillModifiers<i8>>(skills, "skill_modifiers").err()
+                };
+                inner.unwrap_or(diagnostic)
+            }
+            _ => diagnostic,
+        };
+        vec![diagnostic]
+    })?;
+
+    character.validate().map_err(|e| {
+        vec![Diagnostic {
+            location: e.field.to_string(),
+            message: e.to_string(),
+            suggestion: Some(
+                "add it, or give skill_modifiers as a number for every skill".to_string(),
+            ),
+        }]
+    })?;
+    Ok(character)
+}
+
+fn deserialize<T: DeserializeOwned>(value: &Value, prefix: &str) -> Result<T, Diagnostic> {
+    serde_path_to_error::deserialize(value).map_err(|e| {
+        let path = e.path().to_string();
+        let location = match (prefix, path.as_str()) {
+            ("", path) => path.to_string(),
+            (prefix, ".") => prefix.to_string(),
+            (prefix, path) if path.starts_with('[') => format!("{}{}", prefix, path),
+            (prefix, path) => format!("{}.{}", prefix, path),
+        };
+        let offending = lookup(value, e.path());
+        let message = e.into_inner().to_string();
+        // Numbers that may also be strings fail with an unhelpful message from serde-aux
+        if message.contains("StringOrInt") || message.contains("NumericOrNull") {
+            return Diagnostic {
+                location,
+                message: r#"expected a whole number, or a string such as "+3""#.to_string(),
+                suggestion: offending
+                    .filter(|value| value.is_number())
+                    .map(|value| format!("{} is out of range for this field", value)),
+            };
+        }
+        Diagnostic {
+            location,
+            suggestion: suggest(&message),
+            message,
+        }
+    })
+}
+
+fn lookup<'a>(value: &'a Value, path: &serde_path_to_error::Path) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, segment| match segment {
+        Segment::Seq { index } => value.get(index),
+        Segment::Map { key } => value.get(key),
+        _ => None,
+    })
+}
+
+/// Parts of a serde message that are quoted with backticks
+fn quoted(message: &str) -> Vec<&str> {
+    message.split('`').skip(1).step_by(2).collect()
+}
+
+fn suggest(message: &str) -> Option<String> {
+    if message.starts_with("unknown field") || message.starts_with("unknown variant") {
+        let quoted = quoted(message);
+        let (unknown, expected) = quoted.split_first()?;
+        return closest(unknown, expected.iter().copied())
+            .map(|name| format!("did you mean `{}`?", name));
+    }
+    if let Some(skill) = message.strip_prefix("unknown skill ") {
+        return closest(skill, Skill::ALL.iter().map(Skill::name))
+            .map(|name| format!("did you mean `{}`?", name));
+    }
+    if message.starts_with("missing field") {
+        return Some("run the `schema` subcommand to see every field".to_string());
+    }
+    let range = match message.rsplit_once("expected ")?.1 {
+        "i8" => "-128 to 127",
+        "u8" => "0 to 255",
+        "i16" => "-32768 to 32767",
+        "u16" => "0 to 65535",
+        _ => return None,
+    };
+    Some(format!("expected a whole number from {}", range))
+}
+
+/// The candidate closest to `input`, if it is close enough to be a typo
+fn closest<'a>(input: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let input = input.to_lowercase();
+    candidates
+        .map(|candidate| (edit_distance(&input, &candidate.to_lowercase()), candidate))
+        .filter(|(distance, candidate)| *distance <= (candidate.len() / 3).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(json: &str) -> Vec<Diagnostic> {
+        check_json(json.as_bytes()).unwrap_err()
+    }
+
+    const MODIFIERS: &str = r#"{"str": 3, "dex": 1, "con": 2, "int": -1, "wis": 0, "cha": -1}"#;
+
+    #[test]
+    fn suggests_field_names() {
+        let json = format!(
+            r#"{{"name": "A", "attribute_modifiers": {{"str": 3, "dexx": 1}},
+                "saving_throw_modifiers": {0}, "skill_modifiers": {{}}, "initiative_modifier": 1}}"#,
+            MODIFIERS
+        );
+        let diagnostics = check(&json);
+        assert_eq!(diagnostics[0].location, "attribute_modifiers.dexx");
+        assert_eq!(
+            diagnostics[0].suggestion.as_deref(),
+            Some("did you mean `dex`?")
+        );
+    }
+
+    #[test]
+    fn looks_inside_skill_proficiencies() {
+        let json = format!(
+            r#"{{"name": "A", "attribute_modifiers": {0}, "saving_throw_modifiers": {0},
+                "skill_modifiers": {{"proficient": ["Athletics", "Stelth"]}},
+                "initiative_modifier": 1}}"#,
+            MODIFIERS
+        );
+        let diagnostics = check(&json);
+        assert_eq!(diagnostics[0].location, "skill_modifiers.proficient[1]");
+        assert_eq!(
+            diagnostics[0].suggestion.as_deref(),
+            Some("did you mean `Stealth`?")
+        );
+    }
+
+    #[test]
+    fn reports_ranges_and_validation() {
+        let json = format!(
+            r#"{{"name": "A", "attribute_modifiers": {0}, "saving_throw_modifiers": {0},
+                "skill_modifiers": {{"proficient": []}}, "initiative_modifier": 1,
+                "armor_class": 300}}"#,
+            MODIFIERS
+        );
+        let diagnostics = check(&json);
+        assert_eq!(diagnostics[0].location, "armor_class");
+        assert_eq!(
+            diagnostics[0].suggestion.as_deref(),
+            Some("300 is out of range for this field")
+        );
+
+        let diagnostics = check(&json.replace(r#""armor_class": 300"#, r#""speed": 30"#));
+        assert_eq!(diagnostics[0].location, "proficiency_bonus");
+
+        let diagnostics = check("{\n  \"name\": }");
+        assert_eq!(diagnostics[0].location, "line 2 column 11");
+    }
+}
@@ -1,8 +1,13 @@
-use std::cmp::{max, min, Ordering};
+use std::borrow::Cow;
+use std::cmp::{Ordering, Reverse};
 use std::str::FromStr;
 
 use rand::distributions::{Distribution, Uniform};
-use serde::Serialize;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::parser::ParseRollError;
 
 #[derive(Serialize, Clone, Debug, PartialEq, Eq)]
 pub(crate) struct RollSettings {
@@ -10,9 +15,120 @@ pub(crate) struct RollSettings {
     pub sides: u32,
     pub modifier: Option<i32>,
     pub label: Option<String>,
+    /// The DC to meet or beat, from a trailing `vs 15`
+    pub target: Option<i64>,
+    /// From `4d6kh3` or `4d6dl1`: only some of the dice count
+    pub keep: Option<Keep>,
+    /// From `1d10!` or `1d10!>=9`: dice that come up this high or higher are rolled again, and the
+    /// new dice added
+    pub explode: Option<u32>,
+    /// From `5d10>=8`: the total is how many dice came up this high or higher, as for the dice pools
+    /// of World of Darkness
+    pub success: Option<u32>,
+    /// From `d66`: each die is read digit by digit, as a d6 for the tens and a d6 for the units
+    pub composite: bool,
+    /// From `2x(1d6 + 2)`: the total is multiplied, rather than rolled that many times
+    pub multiplier: Option<u32>,
+    pub flags: Flags,
+}
+
+/// Flags written among the label, such as `adv` or `gwf`
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Flags {
+    /// From `adv` or `dis`, on top of the command used to roll
+    pub roll_type: Option<RollType>,
+    /// From `crit`: the dice were doubled for a critical hit
+    pub critical: bool,
+    /// From `gwf`: ones and twos are rolled again once, for Great Weapon Fighting
+    pub great_weapon_fighting: bool,
+    /// Set by the house rules rather than written: each 1 among the kept dice of a dice pool takes
+    /// away a success
+    pub ones_subtract: bool,
+}
+
+/// The dice of a roll that count, such as the highest three of `4d6kh3`. Dropping dice, as in
+/// `4d6dl1`, keeps the others.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Keep {
+    Highest(u32),
+    Lowest(u32),
+}
+
+impl Keep {
+    /// How many of `number` dice are kept
+    pub fn of(&self, number: u32) -> u32 {
+        match self {
+            Keep::Highest(count) | Keep::Lowest(count) => (*count).min(number),
+        }
+    }
+}
+
+impl std::fmt::Display for Keep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Keep::Highest(count) => write!(f, "kh{}", count),
+            Keep::Lowest(count) => write!(f, "kl{}", count),
+        }
+    }
+}
+
+impl Flags {
+    pub const NONE: Flags = Flags {
+        roll_type: None,
+        critical: false,
+        great_weapon_fighting: false,
+        ones_subtract: false,
+    };
+}
+
+/// The die of each digit of a composite die, such as the 6 of `d66` or `d666`. Dice like `d100`,
+/// and the largest die `d9999`, are read as a single die.
+pub(crate) fn composite_digit(sides: u32) -> Option<u32> {
+    let digits = sides.to_string();
+    let first = digits.chars().next()?;
+    ((2..=3).contains(&digits.len())
+        && ('2'..='8').contains(&first)
+        && digits.chars().all(|c| c == first))
+    .then(|| first.to_digit(10))
+    .flatten()
 }
 
 impl RollSettings {
+    /// Whether the total can come up at all, as 17 cannot on `d66`
+    pub fn can_roll(&self, total: i64) -> bool {
+        if !self.composite || self.number != 1 || self.modifier.is_some() {
+            return true;
+        }
+        let digit = composite_digit(self.sides).unwrap_or(9);
+        let digits = total.to_string();
+        digits.len() == self.sides.to_string().len()
+            && digits
+                .chars()
+                .all(|c| c.to_digit(10).is_some_and(|c| (1..=digit).contains(&c)))
+    }
+
+    /// How many dice count towards the total
+    pub fn kept(&self) -> u32 {
+        self.keep.map_or(self.number, |keep| keep.of(self.number))
+    }
+
+    /// The dice to roll for the roll type. Advantage rolls as many more dice as count and keeps the
+    /// highest of them, so `1d20` is rolled as `2d20kh1` and `3d20kh1` as `4d20kh1`. Disadvantage
+    /// keeps the lowest.
+    pub fn with_roll_type(&self, roll_type: &RollType) -> Cow<'_, RollSettings> {
+        let keep = match roll_type {
+            RollType::Straight => return Cow::Borrowed(self),
+            RollType::Advantage => Keep::Highest(self.kept()),
+            RollType::Disadvantage => Keep::Lowest(self.kept()),
+        };
+        Cow::Owned(RollSettings {
+            number: self.number.saturating_add(self.kept()),
+            keep: Some(keep),
+            ..self.clone()
+        })
+    }
+
     pub fn format_modifier(&self) -> String {
         match self.modifier {
             None => "".to_string(),
@@ -27,7 +143,54 @@ impl RollSettings {
     }
 
     pub fn format_parameters(&self) -> String {
-        format!("{}d{}{}", self.number, self.sides, self.format_modifier())
+        crate::expr::DiceExpr::from(self).to_string()
+    }
+
+    /// The canonical form of the whole roll, such as `1d20 + 5, labelled stealth, vs DC 15`
+    pub fn interpretation(&self) -> String {
+        let mut text = self.format_parameters();
+        if let Some(ref label) = self.label {
+            text.push_str(&format!(", labelled {}", label));
+        }
+        if let Some(target) = self.target {
+            text.push_str(&format!(", vs DC {}", target));
+        }
+        match self.flags.roll_type {
+            Some(RollType::Advantage) => text.push_str(", with advantage"),
+            Some(RollType::Disadvantage) => text.push_str(", with disadvantage"),
+            _ => {}
+        }
+        if self.flags.critical {
+            text.push_str(", doubled for a critical hit");
+        }
+        if self.flags.great_weapon_fighting {
+            text.push_str(", rolling ones and twos again");
+        }
+        text
+    }
+
+    /// How the roll was understood, when the input was written some other way
+    pub fn echo(&self, input: &str) -> Option<String> {
+        let squash = |text: &str| -> String {
+            text.chars()
+                .filter(|c| !c.is_whitespace())
+                .flat_map(char::to_lowercase)
+                .collect()
+        };
+        let written = squash(&crate::parser::normalize(input));
+        let expression =
+            squash(&self.format_parameters()) + &squash(self.label.as_deref().unwrap_or_default());
+        let canonical = match self.target {
+            None => vec![expression],
+            Some(target) => vec![
+                format!("{}vs{}", expression, target),
+                format!("{}vsdc{}", expression, target),
+            ],
+        };
+        match canonical.contains(&written) {
+            true => None,
+            false => Some(format!("Interpreted as: {}", self.interpretation())),
+        }
     }
 }
 
@@ -37,78 +200,330 @@ impl std::fmt::Display for RollSettings {
     }
 }
 
+/// Why dice could not be rolled or shown
+#[derive(Error, Debug, PartialEq)]
+pub(crate) enum DiceError {
+    #[error(transparent)]
+    Parse(#[from] ParseRollError),
+    #[error("The total could be too large to count")]
+    TooLarge,
+}
+
+impl DiceError {
+    /// What to tell the chat, when a handler could not roll
+    pub fn apology(&self) -> String {
+        format!(
+            "😓 Sorry, I could not roll that.\n\n💣 <code>{}</code> 💣",
+            teloxide::utils::html::escape(&self.to_string())
+        )
+    }
+}
+
 impl FromStr for RollSettings {
-    type Err = crate::parser::ParseRollError;
+    type Err = DiceError;
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
-        crate::parser::parse_roll(input)
+        let settings = crate::parser::parse_roll(input)?;
+        // Totals of rolls that get this far cannot overflow
+        crate::expr::DiceExpr::from(&settings).bounds()?;
+        Ok(settings)
+    }
+}
+
+/// Times a die explodes at most, so that a d2 exploding on 2 still ends
+pub(crate) const MAX_EXPLOSIONS: u32 = 20;
+
+/// Rolls of more dice are summed up rather than listed die by die
+const SUMMARY_DICE: usize = 100;
+
+/// Rolls of more dice leave the dice out of their JSON
+const MAX_JSON_DICE: usize = 1000;
+
+pub(crate) fn too_many_for_json(rolls: &[u32]) -> bool {
+    rolls.len() > MAX_JSON_DICE
+}
+
+/// The dice of a roll, sampled with one rng. The distribution of each die size is built the first
+/// time that size is rolled, and sampled from then on.
+pub(crate) struct Dice<'r, R> {
+    rng: &'r mut R,
+    distributions: Vec<(u32, Uniform<u32>)>,
+}
+
+impl<'r, R: Rng> Dice<'r, R> {
+    pub fn new(rng: &'r mut R) -> Self {
+        Dice {
+            rng,
+            distributions: Vec::with_capacity(2),
+        }
+    }
+
+    /// Roll a die of `sides`
+    pub fn roll(&mut self, sides: u32) -> u32 {
+        let index = match self
+            .distributions
+            .iter()
+            .position(|(size, _)| *size == sides)
+        {
+            Some(index) => index,
+            None => {
+                self.distributions.push((sides, Uniform::from(1..=sides)));
+                self.distributions.len() - 1
+            }
+        };
+        self.distributions[index].1.sample(self.rng)
     }
 }
 
 #[derive(Serialize, Clone, Debug, PartialEq, Eq)]
 pub(crate) struct Roll<'a> {
+    #[serde(skip_serializing_if = "too_many_for_json")]
     pub rolls: Vec<u32>,
     pub total: i64,
-    pub settings: &'a RollSettings,
+    /// The dice that were rolled, which for advantage are more than were written
+    pub settings: Cow<'a, RollSettings>,
+}
+
+/// Truncate the formatted dice to keep messages under the Telegram limits
+fn truncated(mut results: String, truncate: Option<usize>) -> String {
+    if let Some(truncate) = truncate {
+        if results.len() > truncate {
+            let mut end = truncate;
+            while !results.is_char_boundary(end) {
+                end -= 1;
+            }
+            results.truncate(end);
+            results.push_str("...");
+        }
+    }
+    results
 }
 
 impl<'a> Roll<'a> {
-    fn new(settings: &'a RollSettings) -> Self {
-        let mut rng = rand::thread_rng();
-        let die = Uniform::from(1..=settings.sides);
+    pub fn new(settings: &'a RollSettings) -> Self {
+        Self::with_rng(settings, &mut rand::thread_rng())
+    }
 
-        let rolls: Vec<u32> = (1..=settings.number)
-            .map(|_| die.sample(&mut rng))
-            .collect();
+    pub fn with_rng(settings: &'a RollSettings, rng: &mut impl Rng) -> Self {
+        Self::rolled(Cow::Borrowed(settings), &mut Dice::new(rng))
+    }
 
-        let mut total: i64 = rolls.iter().map(|i| *i as i64).sum();
-        if let Some(modifier) = settings.modifier {
-            total += modifier as i64
+    fn rolled(settings: Cow<'a, RollSettings>, dice: &mut Dice<impl Rng>) -> Self {
+        let composite = match settings.composite {
+            true => composite_digit(settings.sides)
+                .map(|digit| (digit, settings.sides.to_string().len())),
+            false => None,
+        };
+        let mut sample = || match composite {
+            // The tens, then the units
+            Some((digit, places)) => (0..places).fold(0, |value, _| value * 10 + dice.roll(digit)),
+            None => match dice.roll(settings.sides) {
+                1 | 2 if settings.flags.great_weapon_fighting => dice.roll(settings.sides),
+                roll => roll,
+            },
+        };
+        let mut rolls = Vec::with_capacity(settings.number as usize);
+        for _ in 0..settings.number {
+            let mut roll = sample();
+            rolls.push(roll);
+            let Some(threshold) = settings.explode else {
+                continue;
+            };
+            for _ in 0..MAX_EXPLOSIONS {
+                if roll < threshold {
+                    break;
+                }
+                roll = sample();
+                rolls.push(roll);
+            }
         }
 
-        Roll {
+        let mut roll = Roll {
             settings,
             rolls,
-            total,
+            total: 0,
+        };
+        roll.recount();
+        roll
+    }
+
+    /// Work the total out again from the dice, after some of them changed
+    pub fn recount(&mut self) {
+        let kept = self.rolls.iter().zip(self.kept()).filter(|(_, kept)| *kept);
+        // Saturating, for settings that were not parsed and so never had their bounds checked
+        let mut total: i64 = match self.settings.success {
+            None => kept.fold(0, |total, (i, _)| total.saturating_add(*i as i64)),
+            Some(threshold) => {
+                let (successes, ones) = kept.fold((0i64, 0i64), |(successes, ones), (i, _)| {
+                    (
+                        successes + (*i >= threshold) as i64,
+                        ones + (*i == 1) as i64,
+                    )
+                });
+                match self.settings.flags.ones_subtract {
+                    true => successes - ones,
+                    false => successes,
+                }
+            }
+        };
+        if let Some(modifier) = self.settings.modifier {
+            total = total.saturating_add(modifier as i64)
+        }
+        if let Some(multiplier) = self.settings.multiplier {
+            total = total.saturating_mul(multiplier as i64)
         }
+        self.total = total;
+    }
+
+    /// Whether each die counts towards the total. Of equal dice, the first ones are kept.
+    pub fn kept(&self) -> Vec<bool> {
+        let mut kept = vec![true; self.rolls.len()];
+        let Some(keep) = self.settings.keep else {
+            return kept;
+        };
+        let mut order: Vec<usize> = (0..self.rolls.len()).collect();
+        match keep {
+            Keep::Highest(_) => order.sort_by_key(|&index| Reverse(self.rolls[index])),
+            Keep::Lowest(_) => order.sort_by_key(|&index| self.rolls[index]),
+        }
+        for &index in &order[self.settings.kept() as usize..] {
+            kept[index] = false;
+        }
+        kept
+    }
+
+    /// The die that counts, when that is a single d20
+    pub fn natural(&self) -> Option<u32> {
+        if self.settings.sides != 20 || self.settings.kept() != 1 {
+            return None;
+        }
+        self.rolls
+            .iter()
+            .zip(self.kept())
+            .find(|(_, kept)| *kept)
+            .map(|(roll, _)| *roll)
+    }
+
+    /// How many dice came up in each of up to ten even spans of the sides, lowest first
+    fn histogram(&self) -> Vec<(u32, u32, usize)> {
+        let sides = self.settings.sides;
+        let buckets = sides.min(10);
+        let width = sides.div_ceil(buckets);
+        let mut counts = vec![0; sides.div_ceil(width) as usize];
+        for roll in &self.rolls {
+            counts[((roll - 1) / width) as usize] += 1;
+        }
+        counts
+            .into_iter()
+            .enumerate()
+            .map(|(index, count)| {
+                let low = index as u32 * width + 1;
+                (low, (low + width - 1).min(sides), count)
+            })
+            .collect()
+    }
+
+    /// Count, lowest, highest and average of the dice, for rolls too big to list
+    fn summary(&self) -> String {
+        let count = self.rolls.len();
+        let sum: i64 = self.rolls.iter().map(|roll| *roll as i64).sum();
+        format!(
+            "{} dice, lowest {}, highest {}, average {:.1}",
+            count,
+            self.rolls.iter().min().unwrap_or(&0),
+            self.rolls.iter().max().unwrap_or(&0),
+            sum as f64 / count.max(1) as f64
+        )
     }
 
     fn format_results(&self) -> String {
+        if self.rolls.len() > SUMMARY_DICE {
+            return self.summary();
+        }
         self.rolls
             .iter()
-            .map(ToString::to_string)
+            .zip(self.kept())
+            .map(|(roll, kept)| {
+                let exploded = self
+                    .settings
+                    .explode
+                    .is_some_and(|threshold| *roll >= threshold);
+                let roll = format!("{}{}", roll, if exploded { "!" } else { "" });
+                match kept {
+                    true => roll,
+                    false => format!("<s>{}</s>", roll),
+                }
+            })
             .reduce(|a, b| format!("{} + {}", a, b))
-            .expect("to not be empty")
+            .unwrap_or_default()
     }
 
-    fn format_roll(&self, truncate: Option<usize>) -> String {
-        let mut results = self.format_results();
-        if let Some(truncate) = truncate {
-            if results.len() > truncate {
-                results.truncate(truncate);
-                results.push_str("...");
-            }
+    pub fn format_roll(&self, truncate: Option<usize>) -> String {
+        let results = truncated(self.format_results(), truncate);
+        let roll = format!("({}){}", results, self.settings.format_modifier());
+        match self.settings.multiplier {
+            None => roll,
+            Some(multiplier) => format!("{} × ({})", multiplier, roll),
         }
+    }
+
+    /// For dice with named faces, such as a coin. `faces` has a name for every side, in order.
+    pub fn format_faces(&self, faces: &[&str], truncate: Option<usize>) -> String {
+        let results = self
+            .rolls
+            .iter()
+            .map(|roll| faces[*roll as usize - 1])
+            .collect::<Vec<_>>()
+            .join(", ");
+        truncated(results, truncate)
+    }
 
-        format!("({}){}", results, self.settings.format_modifier())
+    /// How often each side came up, from side 1 upwards
+    pub fn tally(&self) -> Vec<usize> {
+        let mut tally = vec![0; self.settings.sides as usize];
+        for roll in &self.rolls {
+            tally[*roll as usize - 1] += 1;
+        }
+        tally
     }
 }
 
-impl<'a> std::fmt::Display for Roll<'a> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl<'a> Roll<'a> {
+    /// The label, parameters, dice and total, with the parameters already formatted
+    fn fmt_with(&self, f: &mut std::fmt::Formatter<'_>, parameters: &str) -> std::fmt::Result {
         if let Some(ref label) = self.settings.label {
             writeln!(f, "<u>{}</u>", label)?;
         }
-        writeln!(f, "Parameters: {}", self.settings)?;
+        writeln!(f, "Parameters: {}", parameters)?;
 
         // https://stackoverflow.com/questions/68768069/telegram-error-badrequest-entities-too-long-error-when-trying-to-send-long-ma
         // tldr; limit is 9500
         writeln!(f, "Roll: {}", self.format_roll(Some(4000)))?;
+        if self.rolls.len() > SUMMARY_DICE {
+            let spans = self
+                .histogram()
+                .iter()
+                .map(|(low, high, count)| match low == high {
+                    true => format!("{}: {}", low, count),
+                    false => format!("{}–{}: {}", low, high, count),
+                })
+                .collect::<Vec<_>>()
+                .join(" · ");
+            writeln!(f, "Spread: {}", spans)?;
+        }
 
         write!(f, "Your final roll is: 🎲 <b>{}</b> 🎲", self.total)
     }
 }
 
+impl<'a> std::fmt::Display for Roll<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Pools such as `5d10>=8` have a `>` in them
+        let parameters = teloxide::utils::html::escape(&self.settings.to_string());
+        self.fmt_with(f, &parameters)
+    }
+}
+
 impl<'a> Ord for Roll<'a> {
     fn cmp(&self, other: &Self) -> Ordering {
         self.total.cmp(&other.total)
@@ -121,7 +536,7 @@ impl<'a> PartialOrd for Roll<'a> {
     }
 }
 
-#[derive(Serialize, Clone, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub(crate) enum RollType {
     Straight,
@@ -139,100 +554,417 @@ impl std::fmt::Display for RollType {
     }
 }
 
+/// How far past the DC a roll has to land to be critical, set per campaign
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub struct Bands {
+    /// Missing the DC by this much or more is a critical failure
+    pub critical_failure: i64,
+    /// Beating the DC by this much or more is a critical success
+    pub critical_success: i64,
+}
+
+impl Default for Bands {
+    fn default() -> Self {
+        Bands {
+            critical_failure: 10,
+            critical_success: 10,
+        }
+    }
+}
+
+/// How a roll against a DC went. Missing or beating the DC by 10 or more is critical, unless the
+/// campaign has other bands.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Degree {
+    CriticalSuccess,
+    Success,
+    Failure,
+    CriticalFailure,
+}
+
+impl Degree {
+    fn new(margin: i64, bands: &Bands) -> Self {
+        match margin {
+            margin if margin >= bands.critical_success => Degree::CriticalSuccess,
+            margin if margin >= 0 => Degree::Success,
+            margin if margin > -bands.critical_failure => Degree::Failure,
+            _ => Degree::CriticalFailure,
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        matches!(self, Degree::CriticalSuccess | Degree::Success)
+    }
+}
+
+impl std::fmt::Display for Degree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Degree::CriticalSuccess => write!(f, "🌟 <b>Critical success</b>"),
+            Degree::Success => write!(f, "✅ <b>Success</b>"),
+            Degree::Failure => write!(f, "❌ <b>Failure</b>"),
+            Degree::CriticalFailure => write!(f, "💀 <b>Critical failure</b>"),
+        }
+    }
+}
+
 #[derive(Serialize, Clone, Debug, PartialEq)]
 pub(crate) struct RollResults<'a> {
     pub roll_type: &'a RollType,
-    pub try_one: Roll<'a>,
-    pub try_two: Option<Roll<'a>>,
+    /// With advantage or disadvantage, only some of its dice count, as for `2d20kh1`
+    pub result: Roll<'a>,
+    /// The roll as it was written
     pub settings: &'a RollSettings,
+    /// The DC the roll is made against
+    pub target: Option<i64>,
+    pub bands: Bands,
 }
 
 impl<'a> RollResults<'a> {
     pub fn new(settings: &'a RollSettings, roll_type: &'a RollType) -> Self {
-        let try_one = Roll::new(settings);
-        let try_two = match roll_type {
-            RollType::Straight => None,
-            RollType::Advantage | RollType::Disadvantage => Some(Roll::new(settings)),
-        };
+        Self::with_rng(settings, roll_type, &mut rand::thread_rng())
+    }
 
+    pub fn with_rng(
+        settings: &'a RollSettings,
+        roll_type: &'a RollType,
+        rng: &mut impl Rng,
+    ) -> Self {
+        // Advantage and disadvantage cancel out
+        let roll_type = match (roll_type, &settings.flags.roll_type) {
+            (RollType::Straight, Some(flag)) => flag,
+            (RollType::Advantage, Some(RollType::Disadvantage))
+            | (RollType::Disadvantage, Some(RollType::Advantage)) => &RollType::Straight,
+            _ => roll_type,
+        };
         RollResults {
             roll_type,
-            try_one,
-            try_two,
+            result: Roll::rolled(settings.with_roll_type(roll_type), &mut Dice::new(rng)),
             settings,
+            target: settings.target,
+            bands: Bands::default(),
         }
     }
 
-    pub fn result(&self) -> &Roll<'a> {
-        match self.roll_type {
-            RollType::Straight => &self.try_one,
-            RollType::Advantage => max(&self.try_one, self.try_two.as_ref().expect("to be some")),
-            RollType::Disadvantage => {
-                min(&self.try_one, self.try_two.as_ref().expect("to be some"))
+    /// How far the total is over the DC, or under it when negative
+    pub fn margin(&self) -> Option<i64> {
+        self.target
+            .map(|target| self.result.total.saturating_sub(target))
+    }
+
+    /// How the roll went against its DC, if it has one
+    pub fn degree(&self) -> Option<Degree> {
+        self.margin().map(|margin| Degree::new(margin, &self.bands))
+    }
+}
+
+impl<'a> RollResults<'a> {
+    /// The results, led by the name of the character who rolled
+    pub fn announce(&self, character: Option<&str>) -> String {
+        let text = self.to_string();
+        let Some(character) = character else {
+            return text;
+        };
+        let name = format!("<b>{}</b> rolls", teloxide::utils::html::escape(character));
+        let label = self
+            .settings
+            .label
+            .as_ref()
+            .map(|label| format!("<u>{}</u>\n", label));
+        match label {
+            Some(label) if text.starts_with(&label) => {
+                format!("{} {}", name, label) + &text[label.len()..]
             }
+            _ => format!("{}\n{}", name, text),
         }
     }
+}
 
-    /// 1 or 2
-    pub fn results_index(&self) -> usize {
-        match self.roll_type {
-            RollType::Straight => 1,
-            RollType::Advantage => {
-                if &self.try_one > self.try_two.as_ref().expect("to be some") {
-                    1
-                } else {
-                    2
-                }
-            }
-            RollType::Disadvantage => {
-                if &self.try_one < self.try_two.as_ref().expect("to be some") {
-                    1
-                } else {
-                    2
+impl<'a> std::fmt::Display for RollResults<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_rolls(f)?;
+        match (self.target, self.margin(), self.degree()) {
+            (Some(target), Some(margin), Some(degree)) => {
+                write!(f, "\nvs DC {}: {}, ", target, degree)?;
+                match margin {
+                    0 => write!(f, "exactly the DC"),
+                    margin if margin > 0 => write!(f, "+{} over DC", margin),
+                    margin => write!(f, "{} under DC", -margin),
                 }
             }
+            _ => Ok(()),
         }
     }
 }
 
-impl<'a> std::fmt::Display for RollResults<'a> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl<'a> RollResults<'a> {
+    fn fmt_rolls(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.roll_type {
-            RollType::Straight => self.try_one.fmt(f),
+            RollType::Straight => std::fmt::Display::fmt(&self.result, f),
+            // As written, rather than as the dice that were rolled for it
             RollType::Advantage | RollType::Disadvantage => {
-                let results_index = self.results_index();
-                if let Some(ref label) = self.settings.label {
-                    writeln!(f, "<u>{}</u>", label)?;
-                }
-                writeln!(
-                    f,
-                    "Parameters: {} with <i>{}</i>",
-                    self.settings, self.roll_type
-                )?;
-                let attempt_one = format!("Attempt one: {}", self.try_one.format_roll(Some(2000)));
-                if results_index == 2 {
-                    writeln!(f, "<s>{}</s>", attempt_one)?;
-                } else {
-                    writeln!(f, "{}", attempt_one)?;
-                }
-                let attempt_two = format!(
-                    "Attempt two: {}",
-                    self.try_two
-                        .as_ref()
-                        .expect("to be some")
-                        .format_roll(Some(2000))
+                let parameters = format!(
+                    "{} with <i>{}</i>",
+                    teloxide::utils::html::escape(&self.settings.to_string()),
+                    self.roll_type
                 );
-                if results_index == 1 {
-                    writeln!(f, "<s>{}</s>", attempt_two)?;
-                } else {
-                    writeln!(f, "{}", attempt_two)?;
-                }
-                write!(
-                    f,
-                    "Your final roll is: 🎲 <b>{}</b> 🎲",
-                    self.result().total
-                )
+                self.result.fmt_with(f, &parameters)
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn announces_the_character() {
+        let settings: RollSettings = "1d1 + 2 stealth".parse().unwrap();
+        let results = RollResults::new(&settings, &RollType::Straight);
+        assert!(results
+            .announce(Some("Thorin"))
+            .starts_with("<b>Thorin</b> rolls <u>stealth</u>\nParameters: 1d1 + 2\n"));
+        let settings: RollSettings = "1d1".parse().unwrap();
+        let results = RollResults::new(&settings, &RollType::Advantage);
+        assert!(results
+            .announce(Some("Thorin"))
+            .starts_with("<b>Thorin</b> rolls\nParameters: 1d1 with <i>Advantage</i>"));
+        assert_eq!(results.announce(None), results.to_string());
+    }
+
+    #[test]
+    fn echoes_what_was_understood() {
+        let echo = |input: &str| RollSettings::from_str(input).unwrap().echo(input);
+        assert_eq!(echo("1d20+5"), None);
+        assert_eq!(echo("1 D 20 - 1 Stealth vs DC 12"), None);
+        assert_eq!(echo("2d6 vs 9"), None);
+        assert_eq!(echo("１ｄ２０+5 vs15"), None);
+        assert_eq!(echo("01d20"), Some("Interpreted as: 1d20".to_string()));
+        assert_eq!(echo("d20+5"), Some("Interpreted as: 1d20 + 5".to_string()));
+        assert_eq!(
+            echo("1d20+05 stealth vs 10"),
+            Some("Interpreted as: 1d20 + 5, labelled stealth, vs DC 10".to_string())
+        );
+    }
+
+    #[test]
+    fn rolls_with_the_given_rng() {
+        use rand::SeedableRng;
+
+        let settings: RollSettings = "10d20".parse().unwrap();
+        let mut one = rand::rngs::StdRng::seed_from_u64(7);
+        let mut two = rand::rngs::StdRng::seed_from_u64(7);
+        assert_eq!(
+            Roll::with_rng(&settings, &mut one).rolls,
+            Roll::with_rng(&settings, &mut two).rolls
+        );
+        assert!(Roll::with_rng(&settings, &mut one)
+            .rolls
+            .iter()
+            .all(|roll| (1..=20).contains(roll)));
+
+        let mut dice = Dice::new(&mut one);
+        for _ in 0..100 {
+            assert!((1..=6).contains(&dice.roll(6)));
+            assert!((1..=20).contains(&dice.roll(20)));
+        }
+        assert_eq!(dice.distributions.len(), 2);
+    }
+
+    #[test]
+    fn keeps_the_highest_d20_for_advantage() {
+        let settings: RollSettings = "1d20 + 5 stealth".parse().unwrap();
+        let mut results = RollResults::new(&settings, &RollType::Advantage);
+        assert_eq!(results.result.settings.to_string(), "2d20kh1 + 5");
+        results.result.rolls = vec![4, 17];
+        results.result.recount();
+        assert_eq!(
+            (results.result.total, results.result.natural()),
+            (22, Some(17))
+        );
+        assert_eq!(
+            results.to_string(),
+            "<u>stealth</u>\nParameters: 1d20 + 5 with <i>Advantage</i>\nRoll: (<s>4</s> + 17) + 5\nYour final roll is: 🎲 <b>22</b> 🎲"
+        );
+
+        let mut results = RollResults::new(&settings, &RollType::Disadvantage);
+        results.result.rolls = vec![4, 17];
+        results.result.recount();
+        assert_eq!(results.result.total, 9);
+
+        let settings: RollSettings = "3d20kh1".parse().unwrap();
+        let advantage = settings.with_roll_type(&RollType::Advantage);
+        assert_eq!(advantage.to_string(), "4d20kh1");
+        let settings: RollSettings = "2d6 + 1".parse().unwrap();
+        let results = RollResults::new(&settings, &RollType::Disadvantage);
+        assert_eq!(results.result.rolls.len(), 4);
+        assert_eq!(
+            results.result.kept().iter().filter(|kept| **kept).count(),
+            2
+        );
+        assert_eq!(
+            settings.with_roll_type(&RollType::Straight),
+            Cow::Borrowed(&settings)
+        );
+    }
+
+    #[test]
+    fn apologises_for_what_it_cannot_parse() {
+        let e = "1d".parse::<RollSettings>().unwrap_err();
+        assert!(matches!(e, DiceError::Parse(_)));
+        assert!(e.apology().starts_with("😓 Sorry, I could not roll that."));
+    }
+
+    #[test]
+    fn keeps_some_of_the_dice() {
+        let settings: RollSettings = "4d6kh3".parse().unwrap();
+        let mut roll = Roll::new(&settings);
+        roll.rolls = vec![3, 1, 6, 3];
+        roll.recount();
+        assert_eq!(roll.total, 12);
+        assert_eq!(roll.kept(), [true, false, true, true]);
+        assert_eq!(roll.format_roll(None), "(3 + <s>1</s> + 6 + 3)");
+
+        let settings: RollSettings = "3d20kl1 + 2".parse().unwrap();
+        let mut roll = Roll::new(&settings);
+        roll.rolls = vec![20, 4, 4];
+        roll.recount();
+        assert_eq!(roll.total, 6);
+        assert_eq!(roll.kept(), [false, true, false]);
+        assert_eq!(roll.natural(), Some(4));
+    }
+
+    #[test]
+    fn explodes_from_the_threshold() {
+        use rand::SeedableRng;
+
+        let settings: RollSettings = "5d10!>=9".parse().unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+        for _ in 0..100 {
+            // Every 9 or 10 brought another die
+            let roll = Roll::with_rng(&settings, &mut rng);
+            let exploded = roll.rolls.iter().filter(|roll| **roll >= 9).count();
+            assert_eq!(roll.rolls.len(), 5 + exploded);
+        }
+
+        let settings: RollSettings = "1d10!>=9".parse().unwrap();
+        let mut roll = Roll::new(&settings);
+        roll.rolls = vec![9, 10, 3];
+        roll.recount();
+        assert_eq!(roll.total, 22);
+        assert_eq!(roll.format_roll(None), "(9! + 10! + 3)");
+    }
+
+    #[test]
+    fn counts_successes() {
+        let settings: RollSettings = "5d10>=8".parse().unwrap();
+        let mut roll = Roll::new(&settings);
+        roll.rolls = vec![8, 1, 10, 7, 3];
+        roll.recount();
+        assert_eq!(roll.total, 2);
+    }
+
+    #[test]
+    fn reads_composite_dice_digit_by_digit() {
+        let settings: RollSettings = "10d66".parse().unwrap();
+        assert!(settings.composite);
+        for roll in Roll::new(&settings).rolls {
+            assert!((1..=6).contains(&(roll / 10)) && (1..=6).contains(&(roll % 10)));
+        }
+        let settings: RollSettings = "d66".parse().unwrap();
+        assert!(settings.can_roll(11) && settings.can_roll(66));
+        assert!(!settings.can_roll(17) && !settings.can_roll(7) && !settings.can_roll(61 + 10));
+        assert!(!"1d100".parse::<RollSettings>().unwrap().composite);
+        assert_eq!(composite_digit(666), Some(6));
+        assert_eq!(composite_digit(11), None);
+        assert_eq!(composite_digit(99), None);
+    }
+
+    #[test]
+    fn multiplies_the_total() {
+        let settings: RollSettings = "2x(3d1 + 2)".parse().unwrap();
+        let roll = Roll::new(&settings);
+        assert_eq!(roll.total, 10);
+        assert_eq!(roll.format_roll(None), "2 × ((1 + 1 + 1) + 2)");
+        assert_eq!(settings.echo("2x(3d1+2)"), None);
+
+        let mut settings: RollSettings = "3d6".parse().unwrap();
+        settings.multiplier = Some(u32::MAX);
+        let mut roll = Roll::new(&settings);
+        roll.rolls = vec![u32::MAX; 3];
+        roll.recount();
+        assert_eq!(roll.total, i64::MAX);
+    }
+
+    #[test]
+    fn sums_up_huge_rolls() {
+        let settings: RollSettings = "500d1 + 2".parse().unwrap();
+        let roll = Roll::new(&settings);
+        assert_eq!(
+            roll.format_roll(Some(4000)),
+            "(500 dice, lowest 1, highest 1, average 1.0) + 2"
+        );
+        assert!(roll.to_string().contains("\nSpread: 1: 500\n"));
+        let settings: RollSettings = "2000d25".parse().unwrap();
+        let roll = Roll::new(&settings);
+        let histogram = roll.histogram();
+        assert_eq!(histogram.len(), 9);
+        assert_eq!((histogram[8].0, histogram[8].1), (25, 25));
+        assert_eq!(
+            histogram.iter().map(|(_, _, count)| count).sum::<usize>(),
+            2000
+        );
+        let json = serde_json::to_value(&roll).unwrap();
+        assert!(json.get("rolls").is_none());
+        assert_eq!(json["total"], roll.total);
+    }
+
+    #[test]
+    fn applies_flags() {
+        let settings: RollSettings = "1d1 + 1 stealth adv".parse().unwrap();
+        assert_eq!(settings.label.as_deref(), Some("stealth"));
+        let results = RollResults::new(&settings, &RollType::Straight);
+        assert_eq!(results.roll_type, &RollType::Advantage);
+        let results = RollResults::new(&settings, &RollType::Disadvantage);
+        assert_eq!(results.roll_type, &RollType::Straight);
+
+        let settings: RollSettings = "2d6+3 crit gwf".parse().unwrap();
+        assert_eq!((settings.number, settings.label), (4, None));
+        let settings: RollSettings = "1d2 gwf".parse().unwrap();
+        assert!(settings.flags.great_weapon_fighting);
+        assert_eq!(
+            settings.interpretation(),
+            "1d2, rolling ones and twos again"
+        );
+    }
+
+    #[test]
+    fn resolves_against_the_dc() {
+        let bands = Bands::default();
+        assert_eq!(Degree::new(10, &bands), Degree::CriticalSuccess);
+        assert_eq!(Degree::new(0, &bands), Degree::Success);
+        assert_eq!(Degree::new(-1, &bands), Degree::Failure);
+        assert_eq!(Degree::new(-10, &bands), Degree::CriticalFailure);
+        let bands = Bands {
+            critical_failure: 5,
+            critical_success: 10,
+        };
+        assert_eq!(Degree::new(-5, &bands), Degree::CriticalFailure);
+        assert_eq!(Degree::new(9, &bands), Degree::Success);
+
+        let settings: RollSettings = "1d1 + 4 vs 10".parse().unwrap();
+        let results = RollResults::new(&settings, &RollType::Straight);
+        assert_eq!(results.degree(), Some(Degree::Failure));
+        assert!(results
+            .to_string()
+            .ends_with("\nvs DC 10: ❌ <b>Failure</b>, 5 under DC"));
+        let settings: RollSettings = "1d1 + 16 vs 10".parse().unwrap();
+        let results = RollResults::new(&settings, &RollType::Straight);
+        assert!(results
+            .to_string()
+            .ends_with("\nvs DC 10: ✅ <b>Success</b>, +7 over DC"));
+    }
+}
@@ -1,28 +1,134 @@
+use std::borrow::Cow;
 use std::fmt::Display;
-use std::fs::File;
-use std::io::BufReader;
 use std::str::FromStr;
 
-use anyhow::Context;
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
 use serde::{Deserialize, Serialize};
-use serde_aux::field_attributes::deserialize_number_from_string;
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
+use thiserror::Error;
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+/// Integers may also be written as strings, such as `"+3"`
+fn number_or_string(_: &mut SchemaGenerator) -> Schema {
+    json_schema!({
+        "oneOf": [
+            {"type": "integer"},
+            {"type": "string", "pattern": "^[+-]?[0-9]+$"}
+        ]
+    })
+}
+
+fn optional_number_or_string(generator: &mut SchemaGenerator) -> Schema {
+    json_schema!({
+        "oneOf": [number_or_string(generator), {"type": "null"}]
+    })
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, Clone)]
 #[serde(rename_all = "snake_case")]
 #[serde(deny_unknown_fields)]
 pub struct Character {
-    name: String,
+    pub name: String,
+
+    pub attribute_modifiers: AttributeModifiers<i8>,
+    pub saving_throw_modifiers: AttributeModifiers<i8>,
+
+    #[serde(rename = "skill_modifiers")]
+    pub skills: Skills,
+
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    #[schemars(schema_with = "number_or_string")]
+    pub initiative_modifier: i8,
+
+    #[serde(default)]
+    #[serde(alias = "ac")]
+    #[serde(deserialize_with = "deserialize_option_number_from_string")]
+    #[schemars(schema_with = "optional_number_or_string")]
+    pub armor_class: Option<u8>,
+    #[serde(default)]
+    #[serde(alias = "max_hp")]
+    #[serde(deserialize_with = "deserialize_option_number_from_string")]
+    #[schemars(schema_with = "optional_number_or_string")]
+    pub max_hit_points: Option<u16>,
+    /// Walking speed in feet
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_option_number_from_string")]
+    #[schemars(schema_with = "optional_number_or_string")]
+    pub speed: Option<u16>,
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_option_number_from_string")]
+    #[schemars(schema_with = "optional_number_or_string")]
+    pub proficiency_bonus: Option<i8>,
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_option_number_from_string")]
+    #[schemars(schema_with = "optional_number_or_string")]
+    pub passive_perception: Option<u8>,
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_option_number_from_string")]
+    #[schemars(schema_with = "optional_number_or_string")]
+    pub spell_save_dc: Option<u8>,
+
+    #[serde(default)]
+    pub attacks: Vec<Attack>,
+
+    #[serde(default)]
+    pub hit_dice: Option<HitDice>,
+    /// Spell slots of each spell level, from the 1st level up
+    #[serde(default)]
+    pub spell_slots: Vec<u8>,
+    /// Abilities with limited uses, such as Second Wind or Ki
+    #[serde(default)]
+    pub resources: Vec<Resource>,
+
+    /// Telegram user ID of the player, for character data managed by the operator
+    #[serde(default)]
+    pub player: Option<i64>,
+}
+
+/// The hit dice of a character at full strength, such as 5 of `d10`
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct HitDice {
+    pub count: u8,
+    pub sides: u8,
+}
 
-    attribute_modifiers: AttributeModifiers<i8>,
-    saving_throw_modifiers: AttributeModifiers<i8>,
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct Resource {
+    pub name: String,
+    pub uses: u8,
+    #[serde(default)]
+    pub recharge: Recharge,
+}
 
-    skill_modifiers: SkillModifiers<i8>,
+/// The rest that restores the uses of a resource
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Recharge {
+    ShortRest,
+    #[default]
+    LongRest,
+}
 
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct Attack {
+    pub name: String,
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    initiative_modifier: i8,
+    #[schemars(schema_with = "number_or_string")]
+    pub to_hit: i8,
+    /// Damage roll, such as `1d8+3`
+    pub damage: String,
+    #[serde(default)]
+    pub damage_type: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, Clone)]
 #[serde(rename_all = "snake_case")]
 #[serde(deny_unknown_fields)]
 pub struct AttributeModifiers<T>
@@ -32,25 +138,31 @@ where
 {
     #[serde(alias = "str")]
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    strength: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub strength: T,
     #[serde(alias = "dex")]
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    dexterity: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub dexterity: T,
     #[serde(alias = "con")]
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    constitution: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub constitution: T,
     #[serde(alias = "int")]
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    intelligence: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub intelligence: T,
     #[serde(alias = "wis")]
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    wisdom: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub wisdom: T,
     #[serde(alias = "cha")]
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    charisma: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub charisma: T,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, Clone)]
 #[serde(rename_all = "PascalCase")]
 #[serde(deny_unknown_fields)]
 pub struct SkillModifiers<T>
@@ -59,72 +171,520 @@ where
     <T as FromStr>::Err: Display,
 {
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    acrobatics: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub acrobatics: T,
     #[serde(deserialize_with = "deserialize_number_from_string")]
+    #[schemars(schema_with = "number_or_string")]
     #[serde(alias = "Animal Handling")]
-    animal_handling: T,
+    pub animal_handling: T,
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    arcana: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub arcana: T,
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    athletics: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub athletics: T,
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    deception: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub deception: T,
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    history: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub history: T,
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    insight: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub insight: T,
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    intimidation: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub intimidation: T,
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    investigation: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub investigation: T,
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    medicine: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub medicine: T,
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    nature: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub nature: T,
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    perception: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub perception: T,
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    performance: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub performance: T,
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    persuasion: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub persuasion: T,
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    religion: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub religion: T,
     #[serde(deserialize_with = "deserialize_number_from_string")]
+    #[schemars(schema_with = "number_or_string")]
     #[serde(alias = "Sleight of Hand")]
-    sleight_of_hand: T,
+    pub sleight_of_hand: T,
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    stealth: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub stealth: T,
     #[serde(deserialize_with = "deserialize_number_from_string")]
-    survival: T,
+    #[schemars(schema_with = "number_or_string")]
+    pub survival: T,
 }
 
-impl Character {
-    pub fn from_json_file<P>(path: P) -> anyhow::Result<Self>
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Ability {
+    Strength,
+    Dexterity,
+    Constitution,
+    Intelligence,
+    Wisdom,
+    Charisma,
+}
+
+impl Ability {
+    pub const ALL: [Ability; 6] = [
+        Ability::Strength,
+        Ability::Dexterity,
+        Ability::Constitution,
+        Ability::Intelligence,
+        Ability::Wisdom,
+        Ability::Charisma,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Ability::Strength => "Strength",
+            Ability::Dexterity => "Dexterity",
+            Ability::Constitution => "Constitution",
+            Ability::Intelligence => "Intelligence",
+            Ability::Wisdom => "Wisdom",
+            Ability::Charisma => "Charisma",
+        }
+    }
+}
+
+impl Display for Ability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Ability {
+    type Err = String;
+
+    /// Full names and the usual three letter abbreviations, ignoring case
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim().to_lowercase();
+        Ability::ALL
+            .into_iter()
+            .find(|ability| {
+                let name = ability.name().to_lowercase();
+                input == name || input == name[..3]
+            })
+            .ok_or_else(|| format!("unknown ability {}", input))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub enum Skill {
+    Acrobatics,
+    AnimalHandling,
+    Arcana,
+    Athletics,
+    Deception,
+    History,
+    Insight,
+    Intimidation,
+    Investigation,
+    Medicine,
+    Nature,
+    Perception,
+    Performance,
+    Persuasion,
+    Religion,
+    SleightOfHand,
+    Stealth,
+    Survival,
+}
+
+impl Skill {
+    pub const ALL: [Skill; 18] = [
+        Skill::Acrobatics,
+        Skill::AnimalHandling,
+        Skill::Arcana,
+        Skill::Athletics,
+        Skill::Deception,
+        Skill::History,
+        Skill::Insight,
+        Skill::Intimidation,
+        Skill::Investigation,
+        Skill::Medicine,
+        Skill::Nature,
+        Skill::Perception,
+        Skill::Performance,
+        Skill::Persuasion,
+        Skill::Religion,
+        Skill::SleightOfHand,
+        Skill::Stealth,
+        Skill::Survival,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Skill::Acrobatics => "Acrobatics",
+            Skill::AnimalHandling => "Animal Handling",
+            Skill::Arcana => "Arcana",
+            Skill::Athletics => "Athletics",
+            Skill::Deception => "Deception",
+            Skill::History => "History",
+            Skill::Insight => "Insight",
+            Skill::Intimidation => "Intimidation",
+            Skill::Investigation => "Investigation",
+            Skill::Medicine => "Medicine",
+            Skill::Nature => "Nature",
+            Skill::Perception => "Perception",
+            Skill::Performance => "Performance",
+            Skill::Persuasion => "Persuasion",
+            Skill::Religion => "Religion",
+            Skill::SleightOfHand => "Sleight of Hand",
+            Skill::Stealth => "Stealth",
+            Skill::Survival => "Survival",
+        }
+    }
+
+    /// The ability the skill is based on
+    pub fn ability(&self) -> Ability {
+        match self {
+            Skill::Athletics => Ability::Strength,
+            Skill::Acrobatics | Skill::SleightOfHand | Skill::Stealth => Ability::Dexterity,
+            Skill::Arcana
+            | Skill::History
+            | Skill::Investigation
+            | Skill::Nature
+            | Skill::Religion => Ability::Intelligence,
+            Skill::AnimalHandling
+            | Skill::Insight
+            | Skill::Medicine
+            | Skill::Perception
+            | Skill::Survival => Ability::Wisdom,
+            Skill::Deception | Skill::Intimidation | Skill::Performance | Skill::Persuasion => {
+                Ability::Charisma
+            }
+        }
+    }
+}
+
+impl Display for Skill {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Skill {
+    type Err = String;
+
+    /// Skill names ignoring case, spaces and underscores, e.g. `sleight_of_hand`
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let normalize = |s: &str| {
+            s.chars()
+                .filter(|c| c.is_alphanumeric())
+                .collect::<String>()
+                .to_lowercase()
+        };
+        let input = normalize(input);
+        Skill::ALL
+            .into_iter()
+            .find(|skill| normalize(skill.name()) == input)
+            .ok_or_else(|| format!("unknown skill {}", input))
+    }
+}
+
+impl TryFrom<String> for Skill {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl JsonSchema for Skill {
+    fn schema_name() -> Cow<'static, str> {
+        "Skill".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        let names: Vec<_> = Skill::ALL.iter().map(Skill::name).collect();
+        json_schema!({
+            "type": "string",
+            "enum": names
+        })
+    }
+}
+
+impl From<Skill> for String {
+    fn from(skill: Skill) -> Self {
+        skill.name().to_string()
+    }
+}
+
+impl<T> AttributeModifiers<T>
+where
+    T: FromStr + serde::de::DeserializeOwned,
+    <T as FromStr>::Err: Display,
+{
+    pub fn get(&self, ability: Ability) -> &T {
+        match ability {
+            Ability::Strength => &self.strength,
+            Ability::Dexterity => &self.dexterity,
+            Ability::Constitution => &self.constitution,
+            Ability::Intelligence => &self.intelligence,
+            Ability::Wisdom => &self.wisdom,
+            Ability::Charisma => &self.charisma,
+        }
+    }
+
+    /// Attributes in the usual order
+    pub fn iter(&self) -> [(Ability, &T); 6] {
+        Ability::ALL.map(|ability| (ability, self.get(ability)))
+    }
+
+    pub fn try_from_fn<F, E>(f: F) -> Result<Self, E>
     where
-        P: AsRef<std::path::Path> + std::fmt::Debug,
+        F: Fn(Ability) -> Result<T, E>,
     {
-        let file = File::open(&path).with_context(|| format!("error opening file {:?}", path))?;
-        let reader = BufReader::new(file);
-        serde_json::from_reader(reader)
-            .with_context(|| format!("error deserializing JSON {:?}", path))
+        Ok(AttributeModifiers {
+            strength: f(Ability::Strength)?,
+            dexterity: f(Ability::Dexterity)?,
+            constitution: f(Ability::Constitution)?,
+            intelligence: f(Ability::Intelligence)?,
+            wisdom: f(Ability::Wisdom)?,
+            charisma: f(Ability::Charisma)?,
+        })
+    }
+}
+
+/// The ability modifier for an ability score
+pub fn modifier_for_score(score: i32) -> i8 {
+    (score - 10)
+        .div_euclid(2)
+        .clamp(i8::MIN as i32, i8::MAX as i32) as i8
+}
+
+/// The proficiency bonus at a character level
+pub fn proficiency_bonus_for_level(level: u8) -> i8 {
+    2 + (level.clamp(1, 20) as i8 - 1) / 4
+}
+
+impl<T> SkillModifiers<T>
+where
+    T: FromStr + serde::de::DeserializeOwned,
+    <T as FromStr>::Err: Display,
+{
+    pub fn get(&self, skill: Skill) -> &T {
+        match skill {
+            Skill::Acrobatics => &self.acrobatics,
+            Skill::AnimalHandling => &self.animal_handling,
+            Skill::Arcana => &self.arcana,
+            Skill::Athletics => &self.athletics,
+            Skill::Deception => &self.deception,
+            Skill::History => &self.history,
+            Skill::Insight => &self.insight,
+            Skill::Intimidation => &self.intimidation,
+            Skill::Investigation => &self.investigation,
+            Skill::Medicine => &self.medicine,
+            Skill::Nature => &self.nature,
+            Skill::Perception => &self.perception,
+            Skill::Performance => &self.performance,
+            Skill::Persuasion => &self.persuasion,
+            Skill::Religion => &self.religion,
+            Skill::SleightOfHand => &self.sleight_of_hand,
+            Skill::Stealth => &self.stealth,
+            Skill::Survival => &self.survival,
+        }
+    }
+}
+
+/// Skill modifiers either as flat numbers, or computed from the attribute modifiers,
+/// proficiency bonus and the skills the character is proficient in.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, Clone)]
+#[serde(untagged)]
+pub enum Skills {
+    Flat(SkillModifiers<i8>),
+    Proficiencies(SkillProficiencies),
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct SkillProficiencies {
+    #[serde(default)]
+    pub proficient: Vec<Skill>,
+    /// Skills that add double the proficiency bonus
+    #[serde(default)]
+    pub expertise: Vec<Skill>,
+}
+
+/// A character that deserialized but does not make sense
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("{field} is {reason}")]
+pub struct InvalidCharacter {
+    pub field: &'static str,
+    pub reason: &'static str,
+}
+
+impl Character {
+    pub fn skill_modifier(&self, skill: Skill) -> i8 {
+        match &self.skills {
+            Skills::Flat(modifiers) => *modifiers.get(skill),
+            Skills::Proficiencies(proficiencies) => {
+                let multiplier = if proficiencies.expertise.contains(&skill) {
+                    2
+                } else if proficiencies.proficient.contains(&skill) {
+                    1
+                } else {
+                    0
+                };
+                let bonus = self.proficiency_bonus.unwrap_or_default();
+                self.attribute_modifiers
+                    .get(skill.ability())
+                    .saturating_add(bonus.saturating_mul(multiplier))
+            }
+        }
+    }
+
+    /// All skill modifiers, alphabetically
+    pub fn skill_modifiers(&self) -> [(Skill, i8); 18] {
+        Skill::ALL.map(|skill| (skill, self.skill_modifier(skill)))
     }
 
-    pub fn load_from_pattern<S: AsRef<str>>(
-        pattern: S,
-    ) -> anyhow::Result<(Vec<Self>, Vec<anyhow::Error>)> {
-        let result = glob::glob(pattern.as_ref())
-            .with_context(|| format!("error figuring out path {}", pattern.as_ref()))?
-            .map(|entry| {
-                entry
-                    .with_context(|| "error handling file")
-                    .and_then(Self::from_json_file)
-            });
+    /// Checks that cannot be expressed in the serde attributes
+    pub fn validate(&self) -> Result<(), InvalidCharacter> {
+        if let Skills::Proficiencies(_) = self.skills {
+            if self.proficiency_bonus.is_none() {
+                return Err(InvalidCharacter {
+                    field: "proficiency_bonus",
+                    reason: "required to compute skill modifiers from proficiencies",
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Find an attack by name, ignoring case. A unique prefix is enough.
+    pub fn attack(&self, name: &str) -> Option<&Attack> {
+        let name = name.to_lowercase();
+        if let Some(attack) = self
+            .attacks
+            .iter()
+            .find(|attack| attack.name.to_lowercase() == name)
+        {
+            return Some(attack);
+        }
+        let mut candidates = self
+            .attacks
+            .iter()
+            .filter(|attack| attack.name.to_lowercase().starts_with(&name));
+        match (candidates.next(), candidates.next()) {
+            (Some(attack), None) => Some(attack),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHARACTER: &str = r#"{
+        "name": "Thorin",
+        "attribute_modifiers": {"str": 3, "dex": "1", "con": 2, "int": -1, "wis": 0, "cha": "-1"},
+        "saving_throw_modifiers": {"str": 5, "dex": 1, "con": 4, "int": -1, "wis": 0, "cha": -1},
+        "skill_modifiers": {
+            "Acrobatics": 1, "Animal Handling": 0, "Arcana": -1, "Athletics": 5, "Deception": -1,
+            "History": -1, "Insight": 0, "Intimidation": 1, "Investigation": -1, "Medicine": 0,
+            "Nature": -1, "Perception": 2, "Performance": -1, "Persuasion": -1, "Religion": -1,
+            "Sleight of Hand": 1, "Stealth": 1, "Survival": 2
+        },
+        "initiative_modifier": "+1"
+    }"#;
+
+    #[test]
+    fn combat_stats_are_optional() {
+        let character: Character = serde_json::from_str(CHARACTER).unwrap();
+        assert_eq!(character.attribute_modifiers.dexterity, 1);
+        assert_eq!(character.skill_modifier(Skill::SleightOfHand), 1);
+        assert_eq!(character.initiative_modifier, 1);
+        assert_eq!(character.armor_class, None);
+        assert_eq!(character.spell_save_dc, None);
 
-        let (ok, err): (Vec<_>, Vec<_>) = result.partition(Result::is_ok);
+        let with_stats = CHARACTER.replacen(
+            r#""name": "Thorin","#,
+            r#""name": "Thorin", "ac": "16", "max_hp": 24, "speed": 25, "proficiency_bonus": 2,"#,
+            1,
+        );
+        let character: Character = serde_json::from_str(&with_stats).unwrap();
+        assert_eq!(character.armor_class, Some(16));
+        assert_eq!(character.max_hit_points, Some(24));
+        assert_eq!(character.speed, Some(25));
+        assert_eq!(character.proficiency_bonus, Some(2));
+        assert_eq!(character.passive_perception, None);
+    }
+
+    #[test]
+    fn finds_attacks_by_name_or_unique_prefix() {
+        let with_attacks = CHARACTER.replacen(
+            r#""name": "Thorin","#,
+            r#""name": "Thorin", "attacks": [
+                {"name": "Longsword", "to_hit": "+5", "damage": "1d8+3", "damage_type": "slashing"},
+                {"name": "Longbow", "to_hit": 3, "damage": "1d8+1"},
+                {"name": "Handaxe", "to_hit": 5, "damage": "1d6+3"}
+            ],"#,
+            1,
+        );
+        let character: Character = serde_json::from_str(&with_attacks).unwrap();
+        assert_eq!(character.attack("longsword").unwrap().to_hit, 5);
+        assert_eq!(character.attack("HAND").unwrap().name, "Handaxe");
+        assert_eq!(character.attack("long"), None);
+        assert_eq!(character.attack("dagger"), None);
+    }
+
+    #[test]
+    fn computes_skill_modifiers_from_proficiencies() {
+        let start = CHARACTER.find(r#""skill_modifiers""#).unwrap();
+        let end = CHARACTER.find(r#""initiative_modifier""#).unwrap();
+        let proficient = format!(
+            r#"{}"skill_modifiers": {{"proficient": ["athletics", "Animal Handling"], "expertise": ["sleight_of_hand"]}}, "proficiency_bonus": 3, {}"#,
+            &CHARACTER[..start],
+            &CHARACTER[end..]
+        );
+        let character: Character = serde_json::from_str(&proficient).unwrap();
+        character.validate().unwrap();
+        assert_eq!(character.skill_modifier(Skill::Athletics), 6);
+        assert_eq!(character.skill_modifier(Skill::AnimalHandling), 3);
+        assert_eq!(character.skill_modifier(Skill::SleightOfHand), 7);
+        assert_eq!(character.skill_modifier(Skill::Arcana), -1);
 
-        let ok = ok.into_iter().map(|r| r.unwrap()).collect();
-        let err = err.into_iter().map(|r| r.unwrap_err()).collect();
+        let missing_bonus = proficient.replace(r#""proficiency_bonus": 3,"#, "");
+        let character: Character = serde_json::from_str(&missing_bonus).unwrap();
+        assert!(character.validate().is_err());
+    }
+
+    #[test]
+    fn parses_ability_and_skill_names() {
+        assert_eq!("dex".parse(), Ok(Ability::Dexterity));
+        assert_eq!("Wisdom".parse(), Ok(Ability::Wisdom));
+        assert!("luck".parse::<Ability>().is_err());
+        assert_eq!("Sleight of Hand".parse(), Ok(Skill::SleightOfHand));
+        assert_eq!("animal_handling".parse(), Ok(Skill::AnimalHandling));
+        assert_eq!("PERCEPTION".parse(), Ok(Skill::Perception));
+    }
 
-        Ok((ok, err))
+    #[test]
+    fn schema_describes_character_data() {
+        let schema = serde_json::to_value(schemars::schema_for!(Character)).unwrap();
+        let required = schema["required"].as_array().unwrap();
+        assert!(required.contains(&"skill_modifiers".into()));
+        assert!(schema["properties"]["armor_class"].is_object());
+        let skills = schema["$defs"]["Skill"]["enum"].as_array().unwrap();
+        assert_eq!(skills.len(), Skill::ALL.len());
+        assert!(skills.contains(&"Sleight of Hand".into()));
     }
 }
@@ -0,0 +1,221 @@
+//! Downtime activities such as crafting, a series of checks rolled over several days until
+//! enough of them succeed or too many fail.
+
+use serde::{Deserialize, Serialize};
+use teloxide::utils::html;
+
+use crate::dice::{Flags, RollResults, RollSettings, RollType};
+use crate::dnd::Character;
+use crate::groupcheck::Check;
+use crate::rest::with_character;
+use crate::storage::{CharacterState, Store};
+use crate::transport::{ChatTransport, Incoming};
+
+/// Most successes or failures a series can call for
+const MAX_CHECKS: u8 = 50;
+
+/// A series of checks against the same DC
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct Series {
+    /// The check or activity, such as `Smith's Tools`
+    pub task: String,
+    pub modifier: i8,
+    pub dc: i64,
+    pub successes_needed: u8,
+    /// Failures that end the series
+    pub failures_allowed: u8,
+    #[serde(default)]
+    pub successes: u8,
+    #[serde(default)]
+    pub failures: u8,
+}
+
+impl Series {
+    /// A series like `Smith's Tools +5 DC 15 5/3`, needing 5 successes before 3 failures.
+    /// Without a modifier, the task is taken for a skill or ability of the character.
+    fn parse(input: &str, character: &Character) -> Option<Self> {
+        let words: Vec<_> = input.split_whitespace().collect();
+        let (counts, words) = match words.split_last()?.0.split_once('/') {
+            Some(counts) => (Some(counts), &words[..words.len() - 1]),
+            None => (None, &words[..]),
+        };
+        let (successes_needed, failures_allowed) = match counts {
+            Some((successes, failures)) => (successes.parse().ok()?, failures.parse().ok()?),
+            None => (3, 3),
+        };
+        let [words @ .., dc_word, dc] = words else {
+            return None;
+        };
+        if !dc_word.eq_ignore_ascii_case("dc") {
+            return None;
+        }
+        let dc = dc.parse().ok()?;
+        let (modifier, words) = match words.split_last() {
+            Some((modifier, words)) if modifier.starts_with(['+', '-']) => {
+                (Some(modifier.parse().ok()?), words)
+            }
+            _ => (None, words),
+        };
+        let task = words.join(" ");
+        let modifier = match modifier {
+            Some(modifier) => modifier,
+            None => Check::parse(&task)?.modifier(character),
+        };
+        let counts = 1..=MAX_CHECKS;
+        match !task.is_empty()
+            && counts.contains(&successes_needed)
+            && counts.contains(&failures_allowed)
+        {
+            true => Some(Series {
+                task,
+                modifier,
+                dc,
+                successes_needed,
+                failures_allowed,
+                successes: 0,
+                failures: 0,
+            }),
+            false => None,
+        }
+    }
+
+    fn progress(&self) -> String {
+        format!(
+            "{} DC {}: {}/{} successes, {}/{} failures",
+            html::escape(&self.task),
+            self.dc,
+            self.successes,
+            self.successes_needed,
+            self.failures,
+            self.failures_allowed
+        )
+    }
+
+    fn done(&self) -> bool {
+        self.successes >= self.successes_needed || self.failures >= self.failures_allowed
+    }
+}
+
+/// Roll the next check of the series of the character, ending it when it is done
+fn roll(name: &str, state: &mut CharacterState) -> String {
+    let Some(ref mut series) = state.downtime else {
+        return format!("{} has no downtime activity. {}", name, USAGE);
+    };
+    let settings = RollSettings {
+        number: 1,
+        sides: 20,
+        modifier: Some(series.modifier as i32),
+        label: None,
+        target: Some(series.dc),
+        keep: None,
+        explode: None,
+        success: None,
+        composite: false,
+        multiplier: None,
+        flags: Flags::NONE,
+    };
+    let results = RollResults::new(&settings, &RollType::Straight);
+    let roll = &results.result;
+    match results.degree().is_some_and(|degree| degree.passed()) {
+        true => series.successes += 1,
+        false => series.failures += 1,
+    }
+    let mut text = format!(
+        "🛠 {}: {} = <b>{}</b>\n{}",
+        name,
+        roll.format_roll(Some(100)),
+        roll.total,
+        series.progress()
+    );
+    if series.done() {
+        text.push_str(match series.successes >= series.successes_needed {
+            true => "\n🎉 The activity is complete.",
+            false => "\n💥 The activity has failed.",
+        });
+        state.downtime = None;
+    }
+    text
+}
+
+const USAGE: &str = "<code>/downtime start Smith's Tools +5 DC 15 5/3</code> starts an activity needing 5 successes before 3 failures
+<code>/downtime start arcana DC 12</code> uses the modifier of your character, needing 3 successes before 3 failures
+<code>/downtime roll</code> rolls the check of the next day
+<code>/downtime</code> shows the progress
+<code>/downtime cancel</code> gives the activity up";
+
+pub(crate) async fn handle_downtime(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let Some(user) = &msg.from else {
+        return Ok(());
+    };
+    let (chat_id, user_id) = (msg.chat_id, user.id);
+    let input = input.trim();
+    let (action, rest) = input.split_once(' ').unwrap_or((input, ""));
+    let text = match action {
+        "" | "start" | "roll" | "cancel" => {
+            store
+                .update(|storage| {
+                    with_character(storage, chat_id, user_id, |character, state| {
+                        let name = html::escape(&character.name);
+                        match (action, state.downtime.as_ref()) {
+                            ("start", _) => match Series::parse(rest, character) {
+                                Some(series) => {
+                                    let text = format!("🛠 {} starts {}", name, series.progress());
+                                    state.downtime = Some(series);
+                                    text
+                                }
+                                None => USAGE.to_string(),
+                            },
+                            ("roll", _) => roll(&name, state),
+                            (_, None) => format!("{} has no downtime activity.", name),
+                            ("cancel", Some(_)) => {
+                                state.downtime = None;
+                                format!("{} gives the activity up.", name)
+                            }
+                            (_, Some(series)) => format!("🛠 {}: {}", name, series.progress()),
+                        }
+                    })
+                })
+                .await?
+        }
+        _ => USAGE.to_string(),
+    };
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_a_series() {
+        let mut character = crate::testing::character("Aria");
+        character.attribute_modifiers.intelligence = 3;
+        let series = Series::parse("Smith's Tools +50 DC 15 2/3", &character).unwrap();
+        assert_eq!(
+            (series.task.as_str(), series.modifier, series.dc),
+            ("Smith's Tools", 50, 15)
+        );
+        assert_eq!(
+            Series::parse("arcana dc 12", &character).map(|series| series.modifier),
+            Some(3)
+        );
+        assert_eq!(Series::parse("Smith's Tools DC 15", &character), None);
+        assert_eq!(Series::parse("arcana DC 12 0/3", &character), None);
+
+        let mut state = CharacterState {
+            downtime: Some(series),
+            ..Default::default()
+        };
+        assert!(roll("Aria", &mut state).ends_with("1/2 successes, 0/3 failures"));
+        assert!(roll("Aria", &mut state).ends_with("The activity is complete."));
+        assert_eq!(state.downtime, None);
+    }
+}
@@ -0,0 +1,408 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_aux::field_attributes::deserialize_number_from_string;
+use teloxide::utils::html;
+
+use crate::combat::Combatant;
+use crate::dice::{Flags, RollResults, RollSettings, RollType};
+use crate::storage::Store;
+use crate::transport::{ChatTransport, Incoming};
+
+/// Upper bound on the number of monsters a single group can produce
+const MAX_GROUP_SIZE: i64 = 50;
+
+/// A table of encounters for some terrains and party levels
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct EncounterTable {
+    pub name: String,
+    pub terrain: Vec<String>,
+    #[serde(default)]
+    pub min_level: Option<u8>,
+    #[serde(default)]
+    pub max_level: Option<u8>,
+    pub encounters: Vec<Encounter>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct Encounter {
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    pub description: String,
+    #[serde(default)]
+    pub monsters: Vec<MonsterGroup>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct MonsterGroup {
+    pub name: String,
+    #[serde(default)]
+    pub count: Count,
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub initiative_modifier: i8,
+}
+
+/// How many monsters show up: either a fixed number or a roll like `2d4`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(untagged)]
+pub enum Count {
+    Fixed(u32),
+    Roll(String),
+}
+
+impl Default for Count {
+    fn default() -> Self {
+        Count::Fixed(1)
+    }
+}
+
+impl Count {
+    fn validate(&self) -> anyhow::Result<()> {
+        let max = match self {
+            Count::Fixed(number) => *number as i64,
+            Count::Roll(expression) => {
+                let settings = RollSettings::from_str(expression)?;
+                crate::expr::DiceExpr::from(&settings).bounds()?.1
+            }
+        };
+        if max > MAX_GROUP_SIZE {
+            bail!("at most {} monsters are allowed in a group", MAX_GROUP_SIZE);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn roll(&self) -> u32 {
+        match self {
+            Count::Fixed(number) => *number,
+            Count::Roll(expression) => {
+                let settings = RollSettings::from_str(expression).expect("to be validated");
+                let results = RollResults::new(&settings, &RollType::Straight);
+                results.result.total.clamp(0, MAX_GROUP_SIZE) as u32
+            }
+        }
+    }
+}
+
+impl EncounterTable {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self
+            .encounters
+            .iter()
+            .all(|encounter| encounter.weight == 0)
+        {
+            bail!("table needs at least one encounter with a non-zero weight");
+        }
+        for encounter in &self.encounters {
+            for group in &encounter.monsters {
+                group
+                    .count
+                    .validate()
+                    .with_context(|| format!("invalid count for {}", group.name))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn matches(&self, terrain: &str, level: Option<u8>) -> bool {
+        let terrain_matches = self
+            .terrain
+            .iter()
+            .any(|candidate| candidate.eq_ignore_ascii_case(terrain));
+        let level_matches = match level {
+            None => true,
+            Some(level) => {
+                self.min_level.is_none_or(|min| level >= min)
+                    && self.max_level.is_none_or(|max| level <= max)
+            }
+        };
+        terrain_matches && level_matches
+    }
+}
+
+/// Pick an encounter, weighted across all tables that match the terrain and level
+pub(crate) fn pick<'a, R: Rng>(
+    tables: &'a [EncounterTable],
+    terrain: &str,
+    level: Option<u8>,
+    rng: &mut R,
+) -> Option<(&'a EncounterTable, &'a Encounter)> {
+    let candidates: Vec<_> = tables
+        .iter()
+        .filter(|table| table.matches(terrain, level))
+        .flat_map(|table| table.encounters.iter().map(move |e| (table, e)))
+        .collect();
+    let weights = WeightedIndex::new(candidates.iter().map(|(_, e)| e.weight)).ok()?;
+    Some(candidates[weights.sample(rng)])
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(EncounterTable),
+    Many(Vec<EncounterTable>),
+}
+
+fn parse_tables(contents: &[u8]) -> anyhow::Result<Vec<EncounterTable>> {
+    let tables = match serde_json::from_slice(contents)
+        .map_err(|_| anyhow!("not a valid encounter table or list of encounter tables"))?
+    {
+        OneOrMany::One(table) => vec![table],
+        OneOrMany::Many(tables) => tables,
+    };
+    for table in &tables {
+        table
+            .validate()
+            .with_context(|| format!("invalid table {}", table.name))?;
+    }
+    Ok(tables)
+}
+
+const USAGE: &str = "<code>/encounter random forest [level] [init]</code> picks an encounter, optionally adding the monsters to /combat
+<code>/encounter list</code> lists the encounter tables of this chat
+<code>/encounter upload</code> as a reply to a JSON file adds or replaces tables
+<code>/encounter remove name</code> removes a table";
+
+pub(crate) async fn handle_encounter(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let mut args = input.split_whitespace();
+    let text = match args.next() {
+        Some("random") => {
+            let Some(terrain) = args.next() else {
+                bot.reply(msg, USAGE.to_string(), vec![]).await?;
+                return Ok(());
+            };
+            let mut level = None;
+            let mut initiative = false;
+            for arg in args {
+                match arg {
+                    "init" | "initiative" => initiative = true,
+                    arg => level = arg.parse().ok().or(level),
+                }
+            }
+            store
+                .update(|storage| random_encounter(storage, chat_id, terrain, level, initiative))
+                .await?
+        }
+        Some("list") => {
+            store
+                .read(|storage| {
+                    let tables = storage
+                        .chat(chat_id)
+                        .map(|chat| chat.encounter_tables.as_slice())
+                        .unwrap_or_default();
+                    if tables.is_empty() {
+                        return "This chat has no encounter tables.".to_string();
+                    }
+                    tables
+                        .iter()
+                        .map(|table| {
+                            format!(
+                                "• <b>{}</b>: {} ({} encounters)",
+                                html::escape(&table.name),
+                                html::escape(&table.terrain.join(", ")),
+                                table.encounters.len()
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                })
+                .await
+        }
+        Some("upload") if crate::auth::is_chat_admin(bot, msg).await? => {
+            match crate::upload::replied_document(bot, msg).await? {
+                Err(e) => e.to_string(),
+                Ok(contents) => match parse_tables(&contents) {
+                    Err(e) => format!("<code>{}</code>", html::escape(&format!("{:#}", e))),
+                    Ok(tables) => {
+                        let names = tables
+                            .iter()
+                            .map(|table| table.name.clone())
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        store
+                            .update(|storage| {
+                                let existing = &mut storage.chat_mut(chat_id).encounter_tables;
+                                for table in tables {
+                                    existing.retain(|t| t.name != table.name);
+                                    existing.push(table);
+                                }
+                            })
+                            .await?;
+                        format!("Saved encounter tables: {}", html::escape(&names))
+                    }
+                },
+            }
+        }
+        Some("remove") if crate::auth::is_chat_admin(bot, msg).await? => {
+            let name = args.collect::<Vec<_>>().join(" ");
+            let removed = store
+                .update(|storage| {
+                    let tables = &mut storage.chat_mut(chat_id).encounter_tables;
+                    let before = tables.len();
+                    tables.retain(|table| table.name != name);
+                    tables.len() != before
+                })
+                .await?;
+            if removed {
+                format!("Removed encounter table {}", html::escape(&name))
+            } else {
+                format!("There is no encounter table named {}", html::escape(&name))
+            }
+        }
+        Some("upload") | Some("remove") => {
+            "Only chat administrators can change encounter tables.".to_string()
+        }
+        _ => USAGE.to_string(),
+    };
+
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+fn random_encounter(
+    storage: &mut crate::storage::Storage,
+    chat_id: i64,
+    terrain: &str,
+    level: Option<u8>,
+    initiative: bool,
+) -> String {
+    let chat = storage.chat_mut(chat_id);
+    let mut rng = rand::thread_rng();
+    let Some((table, encounter)) = pick(&chat.encounter_tables, terrain, level, &mut rng) else {
+        return format!(
+            "No encounter table matches {}{}.",
+            html::escape(terrain),
+            level
+                .map(|l| format!(" at level {}", l))
+                .unwrap_or_default()
+        );
+    };
+
+    let mut text = format!(
+        "🗺️ <b>{}</b>\n{}",
+        html::escape(&table.name),
+        html::escape(&encounter.description)
+    );
+    let mut combatants = vec![];
+    for group in &encounter.monsters {
+        let count = group.count.roll();
+        text.push_str(&format!("\n• {} × {}", count, html::escape(&group.name)));
+        if initiative {
+            let settings = RollSettings {
+                number: 1,
+                sides: 20,
+                modifier: Some(group.initiative_modifier as i32),
+                label: None,
+                target: None,
+                keep: None,
+                explode: None,
+                success: None,
+                composite: false,
+                multiplier: None,
+                flags: Flags::NONE,
+            };
+            for index in 1..=count {
+                let name = if count > 1 {
+                    format!("{} {}", group.name, index)
+                } else {
+                    group.name.clone()
+                };
+                let results = RollResults::new(&settings, &RollType::Straight);
+                combatants.push(Combatant {
+                    name,
+                    initiative: results.result.total,
+                    armor_class: None,
+                    hit_points: None,
+                });
+            }
+        }
+    }
+
+    if initiative {
+        let added = combatants.len();
+        for combatant in combatants {
+            chat.combat.add(combatant);
+        }
+        text.push_str(&format!("\n\nAdded {} combatants to /combat", added));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(name: &str, terrain: &[&str], min: Option<u8>, max: Option<u8>) -> EncounterTable {
+        EncounterTable {
+            name: name.to_string(),
+            terrain: terrain.iter().map(ToString::to_string).collect(),
+            min_level: min,
+            max_level: max,
+            encounters: vec![Encounter {
+                weight: 1,
+                description: name.to_string(),
+                monsters: vec![],
+            }],
+        }
+    }
+
+    #[test]
+    fn only_matching_tables_are_picked() {
+        let tables = [
+            table("Low forest", &["forest"], None, Some(4)),
+            table("High forest", &["Forest", "jungle"], Some(5), None),
+            table("Swamp", &["swamp"], None, None),
+        ];
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..20 {
+            let (table, _) = pick(&tables, "forest", Some(3), &mut rng).unwrap();
+            assert_eq!(table.name, "Low forest");
+            let (table, _) = pick(&tables, "FOREST", Some(7), &mut rng).unwrap();
+            assert_eq!(table.name, "High forest");
+        }
+        assert!(pick(&tables, "desert", None, &mut rng).is_none());
+    }
+
+    #[test]
+    fn parses_tables_and_counts() {
+        let json = r#"{
+            "name": "Forest",
+            "terrain": ["forest"],
+            "encounters": [
+                {"description": "Wolves", "monsters": [{"name": "Wolf", "count": "1d4+1", "initiative_modifier": "+2"}]},
+                {"weight": 3, "description": "Nothing happens"}
+            ]
+        }"#;
+        let tables = parse_tables(json.as_bytes()).unwrap();
+        assert_eq!(tables.len(), 1);
+        let wolves = &tables[0].encounters[0].monsters[0];
+        assert_eq!(wolves.count, Count::Roll("1d4+1".to_string()));
+        assert_eq!(wolves.initiative_modifier, 2);
+        assert_eq!(tables[0].encounters[1].weight, 3);
+
+        let too_many = r#"[{"name": "Horde", "terrain": ["plains"], "encounters": [
+            {"description": "Orcs", "monsters": [{"name": "Orc", "count": "10d20"}]}
+        ]}]"#;
+        assert!(parse_tables(too_many.as_bytes()).is_err());
+    }
+}
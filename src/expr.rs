@@ -0,0 +1,251 @@
+//! The parsed form of a roll as a tree, for code that inspects rolls without parsing strings again.
+
+use crate::dice::{DiceError, Keep, RollSettings, MAX_EXPLOSIONS};
+
+/// An expression of dice and numbers, such as `2d6 + 3`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) enum DiceExpr {
+    /// `number` dice of `sides` sides, summed, or only those kept
+    Dice {
+        number: u32,
+        sides: u32,
+        /// Sides from which a die explodes
+        explode: Option<u32>,
+        keep: Option<Keep>,
+        /// Sides from which a die is a success, when the successes are counted
+        success: Option<u32>,
+        /// Read digit by digit, as `d66` is
+        composite: bool,
+    },
+    Constant(i64),
+    Add(Box<DiceExpr>, Box<DiceExpr>),
+    /// The total of the expression, multiplied
+    Multiply(u32, Box<DiceExpr>),
+}
+
+/// The canonical form, such as `2d6 + 3`, `1d20 - 1` or `2x(1d6 + 2)`
+impl std::fmt::Display for DiceExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiceExpr::Dice {
+                number,
+                sides,
+                explode,
+                keep,
+                success,
+                composite: _,
+            } => {
+                write!(f, "{}d{}", number, sides)?;
+                match explode {
+                    Some(threshold) if threshold == sides => write!(f, "!")?,
+                    Some(threshold) => write!(f, "!>={}", threshold)?,
+                    None => {}
+                }
+                if let Some(keep) = keep {
+                    write!(f, "{}", keep)?;
+                }
+                match success {
+                    Some(threshold) => write!(f, ">={}", threshold),
+                    None => Ok(()),
+                }
+            }
+            DiceExpr::Constant(value) => write!(f, "{}", value),
+            DiceExpr::Add(left, right) => match **right {
+                DiceExpr::Constant(value) if value < 0 => write!(f, "{} - {}", left, -value),
+                _ => write!(f, "{} + {}", left, right),
+            },
+            DiceExpr::Multiply(factor, expr) => write!(f, "{}x({})", factor, expr),
+        }
+    }
+}
+
+/// Computes something from an expression, bottom up
+pub(crate) trait Visitor {
+    type Output;
+
+    fn dice(
+        &mut self,
+        number: u32,
+        sides: u32,
+        explode: Option<u32>,
+        keep: Option<Keep>,
+        success: Option<u32>,
+        composite: bool,
+    ) -> Self::Output;
+
+    fn constant(&mut self, value: i64) -> Self::Output;
+
+    fn add(&mut self, left: Self::Output, right: Self::Output) -> Self::Output;
+
+    fn multiply(&mut self, factor: u32, expr: Self::Output) -> Self::Output;
+}
+
+impl DiceExpr {
+    pub(crate) fn visit<V: Visitor>(&self, visitor: &mut V) -> V::Output {
+        match self {
+            DiceExpr::Dice {
+                number,
+                sides,
+                explode,
+                keep,
+                success,
+                composite,
+            } => visitor.dice(*number, *sides, *explode, *keep, *success, *composite),
+            DiceExpr::Constant(value) => visitor.constant(*value),
+            DiceExpr::Add(left, right) => {
+                let left = left.visit(visitor);
+                let right = right.visit(visitor);
+                visitor.add(left, right)
+            }
+            DiceExpr::Multiply(factor, expr) => {
+                let expr = expr.visit(visitor);
+                visitor.multiply(*factor, expr)
+            }
+        }
+    }
+
+    /// Lowest and highest total the expression can come up with
+    pub(crate) fn bounds(&self) -> Result<(i64, i64), DiceError> {
+        self.visit(&mut Bounds).ok_or(DiceError::TooLarge)
+    }
+}
+
+impl From<&RollSettings> for DiceExpr {
+    fn from(settings: &RollSettings) -> Self {
+        let dice = DiceExpr::Dice {
+            number: settings.number,
+            sides: settings.sides,
+            explode: settings.explode,
+            keep: settings.keep,
+            success: settings.success,
+            composite: settings.composite,
+        };
+        let expr = match settings.modifier {
+            None => dice,
+            Some(modifier) => DiceExpr::Add(
+                Box::new(dice),
+                Box::new(DiceExpr::Constant(modifier as i64)),
+            ),
+        };
+        match settings.multiplier {
+            None => expr,
+            Some(factor) => DiceExpr::Multiply(factor, Box::new(expr)),
+        }
+    }
+}
+
+/// Lowest and highest totals, or nothing when they do not fit in an `i64`
+pub(crate) struct Bounds;
+
+impl Visitor for Bounds {
+    type Output = Option<(i64, i64)>;
+
+    fn dice(
+        &mut self,
+        number: u32,
+        sides: u32,
+        explode: Option<u32>,
+        keep: Option<Keep>,
+        success: Option<u32>,
+        composite: bool,
+    ) -> Self::Output {
+        let kept = keep.map_or(number, |keep| keep.of(number)) as i64;
+        // Such as the 11 of `d66`, with a 1 in every place
+        let least = match composite {
+            true => (10_i64.checked_pow(sides.to_string().len() as u32)? - 1) / 9,
+            false => 1,
+        };
+        let dice = match explode {
+            Some(threshold) if threshold <= sides => kept.checked_mul(MAX_EXPLOSIONS as i64 + 1)?,
+            _ => kept,
+        };
+        match success {
+            None => Some((kept.checked_mul(least)?, dice.checked_mul(sides as i64)?)),
+            Some(_) => Some((0, dice)),
+        }
+    }
+
+    fn constant(&mut self, value: i64) -> Self::Output {
+        Some((value, value))
+    }
+
+    fn add(&mut self, left: Self::Output, right: Self::Output) -> Self::Output {
+        let (left, right) = (left?, right?);
+        Some((left.0.checked_add(right.0)?, left.1.checked_add(right.1)?))
+    }
+
+    fn multiply(&mut self, factor: u32, expr: Self::Output) -> Self::Output {
+        let (low, high) = expr?;
+        Some((
+            low.checked_mul(factor as i64)?,
+            high.checked_mul(factor as i64)?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn builds_and_visits_the_tree() {
+        let settings = RollSettings::from_str("3d6 - 2").unwrap();
+        let expr = DiceExpr::from(&settings);
+        assert_eq!(
+            expr,
+            DiceExpr::Add(
+                Box::new(DiceExpr::Dice {
+                    number: 3,
+                    sides: 6,
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    keep: None,
+                }),
+                Box::new(DiceExpr::Constant(-2))
+            )
+        );
+        assert_eq!(expr.bounds().unwrap(), (1, 16));
+        assert_eq!(expr.to_string(), "3d6 - 2");
+        assert_eq!(
+            DiceExpr::from(&RollSettings::from_str("1d20").unwrap())
+                .bounds()
+                .unwrap(),
+            (1, 20)
+        );
+
+        let expr = DiceExpr::from(&RollSettings::from_str("4d6 dl1").unwrap());
+        assert_eq!(expr.to_string(), "4d6kh3");
+        assert_eq!(expr.bounds().unwrap(), (3, 18));
+
+        let expr = DiceExpr::from(&RollSettings::from_str("1d10 ! >= 9").unwrap());
+        assert_eq!(expr.to_string(), "1d10!>=9");
+        assert_eq!(expr.bounds().unwrap(), (1, 210));
+        let expr = DiceExpr::from(&RollSettings::from_str("1d10!").unwrap());
+        assert_eq!(expr.to_string(), "1d10!");
+
+        let expr = DiceExpr::from(&RollSettings::from_str("d66").unwrap());
+        assert_eq!(expr.bounds().unwrap(), (11, 66));
+
+        let expr = DiceExpr::from(&RollSettings::from_str("5d10>7").unwrap());
+        assert_eq!(expr.to_string(), "5d10>=8");
+        assert_eq!(expr.bounds().unwrap(), (0, 5));
+
+        let expr = DiceExpr::from(&RollSettings::from_str("2x(1d6+2)").unwrap());
+        assert_eq!(expr.to_string(), "2x(1d6 + 2)");
+        assert_eq!(expr.bounds().unwrap(), (6, 16));
+
+        let huge = DiceExpr::Dice {
+            number: u32::MAX,
+            sides: u32::MAX,
+            explode: None,
+            keep: None,
+            success: None,
+            composite: false,
+        };
+        let expr = DiceExpr::Multiply(u32::MAX, Box::new(huge));
+        assert_eq!(expr.bounds(), Err(DiceError::TooLarge));
+    }
+}
@@ -0,0 +1,277 @@
+//! Conversion of Foundry VTT (dnd5e system) actor exports into [`Character`].
+//! Only the parts of the export that we need are described here.
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::dnd::{
+    modifier_for_score, proficiency_bonus_for_level, Ability, AttributeModifiers, Character, Skill,
+    SkillProficiencies, Skills,
+};
+
+#[derive(Deserialize, Debug)]
+struct Actor {
+    name: String,
+    /// Called `data` before Foundry v10
+    #[serde(alias = "data")]
+    system: ActorSystem,
+    #[serde(default)]
+    items: Vec<Item>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ActorSystem {
+    abilities: HashMap<String, AbilityData>,
+    #[serde(default)]
+    skills: HashMap<String, SkillData>,
+    #[serde(default)]
+    attributes: Attributes,
+}
+
+#[derive(Deserialize, Debug)]
+struct AbilityData {
+    value: i32,
+    #[serde(default)]
+    proficient: f32,
+}
+
+#[derive(Deserialize, Debug)]
+struct SkillData {
+    /// Proficiency multiplier: 0, 0.5, 1 or 2
+    #[serde(default)]
+    value: f32,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Attributes {
+    #[serde(default)]
+    ac: Option<ArmorClass>,
+    #[serde(default)]
+    hp: Option<HitPoints>,
+    #[serde(default)]
+    movement: Option<Movement>,
+    #[serde(default)]
+    init: Option<Initiative>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ArmorClass {
+    #[serde(default)]
+    flat: Option<u8>,
+}
+
+#[derive(Deserialize, Debug)]
+struct HitPoints {
+    #[serde(default)]
+    max: Option<u16>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Movement {
+    #[serde(default)]
+    walk: Option<u16>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Initiative {
+    /// Either a number or a formula string such as `"2"`
+    #[serde(default)]
+    bonus: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Item {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(alias = "data")]
+    system: Option<ItemSystem>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ItemSystem {
+    #[serde(default)]
+    levels: Option<u8>,
+}
+
+fn ability_key(ability: Ability) -> &'static str {
+    match ability {
+        Ability::Strength => "str",
+        Ability::Dexterity => "dex",
+        Ability::Constitution => "con",
+        Ability::Intelligence => "int",
+        Ability::Wisdom => "wis",
+        Ability::Charisma => "cha",
+    }
+}
+
+fn skill_key(skill: Skill) -> &'static str {
+    match skill {
+        Skill::Acrobatics => "acr",
+        Skill::AnimalHandling => "ani",
+        Skill::Arcana => "arc",
+        Skill::Athletics => "ath",
+        Skill::Deception => "dec",
+        Skill::History => "his",
+        Skill::Insight => "ins",
+        Skill::Intimidation => "itm",
+        Skill::Investigation => "inv",
+        Skill::Medicine => "med",
+        Skill::Nature => "nat",
+        Skill::Perception => "prc",
+        Skill::Performance => "prf",
+        Skill::Persuasion => "per",
+        Skill::Religion => "rel",
+        Skill::SleightOfHand => "slt",
+        Skill::Stealth => "ste",
+        Skill::Survival => "sur",
+    }
+}
+
+/// Whether the JSON looks like a Foundry VTT actor export rather than our own format
+pub fn is_foundry_export(value: &serde_json::Value) -> bool {
+    let system = value.get("system").or_else(|| value.get("data"));
+    system.and_then(|system| system.get("abilities")).is_some()
+}
+
+pub fn convert(json: &[u8]) -> anyhow::Result<Character> {
+    let actor: Actor = serde_json::from_slice(json).context("not a Foundry VTT actor export")?;
+    let system = &actor.system;
+
+    let level = actor
+        .items
+        .iter()
+        .filter(|item| item.kind == "class")
+        .filter_map(|item| item.system.as_ref().and_then(|system| system.levels))
+        .fold(0u8, |level, levels| level.saturating_add(levels))
+        .clamp(1, 20);
+    let proficiency_bonus = proficiency_bonus_for_level(level);
+
+    let ability = |ability: Ability| {
+        system
+            .abilities
+            .get(ability_key(ability))
+            .with_context(|| format!("missing {} score", ability))
+    };
+    let attributes =
+        AttributeModifiers::try_from_fn(|a| anyhow::Ok(modifier_for_score(ability(a)?.value)))?;
+    let saves = AttributeModifiers::try_from_fn(|a| {
+        let bonus = if ability(a)?.proficient >= 1.0 {
+            proficiency_bonus
+        } else {
+            0
+        };
+        anyhow::Ok(attributes.get(a).saturating_add(bonus))
+    })?;
+
+    let mut proficiencies = SkillProficiencies::default();
+    for skill in Skill::ALL {
+        let multiplier = system
+            .skills
+            .get(skill_key(skill))
+            .map(|skill| skill.value)
+            .unwrap_or_default();
+        if multiplier >= 2.0 {
+            proficiencies.expertise.push(skill);
+        } else if multiplier >= 1.0 {
+            proficiencies.proficient.push(skill);
+        }
+    }
+
+    let attributes_data = &system.attributes;
+    let initiative_bonus = attributes_data
+        .init
+        .as_ref()
+        .and_then(|init| init.bonus.as_ref())
+        .and_then(|bonus| match bonus {
+            serde_json::Value::Number(number) => number.as_i64(),
+            serde_json::Value::String(string) => string.trim().parse().ok(),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    let mut character = Character {
+        name: actor.name.clone(),
+        initiative_modifier: attributes
+            .get(Ability::Dexterity)
+            .saturating_add(initiative_bonus.clamp(-128, 127) as i8),
+        attribute_modifiers: attributes,
+        saving_throw_modifiers: saves,
+        skills: Skills::Proficiencies(proficiencies),
+        // Only a flat armor class is stored; everything else is derived from items
+        armor_class: attributes_data.ac.as_ref().and_then(|ac| ac.flat),
+        max_hit_points: attributes_data.hp.as_ref().and_then(|hp| hp.max),
+        speed: attributes_data
+            .movement
+            .as_ref()
+            .and_then(|movement| movement.walk),
+        proficiency_bonus: Some(proficiency_bonus),
+        passive_perception: None,
+        spell_save_dc: None,
+        attacks: vec![],
+        hit_dice: None,
+        spell_slots: vec![],
+        resources: vec![],
+        player: None,
+    };
+    let perception = character.skill_modifier(Skill::Perception) as i32 + 10;
+    character.passive_perception = u8::try_from(perception).ok();
+    Ok(character)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORT: &str = r#"{
+        "name": "Brom",
+        "type": "character",
+        "system": {
+            "abilities": {
+                "str": {"value": 16, "proficient": 1}, "dex": {"value": 12, "proficient": 0},
+                "con": {"value": 15, "proficient": 1}, "int": {"value": 9, "proficient": 0},
+                "wis": {"value": 11, "proficient": 0}, "cha": {"value": 8, "proficient": 0}
+            },
+            "skills": {
+                "ath": {"value": 1, "ability": "str"}, "prc": {"value": 2, "ability": "wis"},
+                "ste": {"value": 0.5, "ability": "dex"}
+            },
+            "attributes": {
+                "ac": {"flat": 18, "calc": "flat"},
+                "hp": {"value": 20, "max": 44},
+                "movement": {"walk": 25, "units": "ft"},
+                "init": {"ability": "", "bonus": "2"}
+            }
+        },
+        "items": [
+            {"name": "Fighter", "type": "class", "system": {"levels": 3}},
+            {"name": "Cleric", "type": "class", "system": {"levels": 2}},
+            {"name": "Longsword", "type": "weapon", "system": {}}
+        ]
+    }"#;
+
+    #[test]
+    fn converts_foundry_export() {
+        let value: serde_json::Value = serde_json::from_str(EXPORT).unwrap();
+        assert!(is_foundry_export(&value));
+        assert!(!crate::ddb::is_ddb_export(&value));
+
+        let character = convert(EXPORT.as_bytes()).unwrap();
+        character.validate().unwrap();
+        assert_eq!(character.name, "Brom");
+        assert_eq!(character.attribute_modifiers.strength, 3);
+        assert_eq!(character.attribute_modifiers.intelligence, -1);
+        assert_eq!(character.proficiency_bonus, Some(3));
+        assert_eq!(character.saving_throw_modifiers.strength, 6);
+        assert_eq!(character.saving_throw_modifiers.dexterity, 1);
+        assert_eq!(character.skill_modifier(Skill::Athletics), 6);
+        assert_eq!(character.skill_modifier(Skill::Perception), 6);
+        assert_eq!(character.skill_modifier(Skill::Stealth), 1);
+        assert_eq!(character.initiative_modifier, 3);
+        assert_eq!(character.armor_class, Some(18));
+        assert_eq!(character.max_hit_points, Some(44));
+        assert_eq!(character.speed, Some(25));
+        assert_eq!(character.passive_perception, Some(16));
+    }
+}
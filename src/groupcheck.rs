@@ -0,0 +1,254 @@
+//! One check for every character in the chat, such as `/groupcheck perception dc15`.
+
+use teloxide::utils::html;
+
+use crate::dice::{Flags, RollResults, RollSettings, RollType};
+use crate::dnd::{Ability, Character, Skill};
+use crate::storage::{Storage, Store};
+use crate::transport::{ChatTransport, Incoming};
+
+/// Remember who uses the bot in a chat, whose characters are in its group checks,
+/// and the usernames of users with characters
+pub(crate) async fn remember_member(store: &Store, msg: &Incoming) -> anyhow::Result<()> {
+    let Some(user) = &msg.from else {
+        return Ok(());
+    };
+    let (chat_id, user_id) = (msg.chat_id, user.id);
+    let username = user.username.clone();
+    let (known, renamed) = store
+        .read(|storage| {
+            let known = storage
+                .chat(chat_id)
+                .is_some_and(|chat| chat.members.contains(&user_id));
+            let renamed = storage
+                .user(user_id)
+                .is_some_and(|user| user.username != username);
+            (known, renamed)
+        })
+        .await;
+    if !known || renamed {
+        store
+            .update(|storage| {
+                storage.chat_mut(chat_id).members.insert(user_id);
+                if renamed {
+                    storage.user_mut(user_id).username = username;
+                }
+            })
+            .await?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Check {
+    Skill(Skill),
+    Ability(Ability),
+}
+
+impl Check {
+    pub(crate) fn parse(input: &str) -> Option<Self> {
+        input
+            .parse()
+            .map(Check::Skill)
+            .or_else(|_| input.parse().map(Check::Ability))
+            .ok()
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Check::Skill(skill) => skill.name(),
+            Check::Ability(ability) => ability.name(),
+        }
+    }
+
+    pub(crate) fn modifier(&self, character: &Character) -> i8 {
+        match self {
+            Check::Skill(skill) => character.skill_modifier(*skill),
+            Check::Ability(ability) => *character.attribute_modifiers.get(*ability),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Request<'a> {
+    check: Check,
+    dc: Option<i64>,
+    roll_type: RollType,
+    /// Only these characters, or everyone's default character when empty
+    names: Vec<&'a str>,
+}
+
+fn parse_request(input: &str) -> Option<Request<'_>> {
+    let mut args = input.split_whitespace();
+    let check = Check::parse(args.next()?)?;
+    let mut request = Request {
+        check,
+        dc: None,
+        roll_type: RollType::Straight,
+        names: vec![],
+    };
+    for arg in args {
+        let lower = arg.to_lowercase();
+        match lower.as_str() {
+            "adv" | "advantage" => request.roll_type = RollType::Advantage,
+            "dis" | "disadvantage" => request.roll_type = RollType::Disadvantage,
+            dc => match dc.trim_start_matches("dc").parse() {
+                Ok(dc) => request.dc = Some(dc),
+                Err(_) => request.names.push(arg),
+            },
+        }
+    }
+    Some(request)
+}
+
+/// The characters of the members of the chat, or the named characters of anyone in it
+pub(crate) fn characters<'a>(
+    storage: &'a Storage,
+    chat_id: i64,
+    names: &[&str],
+) -> Vec<&'a Character> {
+    let members = storage
+        .chat(chat_id)
+        .map(|chat| chat.members.iter().filter_map(|id| storage.user(*id)))
+        .into_iter()
+        .flatten();
+    let mut characters: Vec<&Character> = if names.is_empty() {
+        members
+            .filter_map(|user| user.character(chat_id, None))
+            .collect()
+    } else {
+        members
+            .flat_map(|user| user.characters.values())
+            .filter(|character| {
+                names
+                    .iter()
+                    .any(|name| character.name.eq_ignore_ascii_case(name))
+            })
+            .collect()
+    };
+    characters.sort_by(|a, b| a.name.cmp(&b.name));
+    characters
+}
+
+const USAGE: &str = "<code>/groupcheck perception dc15</code> rolls perception for the characters of everyone who used the bot in this chat
+<code>/groupcheck stealth 12 adv Aria Brom</code> rolls only for the named characters
+Skills and abilities can be checked.";
+
+fn group_check(characters: &[&Character], request: &Request) -> String {
+    if characters.is_empty() {
+        return "Nobody in this chat has a character. Upload one with /sheet upload.".to_string();
+    }
+    let mut text = format!("👥 <b>{}</b>", request.check.name());
+    if let Some(dc) = request.dc {
+        text.push_str(&format!(" DC {}", dc));
+    }
+    if request.roll_type != RollType::Straight {
+        text.push_str(&format!(" with <i>{}</i>", request.roll_type));
+    }
+
+    let mut passed = 0;
+    for character in characters {
+        let settings = RollSettings {
+            number: 1,
+            sides: 20,
+            modifier: Some(request.check.modifier(character) as i32),
+            label: None,
+            target: None,
+            keep: None,
+            explode: None,
+            success: None,
+            composite: false,
+            multiplier: None,
+            flags: Flags::NONE,
+        };
+        let results = RollResults::new(&settings, &request.roll_type);
+        let roll = &results.result;
+        let mark = match request.dc {
+            Some(dc) if roll.total >= dc => {
+                passed += 1;
+                "✅ "
+            }
+            Some(_) => "❌ ",
+            None => "• ",
+        };
+        text.push_str(&format!(
+            "\n{}{}: {} = <b>{}</b>",
+            mark,
+            html::escape(&character.name),
+            roll.format_roll(Some(100)),
+            roll.total
+        ));
+    }
+    if request.dc.is_some() {
+        text.push_str(&format!("\n\n{} of {} passed", passed, characters.len()));
+    }
+    text
+}
+
+pub(crate) async fn handle_groupcheck(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let text = match parse_request(input) {
+        None => USAGE.to_string(),
+        Some(request) => {
+            store
+                .read(|storage| {
+                    group_check(&characters(storage, chat_id, &request.names), &request)
+                })
+                .await
+        }
+    };
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_requests() {
+        assert_eq!(
+            parse_request("perception dc15"),
+            Some(Request {
+                check: Check::Skill(Skill::Perception),
+                dc: Some(15),
+                roll_type: RollType::Straight,
+                names: vec![],
+            })
+        );
+        assert_eq!(
+            parse_request("str 12 dis Aria"),
+            Some(Request {
+                check: Check::Ability(Ability::Strength),
+                dc: Some(12),
+                roll_type: RollType::Disadvantage,
+                names: vec!["Aria"],
+            })
+        );
+        assert!(parse_request("").is_none());
+        assert!(parse_request("juggling").is_none());
+    }
+
+    #[test]
+    fn checks_against_the_dc() {
+        let mut character = crate::testing::character("Aria");
+        character.attribute_modifiers.wisdom = 30;
+        let request = parse_request("wis dc31").unwrap();
+        let text = group_check(&[&character], &request);
+        assert!(
+            text.starts_with("👥 <b>Wisdom</b> DC 31\n✅ Aria"),
+            "{}",
+            text
+        );
+        assert!(text.ends_with("1 of 1 passed"));
+
+        character.name = "Brom".to_string();
+        let request = parse_request("wis dc51").unwrap();
+        assert!(group_check(&[&character], &request).contains("❌ Brom"));
+    }
+}
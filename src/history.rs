@@ -0,0 +1,220 @@
+//! Roll history, kept by the storage backend.
+
+use chrono::{DateTime, NaiveDate, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::dice::{RollResults, RollType};
+use crate::storage::Store;
+use crate::transport::{ChatTransport, Incoming};
+
+/// A roll made in a chat
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct RollRecord {
+    pub timestamp: DateTime<Utc>,
+    pub chat_id: i64,
+    pub user_id: i64,
+    /// Character the user played in the chat at the time of the roll
+    #[serde(default)]
+    pub character: Option<String>,
+    pub expression: String,
+    pub roll_type: RollType,
+    /// Every die rolled, with the ones that did not count, such as the lower d20 of advantage
+    pub rolls: Vec<u32>,
+    /// The d20 that counted, for rolls of a single kept d20
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub natural: Option<u32>,
+    pub total: i64,
+    /// Whether the roll met its DC, for rolls made `vs` one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passed: Option<bool>,
+    /// Taken back with `/undo`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub voided: bool,
+}
+
+impl RollRecord {
+    pub fn new(
+        chat_id: i64,
+        user_id: i64,
+        character: Option<String>,
+        expression: &str,
+        results: &RollResults,
+    ) -> Self {
+        let result = &results.result;
+        RollRecord {
+            timestamp: Utc::now(),
+            chat_id,
+            user_id,
+            character,
+            expression: expression.to_string(),
+            roll_type: results.roll_type.clone(),
+            rolls: result.rolls.clone(),
+            natural: result.natural(),
+            total: result.total,
+            passed: results.degree().map(|degree| degree.passed()),
+            voided: false,
+        }
+    }
+}
+
+/// Quote a CSV field when it needs it
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Times are in the timezone of the chat
+pub fn to_csv(rolls: &[RollRecord], timezone: Tz) -> String {
+    let mut csv =
+        "timestamp,user_id,character,expression,roll_type,rolls,total,passed\n".to_string();
+    for roll in rolls {
+        let dice = roll
+            .rolls
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let fields = [
+            crate::localtime::rfc3339(roll.timestamp, timezone),
+            roll.user_id.to_string(),
+            roll.character.clone().unwrap_or_default(),
+            roll.expression.clone(),
+            roll.roll_type.to_string().to_lowercase(),
+            dice,
+            roll.total.to_string(),
+            roll.passed
+                .map(|passed| passed.to_string())
+                .unwrap_or_default(),
+        ];
+        let fields: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// When the export starts: `7d` or `12h` ago, or a date in the timezone of the chat
+fn parse_since(input: &str, timezone: Tz, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if let Some(days) = input.strip_suffix('d') {
+        return Some(now - TimeDelta::try_days(days.parse().ok()?)?);
+    }
+    if let Some(hours) = input.strip_suffix('h') {
+        return Some(now - TimeDelta::try_hours(hours.parse().ok()?)?);
+    }
+    let date = NaiveDate::parse_from_str(input, "%Y-%m-%d").ok()?;
+    timezone
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0)?)
+        .earliest()
+        .map(|since| since.with_timezone(&Utc))
+}
+
+const USAGE: &str = "<code>/exportlog</code> sends the roll history of this chat as CSV
+<code>/exportlog 7d json</code> sends the rolls of the last 7 days as JSON
+Since can be like 7d, 12h or a date like 2024-07-01.";
+
+pub(crate) async fn handle_exportlog(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let timezone = store
+        .read(|storage| crate::localtime::timezone(storage, chat_id))
+        .await;
+    let now = Utc::now();
+    let mut since = DateTime::<Utc>::MIN_UTC;
+    let mut json = false;
+    for arg in input.split_whitespace() {
+        match arg.to_lowercase().as_str() {
+            "json" => json = true,
+            "csv" => json = false,
+            arg => match parse_since(arg, timezone, now) {
+                Some(parsed) => since = parsed,
+                None => {
+                    bot.reply(msg, USAGE.to_string(), vec![]).await?;
+                    return Ok(());
+                }
+            },
+        }
+    }
+
+    let rolls = store.rolls(chat_id, since).await?;
+    if rolls.is_empty() {
+        bot.reply(msg, "There are no rolls to export.".to_string(), vec![])
+            .await?;
+        return Ok(());
+    }
+    let (contents, file_name) = match json {
+        true => (serde_json::to_vec_pretty(&rolls)?, "rolls.json"),
+        false => (to_csv(&rolls, timezone).into_bytes(), "rolls.csv"),
+    };
+    bot.reply_with_file(
+        chat_id,
+        msg.message_id,
+        file_name,
+        contents,
+        Some(format!("{} rolls", rolls.len())),
+    )
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_csv() {
+        let roll = RollRecord {
+            timestamp: Utc.with_ymd_and_hms(2024, 7, 3, 12, 0, 0).unwrap(),
+            chat_id: 1,
+            user_id: 2,
+            character: Some("Aria, \"the Bold\"".to_string()),
+            expression: "2d6 + 1".to_string(),
+            roll_type: RollType::Advantage,
+            rolls: vec![3, 4],
+            natural: None,
+            total: 8,
+            passed: None,
+            voided: false,
+        };
+        let check = RollRecord {
+            expression: "1d20 + 3 vs 15".to_string(),
+            roll_type: RollType::Straight,
+            rolls: vec![14],
+            natural: Some(14),
+            total: 17,
+            passed: Some(true),
+            ..roll.clone()
+        };
+        assert_eq!(
+            to_csv(&[roll.clone(), check], Tz::UTC),
+            "timestamp,user_id,character,expression,roll_type,rolls,total,passed\n\
+             2024-07-03T12:00:00+00:00,2,\"Aria, \"\"the Bold\"\"\",2d6 + 1,advantage,3 4,8,\n\
+             2024-07-03T12:00:00+00:00,2,\"Aria, \"\"the Bold\"\"\",1d20 + 3 vs 15,straight,14,17,true\n"
+        );
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        assert!(to_csv(&[roll], berlin).contains("\n2024-07-03T14:00:00+02:00,2,"));
+    }
+
+    #[test]
+    fn parses_since() {
+        let now = Utc.with_ymd_and_hms(2024, 7, 3, 12, 0, 0).unwrap();
+        let london: Tz = "Europe/London".parse().unwrap();
+        assert_eq!(
+            parse_since("2d", london, now),
+            Some(now - TimeDelta::days(2))
+        );
+        assert_eq!(
+            parse_since("2024-07-01", london, now),
+            Some(Utc.with_ymd_and_hms(2024, 6, 30, 23, 0, 0).unwrap())
+        );
+        assert!(parse_since("yesterday", london, now).is_none());
+    }
+}
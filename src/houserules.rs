@@ -0,0 +1,249 @@
+//! Applying the house rules of a campaign to rolls, after they are rolled and before they are shown.
+
+use rand::Rng;
+
+use crate::campaign::{Botch, CritDamage, HouseRules};
+use crate::dice::{Dice, Roll, RollResults, RollSettings};
+use crate::storage::Storage;
+
+/// The house rules of the campaign of the chat, or the rules as written without one
+pub(crate) fn for_chat(storage: &Storage, chat_id: i64) -> HouseRules {
+    storage
+        .chat(chat_id)
+        .and_then(|chat| chat.campaign.as_ref())
+        .map(|campaign| campaign.house_rules.clone())
+        .unwrap_or_default()
+}
+
+pub(crate) fn is_crit(rules: &HouseRules, natural: u32) -> bool {
+    natural >= rules.crit_range
+}
+
+/// Reroll the kept dice that came up 1, once, returning how many were rerolled
+fn reroll_ones(roll: &mut Roll, rng: &mut impl Rng) -> usize {
+    let sides = roll.settings.sides;
+    if sides < 2 {
+        return 0;
+    }
+    let mut dice = Dice::new(rng);
+    let mut rerolled = 0;
+    let kept = roll.kept();
+    for (die_roll, _) in roll
+        .rolls
+        .iter_mut()
+        .zip(kept)
+        .filter(|(die_roll, kept)| **die_roll == 1 && *kept)
+    {
+        *die_roll = dice.roll(sides);
+        rerolled += 1;
+    }
+    roll.recount();
+    rerolled
+}
+
+/// Apply the rules to the damage of an attack, rolling again with the rng it was rolled with.
+/// Returns what was applied.
+pub(crate) fn apply_damage(
+    rules: &HouseRules,
+    damage: &mut Roll,
+    rng: &mut impl Rng,
+) -> Vec<String> {
+    let mut notes = vec![];
+    if rules.reroll_damage_ones {
+        let rerolled = reroll_ones(damage, rng);
+        if rerolled > 0 {
+            notes.push(format!(
+                "🏠 Rerolled {} ones, as the house rules say",
+                rerolled
+            ));
+        }
+    }
+    notes
+}
+
+/// How a dice pool went
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum PoolOutcome {
+    Success,
+    Failure,
+    Botch,
+}
+
+impl std::fmt::Display for PoolOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PoolOutcome::Success => write!(f, "✅ <b>Success</b>"),
+            PoolOutcome::Failure => write!(f, "❌ <b>Failure</b>"),
+            PoolOutcome::Botch => write!(f, "💀 <b>Botch</b>"),
+        }
+    }
+}
+
+/// Take the 1s of a dice pool off its successes if the rules say so, and tell how the pool went.
+/// The total counts successes, so a modifier adds successes.
+fn count_pool(rules: &HouseRules, roll: &mut Roll) -> PoolOutcome {
+    if rules.botch == Botch::OnesSubtract {
+        roll.settings.to_mut().flags.ones_subtract = true;
+        roll.recount();
+    }
+    let ones = roll
+        .rolls
+        .iter()
+        .zip(roll.kept())
+        .any(|(die, kept)| *die == 1 && kept);
+    match rules.botch {
+        Botch::OnesSubtract if roll.total < 0 => PoolOutcome::Botch,
+        Botch::NoSuccesses if roll.total <= 0 && ones => PoolOutcome::Botch,
+        _ if roll.total > 0 => PoolOutcome::Success,
+        _ => PoolOutcome::Failure,
+    }
+}
+
+/// Apply the rules to a roll, which only change anything for dice pools. Returns what was applied.
+pub(crate) fn apply(rules: &HouseRules, results: &mut RollResults) -> Vec<String> {
+    results.bands = rules.degrees;
+    match results.settings.success {
+        Some(_) => vec![count_pool(rules, &mut results.result).to_string()],
+        None => vec![],
+    }
+}
+
+/// The damage dice of a critical hit
+pub(crate) fn crit_damage(rules: &HouseRules, damage: &RollSettings) -> RollSettings {
+    let mut damage = damage.clone();
+    match rules.crit_damage {
+        CritDamage::Double => damage.number *= 2,
+        CritDamage::MaxPlusRoll | CritDamage::Max => {}
+    }
+    damage
+}
+
+/// Change damage rolled for a critical hit where the dice are not simply doubled
+pub(crate) fn apply_crit(rules: &HouseRules, damage: &mut Roll) {
+    let most = damage.settings.kept() as i64 * damage.settings.sides as i64;
+    match rules.crit_damage {
+        CritDamage::Double => {}
+        CritDamage::MaxPlusRoll => {
+            let most = most.saturating_mul(damage.settings.multiplier.unwrap_or(1) as i64);
+            damage.total = damage.total.saturating_add(most)
+        }
+        CritDamage::Max => {
+            damage.rolls.fill(damage.settings.sides);
+            damage.recount();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dice::RollType;
+
+    #[test]
+    fn rerolls_damage_ones() {
+        let rules = HouseRules {
+            reroll_damage_ones: true,
+            ..Default::default()
+        };
+        let settings: RollSettings = "10d6 + 2".parse().unwrap();
+        let mut damage = Roll::new(&settings);
+        damage.rolls = vec![1; 10];
+        let notes = apply_damage(&rules, &mut damage, &mut rand::thread_rng());
+        assert_eq!(notes, ["🏠 Rerolled 10 ones, as the house rules say"]);
+        assert_eq!(damage.total, damage.rolls.iter().sum::<u32>() as i64 + 2);
+        assert!(
+            apply_damage(&HouseRules::default(), &mut damage, &mut rand::thread_rng()).is_empty()
+        );
+
+        // Only the dice that count
+        let settings: RollSettings = "4d6kh3".parse().unwrap();
+        let mut damage = Roll::new(&settings);
+        damage.rolls = vec![1, 1, 5, 6];
+        let notes = apply_damage(&rules, &mut damage, &mut rand::thread_rng());
+        assert_eq!(notes, ["🏠 Rerolled 1 ones, as the house rules say"]);
+        assert_eq!(damage.rolls[1], 1);
+
+        // Rolls other than damage, such as ability scores
+        let mut results = RollResults::new(&settings, &RollType::Straight);
+        results.result.rolls = vec![1, 1, 5, 6];
+        assert!(apply(&rules, &mut results).is_empty());
+        assert_eq!(results.result.rolls, [1, 1, 5, 6]);
+    }
+
+    #[test]
+    fn botches_dice_pools() {
+        let settings: RollSettings = "4d10>=8".parse().unwrap();
+        let pool = |rules: &HouseRules, rolls: Vec<u32>| {
+            let mut results = RollResults::new(&settings, &RollType::Straight);
+            results.result.rolls = rolls;
+            results.result.recount();
+            let notes = apply(rules, &mut results);
+            (results.result.total, notes)
+        };
+        let rules = HouseRules::default();
+        assert_eq!(
+            pool(&rules, vec![9, 1, 3, 8]),
+            (2, vec!["✅ <b>Success</b>".to_string()])
+        );
+        assert_eq!(
+            pool(&rules, vec![1, 1, 3, 2]),
+            (0, vec!["❌ <b>Failure</b>".to_string()])
+        );
+
+        let rules = HouseRules {
+            botch: Botch::OnesSubtract,
+            ..Default::default()
+        };
+        assert_eq!(pool(&rules, vec![9, 1, 3, 8]).0, 1);
+        // The 1 comes off the successes, before the modifier and the multiplier
+        let settings: RollSettings = "2x(4d10>=8 + 1)".parse().unwrap();
+        let mut results = RollResults::new(&settings, &RollType::Straight);
+        results.result.rolls = vec![9, 1, 3, 8];
+        results.result.recount();
+        apply(&rules, &mut results);
+        assert_eq!(results.result.total, 4);
+        // And stays off when counted again
+        results.result.recount();
+        assert_eq!(results.result.total, 4);
+        assert_eq!(pool(&rules, vec![9, 1, 3, 4]).1, ["❌ <b>Failure</b>"]);
+        assert_eq!(
+            pool(&rules, vec![9, 1, 1, 1]),
+            (-2, vec!["💀 <b>Botch</b>".to_string()])
+        );
+
+        let rules = HouseRules {
+            botch: Botch::NoSuccesses,
+            ..Default::default()
+        };
+        assert_eq!(pool(&rules, vec![9, 1, 1, 1]).1, ["✅ <b>Success</b>"]);
+        assert_eq!(pool(&rules, vec![2, 1, 3, 4]).1, ["💀 <b>Botch</b>"]);
+        assert_eq!(pool(&rules, vec![2, 5, 3, 4]).1, ["❌ <b>Failure</b>"]);
+    }
+
+    #[test]
+    fn maxes_crit_damage() {
+        let rules = HouseRules {
+            crit_range: 19,
+            crit_damage: CritDamage::Max,
+            ..Default::default()
+        };
+        assert!(is_crit(&rules, 19));
+        assert!(!is_crit(&HouseRules::default(), 19));
+
+        let settings: RollSettings = "2d6 + 3".parse().unwrap();
+        assert_eq!(crit_damage(&rules, &settings), settings);
+        let mut damage = Roll::new(&settings);
+        apply_crit(&rules, &mut damage);
+        assert_eq!((damage.rolls, damage.total), (vec![6, 6], 15));
+
+        let rules = HouseRules {
+            crit_damage: CritDamage::MaxPlusRoll,
+            ..Default::default()
+        };
+        let mut damage = Roll::new(&settings);
+        let rolled = damage.total;
+        apply_crit(&rules, &mut damage);
+        assert_eq!(damage.total, rolled + 12);
+        assert_eq!(crit_damage(&HouseRules::default(), &settings).number, 4);
+    }
+}
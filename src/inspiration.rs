@@ -0,0 +1,313 @@
+use teloxide::utils::html;
+
+use crate::storage::{CharacterState, Storage, Store};
+use crate::transport::{ChatTransport, Incoming, Sender};
+
+/// Luck points a character with the Lucky feat gets after a long rest
+pub(crate) const LUCK_POINTS: u8 = 3;
+
+const INSPIRATION_USAGE: &str =
+    "<code>/inspiration</code> shows whether a character has inspiration
+<code>/inspiration give</code> gives inspiration, for the GM or chat administrators
+<code>/inspiration use</code> uses your inspiration
+Reply to someone's message to give to or show their character.";
+
+const LUCK_USAGE: &str = "<code>/luck</code> shows your remaining luck points
+<code>/luck use</code> spends a luck point
+<code>/luck reset</code> starts tracking your luck points
+/longrest restores them. Only the GM or chat administrators can reset them otherwise, replying to someone's message to reset theirs.";
+
+/// The user a command is aimed at: whoever was replied to, otherwise the sender
+fn target(msg: &Incoming) -> Option<&Sender> {
+    msg.reply_to
+        .as_ref()
+        .and_then(|replied| replied.from.as_ref())
+        .or(msg.from.as_ref())
+}
+
+fn no_character(user: &Sender) -> String {
+    format!(
+        "{} has no character. Upload one with /sheet upload.",
+        html::escape(&user.name)
+    )
+}
+
+/// Run `f` on the play state of the character the user plays in the chat, without changing it
+fn read_state<F>(storage: &Storage, chat_id: i64, user: &Sender, f: F) -> String
+where
+    F: FnOnce(&str, &CharacterState) -> String,
+{
+    let Some(stored) = storage.user(user.id) else {
+        return no_character(user);
+    };
+    let Some(name) = stored.active_character(chat_id) else {
+        return no_character(user);
+    };
+    let state = stored.states.get(name).cloned().unwrap_or_default();
+    f(&html::escape(name), &state)
+}
+
+/// Run `f` on the play state of the character the user plays in the chat
+fn with_state<F>(storage: &mut Storage, chat_id: i64, user: &Sender, f: F) -> String
+where
+    F: FnOnce(&str, &mut CharacterState) -> String,
+{
+    let user_id = user.id;
+    let state = match storage.user(user_id) {
+        Some(_) => storage.user_mut(user_id).active_state_mut(chat_id),
+        None => None,
+    };
+    match state {
+        None => no_character(user),
+        Some((name, state)) => f(&html::escape(&name), state),
+    }
+}
+
+pub(crate) async fn handle_inspiration(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let (Some(sender), Some(target)) = (&msg.from, target(msg)) else {
+        return Ok(());
+    };
+    let chat_id = msg.chat_id;
+
+    let text = match input.trim() {
+        "" | "show" => {
+            store
+                .read(|storage| {
+                    read_state(storage, chat_id, target, |name, state| {
+                        if state.inspiration {
+                            format!("✨ {} has inspiration.", name)
+                        } else {
+                            format!("{} does not have inspiration.", name)
+                        }
+                    })
+                })
+                .await
+        }
+        "give" if !crate::auth::is_chat_admin(bot, msg).await? => {
+            "Only the GM or chat administrators can give inspiration.".to_string()
+        }
+        "give" => {
+            store
+                .update(|storage| {
+                    with_state(storage, chat_id, target, |name, state| {
+                        if state.inspiration {
+                            format!("{} already has inspiration.", name)
+                        } else {
+                            state.inspiration = true;
+                            format!("✨ {} gained inspiration!", name)
+                        }
+                    })
+                })
+                .await?
+        }
+        "use" => {
+            store
+                .update(|storage| {
+                    with_state(storage, chat_id, sender, |name, state| {
+                        if state.inspiration {
+                            state.inspiration = false;
+                            format!("✨ {} used their inspiration!", name)
+                        } else {
+                            format!("{} does not have inspiration to use.", name)
+                        }
+                    })
+                })
+                .await?
+        }
+        _ => INSPIRATION_USAGE.to_string(),
+    };
+
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+pub(crate) async fn handle_luck(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let Some(sender) = &msg.from else {
+        return Ok(());
+    };
+    let chat_id = msg.chat_id;
+
+    let text = match input.trim() {
+        "" | "show" => {
+            store
+                .read(|storage| {
+                    read_state(storage, chat_id, sender, |name, state| {
+                        match state.luck_points {
+                            None => format!(
+                            "{} does not track luck points. Start with <code>/luck reset</code>.",
+                            name
+                        ),
+                            Some(points) => format!("🍀 {} has {} luck points left.", name, points),
+                        }
+                    })
+                })
+                .await
+        }
+        "use" => {
+            store
+                .update(|storage| {
+                    with_state(storage, chat_id, sender, |name, state| {
+                        match state.luck_points {
+                            None | Some(0) => format!("{} has no luck points left.", name),
+                            Some(points) => {
+                                state.luck_points = Some(points - 1);
+                                format!(
+                                    "🍀 {} spent a luck point, {} left. Roll an extra d20!",
+                                    name,
+                                    points - 1
+                                )
+                            }
+                        }
+                    })
+                })
+                .await?
+        }
+        "reset" => {
+            // Once tracked, luck points only come back with a long rest, unless the GM says so
+            let is_gm = crate::auth::is_chat_admin(bot, msg).await?;
+            let target = target(msg).unwrap_or(sender);
+            if target.id != sender.id && !is_gm {
+                let text =
+                    "Only the GM or chat administrators can reset the luck points of others.";
+                bot.reply(msg, text.to_string(), vec![]).await?;
+                return Ok(());
+            }
+            store
+                .update(|storage| {
+                    with_state(storage, chat_id, target, |name, state| {
+                        if state.luck_points.is_some() && !is_gm {
+                            return format!(
+                                "{} already tracks luck points. They come back with /longrest.",
+                                name
+                            );
+                        }
+                        state.luck_points = Some(LUCK_POINTS);
+                        format!("🍀 {} has {} luck points.", name, LUCK_POINTS)
+                    })
+                })
+                .await?
+        }
+        _ => LUCK_USAGE.to_string(),
+    };
+
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, FakeTransport};
+    use crate::transport::Replied;
+
+    async fn store_with_character() -> Store {
+        let store = testing::store().await;
+        store
+            .update(|storage| {
+                let user = storage.user_mut(8);
+                user.characters
+                    .insert("Aria".to_string(), testing::character("Aria"));
+                user.default_character = Some("Aria".to_string());
+            })
+            .await
+            .unwrap();
+        store
+    }
+
+    /// A message from the user, replying to the player of Aria
+    fn to_aria(user_id: i64) -> Incoming {
+        Incoming {
+            reply_to: Some(Replied {
+                from: Some(testing::sender(8)),
+                attachment: None,
+            }),
+            ..testing::incoming(user_id)
+        }
+    }
+
+    #[tokio::test]
+    async fn gives_spends_and_shows_inspiration() {
+        let bot = FakeTransport::default().with_admin(7);
+        let store = store_with_character().await;
+
+        for (msg, input) in [
+            (to_aria(7), "show"),
+            (to_aria(9), "give"),
+            (to_aria(7), "give"),
+            (to_aria(7), "give"),
+            (testing::incoming(8), ""),
+            (testing::incoming(8), "use"),
+            (testing::incoming(8), "use"),
+            (testing::incoming(9), "use"),
+        ] {
+            handle_inspiration(&bot, &msg, store.clone(), input)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            bot.replies(),
+            [
+                "Aria does not have inspiration.",
+                "Only the GM or chat administrators can give inspiration.",
+                "✨ Aria gained inspiration!",
+                "Aria already has inspiration.",
+                "✨ Aria has inspiration.",
+                "✨ Aria used their inspiration!",
+                "Aria does not have inspiration to use.",
+                "User 9 has no character. Upload one with /sheet upload.",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn shows_without_saving() {
+        let bot = FakeTransport::default();
+        let store = store_with_character().await;
+        let before = store.read(Storage::clone).await;
+
+        handle_inspiration(&bot, &to_aria(7), store.clone(), "show")
+            .await
+            .unwrap();
+        handle_luck(&bot, &testing::incoming(8), store.clone(), "")
+            .await
+            .unwrap();
+        assert_eq!(store.read(Storage::clone).await, before);
+    }
+
+    #[tokio::test]
+    async fn only_the_gm_resets_luck() {
+        let bot = FakeTransport::default().with_admin(7);
+        let store = store_with_character().await;
+        for (msg, input) in [
+            (testing::incoming(8), "reset"),
+            (testing::incoming(8), "use"),
+            (testing::incoming(8), "reset"),
+            (to_aria(9), "reset"),
+            (to_aria(7), "reset"),
+            (testing::incoming(8), ""),
+        ] {
+            handle_luck(&bot, &msg, store.clone(), input).await.unwrap();
+        }
+        assert_eq!(
+            bot.replies(),
+            [
+                "🍀 Aria has 3 luck points.",
+                "🍀 Aria spent a luck point, 2 left. Roll an extra d20!",
+                "Aria already tracks luck points. They come back with /longrest.",
+                "Only the GM or chat administrators can reset the luck points of others.",
+                "🍀 Aria has 3 luck points.",
+                "🍀 Aria has 3 luck points left.",
+            ]
+        );
+    }
+}
@@ -0,0 +1,402 @@
+//! Coin purses and inventories of characters, and splitting loot among the party.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use teloxide::utils::html;
+
+use crate::rest::with_character;
+use crate::storage::{CharacterState, Storage, Store};
+use crate::transport::{ChatTransport, Incoming};
+
+/// Coins by denomination, from platinum down to copper
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct Purse {
+    #[serde(default)]
+    pub pp: i64,
+    #[serde(default)]
+    pub gp: i64,
+    #[serde(default)]
+    pub ep: i64,
+    #[serde(default)]
+    pub sp: i64,
+    #[serde(default)]
+    pub cp: i64,
+}
+
+impl Purse {
+    fn coin(&mut self, denomination: &str) -> Option<&mut i64> {
+        match denomination {
+            "pp" => Some(&mut self.pp),
+            "gp" => Some(&mut self.gp),
+            "ep" => Some(&mut self.ep),
+            "sp" => Some(&mut self.sp),
+            "cp" => Some(&mut self.cp),
+            _ => None,
+        }
+    }
+
+    /// Copper pieces as the fewest gold, silver and copper coins
+    fn from_copper(copper: i64) -> Self {
+        Purse {
+            gp: copper / 100,
+            sp: copper % 100 / 10,
+            cp: copper % 10,
+            ..Default::default()
+        }
+    }
+
+    /// Both purses together, unless a denomination would hold more than an `i64`
+    fn checked_add(&self, other: &Purse) -> Option<Purse> {
+        Some(Purse {
+            pp: self.pp.checked_add(other.pp)?,
+            gp: self.gp.checked_add(other.gp)?,
+            ep: self.ep.checked_add(other.ep)?,
+            sp: self.sp.checked_add(other.sp)?,
+            cp: self.cp.checked_add(other.cp)?,
+        })
+    }
+}
+
+impl std::fmt::Display for Purse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let coins: Vec<_> = [
+            (self.pp, "pp"),
+            (self.gp, "gp"),
+            (self.ep, "ep"),
+            (self.sp, "sp"),
+            (self.cp, "cp"),
+        ]
+        .into_iter()
+        .filter(|(amount, _)| *amount != 0)
+        .map(|(amount, denomination)| format!("{} {}", amount, denomination))
+        .collect();
+        match coins.is_empty() {
+            true => write!(f, "no coins"),
+            false => write!(f, "{}", coins.join(", ")),
+        }
+    }
+}
+
+/// An amount like `+150`, `-3 sp` or `20gp`, in gold pieces unless told otherwise
+fn parse_amount(input: &str) -> Option<(i64, &str)> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(input.len());
+    let (amount, denomination) = input.split_at(split);
+    let amount = amount.trim().trim_start_matches('+').parse().ok()?;
+    match denomination.trim() {
+        "" => Some((amount, "gp")),
+        denomination @ ("pp" | "gp" | "ep" | "sp" | "cp") => Some((amount, denomination)),
+        _ => None,
+    }
+}
+
+fn change_coins(name: &str, state: &mut CharacterState, input: &str) -> String {
+    if input.is_empty() {
+        return format!("💰 {} has {}.", name, state.purse);
+    }
+    let Some((amount, denomination)) = parse_amount(input) else {
+        return GOLD_USAGE.to_string();
+    };
+    let coin = state
+        .purse
+        .coin(denomination)
+        .expect("to be a denomination");
+    let Some(total) = coin.checked_add(amount) else {
+        return TOO_MANY.to_string();
+    };
+    if total < 0 {
+        return format!("{} only has {} {}.", name, coin, denomination);
+    }
+    *coin = total;
+    format!("💰 {} has {}.", name, state.purse)
+}
+
+/// An item like `Potion of Healing x2`
+fn parse_item(input: &str) -> Option<(String, u32)> {
+    let input = input.trim();
+    let (name, count) = match input.rsplit_once(' ') {
+        Some((name, count)) => match count.strip_prefix(['x', 'X']).map(str::parse) {
+            Some(Ok(count)) => (name.trim(), count),
+            _ => (input, 1),
+        },
+        None => (input, 1),
+    };
+    match name.is_empty() || count == 0 {
+        true => None,
+        false => Some((name.to_string(), count)),
+    }
+}
+
+/// The name an item is stored under, with the case of an item already carried
+fn item_key(inventory: &BTreeMap<String, u32>, name: &str) -> String {
+    inventory
+        .keys()
+        .find(|key| key.eq_ignore_ascii_case(name))
+        .cloned()
+        .unwrap_or_else(|| name.to_string())
+}
+
+fn change_inventory(name: &str, state: &mut CharacterState, input: &str) -> String {
+    let (action, rest) = input.split_once(' ').unwrap_or((input, ""));
+    match (action, parse_item(rest)) {
+        ("", _) if state.inventory.is_empty() => format!("🎒 {} carries nothing.", name),
+        ("", _) => {
+            let items: Vec<_> = state
+                .inventory
+                .iter()
+                .map(|(item, count)| match count {
+                    1 => format!("• {}", html::escape(item)),
+                    count => format!("• {} ×{}", html::escape(item), count),
+                })
+                .collect();
+            format!("🎒 {} carries\n{}", name, items.join("\n"))
+        }
+        ("add", Some((item, count))) => {
+            let key = item_key(&state.inventory, &item);
+            let carried = state.inventory.entry(key.clone()).or_default();
+            *carried += count;
+            format!(
+                "🎒 {} now carries {} ×{}.",
+                name,
+                html::escape(&key),
+                carried
+            )
+        }
+        ("remove", Some((item, count))) => {
+            let key = item_key(&state.inventory, &item);
+            match state.inventory.get_mut(&key) {
+                Some(carried) if *carried >= count => {
+                    *carried -= count;
+                    let left = *carried;
+                    if left == 0 {
+                        state.inventory.remove(&key);
+                    }
+                    format!("🎒 {} has {} ×{} left.", name, html::escape(&key), left)
+                }
+                Some(carried) => {
+                    format!("{} only carries {} ×{}.", name, html::escape(&key), carried)
+                }
+                None => format!("{} carries no {}.", name, html::escape(&item)),
+            }
+        }
+        _ => INVENTORY_USAGE.to_string(),
+    }
+}
+
+/// The users and names of the characters in the party: the campaign's players, or everyone in the chat
+fn party(storage: &Storage, chat_id: i64) -> Vec<(i64, String)> {
+    let Some(chat) = storage.chat(chat_id) else {
+        return vec![];
+    };
+    let users = match chat.campaign {
+        Some(ref campaign) => &campaign.players,
+        None => &chat.members,
+    };
+    users
+        .iter()
+        .filter_map(|id| storage.user(*id))
+        .filter_map(|user| Some((user.id, user.active_character(chat_id)?.to_string())))
+        .collect()
+}
+
+/// Split coins evenly among the party, into their purses
+fn split(storage: &mut Storage, chat_id: i64, input: &str) -> String {
+    let Some((amount, denomination)) = parse_amount(input).filter(|(amount, _)| *amount > 0) else {
+        return SPLIT_USAGE.to_string();
+    };
+    let party = party(storage, chat_id);
+    if party.is_empty() {
+        return "Nobody in this chat has a character. Upload one with /sheet upload.".to_string();
+    }
+    let Some(copper) =
+        amount.checked_mul(crate::loot::copper(denomination).expect("to be a coin") as i64)
+    else {
+        return TOO_MANY.to_string();
+    };
+    let share = Purse::from_copper(copper / party.len() as i64);
+    let purses: Option<Vec<_>> = party
+        .iter()
+        .map(|(user_id, name)| {
+            let purse = storage
+                .user(*user_id)
+                .and_then(|user| user.states.get(name))
+                .map(|state| state.purse)
+                .unwrap_or_default();
+            purse.checked_add(&share)
+        })
+        .collect();
+    let Some(purses) = purses else {
+        return TOO_MANY.to_string();
+    };
+    let mut text = format!(
+        "💰 {} {} split {} ways: {} each",
+        amount,
+        denomination,
+        party.len(),
+        share
+    );
+    for ((user_id, name), purse) in party.iter().zip(purses) {
+        let state = storage
+            .user_mut(*user_id)
+            .states
+            .entry(name.clone())
+            .or_default();
+        state.purse = purse;
+        text.push_str(&format!("\n• {}: {}", html::escape(name), state.purse));
+    }
+    let left = copper % party.len() as i64;
+    if left > 0 {
+        text.push_str(&format!("\n{} is left over.", Purse::from_copper(left)));
+    }
+    text
+}
+
+const TOO_MANY: &str = "That is more coins than I can count. Try a smaller amount.";
+
+const GOLD_USAGE: &str = "<code>/gold</code> shows the coins of your character
+<code>/gold +150</code> adds 150 gold pieces
+<code>/gold -3 sp</code> takes 3 silver pieces, and likewise for pp, ep and cp";
+
+const INVENTORY_USAGE: &str = "<code>/inventory</code> lists what your character carries
+<code>/inventory add Potion of Healing x2</code> adds two potions
+<code>/inventory remove Potion of Healing</code> removes one";
+
+const SPLIT_USAGE: &str =
+    "<code>/split 356</code> splits 356 gold pieces evenly among the party, into their purses
+<code>/split 90 sp</code> splits silver pieces, and likewise for pp, ep and cp";
+
+/// Answer a command about the coins or items of the character the user plays
+async fn reply_for_character<F>(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    f: F,
+) -> anyhow::Result<()>
+where
+    F: FnOnce(&str, &mut CharacterState) -> String + Send,
+{
+    let Some(user) = &msg.from else {
+        return Ok(());
+    };
+    let (chat_id, user_id) = (msg.chat_id, user.id);
+    let text = store
+        .update(|storage| {
+            with_character(storage, chat_id, user_id, |character, state| {
+                f(&html::escape(&character.name), state)
+            })
+        })
+        .await?;
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+pub(crate) async fn handle_gold(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let input = input.trim();
+    reply_for_character(bot, msg, store, |name, state| {
+        change_coins(name, state, input)
+    })
+    .await
+}
+
+pub(crate) async fn handle_inventory(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let input = input.trim();
+    reply_for_character(bot, msg, store, |name, state| {
+        change_inventory(name, state, input)
+    })
+    .await
+}
+
+pub(crate) async fn handle_split(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let text = store
+        .update(|storage| split(storage, chat_id, input))
+        .await?;
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_coins_and_items() {
+        let mut state = CharacterState::default();
+        assert_eq!(
+            change_coins("Aria", &mut state, "+150"),
+            "💰 Aria has 150 gp."
+        );
+        assert_eq!(
+            change_coins("Aria", &mut state, "-3 sp"),
+            "Aria only has 0 sp."
+        );
+        assert_eq!(
+            change_coins("Aria", &mut state, "12sp"),
+            "💰 Aria has 150 gp, 12 sp."
+        );
+        assert_eq!(change_coins("Aria", &mut state, "lots"), GOLD_USAGE);
+        assert_eq!(
+            change_coins("Aria", &mut state, "+9223372036854775807"),
+            TOO_MANY
+        );
+        assert_eq!(state.purse.gp, 150);
+
+        change_inventory("Aria", &mut state, "add Potion of Healing x2");
+        change_inventory("Aria", &mut state, "add rope");
+        assert_eq!(
+            change_inventory("Aria", &mut state, "remove potion of healing"),
+            "🎒 Aria has Potion of Healing ×1 left."
+        );
+        assert_eq!(
+            change_inventory("Aria", &mut state, ""),
+            "🎒 Aria carries\n• Potion of Healing\n• rope"
+        );
+        assert!(change_inventory("Aria", &mut state, "remove rope x3").contains("only carries"));
+    }
+
+    #[test]
+    fn splits_among_the_party() {
+        let mut storage = Storage::default();
+        for (id, name) in [(1, "Aria"), (2, "Brom"), (3, "Cass")] {
+            storage.chat_mut(10).members.insert(id);
+            let user = storage.user_mut(id);
+            user.characters
+                .insert(name.to_string(), crate::testing::character(name));
+            user.default_character = Some(name.to_string());
+        }
+        assert_eq!(
+            split(&mut storage, 10, "356"),
+            "💰 356 gp split 3 ways: 118 gp, 6 sp, 6 cp each\n\
+             • Aria: 118 gp, 6 sp, 6 cp\n\
+             • Brom: 118 gp, 6 sp, 6 cp\n\
+             • Cass: 118 gp, 6 sp, 6 cp\n\
+             2 cp is left over."
+        );
+        assert_eq!(split(&mut storage, 10, "-5"), SPLIT_USAGE);
+        assert_eq!(split(&mut storage, 10, "9223372036854775807 pp"), TOO_MANY);
+        let brom = storage.user_mut(2).states.get_mut("Brom").unwrap();
+        brom.purse.gp = i64::MAX;
+        assert_eq!(split(&mut storage, 10, "356"), TOO_MANY);
+        assert_eq!(storage.user(1).unwrap().states["Aria"].purse.gp, 118);
+    }
+}
@@ -0,0 +1,89 @@
+//! Times shown in the timezone of the chat, set with `/settings timezone`, so that reminders, timers,
+//! sessions and exports all agree on what time it is.
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use teloxide::utils::html;
+
+use crate::storage::Storage;
+
+/// The timezone of the chat, UTC when it has not set one
+pub(crate) fn timezone(storage: &Storage, chat_id: i64) -> Tz {
+    storage
+        .chat(chat_id)
+        .and_then(|chat| chat.timezone)
+        .unwrap_or(Tz::UTC)
+}
+
+/// Show the timezone of the chat, or set it to the one named like `Europe/London`
+pub(crate) fn change_timezone(storage: &mut Storage, chat_id: i64, name: Option<&str>) -> String {
+    let Some(name) = name else {
+        return format!("This chat uses {}.", timezone(storage, chat_id));
+    };
+    match name.parse::<Tz>() {
+        Ok(timezone) => {
+            storage.chat_mut(chat_id).timezone = Some(timezone);
+            format!("This chat now uses {}.", timezone)
+        }
+        Err(_) => format!(
+            "{} is not a timezone. Use a name like Europe/London or America/New_York.",
+            html::escape(name)
+        ),
+    }
+}
+
+/// Such as `Sat 2024-07-06 19:00`, for lists headed by the timezone
+pub(crate) fn datetime(time: DateTime<Utc>, timezone: Tz) -> String {
+    time.with_timezone(&timezone)
+        .format("%a %Y-%m-%d %H:%M")
+        .to_string()
+}
+
+/// Such as `Sat 2024-07-06 19:00 CEST`
+pub(crate) fn datetime_with_zone(time: DateTime<Utc>, timezone: Tz) -> String {
+    time.with_timezone(&timezone)
+        .format("%a %Y-%m-%d %H:%M %Z")
+        .to_string()
+}
+
+/// Such as `19:00:30`, for times later today
+pub(crate) fn clock(time: DateTime<Utc>, timezone: Tz) -> String {
+    time.with_timezone(&timezone).format("%H:%M:%S").to_string()
+}
+
+/// Such as `2024-07-06T19:00:00+02:00`, for files
+pub(crate) fn rfc3339(time: DateTime<Utc>, timezone: Tz) -> String {
+    time.with_timezone(&timezone).to_rfc3339()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn formats_in_the_timezone() {
+        let time = Utc.with_ymd_and_hms(2024, 7, 6, 17, 0, 0).unwrap();
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+        assert_eq!(datetime(time, berlin), "Sat 2024-07-06 19:00");
+        assert_eq!(
+            datetime_with_zone(time, berlin),
+            "Sat 2024-07-06 19:00 CEST"
+        );
+        assert_eq!(
+            datetime_with_zone(time, Tz::UTC),
+            "Sat 2024-07-06 17:00 UTC"
+        );
+        assert_eq!(clock(time, berlin), "19:00:00");
+        assert_eq!(rfc3339(time, berlin), "2024-07-06T19:00:00+02:00");
+
+        let mut storage = Storage::default();
+        assert_eq!(timezone(&storage, 1), Tz::UTC);
+        assert_eq!(
+            change_timezone(&mut storage, 1, Some("Europe/Berlin")),
+            "This chat now uses Europe/Berlin."
+        );
+        assert_eq!(timezone(&storage, 1), berlin);
+        assert!(change_timezone(&mut storage, 1, Some("Mars/Olympus")).contains("is not a"));
+    }
+}
@@ -0,0 +1,132 @@
+//! Treasure, rolled on the `loot_` random tables of the chat or the built in ones.
+
+use teloxide::utils::html;
+
+use crate::storage::Store;
+use crate::transport::{ChatTransport, Incoming};
+
+const USAGE: &str = "<code>/loot cr5</code> rolls the treasure of one creature
+<code>/loot cr5 hoard</code> rolls a treasure hoard
+Upload tables named like loot_individual_5 or loot_hoard_11 with /table to use your own treasure. The number is the lowest challenge rating of the band: 0, 5, 11 or 17.";
+
+/// Lowest challenge rating of the band of treasure tables
+fn band(challenge: u32) -> u32 {
+    match challenge {
+        0..=4 => 0,
+        5..=10 => 5,
+        11..=16 => 11,
+        _ => 17,
+    }
+}
+
+/// Worth of the coin, in copper pieces
+pub(crate) fn copper(coin: &str) -> Option<u64> {
+    match coin.trim_end_matches([',', '.']) {
+        "cp" => Some(1),
+        "sp" => Some(10),
+        "ep" => Some(50),
+        "gp" => Some(100),
+        "pp" => Some(1000),
+        _ => None,
+    }
+}
+
+/// Worth of all the coins in the text, such as `30 gp and 5 pp`, in copper pieces
+fn coins(text: &str) -> u64 {
+    let words: Vec<_> = text.split_whitespace().collect();
+    words
+        .windows(2)
+        .filter_map(|pair| Some(pair[0].parse::<u64>().ok()? * copper(pair[1])?))
+        .sum()
+}
+
+fn format_gold(copper: u64) -> String {
+    match copper % 100 {
+        0 => format!("{} gp", copper / 100),
+        rest => format!("{}.{:02} gp", copper / 100, rest),
+    }
+}
+
+fn loot(tables: &[crate::table::RandomTable], input: &str) -> String {
+    let mut challenge = None;
+    let mut kind = "individual";
+    for arg in input.split_whitespace() {
+        match arg.to_lowercase().as_str() {
+            "hoard" => kind = "hoard",
+            "individual" => kind = "individual",
+            arg => match arg.trim_start_matches("cr").parse::<u32>() {
+                Ok(number) => challenge = Some(number),
+                Err(_) => return USAGE.to_string(),
+            },
+        }
+    }
+    let Some(challenge) = challenge else {
+        return USAGE.to_string();
+    };
+
+    let name = format!("loot_{}_{}", kind, band(challenge));
+    match crate::table::roll(tables, &name) {
+        Ok(text) => {
+            let worth = coins(&crate::offline::plain_text(&text));
+            format!(
+                "💰 CR {} {}\n{}\n\nCoins worth {} in total",
+                challenge,
+                kind,
+                text,
+                format_gold(worth)
+            )
+        }
+        Err(e) => html::escape(&format!("{:#}", e)),
+    }
+}
+
+pub(crate) async fn handle_loot(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let text = store
+        .read(|storage| {
+            let tables = storage
+                .chat(chat_id)
+                .map(|chat| chat.random_tables.as_slice())
+                .unwrap_or_default();
+            loot(tables, input)
+        })
+        .await;
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_the_coins() {
+        assert_eq!(coins("12 cp, 3 sp and 2 gp"), 242);
+        assert_eq!(coins("3 × ruby (5000 gp each) and 1 pp."), 1000);
+        assert_eq!(format_gold(242), "2.42 gp");
+        assert_eq!(format_gold(1000), "10 gp");
+        assert_eq!(band(4), 0);
+        assert_eq!(band(30), 17);
+    }
+
+    #[test]
+    fn rolls_on_the_loot_tables() {
+        for challenge in [0, 5, 11, 17] {
+            for kind in ["individual", "hoard"] {
+                let text = loot(&[], &format!("cr{} {}", challenge, kind));
+                assert!(text.starts_with("💰"), "{}", text);
+            }
+        }
+        let tables: Vec<crate::table::RandomTable> = serde_json::from_str(
+            r#"[{"name": "loot_individual_5", "entries": [{"text": "[[2d1*10]] gp"}]}]"#,
+        )
+        .unwrap();
+        assert!(loot(&tables, "7").ends_with("Coins worth 20 gp in total"));
+        assert!(loot(&[], "hoard").starts_with("<code>/loot"));
+    }
+}
@@ -1,20 +1,89 @@
+mod about;
+mod admin;
+mod archive;
+mod attack;
+mod auth;
+mod backup;
+mod campaign;
+mod chance;
+mod characters;
 mod cli;
+mod combat;
+mod concentration;
+mod config;
+mod contest;
+mod ddb;
+mod dddice;
+mod deck;
+mod diagnostics;
 mod dice;
 mod dnd;
+mod downtime;
+mod encounter;
+mod expr;
+mod foundry;
+mod groupcheck;
+mod history;
+mod houserules;
+mod inspiration;
+mod inventory;
+mod localtime;
+mod loot;
+mod monster;
+mod names;
+mod npc;
+mod offline;
+mod output;
 mod parser;
+#[cfg(feature = "postgres")]
+mod postgres;
+mod ratelimit;
+mod reaction;
+mod remind;
+mod report;
+mod reroll;
+mod rest;
+mod rolllog;
+mod rumor;
+mod scheduler;
+mod secret;
+mod session;
+mod settings;
+mod sheet;
+mod stats;
 mod storage;
+mod surge;
+mod table;
+mod telemetry;
+#[cfg(test)]
+mod testing;
+mod timer;
+mod transport;
+mod travel;
+mod undo;
+mod upload;
+mod validate;
+mod watcher;
+mod weather;
+mod webhook;
 
 use std::str::FromStr;
+use std::sync::Arc;
 
-use anyhow::anyhow;
-use clap::Parser;
+use anyhow::{anyhow, Context};
+use clap::{CommandFactory, FromArgMatches};
+use teloxide::adaptors::throttle::Limits;
 use teloxide::adaptors::{CacheMe, DefaultParseMode, Throttle};
 use teloxide::prelude::*;
 use teloxide::requests::RequesterExt;
-use teloxide::types::{InputFile, ParseMode};
+use teloxide::types::ParseMode;
+use teloxide::update_listeners::{self, UpdateListener};
 use teloxide::utils::command::BotCommands;
+use tracing::Instrument;
 
 use dice::*;
+use storage::StorageBackend;
+use transport::ChatTransport;
 
 #[derive(BotCommands, Clone, PartialEq)]
 #[command(
@@ -40,6 +109,100 @@ enum Command {
     Disadvantage(String),
     #[command(description = "Roll with disadvantage, and send data output")]
     DisadvantageData(String),
+    #[command(description = "Roll a random encounter, or manage encounter tables")]
+    Encounter(String),
+    #[command(description = "Show or advance the initiative order")]
+    Combat(String),
+    #[command(description = "Show or upload your character sheet")]
+    Sheet(String),
+    #[command(description = "List your characters and choose the one you play in this chat")]
+    Characters(String),
+    #[command(description = "Attack with one of your character's weapons")]
+    Attack(String),
+    #[command(description = "Show, give or use inspiration")]
+    Inspiration(String),
+    #[command(description = "Track luck points of the Lucky feat")]
+    Luck(String),
+    #[command(description = "Flip one or more coins")]
+    Flip(String),
+    #[command(description = "Pick one of the options separated by |")]
+    Choose(String),
+    #[command(description = "Roll on a random table, or manage random tables")]
+    Table(String),
+    #[command(description = "Roll for a wild magic surge")]
+    Surge(String),
+    #[command(description = "Roll treasure, such as /loot cr5 hoard")]
+    Loot(String),
+    #[command(description = "Roll a name, race, quirk and stats for an NPC")]
+    Npc,
+    #[command(description = "Roll six ability scores, with 4d6 dropping the lowest or 3d6")]
+    Statgen(String),
+    #[command(description = "Check an array of ability scores against the point buy budget")]
+    Pointbuy(String),
+    #[command(description = "Roll a check for every character in the chat")]
+    Groupcheck(String),
+    #[command(description = "Show or spend the hit dice of your character")]
+    Hitdice(String),
+    #[command(description = "Take a short rest, optionally spending hit dice")]
+    Shortrest(String),
+    #[command(description = "Take a long rest")]
+    Longrest,
+    #[command(description = "Spend a spell slot or a use of a resource")]
+    Spend(String),
+    #[command(description = "Show or change the coins of your character")]
+    Gold(String),
+    #[command(description = "Show or change what your character carries")]
+    Inventory(String),
+    #[command(description = "Split coins evenly among the party")]
+    Split(String),
+    #[command(description = "Roll a series of checks for a downtime activity such as crafting")]
+    Downtime(String),
+    #[command(description = "Roll the encounters and weather of some days of travel")]
+    Travel(String),
+    #[command(description = "Roll the weather of a climate and season")]
+    Weather(String),
+    #[command(description = "Roll names, such as /name dwarf female")]
+    Name(String),
+    #[command(description = "Roll a rumor, telling the GM privately whether it is true")]
+    Rumor,
+    #[command(description = "Concentrate on a spell, or stop concentrating")]
+    Concentrate(String),
+    #[command(description = "Manage the monsters of the campaign and attack with them")]
+    Monster(String),
+    #[command(description = "Roll an opposed check between two users")]
+    Contest(String),
+    #[command(description = "Start or manage the campaign of this chat")]
+    Campaign(String),
+    #[command(description = "Remind the chat of a session, once, daily or weekly")]
+    Remind(String),
+    #[command(description = "Start a countdown, such as /timer 10m short rest")]
+    Timer(String),
+    #[command(description = "Show or change the settings of this chat")]
+    Settings(String),
+    #[command(description = "Repeat your latest roll in this chat")]
+    Reroll,
+    #[command(description = "Take back your latest roll in this chat")]
+    Undo,
+    #[command(description = "Start or end a session, summarising its rolls at the end")]
+    Session(String),
+    #[command(description = "Send the roll history of this chat as a CSV or JSON file")]
+    Exportlog(String),
+    #[command(description = "Shuffle a deck of cards, such as cards, tarot or many")]
+    Shuffle(String),
+    #[command(description = "Draw cards from a deck")]
+    Draw(String),
+    #[command(description = "Shuffle a deck and lay out a reading of its cards")]
+    Reading(String),
+    #[command(description = "Show or upload the decks of this chat")]
+    Deck(String),
+    #[command(description = "Show the version and build of the bot")]
+    About,
+    #[command(description = "Check how fast the bot answers")]
+    Ping,
+    #[command(description = "Support whoever hosts the bot")]
+    Donate,
+    #[command(description = "Commands for the operator of the bot")]
+    Admin(String),
 }
 
 fn get_token<S1, S2>(token: Option<S1>, file: Option<S2>) -> anyhow::Result<String>
@@ -51,101 +214,427 @@ where
         return Ok(token.to_string());
     }
     if let Some(file) = file.as_ref() {
-        return Ok(std::fs::read_to_string(file)?.trim().to_string());
+        let file = file.as_ref();
+        let token = std::fs::read_to_string(file)
+            .with_context(|| format!("error reading token file {}", file.display()))?;
+        return Ok(token.trim().to_string());
     }
     Err(anyhow!("No API Key provided"))
 }
 
 type AdaptedBot = DefaultParseMode<Throttle<CacheMe<Bot>>>;
 
-async fn answer(bot: AdaptedBot, msg: Message, cmd: Command) -> ResponseResult<()> {
+/// Record metrics about every command
+async fn instrumented_answer(
+    bot: AdaptedBot,
+    msg: Message,
+    cmd: Command,
+    store: storage::Store,
+    admin: Arc<admin::Admin>,
+    reporter: report::Reporter,
+    roll_log: rolllog::RollLog,
+) -> anyhow::Result<()> {
+    let start = std::time::Instant::now();
+    let incoming = transport::Incoming::from(&msg);
+    if let Err(e) = admin::remember_chat(&store, &msg.chat).await {
+        log::warn!("Error remembering chat {}: {:#}", msg.chat.id, e);
+    }
+    if let Err(e) = groupcheck::remember_member(&store, &incoming).await {
+        log::warn!("Error remembering member of chat {}: {:#}", msg.chat.id, e);
+    }
+    let span = tracing::info_span!(
+        "update",
+        chat_id = msg.chat.id.0,
+        command = telemetry::command_name(&msg).as_str()
+    );
+    let result = telemetry::with_context(
+        &msg,
+        answer(&bot, incoming, cmd, store, admin, roll_log, start).instrument(span),
+    )
+    .await;
+    telemetry::record_command(&msg, start.elapsed(), &result);
+    if let Err(ref e) = result {
+        if let Some(e) = e.downcast_ref::<DiceError>() {
+            let apology = bot
+                .reply(&transport::Incoming::from(&msg), e.apology(), vec![])
+                .await;
+            if let Err(e) = apology {
+                log::warn!("Error apologizing in chat {}: {}", msg.chat.id, e);
+            }
+        }
+        // Only the command name, so that what players wrote stays in their chat
+        reporter.report(format!(
+            "Error handling {} in chat {}: {:#}",
+            telemetry::command_name(&msg),
+            msg.chat.id,
+            e
+        ));
+    }
+    result
+}
+
+async fn answer(
+    bot: &impl transport::ChatTransport,
+    msg: transport::Incoming,
+    cmd: Command,
+    store: storage::Store,
+    admin: Arc<admin::Admin>,
+    roll_log: rolllog::RollLog,
+    received: std::time::Instant,
+) -> anyhow::Result<()> {
     match cmd {
         Command::Help => {
-            bot.send_message(msg.chat.id, Command::descriptions().to_string())
+            bot.send(msg.chat_id, Command::descriptions().to_string())
                 .await?;
         }
         Command::Roll(input) => {
-            handle_roll(bot, msg, input.as_str(), &RollType::Straight, false).await?
+            handle_roll(
+                bot,
+                &msg,
+                store,
+                input.as_str(),
+                &RollType::Straight,
+                false,
+                roll_log,
+            )
+            .await?
         }
         Command::Data(input) => {
-            handle_roll(bot, msg, input.as_str(), &RollType::Straight, true).await?
+            handle_roll(
+                bot,
+                &msg,
+                store,
+                input.as_str(),
+                &RollType::Straight,
+                true,
+                roll_log,
+            )
+            .await?
         }
         Command::Advantage(input) | Command::Adv(input) => {
-            handle_roll(bot, msg, input.as_str(), &RollType::Advantage, false).await?
+            handle_roll(
+                bot,
+                &msg,
+                store,
+                input.as_str(),
+                &RollType::Advantage,
+                false,
+                roll_log,
+            )
+            .await?
         }
         Command::AdvantageData(input) => {
-            handle_roll(bot, msg, input.as_str(), &RollType::Advantage, true).await?
+            handle_roll(
+                bot,
+                &msg,
+                store,
+                input.as_str(),
+                &RollType::Advantage,
+                true,
+                roll_log,
+            )
+            .await?
         }
         Command::Disadvantage(input) | Command::Dis(input) => {
-            handle_roll(bot, msg, input.as_str(), &RollType::Disadvantage, false).await?
+            handle_roll(
+                bot,
+                &msg,
+                store,
+                input.as_str(),
+                &RollType::Disadvantage,
+                false,
+                roll_log,
+            )
+            .await?
         }
         Command::DisadvantageData(input) => {
-            handle_roll(bot, msg, input.as_str(), &RollType::Disadvantage, true).await?
+            handle_roll(
+                bot,
+                &msg,
+                store,
+                input.as_str(),
+                &RollType::Disadvantage,
+                true,
+                roll_log,
+            )
+            .await?
+        }
+        Command::Encounter(input) => {
+            encounter::handle_encounter(bot, &msg, store, input.as_str()).await?
+        }
+        Command::Combat(input) => combat::handle_combat(bot, &msg, store, input.as_str()).await?,
+        Command::Sheet(input) => sheet::handle_sheet(bot, &msg, store, input.as_str()).await?,
+        Command::Characters(input) => {
+            characters::handle_characters(bot, &msg, store, input.as_str()).await?
+        }
+        Command::Attack(input) => attack::handle_attack(bot, &msg, store, input.as_str()).await?,
+        Command::Inspiration(input) => {
+            inspiration::handle_inspiration(bot, &msg, store, input.as_str()).await?
+        }
+        Command::Luck(input) => inspiration::handle_luck(bot, &msg, store, input.as_str()).await?,
+        Command::Flip(input) => chance::handle_flip(bot, &msg, input.as_str()).await?,
+        Command::Choose(input) => chance::handle_choose(bot, &msg, input.as_str()).await?,
+        Command::Table(input) => table::handle_table(bot, &msg, store, input.as_str()).await?,
+        Command::Surge(input) => surge::handle_surge(bot, &msg, store, input.as_str()).await?,
+        Command::Loot(input) => loot::handle_loot(bot, &msg, store, input.as_str()).await?,
+        Command::Npc => npc::handle_npc(bot, &msg, store).await?,
+        Command::Statgen(input) => stats::handle_statgen(bot, &msg, input.as_str()).await?,
+        Command::Pointbuy(input) => stats::handle_pointbuy(bot, &msg, input.as_str()).await?,
+        Command::Groupcheck(input) => {
+            groupcheck::handle_groupcheck(bot, &msg, store, input.as_str()).await?
+        }
+        Command::Hitdice(input) => rest::handle_hitdice(bot, &msg, store, input.as_str()).await?,
+        Command::Shortrest(input) => {
+            rest::handle_shortrest(bot, &msg, store, input.as_str()).await?
+        }
+        Command::Longrest => rest::handle_longrest(bot, &msg, store).await?,
+        Command::Spend(input) => rest::handle_spend(bot, &msg, store, input.as_str()).await?,
+        Command::Gold(input) => inventory::handle_gold(bot, &msg, store, input.as_str()).await?,
+        Command::Inventory(input) => {
+            inventory::handle_inventory(bot, &msg, store, input.as_str()).await?
+        }
+        Command::Split(input) => inventory::handle_split(bot, &msg, store, input.as_str()).await?,
+        Command::Downtime(input) => {
+            downtime::handle_downtime(bot, &msg, store, input.as_str()).await?
+        }
+        Command::Travel(input) => travel::handle_travel(bot, &msg, store, input.as_str()).await?,
+        Command::Weather(input) => {
+            weather::handle_weather(bot, &msg, store, input.as_str()).await?
+        }
+        Command::Name(input) => names::handle_name(bot, &msg, store, input.as_str()).await?,
+        Command::Rumor => rumor::handle_rumor(bot, &msg, store).await?,
+        Command::Concentrate(input) => {
+            concentration::handle_concentrate(bot, &msg, store, input.as_str()).await?
+        }
+        Command::Monster(input) => {
+            monster::handle_monster(bot, &msg, store, input.as_str()).await?
+        }
+        Command::Contest(input) => {
+            contest::handle_contest(bot, &msg, store, input.as_str()).await?
+        }
+        Command::Remind(input) => remind::handle_remind(bot, &msg, store, input.as_str()).await?,
+        Command::Timer(input) => timer::handle_timer(bot, &msg, store, input.as_str()).await?,
+        Command::Campaign(input) => {
+            campaign::handle_campaign(bot, &msg, store, roll_log.secret(), input.as_str()).await?
+        }
+        Command::Settings(input) => {
+            settings::handle_settings(bot, &msg, store, input.as_str()).await?
+        }
+        Command::Reroll if !may_reroll(&store, &msg).await => {
+            bot.reply(
+                &msg,
+                "The house rules of this campaign do not allow you to reroll.".to_string(),
+                vec![],
+            )
+            .await?;
+        }
+        Command::Reroll => match undo::last_roll(&store, &msg).await {
+            Some(last) => {
+                handle_roll(
+                    bot,
+                    &msg,
+                    store,
+                    &last.expression,
+                    &last.roll_type,
+                    false,
+                    roll_log,
+                )
+                .await?
+            }
+            None => {
+                bot.reply(
+                    &msg,
+                    "You have not rolled in this chat yet.".to_string(),
+                    vec![],
+                )
+                .await?;
+            }
+        },
+        Command::Undo => undo::handle_undo(bot, &msg, store).await?,
+        Command::Session(input) => {
+            session::handle_session(bot, &msg, store, input.as_str()).await?
+        }
+        Command::Exportlog(input) => {
+            history::handle_exportlog(bot, &msg, store, input.as_str()).await?
+        }
+        Command::Shuffle(input) => deck::handle_shuffle(bot, &msg, store, input.as_str()).await?,
+        Command::Draw(input) => deck::handle_draw(bot, &msg, store, input.as_str()).await?,
+        Command::Reading(input) => deck::handle_reading(bot, &msg, store, input.as_str()).await?,
+        Command::Deck(input) => deck::handle_deck(bot, &msg, store, input.as_str()).await?,
+        Command::About => about::handle_about(bot, &msg, &admin).await?,
+        Command::Ping => about::handle_ping(bot, &msg, received).await?,
+        Command::Donate => about::handle_donate(bot, &msg, &admin).await?,
+        Command::Admin(input) => {
+            admin::handle_admin(bot, &msg, store, &admin, input.as_str()).await?
         }
     };
 
     Ok(())
 }
 
+async fn may_reroll(store: &storage::Store, msg: &transport::Incoming) -> bool {
+    let Some(user_id) = msg.user_id() else {
+        return false;
+    };
+    let chat_id = msg.chat_id;
+    store
+        .read(|storage| campaign::may_reroll(storage, chat_id, user_id))
+        .await
+}
+
+/// Name of the character the user plays in the chat
+async fn active_character(store: &storage::Store, chat_id: i64, user_id: i64) -> Option<String> {
+    store
+        .read(|storage| {
+            storage
+                .user(user_id)
+                .and_then(|user| user.active_character(chat_id))
+                .map(str::to_string)
+        })
+        .await
+}
+
+/// Keep the roll in the history. Failing to do so does not fail the roll.
+async fn record_roll(
+    store: &storage::Store,
+    chat_id: i64,
+    user_id: i64,
+    character: Option<String>,
+    reply: i32,
+    input: &str,
+    results: &RollResults<'_>,
+) {
+    let record = history::RollRecord::new(chat_id, user_id, character, input, results);
+    if let Err(e) = store.record_roll(&record).await {
+        log::error!("Error recording roll: {:#}", e);
+    }
+    if let Err(e) = undo::remember(store, chat_id, user_id, reply, input, results).await {
+        log::error!("Error remembering the latest roll: {:#}", e);
+    }
+}
+
+/// The answer to a roll, with the notes of house rules and likely typos
+fn roll_text(
+    input: &str,
+    settings: &RollSettings,
+    results: &RollResults,
+    notes: &[String],
+    character: Option<&str>,
+) -> String {
+    let mut text = results.announce(character);
+    if let Some(echo) = settings.echo(input) {
+        text = format!("<i>{}</i>\n{}", teloxide::utils::html::escape(&echo), text);
+    }
+    for note in notes {
+        text.push_str(&format!("\n{}", note));
+    }
+    let label = settings.label.as_deref().unwrap_or_default();
+    for (word, flag) in parser::flag_typos(label) {
+        text.push_str(&format!(
+            "\n❓ <i>{}</i> was read as part of the label. Did you mean <code>{}</code>?",
+            teloxide::utils::html::escape(word),
+            flag
+        ));
+    }
+    text
+}
+
+/// Answers through [`transport::ChatTransport`], so that tests can answer through a fake
 async fn handle_roll(
-    bot: AdaptedBot,
-    msg: Message,
+    bot: &impl transport::ChatTransport,
+    to: &transport::Incoming,
+    store: storage::Store,
     input: &str,
     roll_type: &RollType,
     send_json: bool,
-) -> ResponseResult<()> {
+    roll_log: rolllog::RollLog,
+) -> anyhow::Result<()> {
     let silly_text =  "As a non-language non-model, I just spit out what was written in my code and I can never vary.";
     match input {
-        "" => {
-            bot.send_dice(msg.chat.id)
-                .reply_to_message_id(msg.id)
-                .await?;
-        }
+        "" => bot.roll_native_die(to).await?,
         "eye" | "eyes" | "👀" | "👁" | "👁‍🗨" => {
-            bot.send_message(msg.chat.id, silly_text)
-                .reply_to_message_id(msg.id)
-                .await?;
+            bot.reply(to, silly_text.to_string(), vec![]).await?;
         }
         input => {
-            let settings = RollSettings::from_str(input);
+            let start = std::time::Instant::now();
+            let settings = tracing::info_span!("parse").in_scope(|| RollSettings::from_str(input));
             match settings {
                 Ok(settings) => {
-                    let results = RollResults::new(&settings, roll_type);
+                    let mut results = tracing::info_span!("roll")
+                        .in_scope(|| RollResults::new(&settings, roll_type));
+                    telemetry::record_roll(start.elapsed());
+                    let chat_id = to.chat_id;
+                    let rules = store
+                        .read(|storage| houserules::for_chat(storage, chat_id))
+                        .await;
+                    let notes = houserules::apply(&rules, &mut results);
                     log::debug!("Dice roll: {:?}", results);
-                    let roll_msg = bot
-                        .send_message(msg.chat.id, results.to_string())
-                        .reply_to_message_id(msg.id)
+                    let user_id = to.user_id();
+                    let character = match user_id {
+                        Some(user_id) => active_character(&store, chat_id, user_id).await,
+                        None => None,
+                    };
+                    let text = roll_text(input, &settings, &results, &notes, character.as_deref());
+                    let buttons = user_id
+                        .and_then(|user_id| reroll::first_button(user_id, input, roll_type))
+                        .into_iter()
+                        .collect();
+                    let reply = bot
+                        .reply(to, text, buttons)
+                        .instrument(tracing::info_span!("telegram.send_message"))
                         .await?;
+                    let event = webhook::RollEvent::new(
+                        chat_id,
+                        user_id,
+                        character.clone(),
+                        output::RollOutput::new(input, &results),
+                    );
+                    roll_log.roll(&store, event).await;
+                    if let Some(user_id) = user_id {
+                        record_roll(&store, chat_id, user_id, character, reply, input, &results)
+                            .await;
+                    }
+                    if let Err(e) = settings::expire(&store, to, reply).await {
+                        log::error!("Error scheduling the deletion of a roll: {:#}", e);
+                    }
+                    if let Err(e) = reaction::react(bot, &store, to, &results).await {
+                        log::warn!("Error reacting to a roll: {:#}", e);
+                    }
                     if send_json {
-                        match serde_json::to_string_pretty(&results) {
+                        match serde_json::to_string_pretty(&output::RollOutput::new(
+                            input, &results,
+                        )) {
                             Ok(output_json) => {
-                                bot.send_document(
-                                    msg.chat.id,
-                                    InputFile::memory(output_json.into_bytes())
-                                        .file_name("roll.json"),
+                                bot.reply_with_file(
+                                    chat_id,
+                                    reply,
+                                    "roll.json",
+                                    output_json.into_bytes(),
+                                    None,
                                 )
-                                .reply_to_message_id(roll_msg.id)
                                 .await?;
                             }
                             Err(e) => {
-                                bot.send_message(
-                        msg.chat.id,
-                        format!("Could not convert results to JSON. This is a bug in the bot.\n\n<code>{}</code>", e),
-                    )
-                    .reply_to_message_id(msg.id)
-                    .await?;
+                                bot.reply(
+                                    to,
+                                    format!("Could not convert results to JSON. This is a bug in the bot.\n\n<code>{}</code>", e),
+                                    vec![],
+                                )
+                                .await?;
                             }
                         }
-                        // bot.send_document(msg.chat.id, document)
                     }
                 }
                 Err(e) => {
-                    bot.send_message(
-                        msg.chat.id,
-                        format!("{} \n\nIn other words, it is likely you have made a mistake and I definitely cannot help you to fix it. Try again!\n\n💣 <code>{}</code> 💣", silly_text, e),
-                    )
-                    .reply_to_message_id(msg.id)
-                    .await?;
+                    telemetry::record_parse_failure();
+                    let mut text = format!("{} \n\nIn other words, it is likely you have made a mistake and I definitely cannot help you to fix it. Try again!\n\n💣 <code>{}</code> 💣", silly_text, teloxide::utils::html::escape(&e.to_string()));
+                    if let Some(suggestion) = parser::suggest(input) {
+                        text.push_str(&format!(
+                            "\n\n💡 Did you mean <code>{}</code>?",
+                            teloxide::utils::html::escape(&suggestion)
+                        ));
+                    }
+                    bot.reply(to, text, vec![]).await?;
                 }
             }
         }
@@ -153,15 +642,91 @@ async fn handle_roll(
     Ok(())
 }
 
-async fn run_bot(args: &cli::RunArgs) -> anyhow::Result<()> {
+async fn open_backend(args: &cli::StorageArgs) -> anyhow::Result<Box<dyn StorageBackend>> {
+    #[cfg(feature = "postgres")]
+    if let Some(ref url) = args.database_url {
+        log::info!("Connecting to PostgreSQL...");
+        let backend = postgres::Postgres::connect(url, args.database_max_connections).await?;
+        return Ok(Box::new(backend));
+    }
+    log::info!("Opening storage at {}...", args.storage_path);
+    Ok(Box::new(storage::JsonFile::new(&args.storage_path)))
+}
+
+/// A limit of 0 would block the bot forever, so it counts as 1
+fn throttle_limits(args: &cli::ThrottleArgs) -> Limits {
+    Limits {
+        messages_per_sec_chat: args.throttle_chat_per_second.max(1),
+        messages_per_min_chat: args.throttle_chat_per_minute.max(1),
+        messages_per_min_channel: args.throttle_channel_per_minute.max(1),
+        messages_per_sec_overall: args.throttle_overall_per_second.max(1),
+    }
+}
+
+fn http_client(proxy: Option<&str>) -> anyhow::Result<reqwest::Client> {
+    let mut builder = teloxide::net::default_reqwest_settings();
+    if let Some(proxy) = proxy {
+        let proxy = reqwest::Proxy::all(proxy).context("error parsing the proxy URL")?;
+        builder = builder.proxy(proxy);
+    }
+    builder.build().context("error creating the HTTP client")
+}
+
+/// The bot with the adaptors that every handler expects
+fn adapt(bot: Bot, throttle: &cli::ThrottleArgs) -> AdaptedBot {
+    bot.cache_me()
+        .throttle(throttle_limits(throttle))
+        .parse_mode(ParseMode::Html)
+}
+
+async fn run_bot(args: &cli::RunArgs, reload: Option<admin::Reload>) -> anyhow::Result<()> {
     log::info!("Reading token...");
     let token = get_token(args.bot_token.as_ref(), args.bot_token_file.as_ref())?;
-    let bot = Bot::new(token)
-        .cache_me()
-        .throttle(Default::default())
-        .parse_mode(ParseMode::Html);
+    if args.proxy.is_some() {
+        // The URL may hold credentials, so it is not logged
+        log::info!("Reaching Telegram through a proxy");
+    }
+    let mut bot = Bot::with_client(token.clone(), http_client(args.proxy.as_deref())?);
+    if let Some(ref url) = args.api_url {
+        log::info!("Using the Bot API at {}", url);
+        bot = bot.set_api_url(url.clone());
+    }
+    let bot = adapt(bot, &args.throttle);
+
+    let store = storage::Store::open(open_backend(&args.storage).await?).await?;
+    if let Some(address) = args.metrics_address {
+        log::info!("Serving metrics on {}", address);
+        telemetry::install_metrics(address)?;
+    }
 
-    log::info!("Starting die rolling bot...");
+    let reporter = match args.error_chat {
+        None => report::Reporter::default(),
+        Some(chat_id) => {
+            log::info!("Reporting errors to chat {}", chat_id);
+            let reporter = report::Reporter::spawn(bot.clone(), chat_id, token);
+            reporter.install_panic_hook();
+            reporter
+        }
+    };
+
+    let updates = update_listeners::polling_default(bot.clone()).await;
+    serve(args, reload, bot, store, reporter, updates).await
+}
+
+/// Answer the updates from the listener until it stops, or until Ctrl-C
+async fn serve<L>(
+    args: &cli::RunArgs,
+    reload: Option<admin::Reload>,
+    bot: AdaptedBot,
+    store: storage::Store,
+    reporter: report::Reporter,
+    updates: L,
+) -> anyhow::Result<()>
+where
+    L: UpdateListener,
+    L::Err: std::fmt::Debug,
+{
+    log::info!("Starting die rolling bot {}...", about::version());
     log::info!("Running as: {:#?}", bot.get_me().await?);
 
     if args.set_my_commands {
@@ -170,10 +735,134 @@ async fn run_bot(args: &cli::RunArgs) -> anyhow::Result<()> {
         bot.set_my_commands(commands).await?;
     }
 
-    let handler = Update::filter_message()
-        .branch(dptree::entry().filter_command::<Command>().endpoint(answer));
+    let destination = args.roll_log.as_deref().map(str::parse).transpose()?;
+    if let Some(ref destination) = args.roll_log {
+        log::info!("Logging rolls to {}", destination);
+    }
+    let secret = args
+        .secret_key_file
+        .as_ref()
+        .map(secret::SecretKey::read)
+        .transpose()?;
+    let roll_log =
+        rolllog::RollLog::spawn(destination, http_client(args.proxy.as_deref())?, secret);
+
+    tokio::spawn(scheduler::run(bot.clone(), store.clone()));
+    if let Some(ref pattern) = args.character_data {
+        tokio::spawn(watcher::run(store.clone(), pattern.clone()));
+    }
+    let backups = args.backup_dir.as_ref().map(|directory| backup::Backups {
+        directory: directory.into(),
+        retention: args.backup_retention,
+    });
+    if let Some(ref backups) = backups {
+        let every = std::time::Duration::from_secs(args.backup_interval.max(1) * 60);
+        tokio::spawn(backup::run(store.clone(), backups.clone(), every));
+    }
+    let limiter = Arc::new(ratelimit::RateLimiter::new(args.rate_limit));
+    let admin = Arc::new(admin::Admin::new(args, backups, limiter.clone(), reload));
+
+    let messages = Update::filter_message()
+        .map(|msg: Message| transport::Incoming::from(&msg))
+        .branch(
+            dptree::filter_async(
+                |msg: Message, store: storage::Store, admin: Arc<admin::Admin>| async move {
+                    !admin.allows(&store, &msg.chat).await
+                },
+            )
+            .endpoint(
+                |bot: AdaptedBot, msg: transport::Incoming, admin: Arc<admin::Admin>| async move {
+                    admin::leave_chat(&bot, &msg, &admin).await
+                },
+            ),
+        )
+        .branch(
+            dptree::filter_async(
+                |msg: Message, store: storage::Store, admin: Arc<admin::Admin>| async move {
+                    match msg.from() {
+                        Some(user) => admin::is_banned(&store, &admin, user.id.0 as i64).await,
+                        None => false,
+                    }
+                },
+            )
+            .endpoint(|| async { anyhow::Ok(()) }),
+        )
+        .branch(
+            dptree::entry()
+                .filter_command::<Command>()
+                .filter(|cmd: Command, msg: Message, admin: Arc<admin::Admin>| {
+                    matches!(cmd, Command::Admin(_)) && !admin.is_operator(&msg)
+                })
+                .endpoint(|bot: AdaptedBot, msg: transport::Incoming| async move {
+                    admin::refuse(&bot, &msg).await
+                }),
+        )
+        .branch(
+            dptree::entry()
+                .filter_command::<Command>()
+                .filter_map(|msg: Message, limiter: Arc<ratelimit::RateLimiter>| {
+                    let user = msg.from()?;
+                    match limiter.check(user.id.0 as i64, std::time::Instant::now()) {
+                        ratelimit::Decision::Allow => None,
+                        decision => Some(decision),
+                    }
+                })
+                .endpoint(
+                    |bot: AdaptedBot, msg: transport::Incoming, decision: ratelimit::Decision| async move {
+                        ratelimit::cooldown(&bot, &msg, decision).await
+                    },
+                ),
+        )
+        .branch(
+            dptree::entry()
+                .filter_command::<Command>()
+                .endpoint(instrumented_answer),
+        );
+    let handler = dptree::entry().branch(messages).branch(
+        Update::filter_callback_query()
+            .map(|query: CallbackQuery| transport::Press::from(&query))
+            .branch(
+                dptree::filter_async(
+                    |press: transport::Press, store: storage::Store, admin: Arc<admin::Admin>| async move {
+                        admin::is_banned(&store, &admin, press.from.id).await
+                    },
+                )
+                .endpoint(|| async { anyhow::Ok(()) }),
+            )
+            .branch(
+                dptree::filter_map(
+                    |press: transport::Press, limiter: Arc<ratelimit::RateLimiter>| {
+                        match limiter.check(press.from.id, std::time::Instant::now()) {
+                            ratelimit::Decision::Allow => None,
+                            decision => Some(decision),
+                        }
+                    },
+                )
+                .endpoint(
+                    |bot: AdaptedBot, press: transport::Press, decision: ratelimit::Decision| async move {
+                        ratelimit::cooldown_button(&bot, &press, decision).await
+                    },
+                ),
+            )
+            .branch(
+                dptree::filter(|press: transport::Press| characters::is_button(&press)).endpoint(
+                    |bot: AdaptedBot, press: transport::Press, store: storage::Store| async move {
+                        characters::handle_press(&bot, &press, store).await
+                    },
+                ),
+            )
+            .endpoint(
+                |bot: AdaptedBot,
+                 press: transport::Press,
+                 store: storage::Store,
+                 roll_log: rolllog::RollLog| async move {
+                    reroll::handle_press(&bot, &press, store, roll_log).await
+                },
+            ),
+    );
 
     Dispatcher::builder(bot, handler)
+        .dependencies(dptree::deps![store, admin, limiter, reporter, roll_log])
         .default_handler(|update| async move {
             log::warn!("Unhandled update {:?}", update);
         })
@@ -182,40 +871,380 @@ async fn run_bot(args: &cli::RunArgs) -> anyhow::Result<()> {
         ))
         .enable_ctrlc_handler()
         .build()
-        .dispatch()
+        .dispatch_with_listener(
+            updates,
+            LoggingErrorHandler::with_custom_text("An error from the update listener"),
+        )
         .await;
 
     Ok(())
 }
 
 async fn load_character_data(path: &str) -> anyhow::Result<()> {
-    let (ok, err) = dnd::Character::load_from_pattern(path)?;
-    for character in ok {
-        log::info!("Loaded {:#?}", character);
+    let reports = diagnostics::check_pattern(path)?;
+    let failed = reports
+        .iter()
+        .filter(|report| report.result.is_err())
+        .count();
+    for report in &reports {
+        match report.result {
+            Ok(ref character) => log::info!("Loaded {:#?}", character),
+            Err(_) => log::error!("{}", report),
+        }
     }
-    for error in err {
-        log::error!("{:#?}", error);
+    if failed > 0 {
+        anyhow::bail!(
+            "{} of {} character files are invalid. Run the `schema` subcommand for the JSON Schema that character data must follow.",
+            failed,
+            reports.len()
+        );
     }
     Ok(())
 }
 
+fn print_schema(output: Option<&str>) -> anyhow::Result<()> {
+    let schema = schemars::schema_for!(dnd::Character);
+    let json = serde_json::to_string_pretty(&schema)?;
+    match output {
+        Some(output) => std::fs::write(output, json)?,
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+fn convert_character<F>(convert: F, input: &str, output: Option<&str>) -> anyhow::Result<()>
+where
+    F: FnOnce(&[u8]) -> anyhow::Result<dnd::Character>,
+{
+    let export = std::fs::read(input)?;
+    let character = convert(&export)?;
+    let json = serde_json::to_string_pretty(&character)?;
+    match output {
+        Some(output) => std::fs::write(output, json)?,
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+fn roll_type(adv: bool, dis: bool) -> RollType {
+    match (adv, dis) {
+        (true, _) => RollType::Advantage,
+        (_, true) => RollType::Disadvantage,
+        _ => RollType::Straight,
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    pretty_env_logger::formatted_timed_builder()
-        .filter_level(log::LevelFilter::Info)
-        .init();
-    let cli = cli::Cli::parse();
+    let matches = cli::Cli::command().get_matches();
+    let cli = cli::Cli::from_arg_matches(&matches)?;
+    let _guard = telemetry::init_logging(&cli)?;
     log::debug!("Command line: {:?}", cli);
 
     match cli.command {
         None => {
             println!("{:?}", cli);
         }
-        Some(cli::Command::Run(args)) => {
-            run_bot(&args).await?;
+        Some(cli::Command::Run(mut args)) => {
+            let matches = matches.subcommand_matches("run").unwrap_or(&matches);
+            let reload = match args.config.clone() {
+                None => None,
+                Some(path) => {
+                    let original = (*args).clone();
+                    config::Config::load(&path)?.apply(&mut args, matches);
+                    Some(admin::Reload {
+                        path,
+                        args: original,
+                        matches: matches.clone(),
+                    })
+                }
+            };
+            run_bot(&args, reload).await?;
+        }
+        Some(cli::Command::Validate(args)) => {
+            let matches = matches.subcommand_matches("validate").unwrap_or(&matches);
+            let checks = validate::check(*args, matches).await;
+            for check in &checks {
+                println!("{}", check);
+            }
+            let failed = checks.iter().filter(|check| check.result.is_err()).count();
+            if failed > 0 {
+                anyhow::bail!("{} of {} checks failed", failed, checks.len());
+            }
+        }
+        Some(cli::Command::Roll {
+            expression,
+            adv,
+            dis,
+            json,
+        }) => {
+            let roll_type = roll_type(adv, dis);
+            println!(
+                "{}",
+                offline::roll(&expression.join(" "), &roll_type, json)?
+            );
+        }
+        Some(cli::Command::Simulate {
+            expression,
+            iterations,
+            adv,
+            dis,
+            format,
+        }) => {
+            let distribution =
+                offline::Distribution::simulate(&expression, &roll_type(adv, dis), iterations)?;
+            match format {
+                cli::SimulationFormat::Text => print!("{}", distribution.to_text()),
+                cli::SimulationFormat::Csv => print!("{}", distribution.to_csv()),
+            }
+        }
+        Some(cli::Command::Bench { corpus, duration }) => {
+            let expressions: Vec<String> = match corpus {
+                None => offline::CORPUS.iter().map(ToString::to_string).collect(),
+                Some(corpus) => std::fs::read_to_string(&corpus)
+                    .with_context(|| format!("error reading corpus {}", corpus))?
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(ToString::to_string)
+                    .collect(),
+            };
+            let duration = std::time::Duration::from_millis(duration);
+            let results = offline::bench(&expressions, duration);
+            print!("{}", offline::format_throughput(&results));
+            let results = offline::bench_distributions(&[6, 20, 100], duration);
+            print!("\n{}", offline::format_distributions(&results));
+        }
+        Some(cli::Command::Completions { shell }) => {
+            let mut command = cli::Cli::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+        }
+        Some(cli::Command::ManPage { output }) => {
+            let mut page = vec![];
+            clap_mangen::Man::new(cli::Cli::command()).render(&mut page)?;
+            match output {
+                Some(output) => std::fs::write(output, page)?,
+                None => std::io::Write::write_all(&mut std::io::stdout(), &page)?,
+            }
         }
         Some(cli::Command::LoadCharacterData { path }) => load_character_data(&path).await?,
+        Some(cli::Command::ConvertDdb { input, output }) => {
+            convert_character(ddb::convert, &input, output.as_deref())?
+        }
+        Some(cli::Command::ConvertFoundry { input, output }) => {
+            convert_character(foundry::convert, &input, output.as_deref())?
+        }
+        Some(cli::Command::ExportStorage { storage, output }) => {
+            let archive = archive::export(open_backend(&storage).await?.as_ref()).await?;
+            let json = serde_json::to_string_pretty(&archive)?;
+            match output {
+                Some(output) => std::fs::write(output, json)?,
+                None => println!("{}", json),
+            }
+        }
+        Some(cli::Command::ImportStorage {
+            storage,
+            input,
+            force,
+        }) => {
+            let archive = serde_json::from_slice(&std::fs::read(&input)?)?;
+            archive::import(open_backend(&storage).await?.as_ref(), archive, force).await?;
+        }
+        Some(cli::Command::Schema { output }) => print_schema(output.as_deref())?,
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::{FakeTransport, MockBotApi, Sent};
+
+    #[tokio::test]
+    async fn answers_a_roll() {
+        let (bot, store) = (FakeTransport::default(), testing::store().await);
+        let to = testing::incoming(7);
+        let roll_log = rolllog::RollLog::default();
+        handle_roll(
+            &bot,
+            &to,
+            store.clone(),
+            "1d1 + 2 stealth",
+            &RollType::Straight,
+            true,
+            roll_log,
+        )
+        .await
+        .unwrap();
+
+        let sent = bot.sent();
+        let Sent::Reply { text, buttons, .. } = &sent[0] else {
+            panic!("expected a reply first, got {:?}", sent);
+        };
+        assert!(text.starts_with("<u>stealth</u>\nParameters: 1d1 + 2\n"));
+        assert!(text.ends_with("Your final roll is: 🎲 <b>3</b> 🎲"));
+        assert_eq!(buttons[0].label, "🎲 Reroll");
+        // Nothing special to celebrate
+        assert!(
+            matches!(&sent[1], Sent::File { name, message_id: 1000, .. } if name == "roll.json")
+        );
+        let rolls = store
+            .rolls(testing::CHAT_ID, chrono::DateTime::UNIX_EPOCH)
+            .await
+            .unwrap();
+        assert_eq!((rolls.len(), rolls[0].total), (1, 3));
+    }
+
+    #[tokio::test]
+    async fn suggests_a_fix_for_what_it_cannot_parse() {
+        let (bot, store) = (FakeTransport::default(), testing::store().await);
+        let roll_log = rolllog::RollLog::default();
+        let to = testing::incoming(7);
+        handle_roll(
+            &bot,
+            &to,
+            store,
+            "20d+",
+            &RollType::Straight,
+            false,
+            roll_log,
+        )
+        .await
+        .unwrap();
+        let replies = bot.replies();
+        assert_eq!(replies.len(), 1);
+        assert!(replies[0].contains("💣"));
+        assert!(replies[0].ends_with("💡 Did you mean <code>1d20</code>?"));
+    }
+
+    #[tokio::test]
+    async fn undoes_the_latest_roll() {
+        let (bot, store) = (FakeTransport::default(), testing::store().await);
+        let to = testing::incoming(7);
+        let roll_log = rolllog::RollLog::default();
+        handle_roll(
+            &bot,
+            &to,
+            store.clone(),
+            "1d1",
+            &RollType::Straight,
+            false,
+            roll_log,
+        )
+        .await
+        .unwrap();
+        undo::handle_undo(&bot, &to, store.clone()).await.unwrap();
+        undo::handle_undo(&bot, &to, store.clone()).await.unwrap();
+
+        let sent = bot.sent();
+        assert!(sent.iter().any(|sent| matches!(
+            sent,
+            Sent::Edit { message_id: 1000, text, .. } if text.contains("Voided with /undo")
+        )));
+        let replies = bot.replies();
+        assert_eq!(replies[1], "Voided your roll of <code>1d1</code>.");
+        assert_eq!(replies[2], "You have no roll to undo in this chat.");
+        let rolls = store
+            .rolls(testing::CHAT_ID, chrono::DateTime::UNIX_EPOCH)
+            .await
+            .unwrap();
+        assert!(rolls.is_empty());
+    }
+
+    /// Run the updates through the dispatcher until they have all been answered
+    async fn dispatch(api: &MockBotApi, flags: &[&str], updates: Vec<Update>) {
+        let matches = cli::RunArgs::command()
+            .try_get_matches_from(["run"].iter().chain(flags))
+            .unwrap();
+        let args = cli::RunArgs::from_arg_matches(&matches).unwrap();
+        let bot = adapt(api.bot(), &args.throttle);
+        let store = testing::store().await;
+        let reporter = report::Reporter::default();
+        serve(&args, None, bot, store, reporter, testing::updates(updates))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn answers_updates_through_the_bot_api() {
+        let api = MockBotApi::start().await;
+        dispatch(
+            &api,
+            &[],
+            vec![
+                testing::text_update(1, 42, "/roll 1d1 + 2"),
+                testing::text_update(2, 42, "/adv 1d20"),
+                testing::button_update(3, 42, 1001, "r:42:a:1:1d20"),
+                testing::text_update(4, 42, "/data 1d1"),
+            ],
+        )
+        .await;
+
+        let messages = api.calls_of("sendMessage");
+        let roll = messages[0].json();
+        assert_eq!(roll["chat_id"], testing::CHAT_ID);
+        assert_eq!(roll["reply_to_message_id"], 1);
+        assert_eq!(roll["parse_mode"], "HTML");
+        assert!(roll["text"]
+            .as_str()
+            .unwrap()
+            .ends_with("Your final roll is: 🎲 <b>3</b> 🎲"));
+        let advantage = messages[1].json();
+        assert_eq!(
+            advantage["reply_markup"]["inline_keyboard"][0][0]["callback_data"],
+            "r:42:a:1:1d20"
+        );
+
+        assert_eq!(api.calls_of("answerCallbackQuery").len(), 1);
+        let edit = api.calls_of("editMessageText")[0].json();
+        assert_eq!(edit["message_id"], 1001);
+        assert!(edit["text"].as_str().unwrap().ends_with("🔁 Attempt 2"));
+        assert_eq!(
+            edit["reply_markup"]["inline_keyboard"][0][0]["callback_data"],
+            "r:42:a:2:1d20"
+        );
+
+        let documents = api.calls_of("sendDocument");
+        assert_eq!(documents.len(), 1);
+        let attachment = String::from_utf8_lossy(&documents[0].body);
+        assert!(attachment.contains("filename=\"roll.json\""));
+        assert!(attachment.contains("\"total\": 1"));
+    }
+
+    #[tokio::test]
+    async fn rate_limits_buttons_too() {
+        let api = MockBotApi::start().await;
+        dispatch(
+            &api,
+            &["--rate-limit", "1"],
+            vec![
+                testing::text_update(1, 42, "/roll 1d20"),
+                testing::button_update(2, 42, 1001, "r:42:s:1:1d20"),
+            ],
+        )
+        .await;
+        assert!(api.calls_of("editMessageText").is_empty());
+        let answer = api.calls_of("answerCallbackQuery")[0].json();
+        assert!(answer["text"].as_str().unwrap().starts_with("Easy there!"));
+    }
+
+    #[tokio::test]
+    async fn ignores_buttons_of_banned_users() {
+        let api = MockBotApi::start().await;
+        dispatch(
+            &api,
+            &["--operator", "7"],
+            vec![
+                testing::text_update(1, 42, "/roll 1d20"),
+                testing::text_update(2, 7, "/admin ban 42"),
+                testing::button_update(3, 42, 1001, "r:42:s:1:1d20"),
+            ],
+        )
+        .await;
+        assert_eq!(api.calls_of("sendMessage").len(), 2);
+        assert!(api.calls_of("editMessageText").is_empty());
+        assert!(api.calls_of("answerCallbackQuery").is_empty());
+    }
+}
@@ -0,0 +1,401 @@
+//! Monster stat blocks of a campaign, uploaded by the GM and rolled with `/monster attack`.
+
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+use teloxide::utils::html;
+
+use crate::combat::{Combatant, HitPoints};
+use crate::dice::RollSettings;
+use crate::dnd::Attack;
+use crate::storage::{Storage, Store};
+use crate::transport::{ChatTransport, Incoming};
+
+/// A simplified stat block
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct Monster {
+    pub name: String,
+    pub armor_class: u8,
+    pub hit_points: u16,
+    #[serde(default)]
+    pub initiative_modifier: i8,
+    #[serde(default)]
+    pub attacks: Vec<Attack>,
+}
+
+impl Monster {
+    /// A combatant with the stats of the monster, at full hit points
+    pub fn combatant(&self, name: String, initiative: i64) -> Combatant {
+        Combatant {
+            name,
+            initiative,
+            armor_class: Some(self.armor_class),
+            hit_points: Some(HitPoints {
+                current: self.hit_points as i32,
+                max: self.hit_points,
+            }),
+        }
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        for attack in &self.attacks {
+            attack
+                .damage
+                .parse::<RollSettings>()
+                .with_context(|| format!("invalid damage for {}", attack.name))?;
+        }
+        Ok(())
+    }
+}
+
+/// The monster of a name like `Goblin`, or of a combatant like `goblin2` or `Goblin 2`
+pub(crate) fn find<'a>(monsters: &'a [Monster], name: &str) -> Option<&'a Monster> {
+    let name = crate::combat::normalize(name);
+    let base = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    monsters
+        .iter()
+        .find(|monster| crate::combat::normalize(&monster.name) == name)
+        .or_else(|| {
+            monsters
+                .iter()
+                .find(|monster| crate::combat::normalize(&monster.name) == base)
+        })
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(Monster),
+    Many(Vec<Monster>),
+}
+
+fn parse_monsters(contents: &[u8]) -> anyhow::Result<Vec<Monster>> {
+    let monsters = match serde_json::from_slice(contents)
+        .map_err(|_| anyhow!("not a valid monster or list of monsters"))?
+    {
+        OneOrMany::One(monster) => vec![monster],
+        OneOrMany::Many(monsters) => monsters,
+    };
+    if monsters.is_empty() {
+        bail!("no monsters in the file");
+    }
+    for monster in &monsters {
+        monster
+            .validate()
+            .with_context(|| format!("invalid monster {}", monster.name))?;
+    }
+    Ok(monsters)
+}
+
+/// Attack with a monster of the combat, or of the campaign, applying the damage to a target in the combat
+fn attack(storage: &mut Storage, chat_id: i64, args: &[&str]) -> String {
+    let [attacker, rest @ ..] = args else {
+        return USAGE.to_string();
+    };
+    let rules = crate::houserules::for_chat(storage, chat_id);
+    let chat = storage.chat_mut(chat_id);
+    let monsters = chat
+        .campaign
+        .as_ref()
+        .map(|campaign| campaign.monsters.as_slice())
+        .unwrap_or_default();
+    let Some(monster) = find(monsters, attacker) else {
+        return format!(
+            "The campaign has no monster {}. See /monster list.",
+            html::escape(attacker)
+        );
+    };
+    let name = chat
+        .combat
+        .combatant(attacker)
+        .map(|combatant| combatant.name.clone())
+        .unwrap_or_else(|| monster.name.clone());
+
+    // The last argument may be a combatant to attack
+    let (input, target) = match rest.split_last() {
+        Some((target, attack)) if !attack.is_empty() && chat.combat.combatant(target).is_some() => {
+            (attack.join(" "), Some(*target))
+        }
+        _ => (rest.join(" "), None),
+    };
+    let (attack_name, roll_type) = crate::attack::parse_input(&input);
+    let attack = monster.attacks.iter().find(|attack| {
+        !attack_name.is_empty()
+            && attack
+                .name
+                .to_lowercase()
+                .starts_with(&attack_name.to_lowercase())
+    });
+    let Some(attack) = attack else {
+        let attacks: Vec<_> = monster
+            .attacks
+            .iter()
+            .map(|attack| format!("• {}", html::escape(&attack.name)))
+            .collect();
+        return format!(
+            "{} can attack with:\n{}",
+            html::escape(&monster.name),
+            attacks.join("\n")
+        );
+    };
+
+    let (mut text, hit) =
+        crate::attack::roll_attack(&name, attack, &roll_type, &rules, &mut rand::thread_rng());
+    let (Some(target), Some(hit)) = (target.and_then(|t| chat.combat.combatant_mut(t)), hit) else {
+        return text;
+    };
+    match target.armor_class {
+        Some(armor_class) if !hit.crit && hit.to_hit < armor_class as i64 => {
+            text.push_str(&format!(
+                "\n🛡 Misses {} (AC {})",
+                html::escape(&target.name),
+                armor_class
+            ));
+        }
+        _ => {
+            text.push_str(&format!(
+                "\n🩸 {} takes {} damage",
+                html::escape(&target.name),
+                hit.damage
+            ));
+            if let Some(ref mut hit_points) = target.hit_points {
+                hit_points.current -= hit.damage.clamp(0, i32::MAX as i64) as i32;
+                text.push_str(&format!(", HP {}/{}", hit_points.current, hit_points.max));
+            }
+            let target = target.name.clone();
+            if let Some(save) =
+                crate::concentration::on_damage(storage, chat_id, &target, hit.damage)
+            {
+                text.push_str(&format!("\n{}", save));
+            }
+        }
+    }
+    text
+}
+
+/// Change the hit points of a combatant by a number like `-7` or `+3`
+fn change_hit_points(storage: &mut Storage, chat_id: i64, args: &[&str]) -> String {
+    let [name, change] = args else {
+        return USAGE.to_string();
+    };
+    let Ok(change) = change.trim_start_matches('+').parse::<i32>() else {
+        return USAGE.to_string();
+    };
+    let combat = &mut storage.chat_mut(chat_id).combat;
+    let Some(combatant) = combat.combatant_mut(name) else {
+        return format!("Nobody called {} is in the combat.", html::escape(name));
+    };
+    let Some(ref mut hit_points) = combatant.hit_points else {
+        return format!(
+            "{} has no hit points tracked.",
+            html::escape(&combatant.name)
+        );
+    };
+    hit_points.current = hit_points
+        .current
+        .saturating_add(change)
+        .min(hit_points.max as i32);
+    let mut text = format!(
+        "{} is at HP {}/{}",
+        html::escape(&combatant.name),
+        hit_points.current,
+        hit_points.max
+    );
+    let name = combatant.name.clone();
+    if let Some(save) = crate::concentration::on_damage(storage, chat_id, &name, -(change as i64)) {
+        text.push_str(&format!("\n{}", save));
+    }
+    text
+}
+
+fn list(storage: &Storage, chat_id: i64) -> String {
+    let monsters = storage
+        .chat(chat_id)
+        .and_then(|chat| chat.campaign.as_ref())
+        .map(|campaign| campaign.monsters.as_slice())
+        .unwrap_or_default();
+    if monsters.is_empty() {
+        return "The campaign has no monsters. The GM uploads them with /monster upload."
+            .to_string();
+    }
+    monsters
+        .iter()
+        .map(|monster| {
+            let attacks: Vec<_> = monster
+                .attacks
+                .iter()
+                .map(|attack| html::escape(&attack.name))
+                .collect();
+            format!(
+                "• <b>{}</b>: AC {}, HP {}{}",
+                html::escape(&monster.name),
+                monster.armor_class,
+                monster.hit_points,
+                match attacks.is_empty() {
+                    true => String::new(),
+                    false => format!(", {}", attacks.join(", ")),
+                }
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+const USAGE: &str = "<code>/monster list</code> lists the monsters of the campaign
+<code>/monster upload</code> as a reply to a JSON file adds or replaces monsters
+<code>/monster remove goblin</code> removes a monster
+<code>/monster attack goblin1 bite [adv|dis] [Thorin]</code> attacks, applying the damage to a combatant on a hit
+<code>/monster hp goblin1 -7</code> changes the hit points of a combatant
+Monsters join /combat with their armor class and hit points, such as with <code>/combat start goblin x4</code>.";
+
+pub(crate) async fn handle_monster(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let Some(user) = &msg.from else {
+        return Ok(());
+    };
+    let (chat_id, user_id) = (msg.chat_id, user.id);
+    let args: Vec<_> = input.split_whitespace().collect();
+    let gm = store
+        .read(|storage| {
+            storage
+                .chat(chat_id)
+                .and_then(|chat| chat.campaign.as_ref())
+                .map(|campaign| campaign.gm)
+        })
+        .await;
+
+    let text = match (args.as_slice(), gm) {
+        (["list"], _) => store.read(|storage| list(storage, chat_id)).await,
+        (_, None) => "Monsters belong to a campaign. Start one with /campaign create.".to_string(),
+        ([], _) => USAGE.to_string(),
+        (_, Some(gm)) if gm != user_id => "Only the GM may use monsters.".to_string(),
+        (["upload"], _) => match crate::upload::replied_document(bot, msg).await? {
+            Err(e) => e.to_string(),
+            Ok(contents) => match parse_monsters(&contents) {
+                Err(e) => format!("<code>{}</code>", html::escape(&format!("{:#}", e))),
+                Ok(monsters) => {
+                    let names = monsters
+                        .iter()
+                        .map(|monster| monster.name.clone())
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    store
+                        .update(|storage| {
+                            let campaign = storage
+                                .chat_mut(chat_id)
+                                .campaign
+                                .as_mut()
+                                .expect("to have a campaign");
+                            for monster in monsters {
+                                campaign.monsters.retain(|m| m.name != monster.name);
+                                campaign.monsters.push(monster);
+                            }
+                        })
+                        .await?;
+                    format!("Saved monsters: {}", html::escape(&names))
+                }
+            },
+        },
+        (["remove", name @ ..], _) => {
+            let name = name.join(" ");
+            let removed = store
+                .update(|storage| {
+                    let campaign = storage
+                        .chat_mut(chat_id)
+                        .campaign
+                        .as_mut()
+                        .expect("to have a campaign");
+                    let before = campaign.monsters.len();
+                    campaign
+                        .monsters
+                        .retain(|monster| !monster.name.eq_ignore_ascii_case(&name));
+                    campaign.monsters.len() != before
+                })
+                .await?;
+            match removed {
+                true => format!("Removed {}", html::escape(&name)),
+                false => format!("The campaign has no monster {}", html::escape(&name)),
+            }
+        }
+        (["attack", args @ ..], _) => {
+            store
+                .update(|storage| attack(storage, chat_id, args))
+                .await?
+        }
+        (["hp", args @ ..], _) => {
+            store
+                .update(|storage| change_hit_points(storage, chat_id, args))
+                .await?
+        }
+        _ => USAGE.to_string(),
+    };
+
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::campaign::Campaign;
+
+    const GOBLIN: &str = r#"{
+        "name": "Goblin",
+        "armor_class": 1,
+        "hit_points": 7,
+        "initiative_modifier": 2,
+        "attacks": [{"name": "Scimitar", "to_hit": 4, "damage": "1d1+2", "damage_type": "slashing"}]
+    }"#;
+
+    #[test]
+    fn attacks_combatants() {
+        let monsters = parse_monsters(GOBLIN.as_bytes()).unwrap();
+        assert_eq!(find(&monsters, "goblin2").unwrap().name, "Goblin");
+        assert!(find(&monsters, "ogre").is_none());
+        assert!(parse_monsters(
+            br#"{"name": "Ogre", "armor_class": 11, "hit_points": 59,
+            "attacks": [{"name": "Club", "to_hit": 6, "damage": "big"}]}"#
+        )
+        .is_err());
+
+        let mut storage = Storage::default();
+        let chat = storage.chat_mut(1);
+        chat.campaign = Some(Campaign {
+            name: "Curse of Strahd".to_string(),
+            gm: 3,
+            players: Default::default(),
+            house_rules: Default::default(),
+            monsters: monsters.clone(),
+            webhook: None,
+            dddice: None,
+        });
+        chat.combat
+            .add(monsters[0].combatant("Goblin 1".to_string(), 12));
+        chat.combat
+            .add(monsters[0].combatant("Goblin 2".to_string(), 8));
+
+        let text = attack(&mut storage, 1, &["goblin1", "scim", "goblin2"]);
+        assert!(text.starts_with("⚔️ <b>Goblin 1</b> attacks with <u>Scimitar</u>"));
+        let expected = match text {
+            _ if text.contains("Critical miss") => None,
+            _ if text.contains("Critical hit") => Some("🩸 Goblin 2 takes 4 damage, HP 3/7"),
+            _ => Some("🩸 Goblin 2 takes 3 damage, HP 4/7"),
+        };
+        if let Some(expected) = expected {
+            assert!(text.ends_with(expected), "{}", text);
+        }
+        assert_eq!(
+            change_hit_points(&mut storage, 1, &["goblin2", "+10"]),
+            "Goblin 2 is at HP 7/7"
+        );
+        assert_eq!(
+            change_hit_points(&mut storage, 1, &["goblin2", "-2147483648"]),
+            "Goblin 2 is at HP -2147483641/7"
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! Names for improvised characters, rolled on the `name_` random tables of the chat or the built in ones.
+
+use teloxide::utils::html;
+
+use crate::storage::Store;
+use crate::transport::{ChatTransport, Incoming};
+
+/// Most names rolled at once
+const MAX_NAMES: usize = 10;
+
+/// The culture, gender and number of names of `/name dwarf female 3`, in any order
+fn parse(input: &str) -> Option<(String, Option<&'static str>, usize)> {
+    let mut culture = None;
+    let mut gender = None;
+    let mut count = None;
+    for arg in input.split_whitespace() {
+        let arg = arg.to_lowercase();
+        match arg.as_str() {
+            "male" | "man" | "m" if gender.is_none() => gender = Some("male"),
+            "female" | "woman" | "f" if gender.is_none() => gender = Some("female"),
+            _ => match arg.parse() {
+                Ok(number) if count.is_none() && (1..=MAX_NAMES).contains(&number) => {
+                    count = Some(number)
+                }
+                Ok(_) => return None,
+                Err(_) if culture.is_none() => culture = Some(arg),
+                Err(_) => return None,
+            },
+        }
+    }
+    Some((
+        culture.unwrap_or_else(|| "human".to_string()),
+        gender,
+        count.unwrap_or(1),
+    ))
+}
+
+/// Names from the table of the culture and gender, like `name_dwarf_female`, or of the culture
+fn names(
+    tables: &[crate::table::RandomTable],
+    culture: &str,
+    gender: Option<&str>,
+    count: usize,
+) -> String {
+    let names = [
+        gender.map(|gender| format!("name_{}_{}", culture, gender)),
+        Some(format!("name_{}", culture)),
+    ];
+    let Some(name) = names
+        .into_iter()
+        .flatten()
+        .find(|name| crate::table::find(tables, name).is_some())
+    else {
+        return format!(
+            "There are no names for {}. Upload a random table named name_{} with /table.",
+            html::escape(culture),
+            html::escape(culture)
+        );
+    };
+    let rolled: anyhow::Result<Vec<_>> = (0..count)
+        .map(|_| crate::table::roll_entry(tables, &name))
+        .collect();
+    match rolled {
+        Ok(rolled) => format!("📛 {}", rolled.join("\n📛 ")),
+        Err(e) => format!("<code>{}</code>", html::escape(&format!("{:#}", e))),
+    }
+}
+
+const USAGE: &str = "<code>/name dwarf female</code> rolls a name
+<code>/name elf 5</code> rolls five names of either gender
+The built in cultures are human, dwarf, elf, halfling and orc. Upload random tables named like name_tiefling or name_tiefling_male with /table to use your own name lists.";
+
+pub(crate) async fn handle_name(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let text = match parse(input) {
+        Some((culture, gender, count)) if culture != "help" => {
+            store
+                .read(|storage| {
+                    let tables = storage
+                        .chat(chat_id)
+                        .map(|chat| chat.random_tables.as_slice())
+                        .unwrap_or_default();
+                    names(tables, &culture, gender, count)
+                })
+                .await
+        }
+        _ => USAGE.to_string(),
+    };
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolls_names() {
+        assert_eq!(
+            parse("Dwarf female 3"),
+            Some(("dwarf".to_string(), Some("female"), 3))
+        );
+        assert_eq!(parse(""), Some(("human".to_string(), None, 1)));
+        assert_eq!(parse("elf 50"), None);
+
+        for culture in ["human", "dwarf", "elf", "halfling", "orc"] {
+            let text = names(&[], culture, Some("male"), 2);
+            assert_eq!(text.matches("📛 ").count(), 2, "{}", text);
+        }
+        let tables: Vec<crate::table::RandomTable> = serde_json::from_str(
+            r#"[{"name": "name_tiefling", "entries": [{"text": "Akmenos"}]}]"#,
+        )
+        .unwrap();
+        assert_eq!(names(&tables, "tiefling", Some("male"), 1), "📛 Akmenos");
+        assert!(names(&tables, "goblin", None, 1).starts_with("There are no names"));
+    }
+}
@@ -0,0 +1,90 @@
+//! Improvised NPCs, rolled on the `npc` random table of the chat or the built in one.
+
+use teloxide::utils::html;
+
+use crate::dice::{Flags, Roll, RollSettings};
+use crate::dnd::{modifier_for_score, Ability};
+use crate::storage::Store;
+use crate::transport::{ChatTransport, Incoming};
+
+/// Scores of ordinary people, rather than of heroes
+const SCORE: RollSettings = RollSettings {
+    number: 3,
+    sides: 6,
+    modifier: None,
+    label: None,
+    target: None,
+    keep: None,
+    explode: None,
+    success: None,
+    composite: false,
+    multiplier: None,
+    flags: Flags::NONE,
+};
+
+/// Roll 3d6 for every ability, in order
+fn stats() -> String {
+    Ability::ALL
+        .iter()
+        .map(|ability| {
+            let score = Roll::new(&SCORE).total as i32;
+            format!(
+                "{} {} ({:+})",
+                ability.name()[..3].to_uppercase(),
+                score,
+                modifier_for_score(score)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" · ")
+}
+
+fn npc(tables: &[crate::table::RandomTable]) -> String {
+    match crate::table::roll(tables, "npc") {
+        // Skip the heading of the table
+        Ok(text) => {
+            let description = text.split_once('\n').map(|(_, rest)| rest).unwrap_or(&text);
+            format!("🧑 {}\n<code>{}</code>", description, stats())
+        }
+        Err(e) => html::escape(&format!("{:#}", e)),
+    }
+}
+
+pub(crate) async fn handle_npc(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let text = store
+        .read(|storage| {
+            let tables = storage
+                .chat(chat_id)
+                .map(|chat| chat.random_tables.as_slice())
+                .unwrap_or_default();
+            npc(tables)
+        })
+        .await;
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolls_an_npc() {
+        let text = npc(&[]);
+        assert!(text.starts_with("🧑 "), "{}", text);
+        assert!(text.contains(" who "));
+        assert_eq!(text.matches(" · ").count(), 5);
+
+        let tables: Vec<crate::table::RandomTable> = serde_json::from_str(
+            r#"[{"name": "npc", "entries": [{"text": "{npc_name} the {job}"}]},
+                {"name": "job", "entries": [{"text": "tailor"}]}]"#,
+        )
+        .unwrap();
+        assert!(npc(&tables).contains(" the tailor\n<code>STR "));
+    }
+}
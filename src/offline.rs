@@ -0,0 +1,337 @@
+//! Subcommands that use the dice without a bot, for scripting and for debugging the parser.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::hint::black_box;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use rand::distributions::{Distribution as _, Uniform};
+
+use crate::dice::{Dice, RollResults, RollSettings, RollType};
+
+/// Remove the HTML tags that the chat messages are formatted with, and their escapes
+pub(crate) fn plain_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// The formatted roll, or the results as JSON
+pub fn roll(expression: &str, roll_type: &RollType, json: bool) -> anyhow::Result<String> {
+    let settings = RollSettings::from_str(expression)?;
+    let results = RollResults::new(&settings, roll_type);
+    if json {
+        return Ok(serde_json::to_string_pretty(
+            &crate::output::RollOutput::new(expression, &results),
+        )?);
+    }
+    // Dice that do not count are struck through in chats, which plain text does not have
+    let text = results.to_string().replace("<s>", "~").replace("</s>", "~");
+    Ok(plain_text(&text))
+}
+
+/// Longest bar of a text histogram
+const HISTOGRAM_WIDTH: usize = 50;
+
+/// Totals of many rolls of the same dice
+#[derive(Debug, PartialEq)]
+pub struct Distribution {
+    /// How often each total came up
+    pub counts: BTreeMap<i64, u64>,
+    pub iterations: u64,
+}
+
+impl Distribution {
+    /// Roll with the same roller as the bot
+    pub fn simulate(
+        expression: &str,
+        roll_type: &RollType,
+        iterations: u64,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(iterations > 0, "at least one iteration is needed");
+        let settings = RollSettings::from_str(expression)?;
+        let mut counts = BTreeMap::new();
+        for _ in 0..iterations {
+            let total = RollResults::new(&settings, roll_type).result.total;
+            *counts.entry(total).or_default() += 1;
+        }
+        Ok(Distribution { counts, iterations })
+    }
+
+    pub fn mean(&self) -> f64 {
+        let sum: f64 = self
+            .counts
+            .iter()
+            .map(|(total, count)| *total as f64 * *count as f64)
+            .sum();
+        sum / self.iterations as f64
+    }
+
+    pub fn standard_deviation(&self) -> f64 {
+        let mean = self.mean();
+        let variance: f64 = self
+            .counts
+            .iter()
+            .map(|(total, count)| (*total as f64 - mean).powi(2) * *count as f64)
+            .sum();
+        (variance / self.iterations as f64).sqrt()
+    }
+
+    /// The smallest total that at least half of the rolls are at or below
+    pub fn median(&self) -> i64 {
+        let mut seen = 0;
+        for (total, count) in &self.counts {
+            seen += count;
+            if seen * 2 >= self.iterations {
+                return *total;
+            }
+        }
+        unreachable!("there is at least one iteration")
+    }
+
+    pub fn to_text(&self) -> String {
+        let min = self.counts.keys().next().expect("to not be empty");
+        let max = self.counts.keys().next_back().expect("to not be empty");
+        let mut text = format!(
+            "Iterations: {}\nMin: {}  Max: {}  Median: {}\nMean: {:.3}  Standard deviation: {:.3}\n\n",
+            self.iterations,
+            min,
+            max,
+            self.median(),
+            self.mean(),
+            self.standard_deviation()
+        );
+        let width = self
+            .counts
+            .keys()
+            .map(|total| total.to_string().len())
+            .max();
+        let most = *self.counts.values().max().expect("to not be empty");
+        for (total, count) in &self.counts {
+            let bar = (*count as f64 / most as f64 * HISTOGRAM_WIDTH as f64).round() as usize;
+            writeln!(
+                text,
+                "{:>width$} {:>6.2}% {}",
+                total,
+                *count as f64 * 100.0 / self.iterations as f64,
+                "█".repeat(bar),
+                width = width.unwrap_or_default()
+            )
+            .expect("to write to a string");
+        }
+        text
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = "total,count,probability\n".to_string();
+        for (total, count) in &self.counts {
+            writeln!(
+                csv,
+                "{},{},{}",
+                total,
+                count,
+                *count as f64 / self.iterations as f64
+            )
+            .expect("to write to a string");
+        }
+        csv
+    }
+}
+
+/// Expressions to benchmark when no corpus is given, from common to pathological
+pub const CORPUS: &[&str] = &[
+    "1d20",
+    "1d20 + 5",
+    "2d6 - 1",
+    "8d6",
+    "100d100 + 50",
+    "9999d9999",
+    "9999d9999 + 9999",
+    "0d6",
+    "not dice",
+];
+
+/// Parses and rolls per second of one expression
+#[derive(Debug)]
+pub struct Throughput {
+    pub expression: String,
+    pub parses: f64,
+    /// `None` when the expression does not parse
+    pub rolls: Option<f64>,
+    /// Rolls per second sharing one rng, rather than fetching the one of the thread for each roll
+    pub reused_rng: Option<f64>,
+}
+
+/// How many times per second `f` runs, running it for about `duration`
+fn per_second<F: FnMut()>(duration: Duration, mut f: F) -> f64 {
+    let start = Instant::now();
+    let mut runs = 0u64;
+    while runs == 0 || start.elapsed() < duration {
+        f();
+        runs += 1;
+    }
+    runs as f64 / start.elapsed().as_secs_f64()
+}
+
+/// Spend about `duration` on parsing each expression, and as much on rolling it
+pub fn bench(expressions: &[String], duration: Duration) -> Vec<Throughput> {
+    expressions
+        .iter()
+        .map(|expression| {
+            let parses = per_second(duration, || {
+                let _ = black_box(RollSettings::from_str(black_box(expression)));
+            });
+            let settings = RollSettings::from_str(expression).ok();
+            let rolls = settings.as_ref().map(|settings| {
+                per_second(duration, || {
+                    black_box(RollResults::new(settings, &RollType::Straight));
+                })
+            });
+            let mut rng = rand::thread_rng();
+            let reused_rng = settings.as_ref().map(|settings| {
+                per_second(duration, || {
+                    black_box(RollResults::with_rng(
+                        settings,
+                        &RollType::Straight,
+                        &mut rng,
+                    ));
+                })
+            });
+            Throughput {
+                expression: expression.clone(),
+                parses,
+                rolls,
+                reused_rng,
+            }
+        })
+        .collect()
+}
+
+/// Dice of each size sampled per second, building the distribution for every die and sampling the
+/// one [`Dice`] built for the size
+pub fn bench_distributions(sides: &[u32], duration: Duration) -> Vec<(u32, f64, f64)> {
+    let mut rng = rand::thread_rng();
+    sides
+        .iter()
+        .map(|&sides| {
+            let built = per_second(duration, || {
+                black_box(Uniform::from(1..=black_box(sides)).sample(&mut rng));
+            });
+            let mut dice = Dice::new(&mut rng);
+            let cached = per_second(duration, || {
+                black_box(dice.roll(black_box(sides)));
+            });
+            (sides, built, cached)
+        })
+        .collect()
+}
+
+pub fn format_throughput(results: &[Throughput]) -> String {
+    let width = results
+        .iter()
+        .map(|result| result.expression.len())
+        .chain(std::iter::once("Expression".len()))
+        .max()
+        .unwrap_or_default();
+    let mut text = format!(
+        "{:<width$} {:>14} {:>14} {:>14}\n",
+        "Expression", "Parses/s", "Rolls/s", "Shared rng/s"
+    );
+    let rate = |rate: Option<f64>| match rate {
+        Some(rate) => format!("{:.0}", rate),
+        None => "invalid".to_string(),
+    };
+    for result in results {
+        writeln!(
+            text,
+            "{:<width$} {:>14.0} {:>14} {:>14}",
+            result.expression,
+            result.parses,
+            rate(result.rolls),
+            rate(result.reused_rng)
+        )
+        .expect("to write to a string");
+    }
+    text
+}
+
+pub fn format_distributions(results: &[(u32, f64, f64)]) -> String {
+    let mut text = format!("{:<10} {:>14} {:>14}\n", "Die", "Built/s", "Cached/s");
+    for (sides, built, cached) in results {
+        writeln!(
+            text,
+            "{:<10} {:>14.0} {:>14.0}",
+            format!("d{}", sides),
+            built,
+            cached
+        )
+        .expect("to write to a string");
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolls_to_plain_text() {
+        let text = roll("3d1 + 2", &RollType::Straight, false).unwrap();
+        assert_eq!(
+            text,
+            "Parameters: 3d1 + 2\nRoll: (1 + 1 + 1) + 2\nYour final roll is: 🎲 5 🎲"
+        );
+        let text = roll("1d1", &RollType::Advantage, false).unwrap();
+        assert_eq!(
+            text,
+            "Parameters: 1d1 with Advantage\nRoll: (1 + ~1~)\nYour final roll is: 🎲 1 🎲"
+        );
+
+        let json: serde_json::Value =
+            serde_json::from_str(&roll("1d1", &RollType::Straight, true).unwrap()).unwrap();
+        assert_eq!(json["total"], 1);
+        assert!(roll("d", &RollType::Straight, false).is_err());
+    }
+
+    #[test]
+    fn simulates_distributions() {
+        let distribution = Distribution::simulate("2d1 + 1", &RollType::Straight, 10).unwrap();
+        assert_eq!(distribution.counts, BTreeMap::from([(3, 10)]));
+        assert_eq!(distribution.median(), 3);
+        assert_eq!(distribution.standard_deviation(), 0.0);
+        assert_eq!(distribution.to_csv(), "total,count,probability\n3,10,1\n");
+
+        let distribution = Distribution {
+            counts: BTreeMap::from([(1, 1), (2, 2), (3, 1)]),
+            iterations: 4,
+        };
+        assert_eq!(distribution.mean(), 2.0);
+        assert_eq!(distribution.median(), 2);
+        assert!(distribution.to_text().contains("2  50.00% ████"));
+    }
+
+    #[test]
+    fn benches_valid_and_invalid_expressions() {
+        let expressions = vec!["1d6".to_string(), "not dice".to_string()];
+        let results = bench(&expressions, Duration::ZERO);
+        assert!(results[0].parses > 0.0);
+        assert!(results[0].rolls.is_some());
+        assert!(results[1].rolls.is_none());
+        assert!(results[0].reused_rng.is_some());
+        assert!(format_throughput(&results).contains("invalid"));
+
+        let results = bench_distributions(&[20], Duration::ZERO);
+        assert!(format_distributions(&results).contains("d20"));
+    }
+}
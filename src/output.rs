@@ -0,0 +1,148 @@
+//! The JSON sent by `/data` and printed by `roll --json`. Its fields are a published format versioned
+//! on its own, so the structs of the roller can change without breaking scripts that read it.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::dice::{Degree, RollResults, RollType};
+
+/// Raised when fields are renamed or removed, not when fields are added
+pub(crate) const SCHEMA_VERSION: u32 = 2;
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub(crate) struct RollOutput {
+    pub schema_version: u32,
+    /// Tells rolls apart, such as when they are collected from several chats
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    /// The roll as it was written
+    pub expression: String,
+    /// The roll as the bot read it, such as `1d20 + 5`
+    pub canonical: String,
+    pub label: Option<String>,
+    pub target: Option<i64>,
+    pub roll_type: RollType,
+    /// The dice rolled. Advantage and disadvantage roll another d20, which is not kept.
+    pub groups: Vec<Group>,
+    pub modifier: i64,
+    /// From `2x(1d6 + 2)`, applied after the modifier
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multiplier: Option<u32>,
+    pub total: i64,
+    pub critical: Critical,
+    /// Whether the total met the target, if there was one
+    pub passed: Option<bool>,
+    /// How far the total is over the target, or under it when negative
+    pub margin: Option<i64>,
+    /// Such as `critical_success`, with the bands of the campaign
+    pub degree: Option<Degree>,
+}
+
+/// Dice of the same size, such as the `3d6` of `3d6 + 2`
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub(crate) struct Group {
+    pub dice: String,
+    pub sides: u32,
+    /// Left out for rolls too large to send as JSON
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rolls: Vec<Die>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub(crate) struct Die {
+    pub value: u32,
+    /// Whether the die counts towards the total
+    pub kept: bool,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq, Clone)]
+pub(crate) struct Critical {
+    /// The dice were doubled with the `crit` flag
+    pub doubled: bool,
+    /// The one d20 that counts came up 20
+    pub natural_20: bool,
+    /// The one d20 that counts came up 1
+    pub natural_1: bool,
+}
+
+impl RollOutput {
+    pub(crate) fn new(expression: &str, results: &RollResults) -> Self {
+        let settings = results.settings;
+        let result = &results.result;
+        // The dice that were rolled, such as `2d20kh1` for a d20 with advantage
+        let dice = &result.settings;
+        let rolls = match crate::dice::too_many_for_json(&result.rolls) {
+            true => vec![],
+            false => result
+                .rolls
+                .iter()
+                .zip(result.kept())
+                .map(|(&value, kept)| Die { value, kept })
+                .collect(),
+        };
+        RollOutput {
+            schema_version: SCHEMA_VERSION,
+            id: format!("{:016x}", rand::random::<u64>()),
+            timestamp: Utc::now(),
+            expression: expression.to_string(),
+            canonical: settings.format_parameters(),
+            label: settings.label.clone(),
+            target: results.target,
+            roll_type: results.roll_type.clone(),
+            groups: vec![Group {
+                dice: crate::expr::DiceExpr::Dice {
+                    number: dice.number,
+                    sides: dice.sides,
+                    explode: dice.explode,
+                    keep: dice.keep,
+                    success: dice.success,
+                    composite: dice.composite,
+                }
+                .to_string(),
+                sides: dice.sides,
+                rolls,
+            }],
+            modifier: dice.modifier.unwrap_or(0) as i64,
+            multiplier: dice.multiplier,
+            total: result.total,
+            critical: Critical {
+                doubled: settings.flags.critical,
+                natural_20: result.natural() == Some(20),
+                natural_1: result.natural() == Some(1),
+            },
+            passed: results.degree().map(|degree| degree.passed()),
+            margin: results.margin(),
+            degree: results.degree(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dice::RollSettings;
+
+    #[test]
+    fn describes_a_roll() {
+        let settings: RollSettings = "2d1 + 3 stealth vs 4".parse().unwrap();
+        let results = RollResults::new(&settings, &RollType::Advantage);
+        let json = serde_json::to_value(RollOutput::new("2d1+3 stealth vs 4", &results)).unwrap();
+        assert_eq!(json["schema_version"], SCHEMA_VERSION);
+        assert_eq!(json["expression"], "2d1+3 stealth vs 4");
+        assert_eq!(json["canonical"], "2d1 + 3");
+        assert_eq!(json["label"], "stealth");
+        assert_eq!(json["roll_type"], "advantage");
+        assert_eq!(json["groups"][0]["dice"], "4d1kh2");
+        let rolls = json["groups"][0]["rolls"].as_array().unwrap();
+        assert_eq!(rolls.len(), 4);
+        assert_eq!(rolls[0], serde_json::json!({"value": 1, "kept": true}));
+        assert_eq!(rolls[3], serde_json::json!({"value": 1, "kept": false}));
+        assert_eq!(json["modifier"], 3);
+        assert_eq!(json["total"], 5);
+        assert_eq!(json["passed"], true);
+        assert_eq!(json["margin"], 1);
+        assert_eq!(json["degree"], "success");
+        assert_eq!(json["critical"]["natural_1"], false);
+        assert_eq!(json["id"].as_str().unwrap().len(), 16);
+    }
+}
@@ -1,10 +1,13 @@
 use std::str::FromStr;
 
 use nom::{
+    branch::alt,
+    bytes::complete::{tag, tag_no_case},
+    character::complete::char,
     character::complete::multispace0,
     character::complete::one_of,
     combinator::consumed,
-    error::ParseError,
+    error::{ErrorKind, ParseError},
     multi::{many1, many_m_n},
     sequence::delimited,
     sequence::Tuple,
@@ -12,69 +15,266 @@ use nom::{
 };
 use thiserror::Error;
 
-use crate::dice::RollSettings;
+use crate::dice::{Flags, Keep, RollSettings, RollType};
+
+/// What the parser was looking for when it failed
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Expected {
+    NumberOfDice,
+    DiceSeparator,
+    DieSize,
+    ClosingParenthesis,
+}
+
+impl std::fmt::Display for Expected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expected::NumberOfDice => write!(f, "the number of dice, like the 1 of 1d20,"),
+            Expected::DiceSeparator => write!(f, "'d' after the number of dice"),
+            Expected::DieSize => write!(f, "a die size after 'd'"),
+            Expected::ClosingParenthesis => write!(f, "')' after the multiplied dice"),
+        }
+    }
+}
+
+/// A nom error that remembers what was expected where
+#[derive(Debug, PartialEq)]
+struct RollError<'a> {
+    input: &'a str,
+    expected: Option<Expected>,
+}
+
+impl<'a> ParseError<&'a str> for RollError<'a> {
+    fn from_error_kind(input: &'a str, _: ErrorKind) -> Self {
+        RollError {
+            input,
+            expected: None,
+        }
+    }
+
+    fn append(_: &'a str, _: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+type Parsed<'a, T> = IResult<&'a str, T, RollError<'a>>;
+
+/// Label the errors of `inner` with what it expected
+fn expect<'a, F, O>(expected: Expected, mut inner: F) -> impl FnMut(&'a str) -> Parsed<'a, O>
+where
+    F: FnMut(&'a str) -> Parsed<'a, O>,
+{
+    move |input| {
+        inner(input).map_err(|e| {
+            e.map(|e| RollError {
+                expected: e.expected.or(Some(expected)),
+                ..e
+            })
+        })
+    }
+}
 
 /// A combinator that takes a parser `inner` and produces a parser that also consumes both leading and
 /// trailing whitespace, returning the output of `inner`.
 /// https://docs.rs/nom/latest/nom/recipes/index.html#wrapper-combinators-that-eat-whitespace-before-and-after-a-parser
-fn ws<'a, F: 'a, O, E: ParseError<&'a str>>(
-    inner: F,
-) -> impl FnMut(&'a str) -> IResult<&'a str, O, E>
+fn ws<'a, F, O, E: ParseError<&'a str>>(inner: F) -> impl FnMut(&'a str) -> IResult<&'a str, O, E>
 where
-    F: FnMut(&'a str) -> IResult<&'a str, O, E>,
+    F: FnMut(&'a str) -> IResult<&'a str, O, E> + 'a,
 {
     delimited(multispace0, inner, multispace0)
 }
 
-fn single_decimal(input: &str) -> IResult<&str, char> {
+fn single_decimal(input: &str) -> Parsed<'_, char> {
     ws(one_of("0123456789"))(input)
 }
 
-fn decimal<T>(input: &str, min: usize, max: usize) -> IResult<&str, T>
-where
-    T: FromStr,
-    <T as std::str::FromStr>::Err: std::fmt::Debug,
-{
+fn decimal<T: FromStr>(input: &str, min: usize, max: usize) -> Parsed<'_, T> {
     let (remainning, (_, chars)) = consumed(many_m_n(min, max, single_decimal))(input)?;
     let output = String::from_iter(chars);
-    Ok((remainning, output.parse().expect("parsing to succeed")))
+    match output.parse() {
+        Ok(output) => Ok((remainning, output)),
+        Err(_) => Err(too_big(input)),
+    }
 }
 
-fn dice_seperator(input: &str) -> IResult<&str, char> {
+/// Stop parsing at a number that does not fit, which [`parse_roll`] reports as too big
+fn too_big(input: &str) -> nom::Err<RollError<'_>> {
+    nom::Err::Failure(RollError {
+        input,
+        expected: None,
+    })
+}
+
+fn dice_seperator(input: &str) -> Parsed<'_, char> {
     let (remaining, (_, separator)) = ws(consumed(one_of("dD")))(input)?;
     Ok((remaining, separator))
 }
 
-fn modifier_separator(input: &str) -> IResult<&str, char> {
+fn modifier_separator(input: &str) -> Parsed<'_, char> {
     let (remaining, (_, separator)) = ws(consumed(one_of("+-")))(input)?;
     Ok((remaining, separator))
 }
 
+/// Why a roll could not be parsed. `RollSettings::from_str` is the only way rolls are parsed.
 #[derive(Error, Debug, PartialEq)]
 pub(crate) enum ParseRollError {
-    #[error("Error parsing input: {0}")]
-    ParseError(String),
+    #[error("Expected {expected} at position {position}, {found}")]
+    InvalidFormat {
+        expected: Expected,
+        /// Position of the first character that could not be parsed, counting from 1
+        position: usize,
+        found: Found,
+    },
     #[error("Number of dices and dice sides cannot be zero: {0}")]
     CannotBeZero(String),
     #[error("Input parameter is too big")]
     TooBig,
+    #[error("Dice that explode on every side would never stop: {0}")]
+    ExplodesForever(String),
+}
+
+/// What was in the input where the parser failed
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) enum Found {
+    End,
+    Text(String),
 }
 
-impl<'a> From<nom::error::Error<&'a str>> for ParseRollError {
-    fn from(e: nom::error::Error<&str>) -> Self {
-        ParseRollError::ParseError(e.to_string())
+impl std::fmt::Display for Found {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Found::End => write!(f, "but the input ended"),
+            Found::Text(text) => write!(f, "but found \"{}\"", text),
+        }
     }
 }
 
-fn parse_roll_inner(input: &str) -> IResult<&str, RollSettings> {
+impl ParseRollError {
+    /// An error at `e` in `parsed`, pointed at what was `written` instead: `parsed` is `written`
+    /// with look-alikes replaced one for one, and `added` characters put in front of its trimmed
+    /// start by [`implicit_d20`]
+    fn invalid_format(written: &str, parsed: &str, added: usize, e: RollError<'_>) -> Self {
+        let rest = e.input.trim_start();
+        let offset = parsed[..parsed.len() - rest.len()].chars().count();
+        let skipped = match added {
+            0 => 0,
+            _ => written.chars().take_while(|c| c.is_whitespace()).count(),
+        };
+        let index = offset.saturating_sub(added) + skipped;
+        let rest: String = written.chars().skip(index).collect();
+        let found = match rest.split_whitespace().next() {
+            None => Found::End,
+            Some(word) => Found::Text(word.chars().take(10).collect()),
+        };
+        ParseRollError::InvalidFormat {
+            expected: e.expected.unwrap_or(Expected::NumberOfDice),
+            position: index + 1,
+            found,
+        }
+    }
+}
+
+/// Whether a number stopped only because it ran out of digits it may have
+fn overflows(remaining: &str) -> bool {
+    remaining
+        .trim_start()
+        .starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// The `!` of `1d10!`, exploding on the highest side, or the `!>=9` of `1d10!>=9`
+fn explode(input: &str, sides: u32) -> Parsed<'_, u32> {
     let digits = |i| decimal::<u32>(i, 1, 4);
+    let (remaining, _) = ws(char('!'))(input)?;
+    let threshold = (ws(alt((tag(">="), tag(">")))), digits).parse(remaining);
+    match threshold {
+        Ok((remaining, (">=", threshold))) => Ok((remaining, threshold)),
+        Ok((remaining, (_, threshold))) => Ok((remaining, threshold.saturating_add(1))),
+        Err(_) => Ok((remaining, sides)),
+    }
+}
 
+/// The `kh3` of `4d6kh3`, `k3` for short, or the `dl1` of `4d6dl1` as the dice that are left
+fn keep(input: &str, number: u32) -> Parsed<'_, Keep> {
+    let digits = |i| decimal::<u32>(i, 1, 4);
+    let kind = alt((
+        tag_no_case("kh"),
+        tag_no_case("kl"),
+        tag_no_case("dh"),
+        tag_no_case("dl"),
+        tag_no_case("k"),
+    ));
+    let (remaining, (kind, count)) = (ws(kind), digits).parse(input)?;
+    let keep = match kind.to_ascii_lowercase().as_str() {
+        "kl" => Keep::Lowest(count),
+        "dh" => Keep::Lowest(number.saturating_sub(count)),
+        "dl" => Keep::Highest(number.saturating_sub(count)),
+        _ => Keep::Highest(count),
+    };
+    Ok((remaining, keep))
+}
+
+/// The `>=8` of `5d10>=8`, as the lowest side that is a success
+fn success(input: &str) -> Parsed<'_, u32> {
+    let digits = |i| decimal::<u32>(i, 1, 4);
+    match (ws(alt((tag(">="), tag(">")))), digits).parse(input)? {
+        (remaining, (">=", threshold)) => Ok((remaining, threshold)),
+        (remaining, (_, threshold)) => Ok((remaining, threshold.saturating_add(1))),
+    }
+}
+
+/// The `2x(` of `2x(1d6 + 2)`
+fn multiplier(input: &str) -> Parsed<'_, u32> {
+    let digits = |i| decimal::<u32>(i, 1, 4);
+    let (remaining, (factor, _, _)) = (digits, ws(one_of("xX*")), ws(char('('))).parse(input)?;
+    Ok((remaining, factor))
+}
+
+fn parse_roll_inner(input: &str) -> Parsed<'_, RollSettings> {
     log::debug!("Parsing input: {}", input);
-    let (remaining, (number, _, sides)) = (&digits, &dice_seperator, &digits).parse(input)?;
+    let Ok((remaining, factor)) = multiplier(input) else {
+        return parse_dice(input);
+    };
+    let (remaining, settings) = parse_dice(remaining)?;
+    let (remaining, _) = expect(Expected::ClosingParenthesis, ws(char(')')))(remaining)?;
+    Ok((
+        remaining,
+        RollSettings {
+            multiplier: Some(factor),
+            ..settings
+        },
+    ))
+}
+
+fn parse_dice(input: &str) -> Parsed<'_, RollSettings> {
+    let digits = |i| decimal::<u32>(i, 1, 4);
+
+    // `d20` is a single die
+    let (remaining, number) = match dice_seperator(input) {
+        Ok(_) => (input, 1),
+        Err(_) => expect(Expected::NumberOfDice, digits)(input)?,
+    };
+    if overflows(remaining) {
+        return Err(too_big(remaining));
+    }
+    let (remaining, _) = expect(Expected::DiceSeparator, dice_seperator)(remaining)?;
+    let (remaining, sides) = expect(Expected::DieSize, digits)(remaining)?;
     log::debug!("Parsed Sides: {:?}", sides);
     log::debug!("Parsed Number: {:?}", number);
     log::debug!("Parsed Remaining: {}", remaining);
 
+    let (remaining, explode) = match explode(remaining, sides) {
+        Ok((remaining, threshold)) => (remaining, Some(threshold)),
+        Err(_) => (remaining, None),
+    };
+    let (remaining, keep) = match keep(remaining, number) {
+        Ok((remaining, keep)) => (remaining, Some(keep)),
+        Err(_) => (remaining, None),
+    };
+    let (remaining, success) = match success(remaining) {
+        Ok((remaining, threshold)) => (remaining, Some(threshold)),
+        Err(_) => (remaining, None),
+    };
+
     let modifier_parse = (&modifier_separator, &digits).parse(remaining);
 
     let (remaining, modifier) = match modifier_parse {
@@ -82,12 +282,13 @@ fn parse_roll_inner(input: &str) -> IResult<&str, RollSettings> {
             log::debug!("Modifier Sign: {:?}", modifier_sign);
             log::debug!("Modifier: {:?}", modifier);
             log::debug!("Remaining: {}", remaining);
-            // Parse modifier
-            let modifier = format!("{}{}", modifier_sign, modifier)
-                .parse::<i32>()
-                .expect("to parse");
-
-            (remaining, Some(modifier))
+            let Ok(modifier) = i32::try_from(modifier) else {
+                return Err(too_big(remaining));
+            };
+            match modifier_sign {
+                '-' => (remaining, Some(-modifier)),
+                _ => (remaining, Some(modifier)),
+            }
         }
         Err(_) => (remaining, None),
     };
@@ -99,23 +300,101 @@ fn parse_roll_inner(input: &str) -> IResult<&str, RollSettings> {
             number,
             modifier,
             label: None,
+            target: None,
+            keep,
+            explode,
+            success,
+            composite: crate::dice::composite_digit(sides).is_some(),
+            multiplier: None,
+            flags: Flags::NONE,
         },
     ))
 }
 
+/// Replace the look-alikes that mobile keyboards produce with the characters the parser knows,
+/// one character for another so that positions stay the same
+pub(crate) fn normalize(input: &str) -> std::borrow::Cow<'_, str> {
+    let replacement = |c: char| match c {
+        // Minus sign, dashes and the fullwidth hyphen-minus
+        '\u{2212}' | '\u{2010}'..='\u{2014}' | '\u{FF0D}' => Some('-'),
+        '\u{FF0B}' => Some('+'),
+        '×' | '\u{FF0A}' => Some('*'),
+        '\u{FF44}' => Some('d'),
+        '\u{FF24}' => Some('D'),
+        '\u{FF10}'..='\u{FF19}' => char::from_u32(c as u32 - 0xFF10 + '0' as u32),
+        '\u{3000}' => Some(' '),
+        _ => None,
+    };
+    if !input.chars().any(|c| replacement(c).is_some()) {
+        return input.into();
+    }
+    input
+        .chars()
+        .map(|c| replacement(c).unwrap_or(c))
+        .collect::<String>()
+        .into()
+}
+
+/// `+5` as a modifier on a d20, and `dc 10 flat` or `flat dc 10` as a flat check, a d20 with
+/// nothing added against the DC. Comes with how many characters were put in front of the
+/// trimmed input, all of them for a flat check, which is rewritten as a whole
+fn implicit_d20(input: &str) -> Option<(String, usize)> {
+    let input = input.trim();
+    if input.starts_with(['+', '-']) {
+        return Some((format!("1d20{}", input), "1d20".len()));
+    }
+    let words: Vec<_> = input.split_whitespace().collect();
+    let (dc, rest) = match words.as_slice() {
+        [dc, target, flat, rest @ ..] | [flat, dc, target, rest @ ..]
+            if dc.eq_ignore_ascii_case("dc") && flat.eq_ignore_ascii_case("flat") =>
+        {
+            (target.parse::<u32>().ok()?, rest)
+        }
+        _ => return None,
+    };
+    let label = std::iter::once("flat check").chain(rest.iter().copied());
+    let rewritten = format!("1d20 {} vs {}", label.collect::<Vec<_>>().join(" "), dc);
+    let added = rewritten.chars().count();
+    Some((rewritten, added))
+}
+
 pub(crate) fn parse_roll(input: &str) -> Result<RollSettings, ParseRollError> {
-    let (remaining, mut result) = parse_roll_inner(input).finish()?;
+    let written = input;
+    let input = &normalize(input);
+    let implicit = implicit_d20(input);
+    let (input, added) = match &implicit {
+        Some((rewritten, added)) => (rewritten.as_str(), *added),
+        None => (input.as_ref(), 0),
+    };
+    let (remaining, mut result) = match parse_roll_inner(input).finish() {
+        Ok(parsed) => parsed,
+        // Set apart from the errors of parsers, which always say what they expected
+        Err(RollError { expected: None, .. }) => Err(ParseRollError::TooBig)?,
+        Err(e) => Err(ParseRollError::invalid_format(written, input, added, e))?,
+    };
 
-    if result.number == 0 || result.sides == 0 {
+    if result.number == 0 || result.sides == 0 || result.multiplier == Some(0) || result.kept() == 0
+    {
         Err(ParseRollError::CannotBeZero(input.to_string()))?
     }
 
+    if result.explode.is_some_and(|threshold| threshold <= 1) {
+        Err(ParseRollError::ExplodesForever(input.to_string()))?
+    }
+
     // Check remaining text is not "overflow" digits
     if consumed(consumed(many1(single_decimal)))(remaining).is_ok() {
         Err(ParseRollError::TooBig)?;
     }
 
-    let remaining = remaining.trim();
+    // Flags can follow the target, as in `1d20 vs 15 adv`
+    let (remaining, flags) = split_flags(remaining);
+    if flags.critical {
+        result.number *= 2;
+    }
+    result.flags = flags;
+    let (remaining, target) = split_target(&remaining);
+    result.target = target;
     if !remaining.is_empty() {
         result.label = Some(remaining.to_string());
     }
@@ -123,6 +402,147 @@ pub(crate) fn parse_roll(input: &str) -> Result<RollSettings, ParseRollError> {
     Ok(result)
 }
 
+/// Words of the label that are flags, such as `adv`, rather than part of the label
+const FLAGS: [&str; 6] = ["adv", "advantage", "dis", "disadvantage", "crit", "gwf"];
+
+/// Take the flags out of the label
+fn split_flags(label: &str) -> (String, Flags) {
+    let mut flags = Flags::NONE;
+    let mut words = vec![];
+    for word in label.split_whitespace() {
+        match word.to_lowercase().as_str() {
+            "adv" | "advantage" => flags.roll_type = Some(RollType::Advantage),
+            "dis" | "disadvantage" => flags.roll_type = Some(RollType::Disadvantage),
+            "crit" => flags.critical = true,
+            "gwf" => flags.great_weapon_fighting = true,
+            _ => words.push(word),
+        }
+    }
+    (words.join(" "), flags)
+}
+
+/// Words of the label that look like misspelt flags, with the flag they likely meant
+pub(crate) fn flag_typos(label: &str) -> Vec<(&str, &'static str)> {
+    label
+        .split_whitespace()
+        .filter(|word| word.chars().count() >= 3)
+        .filter_map(|word| {
+            let lowercase = word.to_lowercase();
+            FLAGS
+                .iter()
+                .find(|flag| edit_distance(&lowercase, flag) == 1)
+                .map(|flag| (word, *flag))
+        })
+        .collect()
+}
+
+/// Edits to turn one word into another, where swapping two neighbouring letters is one edit
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<_>, Vec<_>) = (a.chars().collect(), b.chars().collect());
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    distances[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+/// Sides of the dice of the usual sets, which `20d` is more likely a `d20` than twenty dice of
+const STANDARD_SIDES: [u32; 7] = [4, 6, 8, 10, 12, 20, 100];
+
+/// Runs of operators like `++` or `+-` as one, and a trailing one dropped, as in `1d20++5` and `d20+`
+fn fix_operators(input: &str) -> Option<String> {
+    let mut fixed = String::with_capacity(input.len());
+    let mut run = None;
+    for c in input.chars() {
+        match (c, run) {
+            ('+' | '-', None) => run = Some(c),
+            ('+' | '-', Some(sign)) => run = Some(if c == '-' { '-' } else { sign }),
+            (c, _) => {
+                fixed.extend(run.take());
+                fixed.push(c);
+            }
+        }
+    }
+    let fixed = fixed.trim_end().to_string();
+    (fixed != input.trim_end()).then_some(fixed)
+}
+
+/// `20d` as `1d20`, `2d` as `2d6` and `1d` as `1d20`
+fn fix_missing_sides(input: &str) -> Option<String> {
+    let input = input.trim_start();
+    let digits = input.find(|c: char| !c.is_ascii_digit())?;
+    let (number, rest) = input.split_at(digits);
+    let rest = rest.strip_prefix(['d', 'D'])?;
+    if rest.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let number: u32 = number.parse().ok()?;
+    let dice = match number {
+        1 => "1d20".to_string(),
+        number if STANDARD_SIDES.contains(&number) => format!("1d{}", number),
+        number => format!("{}d6", number),
+    };
+    Some(format!("{}{}", dice, rest))
+}
+
+/// Parses without an operator left over at the start of the label, as `1d20+` would
+fn parses_cleanly(candidate: &str) -> bool {
+    parse_roll(candidate).is_ok_and(|settings| {
+        !settings
+            .label
+            .is_some_and(|label| label.starts_with(['+', '-']))
+    })
+}
+
+/// A roll that an input which could not be parsed was likely meant to be, such as `1d20+5` for
+/// `1d20++5`. Of the corrections that parse, the one closest to the input wins.
+pub(crate) fn suggest(input: &str) -> Option<String> {
+    let input = normalize(input).trim().to_string();
+    let fixes: [fn(&str) -> Option<String>; 2] = [fix_operators, fix_missing_sides];
+    let mut candidates = vec![input.clone()];
+    for fix in fixes {
+        let fixed: Vec<_> = candidates.iter().filter_map(|c| fix(c)).collect();
+        candidates.extend(fixed);
+    }
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != input && parses_cleanly(candidate))
+        .min_by_key(|candidate| edit_distance(&input, candidate))
+}
+
+/// Split a trailing `vs 15` or `vs DC 15` off the label
+fn split_target(remaining: &str) -> (&str, Option<i64>) {
+    let Some(index) = remaining.to_ascii_lowercase().rfind("vs") else {
+        return (remaining, None);
+    };
+    let (label, target) = remaining.split_at(index);
+    if !label.is_empty() && !label.ends_with(char::is_whitespace) {
+        return (remaining, None);
+    }
+    let target = target[2..].trim_start();
+    let target = match target.get(..2) {
+        Some(dc) if dc.eq_ignore_ascii_case("dc") => &target[2..],
+        _ => target,
+    };
+    match decimal::<i64>(target, 1, 4) {
+        Ok(("", target)) => (label.trim_end(), Some(target)),
+        _ => (remaining, None),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -165,6 +585,13 @@ mod tests {
                     sides: 20,
                     modifier: None,
                     label: None,
+                    target: None,
+                    keep: None,
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
                 }),
             ),
             (
@@ -174,6 +601,13 @@ mod tests {
                     sides: 20,
                     modifier: Some(3),
                     label: None,
+                    target: None,
+                    keep: None,
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
                 }),
             ),
             (
@@ -183,6 +617,181 @@ mod tests {
                     sides: 20,
                     modifier: Some(-2),
                     label: None,
+                    target: None,
+                    keep: None,
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
+                }),
+            ),
+            (
+                "d20+5",
+                Ok(RollSettings {
+                    number: 1,
+                    sides: 20,
+                    modifier: Some(5),
+                    label: None,
+                    target: None,
+                    keep: None,
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
+                }),
+            ),
+            (
+                "D6",
+                Ok(RollSettings {
+                    number: 1,
+                    sides: 6,
+                    modifier: None,
+                    label: None,
+                    target: None,
+                    keep: None,
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
+                }),
+            ),
+            (
+                "2x(1d6+2) charge",
+                Ok(RollSettings {
+                    number: 1,
+                    sides: 6,
+                    modifier: Some(2),
+                    label: Some("charge".to_string()),
+                    target: None,
+                    keep: None,
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: Some(2),
+                    flags: Flags::NONE,
+                }),
+            ),
+            (
+                "3 × (2d8)",
+                Ok(RollSettings {
+                    number: 2,
+                    sides: 8,
+                    modifier: None,
+                    label: None,
+                    target: None,
+                    keep: None,
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: Some(3),
+                    flags: Flags::NONE,
+                }),
+            ),
+            (
+                "4d6 dl1 Strength",
+                Ok(RollSettings {
+                    number: 4,
+                    sides: 6,
+                    modifier: None,
+                    label: Some("Strength".to_string()),
+                    target: None,
+                    keep: Some(Keep::Highest(3)),
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
+                }),
+            ),
+            (
+                "3d20KL1+2",
+                Ok(RollSettings {
+                    number: 3,
+                    sides: 20,
+                    modifier: Some(2),
+                    label: None,
+                    target: None,
+                    keep: Some(Keep::Lowest(1)),
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
+                }),
+            ),
+            (
+                "d10!>=9 + 1",
+                Ok(RollSettings {
+                    number: 1,
+                    sides: 10,
+                    modifier: Some(1),
+                    label: None,
+                    target: None,
+                    keep: None,
+                    explode: Some(9),
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
+                }),
+            ),
+            (
+                "3d6! kh2",
+                Ok(RollSettings {
+                    number: 3,
+                    sides: 6,
+                    modifier: None,
+                    label: None,
+                    target: None,
+                    keep: Some(Keep::Highest(2)),
+                    explode: Some(6),
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
+                }),
+            ),
+            (
+                "6d10!>=10 > 7 Dexterity",
+                Ok(RollSettings {
+                    number: 6,
+                    sides: 10,
+                    modifier: None,
+                    label: Some("Dexterity".to_string()),
+                    target: None,
+                    keep: None,
+                    explode: Some(10),
+                    success: Some(8),
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
+                }),
+            ),
+            (
+                "2d6!>0",
+                Err(ParseRollError::ExplodesForever("2d6!>0".to_string())),
+            ),
+            (
+                "2d20dl2",
+                Err(ParseRollError::CannotBeZero("2d20dl2".to_string())),
+            ),
+            (
+                "2x(1d6",
+                Err(ParseRollError::InvalidFormat {
+                    expected: Expected::ClosingParenthesis,
+                    position: 7,
+                    found: Found::End,
+                }),
+            ),
+            (
+                "d",
+                Err(ParseRollError::InvalidFormat {
+                    expected: Expected::DieSize,
+                    position: 2,
+                    found: Found::End,
                 }),
             ),
             (
@@ -192,6 +801,13 @@ mod tests {
                     sides: 20,
                     modifier: None,
                     label: None,
+                    target: None,
+                    keep: None,
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
                 }),
             ),
             (
@@ -201,6 +817,13 @@ mod tests {
                     sides: 20,
                     modifier: Some(-2),
                     label: None,
+                    target: None,
+                    keep: None,
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
                 }),
             ),
             (
@@ -210,6 +833,13 @@ mod tests {
                     sides: 20,
                     modifier: None,
                     label: Some("Wisdom saving throw".to_string()),
+                    target: None,
+                    keep: None,
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
                 }),
             ),
             (
@@ -219,6 +849,13 @@ mod tests {
                     sides: 20,
                     modifier: None,
                     label: Some("Wisdom saving throw".to_string()),
+                    target: None,
+                    keep: None,
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
                 }),
             ),
             (
@@ -228,6 +865,13 @@ mod tests {
                     sides: 20,
                     modifier: Some(1),
                     label: Some("Wisdom saving throw".to_string()),
+                    target: None,
+                    keep: None,
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
                 }),
             ),
             (
@@ -237,6 +881,80 @@ mod tests {
                     sides: 20,
                     modifier: Some(1),
                     label: Some("Wisdom saving throw".to_string()),
+                    target: None,
+                    keep: None,
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
+                }),
+            ),
+            (
+                "1d20+5 vs 15",
+                Ok(RollSettings {
+                    number: 1,
+                    sides: 20,
+                    modifier: Some(5),
+                    label: None,
+                    target: Some(15),
+                    keep: None,
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
+                }),
+            ),
+            (
+                "1d20-1 Wisdom saving throw VS DC 12",
+                Ok(RollSettings {
+                    number: 1,
+                    sides: 20,
+                    modifier: Some(-1),
+                    label: Some("Wisdom saving throw".to_string()),
+                    target: Some(12),
+                    keep: None,
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
+                }),
+            ),
+            (
+                "1d20 vs 15 adv",
+                Ok(RollSettings {
+                    number: 1,
+                    sides: 20,
+                    modifier: None,
+                    label: None,
+                    target: Some(15),
+                    keep: None,
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags {
+                        roll_type: Some(RollType::Advantage),
+                        ..Flags::NONE
+                    },
+                }),
+            ),
+            (
+                "1d20 Devs 15",
+                Ok(RollSettings {
+                    number: 1,
+                    sides: 20,
+                    modifier: None,
+                    label: Some("Devs 15".to_string()),
+                    target: None,
+                    keep: None,
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
                 }),
             ),
             (
@@ -246,15 +964,17 @@ mod tests {
                     sides: 9999,
                     modifier: Some(3),
                     label: None,
+                    target: None,
+                    keep: None,
+                    explode: None,
+                    success: None,
+                    composite: false,
+                    multiplier: None,
+                    flags: Flags::NONE,
                 }),
             ),
             // too many dices
-            (
-                "100000d20",
-                Err(ParseRollError::ParseError(
-                    "error OneOf at: 00d20".to_string(),
-                )),
-            ),
+            ("100000d20", Err(ParseRollError::TooBig)),
             // too many sides
             ("1d100000", Err(ParseRollError::TooBig)),
             // modifier too big
@@ -271,9 +991,27 @@ mod tests {
             ),
             (
                 "rubbish",
-                Err(ParseRollError::ParseError(
-                    "error OneOf at: rubbish".to_string(),
-                )),
+                Err(ParseRollError::InvalidFormat {
+                    expected: Expected::NumberOfDice,
+                    position: 1,
+                    found: Found::Text("rubbish".to_string()),
+                }),
+            ),
+            (
+                "2 x6",
+                Err(ParseRollError::InvalidFormat {
+                    expected: Expected::DiceSeparator,
+                    position: 3,
+                    found: Found::Text("x6".to_string()),
+                }),
+            ),
+            (
+                "1d",
+                Err(ParseRollError::InvalidFormat {
+                    expected: Expected::DieSize,
+                    position: 3,
+                    found: Found::End,
+                }),
             ),
         ];
 
@@ -285,9 +1023,86 @@ mod tests {
                     assert_eq!(expected, actual.unwrap());
                 }
                 Err(e) => {
-                    assert_eq!(e, actual.unwrap_err());
+                    assert_eq!(DiceError::Parse(e), actual.unwrap_err());
                 }
             }
         }
     }
+
+    #[test]
+    fn normalizes_look_alikes() {
+        assert_eq!(normalize("1d20+3"), "1d20+3");
+        assert_eq!(normalize("２ｄ６−１ × 2"), "2d6-1 * 2");
+        assert_eq!(
+            RollSettings::from_str("１Ｄ２０ − ２").unwrap().modifier,
+            Some(-2)
+        );
+        // Errors point at what was written, not at its replacement
+        assert_eq!(
+            parse_roll("  ３－"),
+            Err(ParseRollError::InvalidFormat {
+                expected: Expected::DiceSeparator,
+                position: 4,
+                found: Found::Text("－".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn finds_misspelt_flags() {
+        assert_eq!(edit_distance("avd", "adv"), 1);
+        assert_eq!(edit_distance("stealth", "adv"), 6);
+        assert_eq!(
+            flag_typos("Stealth avd Crti"),
+            [("avd", "adv"), ("Crti", "crit")]
+        );
+        assert!(flag_typos("is Grappling").is_empty());
+    }
+
+    #[test]
+    fn suggests_corrections() {
+        assert_eq!(suggest("d20+5"), None);
+        assert_eq!(suggest("d20+").as_deref(), Some("d20"));
+        assert_eq!(suggest("2d").as_deref(), Some("2d6"));
+        assert_eq!(
+            suggest("20d + 3 stealth").as_deref(),
+            Some("1d20 + 3 stealth")
+        );
+        assert_eq!(suggest("1d20++5").as_deref(), Some("1d20+5"));
+        assert_eq!(suggest("1d20+5"), None);
+        assert_eq!(suggest("rubbish"), None);
+    }
+
+    #[test]
+    fn rolls_a_d20_when_the_dice_are_left_out() {
+        let settings = RollSettings::from_str("+5 stealth").unwrap();
+        assert_eq!((settings.number, settings.sides), (1, 20));
+        assert_eq!(settings.modifier, Some(5));
+        assert_eq!(settings.label.as_deref(), Some("stealth"));
+        assert_eq!(RollSettings::from_str("-1").unwrap().modifier, Some(-1));
+        assert_eq!(
+            settings.echo("+5 stealth").as_deref(),
+            Some("Interpreted as: 1d20 + 5, labelled stealth")
+        );
+
+        for input in ["dc 10 flat", "Flat DC 10"] {
+            let settings = RollSettings::from_str(input).unwrap();
+            assert_eq!((settings.sides, settings.modifier), (20, None));
+            assert_eq!(settings.target, Some(10));
+            assert_eq!(settings.label.as_deref(), Some("flat check"));
+        }
+        assert!(RollSettings::from_str("dc 10").is_err());
+    }
+
+    #[test]
+    fn errors_point_at_the_position() {
+        assert_eq!(
+            RollSettings::from_str("1dx").unwrap_err().to_string(),
+            "Expected a die size after 'd' at position 3, but found \"x\""
+        );
+        assert_eq!(
+            RollSettings::from_str("").unwrap_err().to_string(),
+            "Expected the number of dice, like the 1 of 1d20, at position 1, but the input ended"
+        );
+    }
 }
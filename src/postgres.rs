@@ -0,0 +1,138 @@
+//! PostgreSQL storage backend, for deployments that outgrow a single JSON file.
+
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::types::Json;
+
+use crate::history::RollRecord;
+use crate::storage::{Storage, StorageBackend};
+
+static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!();
+
+#[derive(Debug)]
+pub struct Postgres {
+    pool: PgPool,
+}
+
+impl Postgres {
+    /// Connect to the database and bring its schema up to date
+    pub async fn connect(url: &str, max_connections: u32) -> anyhow::Result<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .connect(url)
+            .await
+            .context("error connecting to PostgreSQL")?;
+        MIGRATOR
+            .run(&pool)
+            .await
+            .context("error migrating the database")?;
+        Ok(Postgres { pool })
+    }
+}
+
+#[async_trait]
+impl StorageBackend for Postgres {
+    async fn load(&self) -> anyhow::Result<Storage> {
+        let document: Option<Json<Storage>> =
+            sqlx::query_scalar("SELECT document FROM storage WHERE id = 1")
+                .fetch_optional(&self.pool)
+                .await
+                .context("error loading storage")?;
+        Ok(document.map(|document| document.0).unwrap_or_default())
+    }
+
+    async fn save(&self, storage: &Storage) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO storage (id, document) VALUES (1, $1) \
+             ON CONFLICT (id) DO UPDATE SET document = EXCLUDED.document",
+        )
+        .bind(Json(storage))
+        .execute(&self.pool)
+        .await
+        .context("error saving storage")?;
+        Ok(())
+    }
+
+    async fn record_roll(&self, roll: &RollRecord) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO rolls (chat_id, user_id, timestamp, record) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(roll.chat_id)
+        .bind(roll.user_id)
+        .bind(roll.timestamp)
+        .bind(Json(roll))
+        .execute(&self.pool)
+        .await
+        .context("error recording roll")?;
+        Ok(())
+    }
+
+    async fn rolls(&self, chat_id: i64, since: DateTime<Utc>) -> anyhow::Result<Vec<RollRecord>> {
+        let rolls: Vec<Json<RollRecord>> = sqlx::query_scalar(
+            "SELECT record FROM rolls WHERE chat_id = $1 AND timestamp >= $2 \
+             ORDER BY timestamp, id",
+        )
+        .bind(chat_id)
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await
+        .context("error reading roll history")?;
+        Ok(rolls.into_iter().map(|roll| roll.0).collect())
+    }
+
+    async fn all_rolls(&self) -> anyhow::Result<Vec<RollRecord>> {
+        let rolls: Vec<Json<RollRecord>> =
+            sqlx::query_scalar("SELECT record FROM rolls ORDER BY timestamp, id")
+                .fetch_all(&self.pool)
+                .await
+                .context("error reading roll history")?;
+        Ok(rolls.into_iter().map(|roll| roll.0).collect())
+    }
+
+    async fn count_rolls(&self) -> anyhow::Result<usize> {
+        let count: i64 = sqlx::query_scalar("SELECT count(*) FROM rolls")
+            .fetch_one(&self.pool)
+            .await
+            .context("error counting roll history")?;
+        Ok(count as usize)
+    }
+
+    /// `voided` is only in the record of a voided roll
+    async fn void_last_roll(&self, chat_id: i64, user_id: i64) -> anyhow::Result<bool> {
+        let result = sqlx::query(
+            "UPDATE rolls SET record = jsonb_set(record, '{voided}', 'true') WHERE id = \
+             (SELECT id FROM rolls WHERE chat_id = $1 AND user_id = $2 \
+              AND NOT (record ? 'voided') ORDER BY timestamp DESC, id DESC LIMIT 1)",
+        )
+        .bind(chat_id)
+        .bind(user_id)
+        .execute(&self.pool)
+        .await
+        .context("error voiding roll")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// In one transaction, so that a failed insert keeps the old history
+    async fn replace_rolls(&self, rolls: &[RollRecord]) -> anyhow::Result<()> {
+        let mut transaction = self.pool.begin().await.context("error replacing rolls")?;
+        sqlx::query("DELETE FROM rolls")
+            .execute(&mut *transaction)
+            .await
+            .context("error replacing rolls")?;
+        for roll in rolls {
+            sqlx::query(
+                "INSERT INTO rolls (chat_id, user_id, timestamp, record) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(roll.chat_id)
+            .bind(roll.user_id)
+            .bind(roll.timestamp)
+            .bind(Json(roll))
+            .execute(&mut *transaction)
+            .await
+            .context("error replacing rolls")?;
+        }
+        transaction.commit().await.context("error replacing rolls")
+    }
+}
@@ -0,0 +1,185 @@
+//! Limits how many commands a user can send per minute, to keep spam off the Telegram API.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::transport::{ChatTransport, Incoming, Press};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    /// Tell the user to wait this long. Only happens once until they are allowed again.
+    Cooldown(Duration),
+    /// Ignore the command silently
+    Drop,
+}
+
+#[derive(Debug, Default)]
+struct Window {
+    commands: VecDeque<Instant>,
+    warned: bool,
+}
+
+#[derive(Debug, Default)]
+struct Users {
+    windows: HashMap<i64, Window>,
+    /// When the windows of users who went quiet were last removed
+    swept: Option<Instant>,
+}
+
+impl Users {
+    /// Forget users with no commands in the window, at most once a window, so that a public bot
+    /// does not remember everyone it ever saw
+    fn sweep(&mut self, now: Instant) {
+        if self
+            .swept
+            .is_some_and(|swept| now.duration_since(swept) < WINDOW)
+        {
+            return;
+        }
+        self.swept = Some(now);
+        self.windows.retain(|_, window| {
+            window
+                .commands
+                .back()
+                .is_some_and(|last| now.duration_since(*last) < WINDOW)
+        });
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Commands per user per minute, or 0 for no limit
+    limit: AtomicUsize,
+    users: Mutex<Users>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: usize) -> Self {
+        RateLimiter {
+            limit: AtomicUsize::new(limit),
+            users: Default::default(),
+        }
+    }
+
+    pub fn set_limit(&self, limit: usize) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    pub fn check(&self, user_id: i64, now: Instant) -> Decision {
+        let limit = self.limit.load(Ordering::Relaxed);
+        if limit == 0 {
+            return Decision::Allow;
+        }
+        let mut users = self.users.lock().expect("not to be poisoned");
+        users.sweep(now);
+        let window = users.windows.entry(user_id).or_default();
+        while window
+            .commands
+            .front()
+            .is_some_and(|command| now.duration_since(*command) >= WINDOW)
+        {
+            window.commands.pop_front();
+        }
+
+        if window.commands.len() < limit {
+            window.commands.push_back(now);
+            window.warned = false;
+            Decision::Allow
+        } else if window.warned {
+            Decision::Drop
+        } else {
+            window.warned = true;
+            let oldest = window.commands[0];
+            Decision::Cooldown(WINDOW.saturating_sub(now.duration_since(oldest)))
+        }
+    }
+}
+
+pub(crate) async fn cooldown(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    decision: Decision,
+) -> anyhow::Result<()> {
+    let Decision::Cooldown(wait) = decision else {
+        return Ok(());
+    };
+    bot.reply(
+        msg,
+        format!(
+            "Easy there! Try again in {} seconds. I will ignore you until then.",
+            wait.as_secs().max(1)
+        ),
+        vec![],
+    )
+    .await?;
+    Ok(())
+}
+
+/// Buttons count towards the limit too, as every press of Reroll rolls again
+pub(crate) async fn cooldown_button(
+    bot: &impl ChatTransport,
+    press: &Press,
+    decision: Decision,
+) -> anyhow::Result<()> {
+    let notice = match decision {
+        Decision::Cooldown(wait) => Some(format!(
+            "Easy there! Try again in {} seconds.",
+            wait.as_secs().max(1)
+        )),
+        _ => None,
+    };
+    bot.answer_press(press, notice).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warns_once_then_drops_until_the_window_passes() {
+        let limiter = RateLimiter::new(2);
+        let start = Instant::now();
+        assert_eq!(limiter.check(1, start), Decision::Allow);
+        assert_eq!(
+            limiter.check(1, start + Duration::from_secs(10)),
+            Decision::Allow
+        );
+        assert_eq!(
+            limiter.check(1, start + Duration::from_secs(20)),
+            Decision::Cooldown(Duration::from_secs(40))
+        );
+        assert_eq!(
+            limiter.check(1, start + Duration::from_secs(30)),
+            Decision::Drop
+        );
+        assert_eq!(
+            limiter.check(2, start + Duration::from_secs(30)),
+            Decision::Allow
+        );
+        assert_eq!(
+            limiter.check(1, start + Duration::from_secs(60)),
+            Decision::Allow
+        );
+        assert_eq!(RateLimiter::new(0).check(1, start), Decision::Allow);
+    }
+
+    #[test]
+    fn forgets_users_who_went_quiet() {
+        let limiter = RateLimiter::new(2);
+        let start = Instant::now();
+        for user_id in 1..=3 {
+            limiter.check(user_id, start);
+        }
+        limiter.check(4, start + Duration::from_secs(40));
+        limiter.check(5, start + Duration::from_secs(90));
+        let users = limiter.users.lock().unwrap();
+        let mut remembered: Vec<_> = users.windows.keys().copied().collect();
+        remembered.sort();
+        assert_eq!(remembered, [4, 5]);
+    }
+}
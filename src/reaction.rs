@@ -0,0 +1,339 @@
+//! Reactions, custom messages and stickers on special results, as set with `/settings`.
+
+use anyhow::bail;
+use chrono::{TimeDelta, Utc};
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+use teloxide::prelude::*;
+use teloxide::types::{InputFile, MessageId};
+use teloxide::utils::html;
+
+use crate::campaign::HouseRules;
+use crate::dice::RollResults;
+use crate::settings::{Flourish, Settings};
+use crate::storage::Store;
+use crate::transport::{ChatTransport, Incoming};
+use crate::AdaptedBot;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Natural {
+    Twenty,
+    One,
+}
+
+impl Natural {
+    fn emoji(&self) -> &'static str {
+        match self {
+            Natural::Twenty => "🎉",
+            // 🫠 is not among the reactions Telegram allows
+            Natural::One => "😭",
+        }
+    }
+}
+
+/// Whether a roll of a single d20, or of one kept d20, was a crit or a natural 1
+fn natural(results: &RollResults, rules: &HouseRules) -> Option<Natural> {
+    match results.result.natural() {
+        Some(roll) if crate::houserules::is_crit(rules, roll) => Some(Natural::Twenty),
+        Some(1) => Some(Natural::One),
+        _ => None,
+    }
+}
+
+/// Results that a sticker pack answers
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Outcome {
+    MaxDamage,
+    AllOnes,
+    /// A total of 69 or 420
+    Nice,
+}
+
+impl Outcome {
+    /// Stickers with this emoji are preferred
+    fn emoji(&self) -> &'static str {
+        match self {
+            Outcome::MaxDamage => "💥",
+            Outcome::AllOnes => "💀",
+            Outcome::Nice => "😏",
+        }
+    }
+}
+
+/// At most one sticker per chat in this long
+const STICKER_COOLDOWN: TimeDelta = TimeDelta::minutes(5);
+
+fn outcome(results: &RollResults) -> Option<Outcome> {
+    let settings = results.settings;
+    let roll = &results.result;
+    if matches!(roll.total, 69 | 420) {
+        return Some(Outcome::Nice);
+    }
+    // Several dice, as a single die comes up high too often. A d20 is a natural rather than damage.
+    if settings.number > 1
+        && !matches!(settings.sides, 1 | 20)
+        && roll.rolls.iter().all(|r| *r == settings.sides)
+    {
+        return Some(Outcome::MaxDamage);
+    }
+    if settings.number > 1 && roll.rolls.iter().all(|r| *r == 1) {
+        return Some(Outcome::AllOnes);
+    }
+    None
+}
+
+#[derive(Deserialize)]
+struct Response {
+    ok: bool,
+    #[serde(default)]
+    description: Option<String>,
+}
+
+/// The version of teloxide in use predates reactions, so the method is called directly. The URL
+/// holds the token, so it is kept out of the errors.
+async fn set_reaction(bot: &AdaptedBot, to: &Incoming, emoji: &str) -> anyhow::Result<()> {
+    let bot = bot.inner().inner().inner();
+    let url = bot
+        .api_url()
+        .join(&format!("/bot{}/setMessageReaction", bot.token()))?;
+    let body = serde_json::json!({
+        "chat_id": to.chat_id,
+        "message_id": to.message_id,
+        "reaction": [{"type": "emoji", "emoji": emoji}],
+    });
+    let response = bot
+        .client()
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(serde_json::to_vec(&body)?)
+        .send()
+        .await
+        .map_err(reqwest::Error::without_url)?;
+    let response = response
+        .bytes()
+        .await
+        .map_err(reqwest::Error::without_url)?;
+    let response: Response = serde_json::from_slice(&response)?;
+    if !response.ok {
+        bail!(
+            "error setting reaction: {}",
+            response.description.unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// How to answer a special result, worked out from the settings of the chat
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct Celebration {
+    /// Emoji to react to the command with
+    pub reaction: Option<&'static str>,
+    pub flourish: Option<Flourish>,
+    /// A sticker of the named pack, preferably one with the emoji
+    pub sticker: Option<(String, &'static str)>,
+}
+
+impl Celebration {
+    fn new(settings: &Settings, rules: &HouseRules, results: &RollResults) -> Self {
+        let mut celebration = Celebration::default();
+        if let Some(natural) = natural(results, rules) {
+            if settings.reactions {
+                celebration.reaction = Some(natural.emoji());
+            }
+            celebration.flourish = match natural {
+                Natural::Twenty => settings.crit.clone(),
+                Natural::One => settings.fumble.clone(),
+            };
+        }
+        if let (Some(outcome), Some(pack)) = (outcome(results), &settings.sticker_pack) {
+            celebration.sticker = Some((pack.clone(), outcome.emoji()));
+        }
+        celebration
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Celebration::default()
+    }
+}
+
+/// React to the command of a special result and answer it, as the chat is set up
+pub(crate) async fn react(
+    bot: &impl ChatTransport,
+    store: &Store,
+    to: &Incoming,
+    results: &RollResults<'_>,
+) -> anyhow::Result<()> {
+    let chat_id = to.chat_id;
+    let (settings, rules) = store
+        .read(|storage| {
+            let settings = storage
+                .chat(chat_id)
+                .map(|chat| chat.settings.clone())
+                .unwrap_or_default();
+            (settings, crate::houserules::for_chat(storage, chat_id))
+        })
+        .await;
+    let mut celebration = Celebration::new(&settings, &rules, results);
+    if celebration.sticker.is_some() && !sticker_allowed(store, chat_id).await? {
+        celebration.sticker = None;
+    }
+    if celebration.is_empty() {
+        return Ok(());
+    }
+    bot.react(to, &celebration).await
+}
+
+/// Whether the cooldown of stickers in the chat is over, starting it again if so
+async fn sticker_allowed(store: &Store, chat_id: i64) -> anyhow::Result<bool> {
+    let now = Utc::now();
+    store
+        .update(|storage| {
+            let chat = storage.chat_mut(chat_id);
+            let allowed = chat
+                .sticker_sent
+                .is_none_or(|sent| now - sent >= STICKER_COOLDOWN);
+            if allowed {
+                chat.sticker_sent = Some(now);
+            }
+            allowed
+        })
+        .await
+}
+
+/// Celebrate on Telegram
+pub(crate) async fn celebrate(
+    bot: &AdaptedBot,
+    to: &Incoming,
+    celebration: &Celebration,
+) -> anyhow::Result<()> {
+    if let Some(emoji) = celebration.reaction {
+        set_reaction(bot, to, emoji).await?;
+    }
+    match &celebration.flourish {
+        None => {}
+        Some(Flourish::Text(text)) => {
+            bot.send_message(ChatId(to.chat_id), html::escape(text))
+                .reply_to_message_id(MessageId(to.message_id))
+                .await?;
+        }
+        Some(Flourish::Sticker(id)) => {
+            bot.send_sticker(ChatId(to.chat_id), InputFile::file_id(id))
+                .reply_to_message_id(to.message_id)
+                .await?;
+        }
+        Some(Flourish::Animation(id)) => {
+            bot.send_animation(ChatId(to.chat_id), InputFile::file_id(id))
+                .reply_to_message_id(MessageId(to.message_id))
+                .await?;
+        }
+    }
+    if let Some((pack, emoji)) = &celebration.sticker {
+        send_sticker(bot, to, pack, emoji).await?;
+    }
+    Ok(())
+}
+
+async fn send_sticker(
+    bot: &AdaptedBot,
+    to: &Incoming,
+    pack: &str,
+    emoji: &str,
+) -> anyhow::Result<()> {
+    let set = bot.get_sticker_set(pack).await?;
+    let matching: Vec<_> = set
+        .stickers
+        .iter()
+        .filter(|sticker| sticker.emoji.as_deref() == Some(emoji))
+        .collect();
+    let sticker = match matching.is_empty() {
+        true => set.stickers.choose(&mut rand::thread_rng()),
+        false => matching.choose(&mut rand::thread_rng()).copied(),
+    };
+    if let Some(sticker) = sticker {
+        bot.send_sticker(ChatId(to.chat_id), InputFile::file_id(&sticker.file.id))
+            .reply_to_message_id(to.message_id)
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dice::{RollSettings, RollType};
+
+    #[tokio::test]
+    async fn keeps_the_token_out_of_errors() {
+        // Nothing listens on port 1
+        let bot = Bot::new("1:SECRET").set_api_url("http://127.0.0.1:1".parse().unwrap());
+        let bot = crate::adapt(bot, &Default::default());
+        let to = crate::testing::incoming(7);
+        let e = set_reaction(&bot, &to, "🎉").await.unwrap_err();
+        assert!(!format!("{:#}", e).contains("SECRET"), "{:#}", e);
+    }
+
+    #[test]
+    fn finds_naturals() {
+        let settings: RollSettings = "1d20 + 5".parse().unwrap();
+        for _ in 0..100 {
+            let results = RollResults::new(&settings, &RollType::Straight);
+            let expected = match results.result.rolls[0] {
+                20 => Some(Natural::Twenty),
+                1 => Some(Natural::One),
+                _ => None,
+            };
+            assert_eq!(natural(&results, &HouseRules::default()), expected);
+        }
+        let settings: RollSettings = "2d20".parse().unwrap();
+        let results = RollResults::new(&settings, &RollType::Straight);
+        assert!(natural(&results, &HouseRules::default()).is_none());
+    }
+
+    #[test]
+    fn celebrates_as_the_chat_is_set_up() {
+        let settings: RollSettings = "1d20".parse().unwrap();
+        let mut results = RollResults::new(&settings, &RollType::Straight);
+        results.result.rolls = vec![20];
+        results.result.recount();
+        let rules = HouseRules::default();
+        assert!(Celebration::new(&Settings::default(), &rules, &results).is_empty());
+
+        let chat = Settings {
+            reactions: true,
+            crit: Some(Flourish::Text("Nat 20!".to_string())),
+            sticker_pack: Some("dice".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            Celebration::new(&chat, &rules, &results),
+            Celebration {
+                reaction: Some("🎉"),
+                flourish: Some(Flourish::Text("Nat 20!".to_string())),
+                sticker: None,
+            }
+        );
+
+        let settings: RollSettings = "2d6".parse().unwrap();
+        let mut results = RollResults::new(&settings, &RollType::Straight);
+        results.result.rolls = vec![6, 6];
+        results.result.recount();
+        assert_eq!(
+            Celebration::new(&chat, &rules, &results).sticker,
+            Some(("dice".to_string(), "💥"))
+        );
+    }
+
+    #[test]
+    fn finds_outcomes() {
+        let settings: RollSettings = "3d1".parse().unwrap();
+        let results = RollResults::new(&settings, &RollType::Straight);
+        assert_eq!(outcome(&results), Some(Outcome::AllOnes));
+        let settings: RollSettings = "1d1 + 68".parse().unwrap();
+        let results = RollResults::new(&settings, &RollType::Straight);
+        assert_eq!(outcome(&results), Some(Outcome::Nice));
+        let settings: RollSettings = "1d20".parse().unwrap();
+        for _ in 0..100 {
+            assert!(outcome(&RollResults::new(&settings, &RollType::Straight)).is_none());
+        }
+    }
+}
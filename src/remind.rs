@@ -0,0 +1,279 @@
+//! Reminders for a chat, such as `/remind "Session Saturday 19:00" weekly`.
+
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Datelike, Days, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use teloxide::utils::html;
+
+use crate::scheduler::{Action, Interval, Job, Repeat};
+use crate::storage::{Storage, Store};
+use crate::transport::{ChatTransport, Incoming};
+
+/// Upper bound on the reminders of a chat
+const MAX_REMINDERS: usize = 20;
+
+#[derive(Debug, PartialEq)]
+struct Reminder {
+    text: String,
+    due: DateTime<Utc>,
+    every: Option<Interval>,
+}
+
+/// The first time after `now` on that day at that local time
+fn next_time(
+    timezone: Tz,
+    now: DateTime<Utc>,
+    day: Option<&str>,
+    time: NaiveTime,
+) -> anyhow::Result<DateTime<Utc>> {
+    let today = now.with_timezone(&timezone).date_naive();
+    let day = day.map(str::to_lowercase);
+    let (date, fixed) = match day.as_deref() {
+        None | Some("today") => (today, false),
+        Some("tomorrow") => (today + Days::new(1), true),
+        Some(day) => match NaiveDate::parse_from_str(day, "%Y-%m-%d") {
+            Ok(date) => (date, true),
+            Err(_) => {
+                let weekday: Weekday = day.parse().map_err(|_| anyhow!("unknown day {}", day))?;
+                let ahead = (weekday.num_days_from_monday() + 7
+                    - today.weekday().num_days_from_monday())
+                    % 7;
+                (today + Days::new(ahead as u64), false)
+            }
+        },
+    };
+    let local = |date: NaiveDate| {
+        timezone
+            .from_local_datetime(&date.and_time(time))
+            .earliest()
+            .map(|due| due.with_timezone(&Utc))
+            .ok_or_else(|| anyhow!("{} {} does not exist in {}", date, time, timezone))
+    };
+    let due = local(date)?;
+    if due > now {
+        return Ok(due);
+    }
+    if fixed {
+        bail!("{} {} has already passed", date, time.format("%H:%M"));
+    }
+    // Later today has passed, so the next day, or the same weekday next week
+    let days = match day.as_deref() {
+        None | Some("today") => 1,
+        Some(_) => 7,
+    };
+    local(date + Days::new(days))
+}
+
+/// Text then when, such as `Session Saturday 19:00`, optionally quoted and followed by how often
+fn parse_reminder(input: &str, timezone: Tz, now: DateTime<Utc>) -> anyhow::Result<Reminder> {
+    let mut input = input.trim();
+    let mut every = None;
+    for (suffix, interval) in [
+        ("daily", Some(Interval::Daily)),
+        ("weekly", Some(Interval::Weekly)),
+        ("once", None),
+    ] {
+        if let Some(rest) = input.strip_suffix(suffix) {
+            if rest.ends_with([' ', '"', '”']) {
+                input = rest.trim_end();
+                every = interval;
+                break;
+            }
+        }
+    }
+    let input = input.trim_matches(['"', '“', '”']);
+
+    let mut words: Vec<_> = input.split_whitespace().collect();
+    let time = words
+        .pop()
+        .and_then(|time| NaiveTime::parse_from_str(time, "%H:%M").ok())
+        .ok_or_else(|| anyhow!("a reminder ends with a time, such as 19:00"))?;
+    let day = match words.last() {
+        Some(word) if is_day(word) => words.pop(),
+        _ => None,
+    };
+    let due = next_time(timezone, now, day, time)?;
+    let text = match words.join(" ") {
+        text if text.is_empty() => "Reminder".to_string(),
+        text => text,
+    };
+    Ok(Reminder { text, due, every })
+}
+
+fn is_day(word: &str) -> bool {
+    let word = word.to_lowercase();
+    word == "today"
+        || word == "tomorrow"
+        || word.parse::<Weekday>().is_ok()
+        || NaiveDate::parse_from_str(&word, "%Y-%m-%d").is_ok()
+}
+
+fn is_reminder(storage: &Storage, chat_id: i64, id: u64) -> bool {
+    storage
+        .jobs
+        .for_chat(chat_id)
+        .iter()
+        .any(|job| job.id == id && matches!(job.action, Action::Reminder { .. }))
+}
+
+fn format_job(job: &Job, timezone: Tz) -> Option<String> {
+    let Action::Reminder { ref text, .. } = job.action else {
+        return None;
+    };
+    let mut line = format!(
+        "#{} {} · {}",
+        job.id,
+        html::escape(text),
+        crate::localtime::datetime(job.due, timezone)
+    );
+    match job.repeat.map(|repeat| repeat.every) {
+        Some(Interval::Daily) => line.push_str(", daily"),
+        Some(Interval::Weekly) => line.push_str(", weekly"),
+        None => {}
+    }
+    Some(line)
+}
+
+const USAGE: &str =
+    "<code>/remind \"Session Saturday 19:00\" weekly</code> reminds the chat every week
+<code>/remind Pay the dues tomorrow 12:00</code> reminds the chat once
+<code>/remind list</code> lists the reminders of this chat
+<code>/remind cancel 3</code> cancels a reminder
+<code>/settings timezone Europe/London</code> sets the timezone of this chat
+Days can be today, tomorrow, a weekday or a date like 2024-12-31.";
+
+fn remind(storage: &mut Storage, chat_id: i64, input: &str, now: DateTime<Utc>) -> String {
+    let timezone = crate::localtime::timezone(storage, chat_id);
+    let mut args = input.split_whitespace();
+    match args.next() {
+        None => USAGE.to_string(),
+        Some("list") => {
+            let lines: Vec<_> = storage
+                .jobs
+                .for_chat(chat_id)
+                .into_iter()
+                .filter_map(|job| format_job(job, timezone))
+                .collect();
+            if lines.is_empty() {
+                return "This chat has no reminders.".to_string();
+            }
+            format!("⏰ Reminders, in {}\n{}", timezone, lines.join("\n"))
+        }
+        Some("cancel") => match args
+            .next()
+            .and_then(|id| id.trim_start_matches('#').parse().ok())
+        {
+            Some(id) if is_reminder(storage, chat_id, id) => {
+                storage.jobs.cancel_in_chat(chat_id, id);
+                format!("Cancelled reminder #{}", id)
+            }
+            _ => "There is no reminder with that number. See /remind list.".to_string(),
+        },
+        // From before the timezone moved to /settings
+        Some("timezone") => crate::localtime::change_timezone(storage, chat_id, args.next()),
+        Some(_) => {
+            let reminder = match parse_reminder(input, timezone, now) {
+                Ok(reminder) => reminder,
+                Err(e) => return format!("{}\n\n{}", html::escape(&format!("{:#}", e)), USAGE),
+            };
+            let count = storage
+                .jobs
+                .for_chat(chat_id)
+                .iter()
+                .filter(|job| matches!(job.action, Action::Reminder { .. }))
+                .count();
+            if count >= MAX_REMINDERS {
+                return format!("This chat already has {} reminders.", MAX_REMINDERS);
+            }
+            let repeat = reminder.every.map(|every| Repeat { every, timezone });
+            let id = storage.jobs.schedule_repeating(
+                reminder.due,
+                Action::Reminder {
+                    chat_id,
+                    text: reminder.text.clone(),
+                },
+                repeat,
+            );
+            let job = storage
+                .jobs
+                .for_chat(chat_id)
+                .into_iter()
+                .find(|job| job.id == id)
+                .and_then(|job| format_job(job, timezone))
+                .expect("to be scheduled");
+            format!("⏰ Reminder set, in {}\n{}", timezone, job)
+        }
+    }
+}
+
+pub(crate) async fn handle_remind(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let text = store
+        .update(|storage| remind(storage, chat_id, input, Utc::now()))
+        .await?;
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_reminders() {
+        let london: Tz = "Europe/London".parse().unwrap();
+        // A Wednesday
+        let now = Utc.with_ymd_and_hms(2024, 7, 3, 12, 0, 0).unwrap();
+
+        let reminder = parse_reminder("\"Session Saturday 19:00\" weekly", london, now).unwrap();
+        assert_eq!(
+            reminder,
+            Reminder {
+                text: "Session".to_string(),
+                due: Utc.with_ymd_and_hms(2024, 7, 6, 18, 0, 0).unwrap(),
+                every: Some(Interval::Weekly),
+            }
+        );
+        let reminder = parse_reminder("wed 11:00", london, now).unwrap();
+        assert_eq!(reminder.text, "Reminder");
+        assert_eq!(
+            reminder.due,
+            Utc.with_ymd_and_hms(2024, 7, 10, 10, 0, 0).unwrap()
+        );
+        let reminder = parse_reminder("Lunch 11:00 daily", Tz::UTC, now).unwrap();
+        assert_eq!(
+            reminder.due,
+            Utc.with_ymd_and_hms(2024, 7, 4, 11, 0, 0).unwrap()
+        );
+        assert_eq!(reminder.every, Some(Interval::Daily));
+
+        assert!(parse_reminder("Session 2024-07-01 19:00", london, now).is_err());
+        assert!(parse_reminder("Session soon", london, now).is_err());
+    }
+
+    #[test]
+    fn manages_reminders() {
+        let mut storage = Storage::default();
+        let now = Utc.with_ymd_and_hms(2024, 7, 3, 12, 0, 0).unwrap();
+        assert_eq!(
+            remind(&mut storage, 1, "timezone Europe/Berlin", now),
+            "This chat now uses Europe/Berlin."
+        );
+        let text = remind(&mut storage, 1, "Session Saturday 19:00 weekly", now);
+        assert_eq!(
+            text,
+            "⏰ Reminder set, in Europe/Berlin\n#0 Session · Sat 2024-07-06 19:00, weekly"
+        );
+        assert!(remind(&mut storage, 2, "list", now).contains("no reminders"));
+        assert!(remind(&mut storage, 2, "cancel 0", now).starts_with("There is no"));
+        assert_eq!(
+            remind(&mut storage, 1, "cancel #0", now),
+            "Cancelled reminder #0"
+        );
+    }
+}
@@ -0,0 +1,116 @@
+//! Sends errors to an operator chat, so that they do not only end up in the logs.
+
+use std::time::Duration;
+
+use teloxide::prelude::*;
+use tokio::sync::mpsc;
+
+use crate::AdaptedBot;
+
+/// At most one report is sent per interval. The errors in between are summed up.
+const INTERVAL: Duration = Duration::from_secs(60);
+
+/// Telegram allows 4096 characters per message
+const MAX_LENGTH: usize = 3500;
+
+/// Handle to queue error reports. Does nothing when no chat is configured.
+#[derive(Clone, Debug, Default)]
+pub struct Reporter {
+    sender: Option<mpsc::UnboundedSender<String>>,
+}
+
+impl Reporter {
+    /// Start sending reports to the chat. The token is redacted from every report.
+    pub fn spawn(bot: AdaptedBot, chat_id: i64, token: String) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(run(bot, ChatId(chat_id), token, receiver));
+        Reporter {
+            sender: Some(sender),
+        }
+    }
+
+    pub fn report(&self, error: String) {
+        if let Some(ref sender) = self.sender {
+            // The task only stops when the runtime does
+            let _ = sender.send(error);
+        }
+    }
+
+    /// Report panics too, on top of printing them as usual
+    pub fn install_panic_hook(&self) {
+        let reporter = self.clone();
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            reporter.report(format!("Panic: {}", info));
+            previous(info);
+        }));
+    }
+}
+
+fn redact(text: &str, token: &str) -> String {
+    if token.is_empty() {
+        return text.to_string();
+    }
+    text.replace(token, "[redacted]")
+}
+
+/// The first error in full, and how many more there were
+fn summarize(errors: &[String], token: &str) -> String {
+    let mut summary = redact(&errors[0], token);
+    if summary.len() > MAX_LENGTH {
+        let mut end = MAX_LENGTH;
+        while !summary.is_char_boundary(end) {
+            end -= 1;
+        }
+        summary.truncate(end);
+        summary.push('…');
+    }
+    match errors.len() {
+        1 => summary,
+        n => format!("{}\n\n…and {} more errors since", summary, n - 1),
+    }
+}
+
+async fn run(
+    bot: AdaptedBot,
+    chat_id: ChatId,
+    token: String,
+    mut receiver: mpsc::UnboundedReceiver<String>,
+) {
+    while let Some(first) = receiver.recv().await {
+        let mut errors = vec![first];
+        while let Ok(error) = receiver.try_recv() {
+            errors.push(error);
+        }
+        let text = teloxide::utils::html::escape(&summarize(&errors, &token));
+        if let Err(e) = bot
+            .send_message(chat_id, format!("⚠️ <pre>{}</pre>", text))
+            .await
+        {
+            log::warn!("Could not send error report to chat {}: {}", chat_id, e);
+        }
+        tokio::time::sleep(INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes_redacted_errors() {
+        let errors = vec![
+            "error sending to https://api.telegram.org/bot123:abc/sendMessage".to_string(),
+            "another".to_string(),
+        ];
+        assert_eq!(
+            summarize(&errors[..1], "123:abc"),
+            "error sending to https://api.telegram.org/bot[redacted]/sendMessage"
+        );
+        assert!(summarize(&errors, "123:abc").ends_with("…and 1 more errors since"));
+        assert_eq!(
+            summarize(&["é".repeat(MAX_LENGTH)], "").chars().count(),
+            MAX_LENGTH / 2 + 1
+        );
+    }
+}
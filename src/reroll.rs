@@ -0,0 +1,201 @@
+//! The Reroll button under roll results, which rolls again in place of the old results.
+
+use std::str::FromStr;
+
+use crate::dice::{RollResults, RollSettings, RollType};
+use crate::storage::Store;
+use crate::transport::{ChatTransport, Press};
+
+/// Telegram allows at most this many bytes of callback data
+const MAX_DATA: usize = 64;
+
+const LABEL: &str = "🎲 Reroll";
+
+/// What a Reroll button rolls, kept in its callback data
+#[derive(Debug, PartialEq, Eq)]
+struct Button {
+    /// Only whoever rolled may roll again
+    user_id: i64,
+    roll_type: RollType,
+    attempt: u32,
+    expression: String,
+}
+
+impl Button {
+    fn data(&self) -> String {
+        let roll_type = match self.roll_type {
+            RollType::Straight => 's',
+            RollType::Advantage => 'a',
+            RollType::Disadvantage => 'd',
+        };
+        format!(
+            "r:{}:{}:{}:{}",
+            self.user_id, roll_type, self.attempt, self.expression
+        )
+    }
+
+    fn parse(data: &str) -> Option<Self> {
+        let mut parts = data.strip_prefix("r:")?.splitn(4, ':');
+        let user_id = parts.next()?.parse().ok()?;
+        let roll_type = match parts.next()? {
+            "s" => RollType::Straight,
+            "a" => RollType::Advantage,
+            "d" => RollType::Disadvantage,
+            _ => return None,
+        };
+        let attempt = parts.next()?.parse().ok()?;
+        let expression = parts.next()?.to_string();
+        Some(Button {
+            user_id,
+            roll_type,
+            attempt,
+            expression,
+        })
+    }
+}
+
+/// The button for a roll, when its expression is short enough for the callback data
+fn keyboard(button: &Button) -> Option<crate::transport::Button> {
+    let data = button.data();
+    (data.len() <= MAX_DATA).then(|| crate::transport::Button {
+        label: LABEL.to_string(),
+        data,
+    })
+}
+
+/// The button to put under the first results of a roll
+pub(crate) fn first_button(
+    user_id: i64,
+    expression: &str,
+    roll_type: &RollType,
+) -> Option<crate::transport::Button> {
+    keyboard(&Button {
+        user_id,
+        roll_type: roll_type.clone(),
+        attempt: 1,
+        expression: expression.to_string(),
+    })
+}
+
+fn attempt_text(
+    results: &RollResults,
+    character: Option<&str>,
+    notes: &[String],
+    attempt: u32,
+) -> String {
+    let mut text = results.announce(character);
+    for note in notes {
+        text.push_str(&format!("\n{}", note));
+    }
+    format!("{}\n\n🔁 Attempt {}", text, attempt)
+}
+
+pub(crate) async fn handle_press(
+    bot: &impl ChatTransport,
+    press: &Press,
+    store: Store,
+    roll_log: crate::rolllog::RollLog,
+) -> anyhow::Result<()> {
+    let Some(button) = Button::parse(&press.data) else {
+        return Ok(());
+    };
+    let Some((chat_id, message_id)) = press.message else {
+        return Ok(());
+    };
+    let user_id = press.from.id;
+    if user_id != button.user_id {
+        let notice = "Only whoever rolled may roll again.".to_string();
+        bot.answer_press(press, Some(notice)).await?;
+        return Ok(());
+    }
+    let (allowed, rules) = store
+        .read(|storage| {
+            (
+                crate::campaign::may_reroll(storage, chat_id, user_id),
+                crate::houserules::for_chat(storage, chat_id),
+            )
+        })
+        .await;
+    if !allowed {
+        let notice = "The house rules of this campaign do not allow you to reroll.".to_string();
+        bot.answer_press(press, Some(notice)).await?;
+        return Ok(());
+    }
+    bot.answer_press(press, None).await?;
+
+    let next = Button {
+        attempt: button.attempt + 1,
+        ..button
+    };
+    let settings = match RollSettings::from_str(&next.expression) {
+        Ok(settings) => settings,
+        Err(e) => {
+            let to = crate::transport::Incoming {
+                chat_id,
+                message_id,
+                ..Default::default()
+            };
+            bot.reply(&to, e.apology(), vec![]).await?;
+            return Ok(());
+        }
+    };
+    let mut results = RollResults::new(&settings, &next.roll_type);
+    let notes = crate::houserules::apply(&rules, &mut results);
+    let character = crate::active_character(&store, chat_id, user_id).await;
+    bot.edit(
+        chat_id,
+        message_id,
+        attempt_text(&results, character.as_deref(), &notes, next.attempt),
+        keyboard(&next).into_iter().collect(),
+    )
+    .await?;
+    let event = crate::webhook::RollEvent::new(
+        chat_id,
+        Some(user_id),
+        character.clone(),
+        crate::output::RollOutput::new(&next.expression, &results),
+    );
+    roll_log.roll(&store, event).await;
+    crate::record_roll(
+        &store,
+        chat_id,
+        user_id,
+        character,
+        message_id,
+        &next.expression,
+        &results,
+    )
+    .await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_buttons() {
+        let button = Button {
+            user_id: 42,
+            roll_type: RollType::Advantage,
+            attempt: 3,
+            expression: "1d20 + 5 init: fast".to_string(),
+        };
+        assert_eq!(button.data(), "r:42:a:3:1d20 + 5 init: fast");
+        assert_eq!(Button::parse(&button.data()), Some(button));
+        assert!(Button::parse("r:42:x:1:1d20").is_none());
+
+        let long = Button {
+            user_id: 42,
+            roll_type: RollType::Straight,
+            attempt: 1,
+            expression: "1d20 ".repeat(20),
+        };
+        assert!(keyboard(&long).is_none());
+        assert!(first_button(42, &long.expression, &RollType::Straight).is_none());
+        assert_eq!(
+            first_button(42, "1d20", &RollType::Straight).map(|button| button.data),
+            Some("r:42:s:1:1d20".to_string())
+        );
+    }
+}
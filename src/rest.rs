@@ -0,0 +1,529 @@
+//! Short and long rests, spending hit dice with `/hitdice`, and what rests restore with `/spend`.
+
+use teloxide::utils::html;
+
+use crate::dice::{Flags, RollResults, RollSettings, RollType};
+use crate::dnd::{Character, Recharge};
+use crate::storage::{CharacterState, Storage, Store};
+use crate::transport::{ChatTransport, Incoming};
+
+fn ordinal(level: usize) -> String {
+    let suffix = match level {
+        1 => "st",
+        2 => "nd",
+        3 => "rd",
+        _ => "th",
+    };
+    format!("{}{}", level, suffix)
+}
+
+/// Roll `count` hit dice with the Constitution modifier, returning the healing and how it was rolled
+pub(crate) fn spend_hit_dice(
+    character: &Character,
+    state: &mut CharacterState,
+    count: u8,
+) -> Result<(i64, String), String> {
+    let Some(hit_dice) = character.hit_dice else {
+        return Err(format!(
+            "{} has no hit dice on their sheet.",
+            html::escape(&character.name)
+        ));
+    };
+    let left = hit_dice.count.saturating_sub(state.hit_dice_spent);
+    if count == 0 || count > left {
+        return Err(format!(
+            "{} has {} of {} d{} hit dice left.",
+            html::escape(&character.name),
+            left,
+            hit_dice.count,
+            hit_dice.sides
+        ));
+    }
+    let constitution = *character
+        .attribute_modifiers
+        .get(crate::dnd::Ability::Constitution) as i32;
+    let settings = RollSettings {
+        number: count as u32,
+        sides: hit_dice.sides as u32,
+        modifier: Some(constitution * count as i32).filter(|modifier| *modifier != 0),
+        label: None,
+        target: None,
+        keep: None,
+        explode: None,
+        success: None,
+        composite: false,
+        multiplier: None,
+        flags: Flags::NONE,
+    };
+    let results = RollResults::new(&settings, &RollType::Straight);
+    let roll = &results.result;
+    let healing = roll.total.max(0);
+    state.hit_dice_spent += count;
+    let line = format!(
+        "🎲 Hit dice: {} = <b>{}</b> healing, {} of {} d{} left",
+        roll.format_roll(Some(200)),
+        healing,
+        left - count,
+        hit_dice.count,
+        hit_dice.sides
+    );
+    Ok((healing, line))
+}
+
+fn hit_dice_left(character: &Character, state: &CharacterState) -> String {
+    let name = html::escape(&character.name);
+    match character.hit_dice {
+        None => format!(
+            "{} has no hit dice on their sheet. Add them as <code>\"hit_dice\": {{\"count\": 5, \"sides\": 10}}</code>.",
+            name
+        ),
+        Some(hit_dice) => format!(
+            "🎲 {} has {} of {} d{} hit dice left.",
+            name,
+            hit_dice.count.saturating_sub(state.hit_dice_spent),
+            hit_dice.count,
+            hit_dice.sides
+        ),
+    }
+}
+
+/// Heal the character in the combat of the chat, or restore them fully without `healing`
+pub(crate) fn heal(
+    storage: &mut Storage,
+    chat_id: i64,
+    name: &str,
+    healing: Option<i64>,
+) -> Option<String> {
+    let combatant = storage.chat_mut(chat_id).combat.combatant_mut(name)?;
+    let hit_points = combatant.hit_points.as_mut()?;
+    hit_points.current = match healing {
+        Some(healing) => (hit_points.current as i64 + healing).min(hit_points.max as i64) as i32,
+        None => hit_points.max as i32,
+    };
+    Some(format!("❤️ HP {}/{}", hit_points.current, hit_points.max))
+}
+
+/// Restore the resources that recharge on the rest, returning their names
+fn recharge(character: &Character, state: &mut CharacterState, long: bool) -> Vec<String> {
+    character
+        .resources
+        .iter()
+        .filter(|resource| long || resource.recharge == Recharge::ShortRest)
+        .filter(|resource| state.resources_spent.remove(&resource.name).unwrap_or(0) > 0)
+        .map(|resource| html::escape(&resource.name))
+        .collect()
+}
+
+fn short_rest(
+    character: &Character,
+    state: &mut CharacterState,
+    hit_dice: u8,
+) -> Result<(i64, String), String> {
+    let mut text = format!(
+        "🛌 <b>{}</b> takes a short rest",
+        html::escape(&character.name)
+    );
+    let mut healing = 0;
+    if hit_dice > 0 {
+        let (rolled, line) = spend_hit_dice(character, state, hit_dice)?;
+        healing = rolled;
+        text.push_str(&format!("\n{}", line));
+    }
+    let restored = recharge(character, state, false);
+    if !restored.is_empty() {
+        text.push_str(&format!("\n🔋 Restored: {}", restored.join(", ")));
+    }
+    Ok((healing, text))
+}
+
+fn long_rest(character: &Character, state: &mut CharacterState) -> String {
+    let mut text = format!(
+        "🏕 <b>{}</b> takes a long rest",
+        html::escape(&character.name)
+    );
+    if let Some(hit_dice) = character.hit_dice {
+        // The sheet may have fewer hit dice now than were spent
+        state.hit_dice_spent = state.hit_dice_spent.min(hit_dice.count);
+        let regained = state.hit_dice_spent.min((hit_dice.count / 2).max(1));
+        state.hit_dice_spent -= regained;
+        text.push_str(&format!(
+            "\n🎲 Regained {} hit dice, {} of {} d{} left",
+            regained,
+            hit_dice.count.saturating_sub(state.hit_dice_spent),
+            hit_dice.count,
+            hit_dice.sides
+        ));
+    }
+    state.spell_slots_spent.clear();
+    let slots: Vec<_> = character
+        .spell_slots
+        .iter()
+        .enumerate()
+        .filter(|(_, slots)| **slots > 0)
+        .map(|(level, slots)| format!("{} × {}", slots, ordinal(level + 1)))
+        .collect();
+    if !slots.is_empty() {
+        text.push_str(&format!("\n✨ Spell slots: {}", slots.join(", ")));
+    }
+    let restored = recharge(character, state, true);
+    if !restored.is_empty() {
+        text.push_str(&format!("\n🔋 Restored: {}", restored.join(", ")));
+    }
+    if state.luck_points.is_some() {
+        state.luck_points = Some(crate::inspiration::LUCK_POINTS);
+        text.push_str(&format!(
+            "\n🍀 Luck points: {}",
+            crate::inspiration::LUCK_POINTS
+        ));
+    }
+    text
+}
+
+/// Run `f` on the character the user plays in the chat and its play state
+pub(crate) fn with_character<F>(storage: &mut Storage, chat_id: i64, user_id: i64, f: F) -> String
+where
+    F: FnOnce(&Character, &mut CharacterState) -> String,
+{
+    let Some(character) = storage
+        .user(user_id)
+        .and_then(|user| user.character(chat_id, None))
+        .cloned()
+    else {
+        return "You have no character. Upload one with /sheet upload.".to_string();
+    };
+    let (_, state) = storage
+        .user_mut(user_id)
+        .active_state_mut(chat_id)
+        .expect("to have a character");
+    f(&character, state)
+}
+
+const HIT_DICE_USAGE: &str = "<code>/hitdice</code> shows the hit dice you have left
+<code>/hitdice spend 2</code> rolls two of them with your Constitution modifier to heal
+Hit dice come back with /longrest.";
+
+pub(crate) async fn handle_hitdice(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let Some(user) = &msg.from else {
+        return Ok(());
+    };
+    let (chat_id, user_id) = (msg.chat_id, user.id);
+    let args: Vec<_> = input.split_whitespace().collect();
+    // Hit dice to spend, where none shows the hit dice left
+    let count = match args.as_slice() {
+        [] => Some(None),
+        ["spend"] => Some(Some(1)),
+        ["spend", count] => count.parse().ok().map(Some),
+        _ => None,
+    };
+    let text = match count {
+        None => HIT_DICE_USAGE.to_string(),
+        Some(count) => {
+            store
+                .update(|storage| {
+                    let mut healed = None;
+                    let text = with_character(storage, chat_id, user_id, |character, state| {
+                        let Some(count) = count else {
+                            return hit_dice_left(character, state);
+                        };
+                        match spend_hit_dice(character, state, count) {
+                            Ok((healing, line)) => {
+                                healed = Some((character.name.clone(), healing));
+                                format!("{}\n{}", html::escape(&character.name), line)
+                            }
+                            Err(e) => e,
+                        }
+                    });
+                    let healed = healed
+                        .and_then(|(name, healing)| heal(storage, chat_id, &name, Some(healing)));
+                    match healed {
+                        Some(hit_points) => format!("{}\n{}", text, hit_points),
+                        None => text,
+                    }
+                })
+                .await?
+        }
+    };
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+const SHORT_REST_USAGE: &str = "<code>/shortrest</code> takes a short rest
+<code>/shortrest 2</code> spends two hit dice on it to heal";
+
+pub(crate) async fn handle_shortrest(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let Some(user) = &msg.from else {
+        return Ok(());
+    };
+    let (chat_id, user_id) = (msg.chat_id, user.id);
+    let hit_dice = match input.trim() {
+        "" => Some(0),
+        count => count.parse().ok(),
+    };
+    let text = match hit_dice {
+        None => SHORT_REST_USAGE.to_string(),
+        Some(hit_dice) => {
+            store
+                .update(|storage| {
+                    let mut rested = None;
+                    let text = with_character(storage, chat_id, user_id, |character, state| {
+                        match short_rest(character, state, hit_dice) {
+                            Ok((healing, text)) => {
+                                rested = Some((character.name.clone(), healing));
+                                text
+                            }
+                            Err(e) => e,
+                        }
+                    });
+                    match rested {
+                        Some((name, healing)) if healing > 0 => {
+                            match heal(storage, chat_id, &name, Some(healing)) {
+                                Some(hit_points) => format!("{}\n{}", text, hit_points),
+                                None => text,
+                            }
+                        }
+                        _ => text,
+                    }
+                })
+                .await?
+        }
+    };
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+pub(crate) async fn handle_longrest(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+) -> anyhow::Result<()> {
+    let Some(user) = &msg.from else {
+        return Ok(());
+    };
+    let (chat_id, user_id) = (msg.chat_id, user.id);
+    let text = store
+        .update(|storage| {
+            let mut rested = None;
+            let text = with_character(storage, chat_id, user_id, |character, state| {
+                rested = Some(character.name.clone());
+                long_rest(character, state)
+            });
+            match rested.and_then(|name| heal(storage, chat_id, &name, None)) {
+                Some(hit_points) => format!("{}\n{}", text, hit_points),
+                None => text,
+            }
+        })
+        .await?;
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+/// Spend a spell slot of a level or a use of a resource
+fn spend(character: &Character, state: &mut CharacterState, what: &str) -> String {
+    let name = html::escape(&character.name);
+    if let Some(level) = what.strip_prefix("slot").map(str::trim) {
+        let slots = level
+            .trim_end_matches(|c: char| c.is_ascii_alphabetic())
+            .parse::<usize>()
+            .ok()
+            .filter(|level| *level > 0)
+            .and_then(|level| Some((level, *character.spell_slots.get(level - 1)?)));
+        let Some((level, slots)) = slots else {
+            return format!("{} has no spell slots of that level.", name);
+        };
+        if state.spell_slots_spent.len() < level {
+            state.spell_slots_spent.resize(level, 0);
+        }
+        let spent = &mut state.spell_slots_spent[level - 1];
+        if *spent >= slots {
+            return format!("{} has no {} level spell slots left.", name, ordinal(level));
+        }
+        *spent += 1;
+        return format!(
+            "✨ {} spends a {} level spell slot, {} of {} left.",
+            name,
+            ordinal(level),
+            slots - *spent,
+            slots
+        );
+    }
+    let Some(resource) = character
+        .resources
+        .iter()
+        .find(|resource| resource.name.eq_ignore_ascii_case(what))
+    else {
+        return format!("{} has no resource called {}.", name, html::escape(what));
+    };
+    let spent = state
+        .resources_spent
+        .entry(resource.name.clone())
+        .or_default();
+    if *spent >= resource.uses {
+        return format!(
+            "{} has no uses of {} left.",
+            name,
+            html::escape(&resource.name)
+        );
+    }
+    *spent += 1;
+    format!(
+        "🔋 {} uses {}, {} of {} left.",
+        name,
+        html::escape(&resource.name),
+        resource.uses - *spent,
+        resource.uses
+    )
+}
+
+/// What is left until the next rest
+fn remaining(character: &Character, state: &CharacterState) -> String {
+    let mut lines = vec![format!("<b>{}</b>", html::escape(&character.name))];
+    for (level, slots) in character.spell_slots.iter().enumerate() {
+        let spent = state.spell_slots_spent.get(level).copied().unwrap_or(0);
+        if *slots > 0 {
+            lines.push(format!(
+                "✨ {} level slots: {}/{}",
+                ordinal(level + 1),
+                slots.saturating_sub(spent),
+                slots
+            ));
+        }
+    }
+    for resource in &character.resources {
+        let spent = state
+            .resources_spent
+            .get(&resource.name)
+            .copied()
+            .unwrap_or(0);
+        lines.push(format!(
+            "🔋 {}: {}/{}",
+            html::escape(&resource.name),
+            resource.uses.saturating_sub(spent),
+            resource.uses
+        ));
+    }
+    if lines.len() == 1 {
+        lines.push("No spell slots or resources on the sheet.".to_string());
+    }
+    lines.join("\n")
+}
+
+pub(crate) async fn handle_spend(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let Some(user) = &msg.from else {
+        return Ok(());
+    };
+    let (chat_id, user_id) = (msg.chat_id, user.id);
+    let what = input.trim().to_lowercase();
+    let text = store
+        .update(|storage| {
+            with_character(storage, chat_id, user_id, |character, state| {
+                match what.as_str() {
+                    "" => remaining(character, state),
+                    what => spend(character, state, what),
+                }
+            })
+        })
+        .await?;
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dnd::{HitDice, Resource};
+
+    fn fighter() -> Character {
+        let mut character = Character {
+            hit_dice: Some(HitDice { count: 5, sides: 1 }),
+            spell_slots: vec![2],
+            resources: vec![
+                Resource {
+                    name: "Second Wind".to_string(),
+                    uses: 1,
+                    recharge: Recharge::ShortRest,
+                },
+                Resource {
+                    name: "Indomitable".to_string(),
+                    uses: 1,
+                    recharge: Recharge::LongRest,
+                },
+            ],
+            ..crate::testing::character("Brom")
+        };
+        character.attribute_modifiers.strength = 3;
+        character.attribute_modifiers.constitution = 2;
+        character
+    }
+
+    #[test]
+    fn rests_restore_resources() {
+        let character = fighter();
+        let mut state = CharacterState::default();
+        assert!(spend(&character, &mut state, "second wind").ends_with("0 of 1 left."));
+        assert!(spend(&character, &mut state, "second wind").contains("no uses"));
+        spend(&character, &mut state, "indomitable");
+        assert_eq!(
+            spend(&character, &mut state, "slot 1st"),
+            "✨ Brom spends a 1st level spell slot, 1 of 2 left."
+        );
+
+        let (healing, text) = short_rest(&character, &mut state, 2).unwrap();
+        assert_eq!(healing, 6);
+        assert_eq!(
+            text,
+            "🛌 <b>Brom</b> takes a short rest\n\
+             🎲 Hit dice: (1 + 1) + 4 = <b>6</b> healing, 3 of 5 d1 left\n\
+             🔋 Restored: Second Wind"
+        );
+        assert!(short_rest(&character, &mut state, 4).is_err());
+
+        assert_eq!(
+            long_rest(&character, &mut state),
+            "🏕 <b>Brom</b> takes a long rest\n\
+             🎲 Regained 2 hit dice, 5 of 5 d1 left\n\
+             ✨ Spell slots: 2 × 1st\n\
+             🔋 Restored: Indomitable"
+        );
+        assert_eq!(state, CharacterState::default());
+    }
+
+    #[test]
+    fn spends_hit_dice() {
+        let mut character = fighter();
+        let mut state = CharacterState::default();
+        assert_eq!(
+            spend_hit_dice(&character, &mut state, 5).unwrap().1,
+            "🎲 Hit dice: (1 + 1 + 1 + 1 + 1) + 10 = <b>15</b> healing, 0 of 5 d1 left"
+        );
+        assert_eq!(
+            spend_hit_dice(&character, &mut state, 1).unwrap_err(),
+            "Brom has 0 of 5 d1 hit dice left."
+        );
+        assert_eq!(
+            hit_dice_left(&character, &state),
+            "🎲 Brom has 0 of 5 d1 hit dice left."
+        );
+
+        // The sheet was uploaded again with fewer hit dice
+        character.hit_dice.as_mut().unwrap().count = 3;
+        assert!(long_rest(&character, &mut state).contains("Regained 1 hit dice, 1 of 3 d1 left"));
+        assert_eq!(state.hit_dice_spent, 2);
+        character.hit_dice = None;
+        assert!(hit_dice_left(&character, &state).contains("no hit dice"));
+    }
+}
@@ -0,0 +1,196 @@
+//! Every roll as a line of JSON, in the format of `/data`, appended to a file or posted to a webhook
+//! as it happens. For dashboards of operators who do not want to read the storage. Rolls of campaigns
+//! with a webhook of their own are posted there too.
+
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::Context;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::output::RollOutput;
+use crate::secret::SecretKey;
+use crate::storage::Store;
+use crate::webhook::RollEvent;
+
+/// Where the rolls go
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Destination {
+    File(PathBuf),
+    /// Each roll is posted on its own, as `application/x-ndjson`
+    Webhook(reqwest::Url),
+}
+
+impl FromStr for Destination {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.starts_with("http://") || s.starts_with("https://") {
+            true => Ok(Destination::Webhook(
+                s.parse().context("error parsing the roll log URL")?,
+            )),
+            false => Ok(Destination::File(s.into())),
+        }
+    }
+}
+
+/// Handle to log rolls. Only campaign webhooks get them when no destination is configured.
+#[derive(Clone, Debug, Default)]
+pub struct RollLog {
+    sender: Option<mpsc::UnboundedSender<String>>,
+    client: reqwest::Client,
+    /// Opens the dddice keys of campaigns
+    secret: Option<SecretKey>,
+}
+
+impl RollLog {
+    pub fn spawn(
+        destination: Option<Destination>,
+        client: reqwest::Client,
+        secret: Option<SecretKey>,
+    ) -> Self {
+        let sender = destination.map(|destination| {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            tokio::spawn(run(destination, client.clone(), receiver));
+            sender
+        });
+        RollLog {
+            sender,
+            client,
+            secret,
+        }
+    }
+
+    pub(crate) fn secret(&self) -> Option<&SecretKey> {
+        self.secret.as_ref()
+    }
+
+    /// Log the roll, and send it wherever the campaign of the chat wants its rolls
+    pub(crate) async fn roll(&self, store: &Store, event: RollEvent) {
+        self.log(&event.roll);
+        let chat_id = event.chat_id;
+        let (webhook, dddice) = store
+            .read(|storage| {
+                let campaign = storage
+                    .chat(chat_id)
+                    .and_then(|chat| chat.campaign.as_ref());
+                (
+                    campaign.and_then(|campaign| campaign.webhook.clone()),
+                    campaign.and_then(|campaign| campaign.dddice.clone()),
+                )
+            })
+            .await;
+        if let Some(dddice) = dddice {
+            match self
+                .secret
+                .as_ref()
+                .map(|secret| secret.open(&dddice.api_key))
+            {
+                Some(Ok(api_key)) => drop(tokio::spawn(crate::dddice::show(
+                    self.client.clone(),
+                    dddice,
+                    api_key,
+                    event.roll.clone(),
+                ))),
+                Some(Err(e)) => {
+                    log::warn!("Could not open the dddice key of chat {}: {}", chat_id, e)
+                }
+                None => log::warn!(
+                    "Not showing a roll of chat {} in dddice without a secret key",
+                    chat_id
+                ),
+            }
+        }
+        let Some(webhook) = webhook else {
+            return;
+        };
+        match serde_json::to_string(&event) {
+            Ok(body) => drop(tokio::spawn(crate::webhook::deliver(webhook, body))),
+            Err(e) => log::error!("Error converting a roll to JSON: {}", e),
+        }
+    }
+
+    pub fn log(&self, output: &RollOutput) {
+        let Some(ref sender) = self.sender else {
+            return;
+        };
+        match serde_json::to_string(output) {
+            // The task only stops when the runtime does
+            Ok(line) => drop(sender.send(line)),
+            Err(e) => log::error!("Error converting a roll to JSON: {}", e),
+        }
+    }
+}
+
+async fn write(
+    destination: &Destination,
+    client: &reqwest::Client,
+    line: String,
+) -> anyhow::Result<()> {
+    match destination {
+        Destination::File(path) => {
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await
+                .with_context(|| format!("error opening file {:?}", path))?;
+            file.write_all(format!("{}\n", line).as_bytes())
+                .await
+                .with_context(|| format!("error writing file {:?}", path))?;
+            // Otherwise the write may still be underway when the file is dropped
+            file.flush()
+                .await
+                .with_context(|| format!("error writing file {:?}", path))
+        }
+        Destination::Webhook(url) => {
+            client
+                .post(url.clone())
+                .header(reqwest::header::CONTENT_TYPE, "application/x-ndjson")
+                .body(line)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        }
+    }
+}
+
+/// Rolls are written one at a time, so that lines of the file never interleave
+async fn run(
+    destination: Destination,
+    client: reqwest::Client,
+    mut receiver: mpsc::UnboundedReceiver<String>,
+) {
+    while let Some(line) = receiver.recv().await {
+        if let Err(e) = write(&destination, &client, line).await {
+            log::warn!("Error logging a roll: {:#}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn appends_rolls_to_a_file() {
+        assert!(matches!(
+            "https://example.com/rolls".parse(),
+            Ok(Destination::Webhook(_))
+        ));
+        let path = std::env::temp_dir().join(format!("roll-log-{}.ndjson", std::process::id()));
+        let destination: Destination = path.to_str().unwrap().parse().unwrap();
+        let client = reqwest::Client::new();
+        write(&destination, &client, "{\"total\":1}".to_string())
+            .await
+            .unwrap();
+        write(&destination, &client, "{\"total\":2}".to_string())
+            .await
+            .unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "{\"total\":1}\n{\"total\":2}\n");
+    }
+}
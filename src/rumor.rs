@@ -0,0 +1,83 @@
+//! Tavern gossip, rolled on the `rumor` random tables of the chat or the built in ones. Whether the
+//! rumor is true is rolled on `rumor_truth` and only told to the GM, in a private chat.
+
+use teloxide::utils::html;
+
+use crate::storage::Store;
+use crate::transport::{ChatTransport, Incoming};
+
+/// The rumor, and whether it is true
+fn rumor(tables: &[crate::table::RandomTable]) -> anyhow::Result<(String, String)> {
+    Ok((
+        crate::table::roll_entry(tables, "rumor")?,
+        crate::table::roll_entry(tables, "rumor_truth")?,
+    ))
+}
+
+pub(crate) async fn handle_rumor(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+) -> anyhow::Result<()> {
+    let Some(user) = &msg.from else {
+        return Ok(());
+    };
+    let chat_id = msg.chat_id;
+    let (rolled, gm) = store
+        .read(|storage| {
+            let chat = storage.chat(chat_id);
+            let tables = chat
+                .map(|chat| chat.random_tables.as_slice())
+                .unwrap_or_default();
+            let gm = chat
+                .and_then(|chat| chat.campaign.as_ref())
+                .map(|campaign| campaign.gm);
+            (rumor(tables), gm)
+        })
+        .await;
+    let (text, truth) = match rolled {
+        Ok(rolled) => rolled,
+        Err(e) => {
+            bot.reply(msg, html::escape(&format!("{:#}", e)), vec![])
+                .await?;
+            return Ok(());
+        }
+    };
+    bot.reply(msg, format!("🗣 {}", text), vec![]).await?;
+
+    // Without a campaign, whoever asked for the rumor is the GM
+    let gm = gm.unwrap_or(user.id);
+    let secret = format!(
+        "🤫 The rumor in {} is <b>{}</b>:\n{}",
+        html::escape(msg.chat_title.as_deref().unwrap_or("your chat")),
+        truth,
+        text
+    );
+    if bot.send(gm, secret).await.is_err() {
+        // GMs who never started a chat with the bot cannot be told
+        bot.send(
+            msg.chat_id,
+            "The GM could not be told whether the rumor is true. Start a private chat with me first."
+                .to_string(),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolls_a_rumor_and_its_truth() {
+        let (text, truth) = rumor(&[]).unwrap();
+        assert!(text.ends_with('.'), "{}", text);
+        assert!(["true", "false", "partly true"].contains(&truth.as_str()));
+
+        let tables: Vec<crate::table::RandomTable> =
+            serde_json::from_str(r#"[{"name": "rumor_truth", "entries": [{"text": "a lie"}]}]"#)
+                .unwrap();
+        assert_eq!(rumor(&tables).unwrap().1, "a lie");
+    }
+}
@@ -0,0 +1,431 @@
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use teloxide::utils::html;
+use teloxide::RequestError;
+
+use crate::storage::Store;
+use crate::transport::{ChatTransport, Incoming};
+
+/// How often the queue is checked for due jobs
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Times a job is run before it is given up on, when it keeps failing for a reason that may pass
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Something the bot has to do at a later point in time
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Action {
+    SendMessage {
+        chat_id: i64,
+        text: String,
+    },
+    DeleteMessage {
+        chat_id: i64,
+        message_id: i32,
+    },
+    /// Set with `/remind`
+    Reminder {
+        chat_id: i64,
+        text: String,
+    },
+    /// Set with `/timer`, answering the message that started it
+    Timer {
+        chat_id: i64,
+        message_id: i32,
+        label: String,
+    },
+}
+
+impl Action {
+    pub fn chat_id(&self) -> i64 {
+        match self {
+            Action::SendMessage { chat_id, .. }
+            | Action::DeleteMessage { chat_id, .. }
+            | Action::Reminder { chat_id, .. }
+            | Action::Timer { chat_id, .. } => *chat_id,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Interval {
+    Daily,
+    Weekly,
+}
+
+impl Interval {
+    fn days(&self) -> u64 {
+        match self {
+            Interval::Daily => 1,
+            Interval::Weekly => 7,
+        }
+    }
+}
+
+/// Run a job again after it ran, at the same local time in the timezone
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct Repeat {
+    pub every: Interval,
+    pub timezone: Tz,
+}
+
+impl Repeat {
+    /// The first time after `now` that the job is due again
+    pub fn next(&self, due: DateTime<Utc>, now: DateTime<Utc>) -> DateTime<Utc> {
+        let mut next = due.with_timezone(&self.timezone).naive_local();
+        loop {
+            next = next + chrono::Days::new(self.every.days());
+            // A time skipped by daylight saving time is moved to the hour after
+            let due = self
+                .timezone
+                .from_local_datetime(&next)
+                .earliest()
+                .or_else(|| {
+                    self.timezone
+                        .from_local_datetime(&(next + TimeDelta::hours(1)))
+                        .earliest()
+                })
+                .map(|due| due.with_timezone(&Utc));
+            match due {
+                Some(due) if due > now => return due,
+                _ => continue,
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    pub id: u64,
+    pub due: DateTime<Utc>,
+    pub action: Action,
+    #[serde(default)]
+    pub repeat: Option<Repeat>,
+    /// Runs that failed for a reason that may pass, since the job last ran
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub attempts: u32,
+}
+
+fn is_zero(attempts: &u32) -> bool {
+    *attempts == 0
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct JobQueue {
+    next_id: u64,
+    jobs: Vec<Job>,
+}
+
+impl JobQueue {
+    /// Schedule an action, returning the ID of the job
+    pub fn schedule(&mut self, due: DateTime<Utc>, action: Action) -> u64 {
+        self.schedule_repeating(due, action, None)
+    }
+
+    pub fn schedule_repeating(
+        &mut self,
+        due: DateTime<Utc>,
+        action: Action,
+        repeat: Option<Repeat>,
+    ) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.jobs.push(Job {
+            id,
+            due,
+            action,
+            repeat,
+            attempts: 0,
+        });
+        id
+    }
+
+    /// Pending jobs of a chat, earliest first
+    pub fn for_chat(&self, chat_id: i64) -> Vec<&Job> {
+        let mut jobs: Vec<_> = self
+            .jobs
+            .iter()
+            .filter(|job| job.action.chat_id() == chat_id)
+            .collect();
+        jobs.sort_by_key(|job| job.due);
+        jobs
+    }
+
+    /// Cancel a job of a chat, returning `true` if it was pending
+    pub fn cancel_in_chat(&mut self, chat_id: i64, id: u64) -> bool {
+        let before = self.jobs.len();
+        self.jobs
+            .retain(|job| job.id != id || job.action.chat_id() != chat_id);
+        self.jobs.len() != before
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    /// All jobs that are due, earliest first. They stay in the queue until they are finished.
+    pub fn due(&self, now: DateTime<Utc>) -> Vec<Job> {
+        let mut due: Vec<_> = self
+            .jobs
+            .iter()
+            .filter(|job| job.due <= now)
+            .cloned()
+            .collect();
+        due.sort_by_key(|job| job.due);
+        due
+    }
+
+    /// Remove a job that ran, or schedule it again if it repeats. A job that was cancelled while it
+    /// ran stays cancelled.
+    pub fn finish(&mut self, id: u64, now: DateTime<Utc>) {
+        let Some(index) = self.jobs.iter().position(|job| job.id == id) else {
+            return;
+        };
+        let job = &mut self.jobs[index];
+        job.attempts = 0;
+        match job.repeat {
+            Some(repeat) => job.due = repeat.next(job.due, now),
+            None => drop(self.jobs.remove(index)),
+        }
+    }
+
+    /// Run a job that failed for a reason that may pass again after `delay`, returning `false` if
+    /// it failed too often and was finished instead
+    pub fn retry(&mut self, id: u64, now: DateTime<Utc>, delay: Duration) -> bool {
+        let Some(job) = self.jobs.iter_mut().find(|job| job.id == id) else {
+            return false;
+        };
+        job.attempts += 1;
+        if job.attempts >= MAX_ATTEMPTS {
+            self.finish(id, now);
+            return false;
+        }
+        job.due = now + TimeDelta::from_std(delay).unwrap_or(TimeDelta::days(1));
+        true
+    }
+}
+
+/// How long to wait before running the job again, if that could go differently, such as when
+/// Telegram could not be reached. Waits as long as Telegram asks, and longer after every attempt
+/// otherwise.
+fn retry_delay(e: &anyhow::Error, attempts: u32) -> Option<Duration> {
+    match e.downcast_ref::<RequestError>()? {
+        RequestError::RetryAfter(delay) => Some(*delay),
+        RequestError::Network(_) | RequestError::Io(_) => Some(POLL_INTERVAL * 2u32.pow(attempts)),
+        _ => None,
+    }
+}
+
+async fn execute(bot: &impl ChatTransport, action: &Action) -> anyhow::Result<()> {
+    match action {
+        Action::SendMessage { chat_id, text } => {
+            bot.send(*chat_id, text.clone()).await?;
+        }
+        Action::DeleteMessage {
+            chat_id,
+            message_id,
+        } => bot.delete(*chat_id, *message_id).await?,
+        Action::Reminder { chat_id, text } => {
+            bot.send(*chat_id, format!("⏰ {}", html::escape(text)))
+                .await?;
+        }
+        Action::Timer {
+            chat_id,
+            message_id,
+            label,
+        } => {
+            let to = Incoming {
+                chat_id: *chat_id,
+                message_id: *message_id,
+                ..Default::default()
+            };
+            bot.reply(
+                &to,
+                format!("⌛ Time is up: {}", html::escape(label)),
+                vec![],
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Run due jobs forever. Jobs that came due while the bot was offline are run on the first tick, and
+/// jobs that failed for a reason that may pass are run again later, up to [`MAX_ATTEMPTS`] times.
+pub async fn run(bot: impl ChatTransport, store: Store) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let due = store.read(|storage| storage.jobs.due(Utc::now())).await;
+        for job in due {
+            log::debug!("Running job {:?}", job);
+            let result = execute(&bot, &job.action).await;
+            let retry = result
+                .as_ref()
+                .err()
+                .and_then(|e| Some((e, retry_delay(e, job.attempts)?)));
+            if let Some((e, delay)) = retry {
+                let retried = store
+                    .update(|storage| storage.jobs.retry(job.id, Utc::now(), delay))
+                    .await;
+                match retried {
+                    Ok(true) => log::warn!(
+                        "Error running job {}, trying again in {:?}: {:#}",
+                        job.id,
+                        delay,
+                        e
+                    ),
+                    Ok(false) => log::error!(
+                        "Error running job {}, giving up after {} attempts: {:#}",
+                        job.id,
+                        MAX_ATTEMPTS,
+                        e
+                    ),
+                    Err(e) => log::error!("Error retrying job {}: {:#}", job.id, e),
+                }
+                continue;
+            }
+            if let Err(e) = result {
+                log::error!("Error running job {}: {:#}", job.id, e);
+            }
+            if let Err(e) = store
+                .update(|storage| storage.jobs.finish(job.id, Utc::now()))
+                .await
+            {
+                log::error!("Error finishing job {}: {:#}", job.id, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeTransport, Sent};
+
+    #[tokio::test]
+    async fn timers_answer_the_message_that_set_them() {
+        let bot = FakeTransport::default();
+        let action = Action::Timer {
+            chat_id: 1,
+            message_id: 2,
+            label: "<rage>".to_string(),
+        };
+        execute(&bot, &action).await.unwrap();
+        let Sent::Reply { to, text, .. } = &bot.sent()[0] else {
+            panic!("expected a reply, got {:?}", bot.sent());
+        };
+        assert_eq!((to.chat_id, to.message_id), (1, 2));
+        assert_eq!(text, "⌛ Time is up: &lt;rage&gt;");
+        assert_eq!(
+            retry_delay(&anyhow::anyhow!("not a request error"), 0),
+            None
+        );
+        let e = anyhow::Error::from(RequestError::RetryAfter(Duration::from_secs(30)));
+        assert_eq!(retry_delay(&e, 3), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn due_only_returns_due_jobs_in_order() {
+        let now = Utc::now();
+        let mut queue = JobQueue::default();
+        let later = queue.schedule(
+            now + TimeDelta::minutes(5),
+            Action::SendMessage {
+                chat_id: 1,
+                text: "later".to_string(),
+            },
+        );
+        let second = queue.schedule(
+            now - TimeDelta::minutes(1),
+            Action::SendMessage {
+                chat_id: 1,
+                text: "second".to_string(),
+            },
+        );
+        let first = queue.schedule(
+            now - TimeDelta::minutes(2),
+            Action::DeleteMessage {
+                chat_id: 1,
+                message_id: 2,
+            },
+        );
+
+        let due: Vec<_> = queue.due(now).into_iter().map(|job| job.id).collect();
+        assert_eq!(due, vec![first, second]);
+        // Until they ran
+        assert_eq!(queue.due(now).len(), 2);
+        queue.finish(first, now);
+        queue.finish(second, now);
+        assert!(queue.due(now).is_empty());
+        assert_eq!(queue.len(), 1);
+        assert!(queue.cancel_in_chat(1, later));
+        assert!(!queue.cancel_in_chat(1, later));
+    }
+
+    #[test]
+    fn gives_up_on_jobs_that_keep_failing() {
+        let now = Utc::now();
+        let mut queue = JobQueue::default();
+        let id = queue.schedule(
+            now,
+            Action::SendMessage {
+                chat_id: 1,
+                text: "unlucky".to_string(),
+            },
+        );
+        for _ in 1..MAX_ATTEMPTS {
+            assert!(queue.retry(id, now, Duration::from_secs(30)));
+            assert!(queue.due(now).is_empty());
+            assert_eq!(queue.due(now + TimeDelta::seconds(30)).len(), 1);
+        }
+        assert!(!queue.retry(id, now, Duration::from_secs(30)));
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn repeating_jobs_keep_the_local_time() {
+        let timezone: Tz = "Europe/London".parse().unwrap();
+        let repeat = Repeat {
+            every: Interval::Weekly,
+            timezone,
+        };
+        // Saturday before the clocks go forward, at 19:00 GMT
+        let due = Utc.with_ymd_and_hms(2024, 3, 30, 19, 0, 0).unwrap();
+        let next = repeat.next(due, due);
+        assert_eq!(next, Utc.with_ymd_and_hms(2024, 4, 6, 18, 0, 0).unwrap());
+        // Missed runs are skipped
+        let later = due + TimeDelta::days(20);
+        assert_eq!(
+            repeat.next(due, later),
+            Utc.with_ymd_and_hms(2024, 4, 20, 18, 0, 0).unwrap()
+        );
+
+        let mut queue = JobQueue::default();
+        let id = queue.schedule_repeating(
+            due,
+            Action::Reminder {
+                chat_id: 1,
+                text: "Session".to_string(),
+            },
+            Some(repeat),
+        );
+        assert_eq!(queue.due(due).len(), 1);
+        queue.finish(id, due);
+        assert_eq!(queue.for_chat(1)[0].due, next);
+        assert!(queue.for_chat(2).is_empty());
+        assert!(!queue.cancel_in_chat(2, id));
+        assert!(queue.cancel_in_chat(1, id));
+    }
+}
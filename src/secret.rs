@@ -0,0 +1,83 @@
+//! Secrets that users give the bot, such as dddice keys, encrypted with a key of the operator. The
+//! storage and its backups only ever hold them sealed.
+
+use std::path::Path;
+
+use anyhow::Context;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+const NONCE_LENGTH: usize = 24;
+
+/// Key from `--secret-key-file`, 32 bytes in hex such as from `openssl rand -hex 32`
+#[derive(Clone)]
+pub struct SecretKey(XChaCha20Poly1305);
+
+/// Without the key, so that it stays out of logs
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretKey([redacted])")
+    }
+}
+
+impl SecretKey {
+    pub fn read<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("error reading secret key file {}", path.display()))?;
+        contents
+            .trim()
+            .parse()
+            .with_context(|| format!("error parsing secret key file {}", path.display()))
+    }
+
+    /// The nonce and the ciphertext, in hex
+    pub fn seal(&self, secret: &str) -> String {
+        let nonce = rand::random::<[u8; NONCE_LENGTH]>();
+        let ciphertext = self
+            .0
+            .encrypt(XNonce::from_slice(&nonce), secret.as_bytes())
+            .expect("a secret to fit in a message");
+        hex::encode([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    pub fn open(&self, sealed: &str) -> anyhow::Result<String> {
+        let sealed = hex::decode(sealed).context("a sealed secret is not hex")?;
+        anyhow::ensure!(sealed.len() > NONCE_LENGTH, "a sealed secret is too short");
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+        let secret = self
+            .0
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("a secret was sealed with another key"))?;
+        String::from_utf8(secret).context("a sealed secret is not text")
+    }
+}
+
+impl std::str::FromStr for SecretKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let key = hex::decode(s).context("the secret key is not hex")?;
+        anyhow::ensure!(key.len() == 32, "the secret key is not 32 bytes");
+        Ok(SecretKey(XChaCha20Poly1305::new_from_slice(&key)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_what_it_sealed() {
+        let key: SecretKey = "00".repeat(32).parse().unwrap();
+        let sealed = key.seal("dddice-key");
+        assert!(!sealed.contains("dddice-key"));
+        assert_ne!(sealed, key.seal("dddice-key"));
+        assert_eq!(key.open(&sealed).unwrap(), "dddice-key");
+
+        let other: SecretKey = "01".repeat(32).parse().unwrap();
+        assert!(other.open(&sealed).is_err());
+        assert!(key.open("dddice-key").is_err());
+        assert!("00".repeat(16).parse::<SecretKey>().is_err());
+    }
+}
@@ -0,0 +1,234 @@
+//! Game sessions, summarised from the roll history with `/session end`.
+
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use teloxide::utils::html;
+
+use crate::dice::RollSettings;
+use crate::history::RollRecord;
+use crate::storage::Store;
+use crate::transport::{ChatTransport, Incoming};
+
+fn who(roll: &RollRecord) -> String {
+    match roll.character {
+        Some(ref character) => html::escape(character),
+        None => "Someone".to_string(),
+    }
+}
+
+fn describe(roll: &RollRecord) -> String {
+    format!(
+        "{}: <code>{}</code> = <b>{}</b>",
+        who(roll),
+        html::escape(&roll.expression),
+        roll.total
+    )
+}
+
+/// The notable rolls made between `started` and `ended`
+fn summary(
+    rolls: &[RollRecord],
+    started: DateTime<Utc>,
+    ended: DateTime<Utc>,
+    timezone: Tz,
+) -> String {
+    let minutes = (ended - started).num_minutes();
+    let mut text = format!(
+        "📜 <b>Session summary</b>\n{} rolls over {}h {:02}m, since {}",
+        rolls.len(),
+        minutes / 60,
+        minutes % 60,
+        crate::localtime::datetime_with_zone(started, timezone)
+    );
+    if rolls.is_empty() {
+        return text;
+    }
+
+    let parsed: Vec<_> = rolls
+        .iter()
+        .map(|roll| (roll, RollSettings::from_str(&roll.expression).ok()))
+        .collect();
+    let (d20s, others): (Vec<_>, Vec<_>) = parsed
+        .iter()
+        .partition(|(_, settings)| settings.as_ref().is_some_and(|s| s.sides == 20));
+    let crits = rolls.iter().filter(|roll| roll.natural == Some(20)).count();
+    let fumbles = rolls.iter().filter(|roll| roll.natural == Some(1)).count();
+    text.push_str(&format!(
+        "\n\n💥 {} natural 20s, 💀 {} natural 1s",
+        crits, fumbles
+    ));
+
+    let checks: Vec<_> = rolls.iter().filter_map(|roll| roll.passed).collect();
+    if !checks.is_empty() {
+        let passed = checks.iter().filter(|passed| **passed).count();
+        text.push_str(&format!(
+            "\n🎯 {} of {} rolls against a DC passed",
+            passed,
+            checks.len()
+        ));
+    }
+
+    if let Some((roll, _)) = others.iter().max_by_key(|(roll, _)| roll.total) {
+        text.push_str(&format!("\n🗡 Biggest damage: {}", describe(roll)));
+    }
+    let worst_save = d20s
+        .iter()
+        .filter(|(_, settings)| {
+            settings
+                .as_ref()
+                .and_then(|s| s.label.as_ref())
+                .is_some_and(|label| label.to_lowercase().contains("save"))
+        })
+        .min_by_key(|(roll, _)| roll.total);
+    if let Some((roll, _)) = worst_save {
+        text.push_str(&format!("\n🛡 Worst save: {}", describe(roll)));
+    }
+    text
+}
+
+const USAGE: &str = "<code>/session start</code> starts a session
+<code>/session end</code> ends it with a summary of its rolls
+<code>/session end pin</code> also pins the summary";
+
+pub(crate) async fn handle_session(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let mut args = input.split_whitespace();
+    let text = match args.next() {
+        Some("start") if crate::auth::is_chat_admin(bot, msg).await? => {
+            let (started, timezone) = store
+                .read(|storage| {
+                    (
+                        storage.chat(chat_id).and_then(|chat| chat.session_started),
+                        crate::localtime::timezone(storage, chat_id),
+                    )
+                })
+                .await;
+            match started {
+                Some(started) => format!(
+                    "A session is already running since {}. End it with /session end.",
+                    crate::localtime::datetime_with_zone(started, timezone)
+                ),
+                None => {
+                    store
+                        .update(|storage| {
+                            storage.chat_mut(chat_id).session_started = Some(Utc::now())
+                        })
+                        .await?;
+                    "📜 Session started. Rolls from now on count towards its summary.".to_string()
+                }
+            }
+        }
+        Some("end") if crate::auth::is_chat_admin(bot, msg).await? => {
+            let (started, timezone) = store
+                .update(|storage| {
+                    (
+                        storage.chat_mut(chat_id).session_started.take(),
+                        crate::localtime::timezone(storage, chat_id),
+                    )
+                })
+                .await?;
+            let Some(started) = started else {
+                bot.reply(
+                    msg,
+                    "No session is running. Start one with /session start.".to_string(),
+                    vec![],
+                )
+                .await?;
+                return Ok(());
+            };
+            let rolls = store.rolls(chat_id, started).await?;
+            let sent = bot
+                .reply(msg, summary(&rolls, started, Utc::now(), timezone), vec![])
+                .await?;
+            if args.next() == Some("pin") {
+                if let Err(e) = bot.pin(chat_id, sent).await {
+                    log::warn!("Unable to pin the session summary: {:#}", e);
+                    bot.reply(
+                        msg,
+                        "I could not pin the summary. I need to be allowed to pin messages."
+                            .to_string(),
+                        vec![],
+                    )
+                    .await?;
+                }
+            }
+            return Ok(());
+        }
+        Some("start") | Some("end") => {
+            "Only administrators of this chat may start or end sessions.".to_string()
+        }
+        _ => USAGE.to_string(),
+    };
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dice::RollType;
+    use chrono::{TimeDelta, TimeZone};
+
+    fn record(character: &str, expression: &str, rolls: Vec<u32>, total: i64) -> RollRecord {
+        RollRecord {
+            timestamp: Utc.with_ymd_and_hms(2024, 7, 3, 19, 0, 0).unwrap(),
+            chat_id: 1,
+            user_id: 2,
+            character: Some(character.to_string()),
+            expression: expression.to_string(),
+            roll_type: RollType::Straight,
+            natural: rolls
+                .first()
+                .copied()
+                .filter(|_| expression.starts_with("1d20")),
+            rolls,
+            total,
+            passed: None,
+            voided: false,
+        }
+    }
+
+    #[test]
+    fn summarises_sessions() {
+        let started = Utc.with_ymd_and_hms(2024, 7, 3, 18, 0, 0).unwrap();
+        let ended = started + TimeDelta::minutes(185);
+        let rolls = [
+            record("Aria", "1d20 + 5 attack", vec![20], 25),
+            record("Aria", "2d6 + 3 longsword", vec![6, 5], 14),
+            record("Brom", "8d6 fireball", vec![6, 4, 3, 5, 2, 6, 1, 4], 31),
+            record("Brom", "1d20 + 2 dex save", vec![1], 3),
+            record("Aria", "1d20 + 1 wis save", vec![9], 10),
+            // The 20 of advantage and the 1 of disadvantage are dropped
+            RollRecord {
+                roll_type: RollType::Advantage,
+                rolls: vec![20, 4],
+                natural: Some(4),
+                ..record("Aria", "1d20 + 5 stealth", vec![], 9)
+            },
+            RollRecord {
+                roll_type: RollType::Disadvantage,
+                rolls: vec![12, 1],
+                natural: Some(12),
+                ..record("Brom", "1d20 + 2 athletics", vec![], 14)
+            },
+        ];
+        assert_eq!(
+            summary(&rolls, started, ended, Tz::UTC),
+            "📜 <b>Session summary</b>\n7 rolls over 3h 05m, since Wed 2024-07-03 18:00 UTC\n\n\
+             💥 1 natural 20s, 💀 1 natural 1s\n\
+             🗡 Biggest damage: Brom: <code>8d6 fireball</code> = <b>31</b>\n\
+             🛡 Worst save: Brom: <code>1d20 + 2 dex save</code> = <b>3</b>"
+        );
+        assert_eq!(
+            summary(&[], started, ended, "Europe/Berlin".parse().unwrap()),
+            "📜 <b>Session summary</b>\n0 rolls over 3h 05m, since Wed 2024-07-03 20:00 CEST"
+        );
+    }
+}
@@ -0,0 +1,369 @@
+//! Settings of a chat, shown to everyone and changed by its administrators with `/settings`.
+
+use chrono::{TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use teloxide::utils::html;
+
+use crate::scheduler::Action;
+use crate::storage::Store;
+use crate::transport::{Attachment, ChatTransport, Incoming};
+
+/// Telegram only lets bots delete messages younger than 48 hours
+const MAX_EPHEMERAL_MINUTES: u32 = 48 * 60;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct Settings {
+    /// Delete roll results after a while
+    #[serde(default)]
+    pub ephemeral: Option<Ephemeral>,
+    /// React to natural 20s and 1s
+    #[serde(default)]
+    pub reactions: bool,
+    /// Posted on natural 20s
+    #[serde(default)]
+    pub crit: Option<Flourish>,
+    /// Posted on natural 1s
+    #[serde(default)]
+    pub fumble: Option<Flourish>,
+    /// Name of the sticker set to answer special results with
+    #[serde(default)]
+    pub sticker_pack: Option<String>,
+    /// Whether concentration saves after damage are rolled or asked for
+    #[serde(default)]
+    pub concentration: crate::concentration::Saves,
+}
+
+/// The sticker or GIF that the command answers
+#[derive(Debug, Default)]
+struct Replied {
+    flourish: Option<Flourish>,
+    pack: Option<String>,
+}
+
+impl Replied {
+    fn new(attachment: Option<&Attachment>) -> Self {
+        Replied {
+            flourish: attachment.and_then(Flourish::from_attachment),
+            pack: match attachment {
+                Some(Attachment::Sticker { pack, .. }) => pack.clone(),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// What to post on a natural 20 or 1
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case", tag = "type", content = "value")]
+pub enum Flourish {
+    Text(String),
+    /// File ID of a sticker
+    Sticker(String),
+    /// File ID of a GIF
+    Animation(String),
+}
+
+impl Flourish {
+    /// The sticker or GIF of a message
+    fn from_attachment(attachment: &Attachment) -> Option<Self> {
+        match attachment {
+            Attachment::Sticker { file_id, .. } => Some(Flourish::Sticker(file_id.clone())),
+            Attachment::Animation { file_id } => Some(Flourish::Animation(file_id.clone())),
+            Attachment::Document { .. } => None,
+        }
+    }
+
+    fn describe(flourish: &Option<Self>) -> String {
+        match flourish {
+            None => "off".to_string(),
+            Some(Flourish::Text(text)) => format!("“{}”", html::escape(text)),
+            Some(Flourish::Sticker(_)) => "a sticker".to_string(),
+            Some(Flourish::Animation(_)) => "a GIF".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub struct Ephemeral {
+    pub minutes: u32,
+    /// Delete the command that asked for the roll too
+    #[serde(default)]
+    pub commands: bool,
+}
+
+fn describe(settings: &Settings) -> String {
+    let ephemeral = match settings.ephemeral {
+        None => "off".to_string(),
+        Some(Ephemeral { minutes, commands }) => format!(
+            "roll results{} are deleted after {} minutes",
+            if commands { " and their commands" } else { "" },
+            minutes
+        ),
+    };
+    format!(
+        "⚙️ Settings of this chat\nEphemeral: {}\nReactions: {}\nOn natural 20s: {}\nOn natural 1s: {}\nStickers: {}\nConcentration saves: {}",
+        ephemeral,
+        if settings.reactions { "on" } else { "off" },
+        Flourish::describe(&settings.crit),
+        Flourish::describe(&settings.fumble),
+        match settings.sticker_pack {
+            Some(ref pack) => html::escape(pack),
+            None => "off".to_string(),
+        },
+        match settings.concentration {
+            crate::concentration::Saves::Roll => "rolled",
+            crate::concentration::Saves::Prompt => "asked for",
+        }
+    )
+}
+
+const USAGE: &str = "<code>/settings</code> shows the settings of this chat
+<code>/settings ephemeral 10</code> deletes roll results after 10 minutes
+<code>/settings ephemeral 10 commands</code> deletes the commands that asked for them too
+<code>/settings ephemeral off</code> keeps roll results
+<code>/settings reactions on</code> reacts to natural 20s and natural 1s
+<code>/settings crit Critical hit!</code> posts a message on natural 20s
+<code>/settings fumble</code> in reply to a sticker or GIF posts it on natural 1s
+<code>/settings crit off</code> posts nothing on natural 20s
+<code>/settings stickers</code> in reply to a sticker answers max damage, all 1s, 69 and 420 with stickers of its set
+<code>/settings stickers off</code> sends no stickers
+<code>/settings timezone Europe/London</code> shows times in that timezone, and reads them in it
+<code>/settings concentration prompt</code> asks for concentration saves after damage instead of rolling them, or <code>roll</code>";
+
+/// Change a setting, returning what to answer
+fn change(settings: &mut Settings, args: &[&str], replied: Replied) -> String {
+    match args {
+        ["stickers", "off"] => {
+            settings.sticker_pack = None;
+            describe(settings)
+        }
+        ["stickers", rest @ ..] => {
+            let pack = match rest {
+                [] => replied.pack,
+                [pack] => Some(pack.to_string()),
+                _ => None,
+            };
+            match pack {
+                Some(pack) => {
+                    settings.sticker_pack = Some(pack);
+                    describe(settings)
+                }
+                None => USAGE.to_string(),
+            }
+        }
+        [natural @ ("crit" | "fumble"), rest @ ..] => {
+            let flourish = match rest {
+                [] => match replied.flourish {
+                    Some(flourish) => Some(flourish),
+                    None => return USAGE.to_string(),
+                },
+                ["off"] => None,
+                words => Some(Flourish::Text(words.join(" "))),
+            };
+            match *natural {
+                "crit" => settings.crit = flourish,
+                _ => settings.fumble = flourish,
+            }
+            describe(settings)
+        }
+        ["ephemeral", "off"] => {
+            settings.ephemeral = None;
+            "Roll results are kept from now on.".to_string()
+        }
+        ["ephemeral", minutes, rest @ ..] if rest.is_empty() || rest == ["commands"] => {
+            match minutes.parse() {
+                Ok(minutes @ 1..=MAX_EPHEMERAL_MINUTES) => {
+                    settings.ephemeral = Some(Ephemeral {
+                        minutes,
+                        commands: !rest.is_empty(),
+                    });
+                    describe(settings)
+                }
+                _ => format!(
+                    "Results can be deleted after 1 to {} minutes.",
+                    MAX_EPHEMERAL_MINUTES
+                ),
+            }
+        }
+        ["concentration", saves @ ("roll" | "prompt")] => {
+            settings.concentration = match *saves {
+                "roll" => crate::concentration::Saves::Roll,
+                _ => crate::concentration::Saves::Prompt,
+            };
+            describe(settings)
+        }
+        ["reactions", toggle @ ("on" | "off")] => {
+            settings.reactions = *toggle == "on";
+            describe(settings)
+        }
+        _ => USAGE.to_string(),
+    }
+}
+
+pub(crate) async fn handle_settings(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let args: Vec<_> = input.split_whitespace().collect();
+    let text = if args.is_empty() {
+        store
+            .read(|storage| {
+                let settings = storage
+                    .chat(chat_id)
+                    .map(|chat| chat.settings.clone())
+                    .unwrap_or_default();
+                format!(
+                    "{}\nTimezone: {}",
+                    describe(&settings),
+                    crate::localtime::timezone(storage, chat_id)
+                )
+            })
+            .await
+    } else if args == ["timezone"] {
+        store
+            .read(|storage| {
+                format!(
+                    "This chat uses {}.",
+                    crate::localtime::timezone(storage, chat_id)
+                )
+            })
+            .await
+    } else if crate::auth::is_chat_admin(bot, msg).await? {
+        if let ["timezone", name] = args.as_slice() {
+            store
+                .update(|storage| crate::localtime::change_timezone(storage, chat_id, Some(name)))
+                .await?
+        } else {
+            let replied = Replied::new(
+                msg.reply_to
+                    .as_ref()
+                    .and_then(|replied| replied.attachment.as_ref()),
+            );
+            store
+                .update(|storage| change(&mut storage.chat_mut(chat_id).settings, &args, replied))
+                .await?
+        }
+    } else {
+        "Only administrators of this chat may change its settings.".to_string()
+    };
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+/// Schedule the deletion of roll results, when the chat is set to be ephemeral
+pub(crate) async fn expire(store: &Store, to: &Incoming, reply: i32) -> anyhow::Result<()> {
+    let chat_id = to.chat_id;
+    let ephemeral = store
+        .read(|storage| {
+            storage
+                .chat(chat_id)
+                .and_then(|chat| chat.settings.ephemeral)
+        })
+        .await;
+    let Some(ephemeral) = ephemeral else {
+        return Ok(());
+    };
+    let due = Utc::now() + TimeDelta::minutes(ephemeral.minutes as i64);
+    let mut messages = vec![reply];
+    if ephemeral.commands {
+        messages.push(to.message_id);
+    }
+    store
+        .update(|storage| {
+            for message in messages {
+                storage.jobs.schedule(
+                    due,
+                    Action::DeleteMessage {
+                        chat_id,
+                        message_id: message,
+                    },
+                );
+            }
+        })
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_ephemeral() {
+        let mut settings = Settings::default();
+        assert_eq!(
+            change(
+                &mut settings,
+                &["ephemeral", "10", "commands"],
+                Replied::default()
+            ),
+            "⚙️ Settings of this chat\n\
+             Ephemeral: roll results and their commands are deleted after 10 minutes\n\
+             Reactions: off\n\
+             On natural 20s: off\n\
+             On natural 1s: off\n\
+             Stickers: off\n\
+             Concentration saves: rolled"
+        );
+        assert_eq!(
+            settings.ephemeral,
+            Some(Ephemeral {
+                minutes: 10,
+                commands: true
+            })
+        );
+        assert!(
+            change(&mut settings, &["ephemeral", "0"], Replied::default())
+                .starts_with("Results can be")
+        );
+        assert!(change(
+            &mut settings,
+            &["ephemeral", "10", "all"],
+            Replied::default()
+        )
+        .starts_with("<code>"));
+        change(&mut settings, &["ephemeral", "off"], Replied::default());
+        assert_eq!(settings, Settings::default());
+        assert!(
+            change(&mut settings, &["reactions", "on"], Replied::default())
+                .contains("Reactions: on")
+        );
+    }
+
+    #[test]
+    fn changes_flourishes() {
+        let mut settings = Settings::default();
+        let text = change(
+            &mut settings,
+            &["crit", "Roll", "<for>", "it!"],
+            Replied::default(),
+        );
+        assert!(
+            text.contains("On natural 20s: “Roll &lt;for&gt; it!”"),
+            "{}",
+            text
+        );
+        let sticker = Flourish::Sticker("CAACAgIAAxkBAAE".to_string());
+        let replied = Replied {
+            flourish: Some(sticker.clone()),
+            pack: Some("dice".to_string()),
+        };
+        let text = change(&mut settings, &["fumble"], replied);
+        assert!(text.contains("On natural 1s: a sticker"));
+        assert_eq!(settings.fumble, Some(sticker));
+        assert!(change(&mut settings, &["crit"], Replied::default()).starts_with("<code>"));
+        change(&mut settings, &["crit", "off"], Replied::default());
+        assert_eq!(settings.crit, None);
+        let text = change(
+            &mut settings,
+            &["stickers", "Dice_Goblins"],
+            Replied::default(),
+        );
+        assert!(text.ends_with("Stickers: Dice_Goblins\nConcentration saves: rolled"));
+    }
+}
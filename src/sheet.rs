@@ -0,0 +1,137 @@
+use teloxide::utils::html;
+
+use crate::dnd::Character;
+use crate::storage::Store;
+use crate::transport::{ChatTransport, Incoming};
+
+fn format_sheet(character: &Character) -> String {
+    let mut text = format!("📜 <b>{}</b>", html::escape(&character.name));
+
+    let mut stats = vec![];
+    if let Some(armor_class) = character.armor_class {
+        stats.push(format!("🛡️ AC {}", armor_class));
+    }
+    if let Some(max_hit_points) = character.max_hit_points {
+        stats.push(format!("❤️ HP {}", max_hit_points));
+    }
+    if let Some(speed) = character.speed {
+        stats.push(format!("🏃 {} ft", speed));
+    }
+    if let Some(hit_dice) = character.hit_dice {
+        stats.push(format!("🎲 {}d{}", hit_dice.count, hit_dice.sides));
+    }
+    if !stats.is_empty() {
+        text.push_str(&format!("\n{}", stats.join(" · ")));
+    }
+
+    let mut bonuses = vec![format!("Initiative {:+}", character.initiative_modifier)];
+    if let Some(proficiency_bonus) = character.proficiency_bonus {
+        bonuses.push(format!("Proficiency {:+}", proficiency_bonus));
+    }
+    if let Some(passive_perception) = character.passive_perception {
+        bonuses.push(format!("Passive Perception {}", passive_perception));
+    }
+    if let Some(spell_save_dc) = character.spell_save_dc {
+        bonuses.push(format!("Spell save DC {}", spell_save_dc));
+    }
+    text.push_str(&format!("\n{}", bonuses.join(" · ")));
+
+    text.push_str("\n\n<b>Abilities</b> (save)");
+    let saves = character.saving_throw_modifiers.iter();
+    for ((name, modifier), (_, save)) in character.attribute_modifiers.iter().into_iter().zip(saves)
+    {
+        text.push_str(&format!("\n{} {:+} ({:+})", name, modifier, save));
+    }
+
+    text.push_str("\n\n<b>Skills</b>");
+    for (name, modifier) in character.skill_modifiers() {
+        text.push_str(&format!("\n{} {:+}", name, modifier));
+    }
+
+    if !character.attacks.is_empty() {
+        text.push_str("\n\n<b>Attacks</b>");
+    }
+    for attack in &character.attacks {
+        text.push_str(&format!(
+            "\n{} {:+}, {}",
+            html::escape(&attack.name),
+            attack.to_hit,
+            html::escape(&attack.damage)
+        ));
+        if let Some(ref damage_type) = attack.damage_type {
+            text.push_str(&format!(" {}", html::escape(damage_type)));
+        }
+    }
+    text
+}
+
+/// Parse our own character format, a D&D Beyond export or a Foundry VTT actor
+fn parse_character(contents: &[u8]) -> anyhow::Result<Character> {
+    let value: serde_json::Value = serde_json::from_slice(contents)?;
+    let character = if crate::ddb::is_ddb_export(&value) {
+        crate::ddb::convert(contents)?
+    } else if crate::foundry::is_foundry_export(&value) {
+        crate::foundry::convert(contents)?
+    } else {
+        return crate::diagnostics::check_json(contents).map_err(|diagnostics| {
+            let diagnostics: Vec<_> = diagnostics.iter().map(ToString::to_string).collect();
+            anyhow::anyhow!(diagnostics.join("\n"))
+        });
+    };
+    character.validate()?;
+    Ok(character)
+}
+
+const USAGE: &str = "<code>/sheet [name]</code> shows your default or named character
+<code>/sheet upload</code> as a reply to a character JSON file, D&amp;D Beyond export or Foundry VTT actor saves the character";
+
+pub(crate) async fn handle_sheet(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let Some(user) = &msg.from else {
+        return Ok(());
+    };
+    let user_id = user.id;
+
+    let text = match input.trim() {
+        "help" => USAGE.to_string(),
+        "upload" => match crate::upload::replied_document(bot, msg).await? {
+            Err(e) => e.to_string(),
+            Ok(contents) => match parse_character(&contents) {
+                Err(e) => format!(
+                    "That is not a valid character.\n\n<code>{}</code>",
+                    html::escape(&format!("{:#}", e))
+                ),
+                Ok(character) => {
+                    let name = character.name.clone();
+                    store
+                        .update(|storage| {
+                            let user = storage.user_mut(user_id);
+                            user.default_character.get_or_insert_with(|| name.clone());
+                            user.characters.insert(name.clone(), character);
+                        })
+                        .await?;
+                    format!("Saved {}", html::escape(&name))
+                }
+            },
+        },
+        name => {
+            let name = Some(name).filter(|name| !name.is_empty());
+            store
+                .read(|storage| {
+                    storage
+                        .user(user_id)
+                        .and_then(|user| user.character(msg.chat_id, name))
+                        .map(format_sheet)
+                })
+                .await
+                .unwrap_or_else(|| format!("I could not find that character.\n\n{}", USAGE))
+        }
+    };
+
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
@@ -0,0 +1,313 @@
+//! Ability score arrays for new characters.
+
+use crate::dice::{Flags, Roll, RollSettings};
+use crate::dnd::modifier_for_score;
+use crate::transport::{ChatTransport, Incoming};
+
+/// Point buy cost of a score, for scores that point buy allows
+fn point_cost(score: i32) -> Option<i32> {
+    match score {
+        8..=13 => Some(score - 8),
+        14 => Some(7),
+        15 => Some(9),
+        _ => None,
+    }
+}
+
+/// How to roll each score
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Method {
+    /// 4d6, dropping the lowest die
+    DropLowest,
+    Straight,
+}
+
+impl Method {
+    fn parse(input: &str) -> Option<Self> {
+        match input.trim().to_lowercase().as_str() {
+            "" | "4d6" | "4d6kh3" | "4d6dl" | "4d6dl1" => Some(Method::DropLowest),
+            "3d6" => Some(Method::Straight),
+            _ => None,
+        }
+    }
+
+    fn dice(&self) -> RollSettings {
+        RollSettings {
+            number: match self {
+                Method::DropLowest => 4,
+                Method::Straight => 3,
+            },
+            sides: 6,
+            modifier: None,
+            label: None,
+            target: None,
+            keep: None,
+            explode: None,
+            success: None,
+            composite: false,
+            multiplier: None,
+            flags: Flags::NONE,
+        }
+    }
+}
+
+/// A score, and the dice it was rolled with with the dropped die last
+#[derive(Debug, PartialEq, Eq)]
+struct Score {
+    total: i32,
+    dice: Vec<u32>,
+    dropped: Option<u32>,
+}
+
+impl Score {
+    fn new(method: Method, mut dice: Vec<u32>) -> Self {
+        dice.sort_unstable_by(|a, b| b.cmp(a));
+        let dropped = match method {
+            Method::DropLowest => dice.pop(),
+            Method::Straight => None,
+        };
+        Score {
+            total: dice.iter().sum::<u32>() as i32,
+            dice,
+            dropped,
+        }
+    }
+
+    fn roll(method: Method) -> Self {
+        Score::new(method, Roll::new(&method.dice()).rolls)
+    }
+}
+
+impl std::fmt::Display for Score {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let dice = self
+            .dice
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(
+            f,
+            "<b>{:>2}</b> ({:+}) [{}",
+            self.total,
+            modifier_for_score(self.total),
+            dice
+        )?;
+        if let Some(dropped) = self.dropped {
+            write!(f, ", <s>{}</s>", dropped)?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// Totals of an array, and what it would cost with point buy
+fn summary(scores: &[i32]) -> String {
+    let modifiers: i32 = scores
+        .iter()
+        .map(|score| modifier_for_score(*score) as i32)
+        .sum();
+    let cost: Option<i32> = scores.iter().map(|score| point_cost(*score)).sum();
+    format!(
+        "Total {}, modifiers {:+}, {}",
+        scores.iter().sum::<i32>(),
+        modifiers,
+        match cost {
+            Some(cost) => format!("{} points with point buy", cost),
+            None => "beyond point buy".to_string(),
+        }
+    )
+}
+
+const STATGEN_USAGE: &str =
+    "<code>/statgen</code> rolls six scores with 4d6, dropping the lowest die
+<code>/statgen 3d6</code> rolls six scores with 3d6";
+
+fn statgen(input: &str) -> String {
+    let Some(method) = Method::parse(input) else {
+        return STATGEN_USAGE.to_string();
+    };
+    let mut scores: Vec<_> = (0..6).map(|_| Score::roll(method)).collect();
+    scores.sort_unstable_by_key(|score| std::cmp::Reverse(score.total));
+    let totals: Vec<_> = scores.iter().map(|score| score.total).collect();
+    format!(
+        "🎲 {}\n\n{}",
+        scores
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        summary(&totals)
+    )
+}
+
+pub(crate) async fn handle_statgen(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    input: &str,
+) -> anyhow::Result<()> {
+    bot.reply(msg, statgen(input), vec![]).await?;
+    Ok(())
+}
+
+/// Points to spend on the six scores
+const BUDGET: i32 = 27;
+
+/// How many arrays to suggest at most
+const SUGGESTIONS: usize = 5;
+
+/// Ways to buy `count` more scores for exactly `points`, highest scores first
+fn completions(count: usize, points: i32, highest: i32) -> Vec<Vec<i32>> {
+    if count == 0 {
+        return match points {
+            0 => vec![vec![]],
+            _ => vec![],
+        };
+    }
+    let mut arrays = vec![];
+    for score in (8..=highest).rev() {
+        let cost = point_cost(score).expect("to be within point buy");
+        if cost > points {
+            continue;
+        }
+        for mut rest in completions(count - 1, points - cost, score) {
+            rest.insert(0, score);
+            arrays.push(rest);
+        }
+    }
+    arrays
+}
+
+fn format_array(scores: &[i32]) -> String {
+    scores
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+const POINTBUY_USAGE: &str =
+    "<code>/pointbuy 15 14 13 12 10 8</code> checks an array against the 27 point budget
+<code>/pointbuy 15 15</code> suggests what to buy with the points left";
+
+fn pointbuy(input: &str) -> String {
+    let Ok(scores) = input
+        .split_whitespace()
+        .map(str::parse::<i32>)
+        .collect::<Result<Vec<_>, _>>()
+    else {
+        return POINTBUY_USAGE.to_string();
+    };
+    if scores.len() > 6 {
+        return POINTBUY_USAGE.to_string();
+    }
+    let mut breakdown = vec![];
+    for score in &scores {
+        match point_cost(*score) {
+            Some(cost) => breakdown.push(format!("{} costs {}", score, cost)),
+            None => return format!("{} is not allowed, point buy scores are 8 to 15.", score),
+        }
+    }
+    let spent: i32 = scores.iter().filter_map(|score| point_cost(*score)).sum();
+    let left = BUDGET - spent;
+
+    let mut text = String::new();
+    if !breakdown.is_empty() {
+        text.push_str(&format!(
+            "{}\nSpent {} of {} points\n",
+            breakdown.join("\n"),
+            spent,
+            BUDGET
+        ));
+    }
+    if left < 0 {
+        text.push_str(&format!("❌ That is {} points over budget.", -left));
+        return text;
+    }
+    if scores.len() == 6 {
+        if left == 0 {
+            text.push_str(&format!("✅ A legal array. {}", summary(&scores)));
+        } else {
+            text.push_str(&format!("⚠️ {} points are left to spend.", left));
+        }
+        return text;
+    }
+
+    let arrays = completions(6 - scores.len(), left, 15);
+    if arrays.is_empty() {
+        text.push_str(&format!(
+            "Nothing uses up the {} points left for the remaining scores.",
+            left
+        ));
+        return text;
+    }
+    text.push_str(&format!(
+        "{} points left for the remaining scores, such as:\n",
+        left
+    ));
+    let suggestions: Vec<_> = arrays
+        .iter()
+        .take(SUGGESTIONS)
+        .map(|rest| {
+            format!(
+                "<code>{}</code>",
+                format_array(&[&scores[..], rest].concat())
+            )
+        })
+        .collect();
+    text.push_str(&suggestions.join("\n"));
+    text
+}
+
+pub(crate) async fn handle_pointbuy(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    input: &str,
+) -> anyhow::Result<()> {
+    bot.reply(msg, pointbuy(input), vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_the_lowest_die() {
+        let score = Score::new(Method::DropLowest, vec![2, 6, 1, 5]);
+        assert_eq!(score.total, 13);
+        assert_eq!(score.to_string(), "<b>13</b> (+1) [6, 5, 2, <s>1</s>]");
+        assert_eq!(Score::new(Method::Straight, vec![2, 6, 1]).total, 9);
+        assert_eq!(Method::parse("4d6kh3"), Some(Method::DropLowest));
+        assert!(Method::parse("5d6").is_none());
+
+        assert_eq!(
+            summary(&[15, 14, 13, 12, 10, 8]),
+            "Total 72, modifiers +5, 27 points with point buy"
+        );
+        assert!(summary(&[18, 3, 10, 10, 10, 10]).ends_with("beyond point buy"));
+        assert_eq!(statgen("").lines().count(), 8);
+    }
+
+    #[test]
+    fn checks_point_buy_arrays() {
+        assert!(pointbuy("15 14 13 12 10 8").contains("✅ A legal array."));
+        assert!(pointbuy("15 15 15 15 8 8").ends_with("❌ That is 9 points over budget."));
+        assert!(pointbuy("8 8 8 8 8 8").ends_with("⚠️ 27 points are left to spend."));
+        assert_eq!(
+            pointbuy("16"),
+            "16 is not allowed, point buy scores are 8 to 15."
+        );
+        assert!(pointbuy("15 15 15 8 8").ends_with(
+            "0 points left for the remaining scores, such as:\n<code>15 15 15 8 8 8</code>"
+        ));
+        assert!(pointbuy("").contains("<code>15 15 15 8 8 8</code>"));
+        assert!(pointbuy("many").starts_with("<code>/pointbuy"));
+
+        for array in completions(6, BUDGET, 15) {
+            assert_eq!(
+                array.iter().filter_map(|s| point_cost(*s)).sum::<i32>(),
+                BUDGET
+            );
+        }
+    }
+}
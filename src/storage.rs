@@ -1,6 +1,17 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::Instrument;
+
+use crate::history::RollRecord;
+use crate::scheduler::JobQueue;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 #[serde(rename_all = "snake_case")]
@@ -10,11 +21,468 @@ pub struct User {
     pub id: i64,
     pub default_character: Option<String>,
     pub characters: HashMap<String, crate::dnd::Character>,
+    /// Things that change during play, by character name
+    #[serde(default)]
+    pub states: HashMap<String, CharacterState>,
+    /// Character played in a chat instead of the default character, by chat ID
+    #[serde(default)]
+    pub chat_characters: HashMap<i64, String>,
+    /// Telegram username, to find the user by an @mention
+    #[serde(default)]
+    pub username: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+/// Play state of a character, kept apart from the character sheet
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct CharacterState {
+    #[serde(default)]
+    pub inspiration: bool,
+    /// Only tracked for characters with the Lucky feat
+    #[serde(default)]
+    pub luck_points: Option<u8>,
+    /// The spell the character concentrates on
+    #[serde(default)]
+    pub concentrating: Option<String>,
+    /// Hit dice spent since they were regained on a long rest
+    #[serde(default)]
+    pub hit_dice_spent: u8,
+    /// Spell slots spent of each spell level, from the 1st level up
+    #[serde(default)]
+    pub spell_slots_spent: Vec<u8>,
+    /// Uses spent of each resource, by name
+    #[serde(default)]
+    pub resources_spent: BTreeMap<String, u8>,
+    #[serde(default)]
+    pub purse: crate::inventory::Purse,
+    /// Items carried, by name, with how many of each
+    #[serde(default)]
+    pub inventory: BTreeMap<String, u32>,
+    /// The series of checks of the downtime activity under way
+    #[serde(default)]
+    pub downtime: Option<crate::downtime::Series>,
+}
+
+impl User {
+    /// Name of the character played in the chat, the default character unless another was chosen
+    pub fn active_character(&self, chat_id: i64) -> Option<&str> {
+        self.chat_characters
+            .get(&chat_id)
+            .filter(|name| self.characters.contains_key(*name))
+            .or(self.default_character.as_ref())
+            .map(String::as_str)
+    }
+
+    /// The named character, or the character played in the chat when no name is given
+    pub fn character(&self, chat_id: i64, name: Option<&str>) -> Option<&crate::dnd::Character> {
+        let name = name.or(self.active_character(chat_id))?;
+        self.characters.get(name)
+    }
+
+    /// Play state of the character played in the chat, if the user has one
+    pub fn active_state_mut(&mut self, chat_id: i64) -> Option<(String, &mut CharacterState)> {
+        let name = self.active_character(chat_id)?.to_string();
+        let state = self.states.entry(name.clone()).or_default();
+        Some((name, state))
+    }
+}
+
+/// Data belonging to a chat rather than a user
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct Chat {
+    #[serde(default)]
+    pub encounter_tables: Vec<crate::encounter::EncounterTable>,
+    #[serde(default)]
+    pub combat: crate::combat::Tracker,
+    /// The deck shuffled or drawn from last comes first
+    #[serde(default)]
+    pub decks: Vec<crate::deck::Deck>,
+    #[serde(default)]
+    pub random_tables: Vec<crate::table::RandomTable>,
+    /// Users who used the bot in this chat
+    #[serde(default)]
+    pub members: BTreeSet<i64>,
+    /// For reading and showing times, UTC when not set
+    #[serde(default)]
+    pub timezone: Option<chrono_tz::Tz>,
+    /// When `/session start` was used, until `/session end`
+    #[serde(default)]
+    pub session_started: Option<chrono::DateTime<chrono::Utc>>,
+    /// The latest roll of each user, for `/undo` and `/reroll`
+    #[serde(default)]
+    pub last_rolls: HashMap<i64, crate::undo::LastRoll>,
+    #[serde(default)]
+    pub settings: crate::settings::Settings,
+    /// When the last sticker was sent for a special result
+    #[serde(default)]
+    pub sticker_sent: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default)]
+    pub campaign: Option<crate::campaign::Campaign>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Default)]
 #[serde(rename_all = "snake_case")]
 #[serde(deny_unknown_fields)]
 pub struct Storage {
+    #[serde(default)]
     user_characters: HashMap<i64, User>,
+
+    #[serde(default)]
+    chats: HashMap<i64, Chat>,
+
+    /// Scheduled actions that have to survive a restart
+    #[serde(default)]
+    pub jobs: JobQueue,
+
+    /// Group chats that an operator approved with `/admin approve`
+    #[serde(default)]
+    pub approved_chats: BTreeSet<i64>,
+
+    /// Users ignored after `/admin ban`
+    #[serde(default)]
+    pub banned_users: BTreeSet<i64>,
+
+    /// Chats the bot has been used in, with their title or name
+    #[serde(default)]
+    pub known_chats: BTreeMap<i64, String>,
+}
+
+impl Storage {
+    pub fn user(&self, user_id: i64) -> Option<&User> {
+        self.user_characters.get(&user_id)
+    }
+
+    pub fn users(&self) -> impl Iterator<Item = &User> {
+        self.user_characters.values()
+    }
+
+    pub fn user_mut(&mut self, user_id: i64) -> &mut User {
+        self.user_characters.entry(user_id).or_insert_with(|| User {
+            id: user_id,
+            default_character: None,
+            characters: Default::default(),
+            states: Default::default(),
+            chat_characters: Default::default(),
+            username: None,
+        })
+    }
+
+    pub fn chat(&self, chat_id: i64) -> Option<&Chat> {
+        self.chats.get(&chat_id)
+    }
+
+    pub fn chat_mut(&mut self, chat_id: i64) -> &mut Chat {
+        self.chats.entry(chat_id).or_default()
+    }
+}
+
+/// Where the storage is persisted
+#[async_trait]
+pub trait StorageBackend: Send + Sync + std::fmt::Debug {
+    async fn load(&self) -> anyhow::Result<Storage>;
+    async fn save(&self, storage: &Storage) -> anyhow::Result<()>;
+
+    /// Append a roll to the roll history
+    async fn record_roll(&self, roll: &RollRecord) -> anyhow::Result<()>;
+    /// Rolls made in a chat since the given time, oldest first
+    async fn rolls(&self, chat_id: i64, since: DateTime<Utc>) -> anyhow::Result<Vec<RollRecord>>;
+    /// Every roll in the history, oldest first
+    async fn all_rolls(&self) -> anyhow::Result<Vec<RollRecord>>;
+    /// How many rolls are in the history, without reading them
+    async fn count_rolls(&self) -> anyhow::Result<usize>;
+    /// Mark the latest roll of a user in a chat that is not voided yet as voided, if there is one
+    async fn void_last_roll(&self, chat_id: i64, user_id: i64) -> anyhow::Result<bool>;
+    /// Replace the whole roll history
+    async fn replace_rolls(&self, rolls: &[RollRecord]) -> anyhow::Result<()>;
+}
+
+/// Storage kept in a single JSON file, with the roll history in a JSON lines file next to it
+#[derive(Debug)]
+pub struct JsonFile {
+    path: PathBuf,
+    /// Held while the roll history is written, so that voiding a roll never loses an append
+    history: Mutex<()>,
+}
+
+impl JsonFile {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        JsonFile {
+            path: path.as_ref().to_path_buf(),
+            history: Mutex::new(()),
+        }
+    }
+
+    fn sibling(&self, extension: &str) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(extension);
+        path.into()
+    }
+
+    /// Rewrite the roll history through a temporary file, like the storage. Call with the
+    /// history lock held.
+    async fn write_history(&self, rolls: &[RollRecord]) -> anyhow::Result<()> {
+        let path = self.sibling(".history");
+        let mut contents = vec![];
+        for roll in rolls {
+            contents.extend(serde_json::to_vec(roll)?);
+            contents.push(b'\n');
+        }
+        let temporary = self.sibling(".history.tmp");
+        tokio::fs::write(&temporary, contents)
+            .await
+            .with_context(|| format!("error writing file {:?}", temporary))?;
+        tokio::fs::rename(&temporary, &path)
+            .await
+            .with_context(|| format!("error replacing file {:?}", path))
+    }
+}
+
+#[async_trait]
+impl StorageBackend for JsonFile {
+    /// A missing file is treated as empty storage.
+    async fn load(&self) -> anyhow::Result<Storage> {
+        let path = &self.path;
+        let contents = match tokio::fs::read(path).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Default::default()),
+            contents => contents.with_context(|| format!("error reading file {:?}", path))?,
+        };
+        serde_json::from_slice(&contents)
+            .with_context(|| format!("error deserializing storage {:?}", path))
+    }
+
+    /// Write to a temporary file first, so that a crash never leaves a truncated file behind.
+    async fn save(&self, storage: &Storage) -> anyhow::Result<()> {
+        let path = &self.path;
+        let contents = serde_json::to_vec_pretty(storage)
+            .with_context(|| format!("error serializing storage {:?}", path))?;
+        let temporary = self.sibling(".tmp");
+        tokio::fs::write(&temporary, contents)
+            .await
+            .with_context(|| format!("error writing file {:?}", temporary))?;
+        tokio::fs::rename(&temporary, path)
+            .await
+            .with_context(|| format!("error replacing file {:?}", path))
+    }
+
+    async fn record_roll(&self, roll: &RollRecord) -> anyhow::Result<()> {
+        let path = self.sibling(".history");
+        let mut line = serde_json::to_vec(roll)?;
+        line.push(b'\n');
+        let _history = self.history.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("error opening file {:?}", path))?;
+        file.write_all(&line)
+            .await
+            .with_context(|| format!("error writing file {:?}", path))?;
+        file.flush()
+            .await
+            .with_context(|| format!("error writing file {:?}", path))
+    }
+
+    async fn rolls(&self, chat_id: i64, since: DateTime<Utc>) -> anyhow::Result<Vec<RollRecord>> {
+        let mut rolls = self.all_rolls().await?;
+        rolls.retain(|roll| roll.chat_id == chat_id && roll.timestamp >= since);
+        Ok(rolls)
+    }
+
+    async fn all_rolls(&self) -> anyhow::Result<Vec<RollRecord>> {
+        let path = self.sibling(".history");
+        let contents = match tokio::fs::read_to_string(&path).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            contents => contents.with_context(|| format!("error reading file {:?}", path))?,
+        };
+        contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line)
+                    .with_context(|| format!("error deserializing roll history {:?}", path))
+            })
+            .collect()
+    }
+
+    /// One roll per line, counted without deserializing them
+    async fn count_rolls(&self) -> anyhow::Result<usize> {
+        let path = self.sibling(".history");
+        let contents = match tokio::fs::read_to_string(&path).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            contents => contents.with_context(|| format!("error reading file {:?}", path))?,
+        };
+        Ok(contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .count())
+    }
+
+    /// The history is rewritten, through a temporary file like the storage
+    async fn void_last_roll(&self, chat_id: i64, user_id: i64) -> anyhow::Result<bool> {
+        let _history = self.history.lock().await;
+        let mut rolls = self.all_rolls().await?;
+        let Some(roll) = rolls
+            .iter_mut()
+            .rev()
+            .find(|roll| roll.chat_id == chat_id && roll.user_id == user_id && !roll.voided)
+        else {
+            return Ok(false);
+        };
+        roll.voided = true;
+        self.write_history(&rolls).await?;
+        Ok(true)
+    }
+
+    async fn replace_rolls(&self, rolls: &[RollRecord]) -> anyhow::Result<()> {
+        let _history = self.history.lock().await;
+        self.write_history(rolls).await
+    }
+}
+
+/// Shared handle to the storage, persisted after every update.
+#[derive(Clone, Debug)]
+pub struct Store {
+    backend: Arc<dyn StorageBackend>,
+    storage: Arc<Mutex<Storage>>,
+}
+
+impl Store {
+    pub async fn open(backend: Box<dyn StorageBackend>) -> anyhow::Result<Self> {
+        let storage = backend.load().await?;
+        Ok(Store {
+            backend: Arc::from(backend),
+            storage: Arc::new(Mutex::new(storage)),
+        })
+    }
+
+    pub fn backend(&self) -> &dyn StorageBackend {
+        self.backend.as_ref()
+    }
+
+    pub async fn read<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Storage) -> R,
+    {
+        let storage = self
+            .storage
+            .lock()
+            .instrument(tracing::info_span!("storage.read"))
+            .await;
+        f(&storage)
+    }
+
+    /// Mutate the storage and persist it through the backend.
+    pub async fn update<F, R>(&self, f: F) -> anyhow::Result<R>
+    where
+        F: FnOnce(&mut Storage) -> R,
+    {
+        let span = tracing::info_span!("storage.update");
+        let mut storage = self.storage.lock().instrument(span.clone()).await;
+        let result = span.in_scope(|| f(&mut storage));
+        self.backend.save(&storage).instrument(span).await?;
+        Ok(result)
+    }
+
+    pub async fn record_roll(&self, roll: &RollRecord) -> anyhow::Result<()> {
+        self.backend
+            .record_roll(roll)
+            .instrument(tracing::info_span!("storage.record_roll"))
+            .await
+    }
+
+    /// Rolls made in a chat since the given time, leaving out the voided ones
+    pub async fn rolls(
+        &self,
+        chat_id: i64,
+        since: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<RollRecord>> {
+        let mut rolls = self.backend.rolls(chat_id, since).await?;
+        rolls.retain(|roll| !roll.voided);
+        Ok(rolls)
+    }
+
+    pub async fn void_last_roll(&self, chat_id: i64, user_id: i64) -> anyhow::Result<bool> {
+        self.backend
+            .void_last_roll(chat_id, user_id)
+            .instrument(tracing::info_span!("storage.void_last_roll"))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dice::{RollResults, RollSettings, RollType};
+
+    #[tokio::test]
+    async fn json_file_round_trips() {
+        let path = std::env::temp_dir().join(format!("storage-{}.json", std::process::id()));
+        let backend = JsonFile::new(&path);
+        assert_eq!(backend.load().await.unwrap(), Storage::default());
+
+        let mut storage = Storage::default();
+        storage.user_mut(42).default_character = Some("Thorin".to_string());
+        backend.save(&storage).await.unwrap();
+        assert_eq!(backend.load().await.unwrap(), storage);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn json_file_voids_rolls() {
+        let path = std::env::temp_dir().join(format!("voids-{}.json", std::process::id()));
+        let backend = JsonFile::new(&path);
+        assert!(!backend.void_last_roll(1, 7).await.unwrap());
+
+        let settings: RollSettings = "1d20".parse().unwrap();
+        let results = RollResults::new(&settings, &RollType::Straight);
+        for user_id in [7, 7, 8] {
+            let roll = RollRecord::new(1, user_id, None, "1d20", &results);
+            backend.record_roll(&roll).await.unwrap();
+        }
+        assert!(backend.void_last_roll(1, 7).await.unwrap());
+        let voided: Vec<_> = backend
+            .all_rolls()
+            .await
+            .unwrap()
+            .iter()
+            .map(|roll| roll.voided)
+            .collect();
+        assert_eq!(voided, [false, true, false]);
+
+        // The voided roll stays voided, and the one before it is next
+        assert!(backend.void_last_roll(1, 7).await.unwrap());
+        assert!(!backend.void_last_roll(1, 7).await.unwrap());
+        let voided: Vec<_> = backend
+            .all_rolls()
+            .await
+            .unwrap()
+            .iter()
+            .map(|roll| roll.voided)
+            .collect();
+        assert_eq!(voided, [true, true, false]);
+        assert_eq!(backend.count_rolls().await.unwrap(), 3);
+        std::fs::remove_file(backend.sibling(".history")).unwrap();
+    }
+
+    #[tokio::test]
+    async fn json_file_keeps_rolls_recorded_while_voiding() {
+        let path = std::env::temp_dir().join(format!("racing-{}.json", std::process::id()));
+        let backend = JsonFile::new(&path);
+        let settings: RollSettings = "1d20".parse().unwrap();
+        let results = RollResults::new(&settings, &RollType::Straight);
+        let roll = RollRecord::new(1, 7, None, "1d20", &results);
+        backend.record_roll(&roll).await.unwrap();
+        for _ in 0..20 {
+            let (recorded, voided) =
+                tokio::join!(backend.record_roll(&roll), backend.void_last_roll(1, 7));
+            recorded.unwrap();
+            voided.unwrap();
+        }
+        assert_eq!(backend.all_rolls().await.unwrap().len(), 21);
+        std::fs::remove_file(backend.sibling(".history")).unwrap();
+    }
 }
@@ -0,0 +1,91 @@
+//! Wild magic surges, rolled on the `wild_magic` table of the chat or the built in one.
+
+use teloxide::utils::html;
+
+use crate::dice::{Flags, Roll, RollSettings};
+use crate::storage::Store;
+use crate::transport::{ChatTransport, Incoming};
+
+const TABLE: &str = "wild_magic";
+
+const TRIGGER: RollSettings = RollSettings {
+    number: 1,
+    sides: 20,
+    modifier: None,
+    label: None,
+    target: None,
+    keep: None,
+    explode: None,
+    success: None,
+    composite: false,
+    multiplier: None,
+    flags: Flags::NONE,
+};
+
+const USAGE: &str = "<code>/surge</code> rolls a d20 after casting, surging on a 1
+<code>/surge 2</code> surges on a 2 or lower
+<code>/surge now</code> skips the d20 and rolls on the table
+Upload a table named wild_magic with /table to use your own effects.";
+
+/// Roll the trigger die, and on a surge the effect, from `tables`
+fn surge(tables: &[crate::table::RandomTable], input: &str) -> String {
+    let trigger = match input.trim() {
+        "now" => None,
+        "" => Some(1),
+        threshold => match threshold.parse::<u32>() {
+            Ok(threshold) if (1..=20).contains(&threshold) => Some(threshold),
+            _ => return USAGE.to_string(),
+        },
+    };
+
+    let mut text = String::new();
+    if let Some(threshold) = trigger {
+        let total = Roll::new(&TRIGGER).total;
+        if total > threshold as i64 {
+            return format!("🎲 {} on the d20, no surge this time.", total);
+        }
+        text.push_str(&format!("🎲 {} on the d20, wild magic surges!\n", total));
+    }
+    match crate::table::roll(tables, TABLE) {
+        Ok(effect) => text.push_str(&effect),
+        Err(e) => text.push_str(&html::escape(&format!("{:#}", e))),
+    }
+    text
+}
+
+pub(crate) async fn handle_surge(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let text = store
+        .read(|storage| {
+            let tables = storage
+                .chat(chat_id)
+                .map(|chat| chat.random_tables.as_slice())
+                .unwrap_or_default();
+            surge(tables, input)
+        })
+        .await;
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn surges_on_the_trigger() {
+        assert!(surge(&[], "now").starts_with("🎲 <b>wild_magic</b> (1d100: "));
+        assert!(surge(&[], "20").contains("wild magic surges!"));
+        assert!(surge(&[], "21").starts_with("<code>/surge</code>"));
+
+        let tables: Vec<crate::table::RandomTable> =
+            serde_json::from_str(r#"[{"name": "Wild_Magic", "entries": [{"text": "Sparks"}]}]"#)
+                .unwrap();
+        assert_eq!(surge(&tables, "now"), "🎲 <b>Wild_Magic</b>\nSparks");
+    }
+}
@@ -0,0 +1,449 @@
+//! Random tables uploaded to a chat, whose entries can roll dice and roll on other tables.
+
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+use anyhow::{anyhow, bail, Context};
+use rand::distributions::{Distribution, WeightedIndex};
+use serde::{Deserialize, Serialize};
+use teloxide::utils::html;
+
+use crate::dice::{Roll, RollSettings};
+use crate::storage::Store;
+use crate::transport::{ChatTransport, Incoming};
+
+/// How deep tables can refer to tables, which also stops tables that refer to themselves
+const MAX_DEPTH: usize = 8;
+
+/// Upper bound on how many totals the dice of a table can have, each needing an entry
+const MAX_RANGE: i64 = 10000;
+
+/// A table rolled with dice, such as `1d100` against ranges, or picked from by weight
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct RandomTable {
+    pub name: String,
+    /// Without dice, entries are picked by weight
+    #[serde(default)]
+    pub roll: Option<String>,
+    pub entries: Vec<Entry>,
+}
+
+/// Text of an entry can have inline rolls like `[[2d6]]` or `[[2d6*10]]`, and rolls on other tables like `{gems}`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    /// The totals of `roll` that pick this entry
+    #[serde(default)]
+    pub range: Option<Range>,
+    pub text: String,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// A single total such as `7`, or an inclusive range such as `[1, 2]`
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(untagged)]
+pub enum Range {
+    Single(i64),
+    Span([i64; 2]),
+}
+
+impl Range {
+    fn contains(&self, total: i64) -> bool {
+        match *self {
+            Range::Single(value) => value == total,
+            Range::Span([low, high]) => (low..=high).contains(&total),
+        }
+    }
+}
+
+impl RandomTable {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.name.is_empty() || self.name.contains(char::is_whitespace) {
+            bail!("the name of a table is a single word");
+        }
+        match self.roll {
+            None => {
+                if self.entries.iter().all(|entry| entry.weight == 0) {
+                    bail!("table needs at least one entry with a non-zero weight");
+                }
+            }
+            Some(ref expression) => {
+                let settings = RollSettings::from_str(expression)?;
+                let (low, high) = crate::expr::DiceExpr::from(&settings).bounds()?;
+                if let Some(entry) = self.entries.iter().find(|entry| entry.range.is_none()) {
+                    bail!("entry {} has no range for {}", entry.text, expression);
+                }
+                if high - low > MAX_RANGE {
+                    bail!("{} has more than {} totals", expression, MAX_RANGE);
+                }
+                if let Some(total) = (low..=high)
+                    .filter(|total| settings.can_roll(*total))
+                    .find(|total| self.entry_for(*total).is_none())
+                {
+                    bail!("no entry for a {} on {}", total, expression);
+                }
+            }
+        }
+        for entry in &self.entries {
+            for expression in inline_rolls(&entry.text) {
+                let (settings, multiplier) = inline_parts(expression)
+                    .with_context(|| format!("invalid roll [[{}]]", expression))?;
+                let (low, high) = crate::expr::DiceExpr::from(&settings).bounds()?;
+                if low.checked_mul(multiplier).is_none() || high.checked_mul(multiplier).is_none() {
+                    bail!("[[{}]] can total more than the bot can count", expression);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn entry_for(&self, total: i64) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.range.is_some_and(|range| range.contains(total)))
+    }
+
+    /// The entry, and the total of the dice when the table has them
+    fn pick(&self) -> (Option<i64>, &Entry) {
+        match self.roll {
+            None => {
+                let weights = WeightedIndex::new(self.entries.iter().map(|entry| entry.weight))
+                    .expect("to be validated");
+                (None, &self.entries[weights.sample(&mut rand::thread_rng())])
+            }
+            Some(ref expression) => {
+                let settings = RollSettings::from_str(expression).expect("to be validated");
+                let total = Roll::new(&settings).total;
+                (Some(total), self.entry_for(total).expect("to be validated"))
+            }
+        }
+    }
+}
+
+/// Tables that every chat has, unless it uploads its own with the same name
+static BUILT_IN: LazyLock<Vec<RandomTable>> = LazyLock::new(|| {
+    [
+        include_str!("../tables/wild_magic.json"),
+        include_str!("../tables/loot.json"),
+        include_str!("../tables/npc.json"),
+        include_str!("../tables/names.json"),
+        include_str!("../tables/rumors.json"),
+        include_str!("../tables/weather.json"),
+    ]
+    .iter()
+    .flat_map(
+        |json| match serde_json::from_str(json).expect("built in tables to be valid") {
+            OneOrMany::One(table) => vec![table],
+            OneOrMany::Many(tables) => tables,
+        },
+    )
+    .collect()
+});
+
+/// The table of the chat with that name, or the built in one
+pub(crate) fn find<'a>(tables: &'a [RandomTable], name: &str) -> Option<&'a RandomTable> {
+    tables
+        .iter()
+        .chain(BUILT_IN.iter())
+        .find(|table| table.name.eq_ignore_ascii_case(name))
+}
+
+/// The expressions of the inline rolls of `text`, such as the `2d6` of `[[2d6]] gp`
+fn inline_rolls(text: &str) -> impl Iterator<Item = &str> {
+    text.split("[[")
+        .skip(1)
+        .filter_map(|rest| rest.split_once("]]").map(|(expression, _)| expression))
+}
+
+/// The dice and the factor of an inline roll, such as `2d6` or `4d6*100`
+fn inline_parts(expression: &str) -> anyhow::Result<(RollSettings, i64)> {
+    let (dice, multiplier) = match expression.split_once('*') {
+        None => (expression, 1),
+        Some((dice, multiplier)) => (dice, multiplier.trim().parse::<i64>()?),
+    };
+    Ok((RollSettings::from_str(dice)?, multiplier))
+}
+
+/// Total of an inline roll
+fn inline_roll(expression: &str) -> anyhow::Result<i64> {
+    let (settings, multiplier) = inline_parts(expression)?;
+    Roll::new(&settings)
+        .total
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow!("the total is too large to count"))
+}
+
+/// Replace the inline rolls and table references of `text`, as HTML
+fn expand(tables: &[RandomTable], text: &str, depth: usize) -> anyhow::Result<String> {
+    let mut expanded = String::new();
+    let mut rest = text;
+    loop {
+        let next = [rest.find("[["), rest.find('{')]
+            .into_iter()
+            .flatten()
+            .min();
+        let Some(start) = next else {
+            expanded.push_str(&html::escape(rest));
+            return Ok(expanded);
+        };
+        expanded.push_str(&html::escape(&rest[..start]));
+        if rest[start..].starts_with("[[") {
+            let end = rest[start..]
+                .find("]]")
+                .ok_or_else(|| anyhow!("[[ without ]] in {}", text))?;
+            let expression = &rest[start + 2..start + end];
+            let total = inline_roll(expression)
+                .with_context(|| format!("invalid roll [[{}]]", expression))?;
+            expanded.push_str(&format!("<b>{}</b>", total));
+            rest = &rest[start + end + 2..];
+        } else {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| anyhow!("{{ without }} in {}", text))?;
+            let name = &rest[start + 1..start + end];
+            expanded.push_str(&roll_text(tables, name, depth + 1)?);
+            rest = &rest[start + end + 1..];
+        }
+    }
+}
+
+fn roll_text(tables: &[RandomTable], name: &str, depth: usize) -> anyhow::Result<String> {
+    if depth > MAX_DEPTH {
+        bail!("tables refer to each other more than {} deep", MAX_DEPTH);
+    }
+    let table = find(tables, name).ok_or_else(|| anyhow!("there is no table named {}", name))?;
+    let (_, entry) = table.pick();
+    expand(tables, &entry.text, depth)
+}
+
+/// Roll on the table for just the text of the entry, as HTML
+pub(crate) fn roll_entry(tables: &[RandomTable], name: &str) -> anyhow::Result<String> {
+    roll_text(tables, name, 0)
+}
+
+/// Roll on the table, with a heading saying what the dice came up with
+pub(crate) fn roll(tables: &[RandomTable], name: &str) -> anyhow::Result<String> {
+    let table = find(tables, name).ok_or_else(|| anyhow!("there is no table named {}", name))?;
+    let (total, entry) = table.pick();
+    let heading = match (total, &table.roll) {
+        (Some(total), Some(expression)) => format!(
+            "🎲 <b>{}</b> ({}: {})",
+            html::escape(&table.name),
+            html::escape(expression),
+            total
+        ),
+        _ => format!("🎲 <b>{}</b>", html::escape(&table.name)),
+    };
+    Ok(format!("{}\n{}", heading, expand(tables, &entry.text, 0)?))
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(RandomTable),
+    Many(Vec<RandomTable>),
+}
+
+fn parse_tables(contents: &[u8]) -> anyhow::Result<Vec<RandomTable>> {
+    let tables = match serde_json::from_slice(contents)
+        .map_err(|_| anyhow!("not a valid random table or list of random tables"))?
+    {
+        OneOrMany::One(table) => vec![table],
+        OneOrMany::Many(tables) => tables,
+    };
+    for table in &tables {
+        table
+            .validate()
+            .with_context(|| format!("invalid table {}", table.name))?;
+    }
+    Ok(tables)
+}
+
+const USAGE: &str = "<code>/table name</code> rolls on a table
+<code>/table list</code> lists the random tables of this chat, and the built in ones
+<code>/table upload</code> as a reply to a JSON file adds or replaces tables
+<code>/table remove name</code> removes a table
+
+Tables are rolled with dice against ranges, such as <code>{\"name\": \"surge\", \"roll\": \"1d100\", \"entries\": [{\"range\": [1, 50], \"text\": \"You glow\"}, {\"range\": [51, 100], \"text\": \"You take [[1d10]] damage\"}]}</code>, or picked from by weight. <code>{other}</code> in the text of an entry rolls on the table named other.";
+
+pub(crate) async fn handle_table(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let mut args = input.split_whitespace();
+    let text = match args.next() {
+        None => USAGE.to_string(),
+        Some("list") => {
+            store
+                .read(|storage| {
+                    let tables = storage
+                        .chat(chat_id)
+                        .map(|chat| chat.random_tables.as_slice())
+                        .unwrap_or_default();
+                    let built_in = BUILT_IN
+                        .iter()
+                        .filter(|table| find(tables, &table.name) == Some(table))
+                        .map(|table| html::escape(&table.name))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    let mut text = tables
+                        .iter()
+                        .map(|table| {
+                            format!(
+                                "• <b>{}</b>: {} ({} entries)",
+                                html::escape(&table.name),
+                                html::escape(table.roll.as_deref().unwrap_or("weighted")),
+                                table.entries.len()
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    if tables.is_empty() {
+                        text.push_str("This chat has no random tables.");
+                    }
+                    text.push_str(&format!("\n\nBuilt in: {}", built_in));
+                    text
+                })
+                .await
+        }
+        Some("upload") if crate::auth::is_chat_admin(bot, msg).await? => {
+            match crate::upload::replied_document(bot, msg).await? {
+                Err(e) => e.to_string(),
+                Ok(contents) => match parse_tables(&contents) {
+                    Err(e) => format!("<code>{}</code>", html::escape(&format!("{:#}", e))),
+                    Ok(tables) => {
+                        let names = tables
+                            .iter()
+                            .map(|table| table.name.clone())
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        store
+                            .update(|storage| {
+                                let existing = &mut storage.chat_mut(chat_id).random_tables;
+                                for table in tables {
+                                    existing.retain(|t| !t.name.eq_ignore_ascii_case(&table.name));
+                                    existing.push(table);
+                                }
+                            })
+                            .await?;
+                        format!("Saved random tables: {}", html::escape(&names))
+                    }
+                },
+            }
+        }
+        Some("remove") if crate::auth::is_chat_admin(bot, msg).await? => {
+            let name = args.next().unwrap_or_default().to_string();
+            let removed = store
+                .update(|storage| {
+                    let tables = &mut storage.chat_mut(chat_id).random_tables;
+                    let before = tables.len();
+                    tables.retain(|table| !table.name.eq_ignore_ascii_case(&name));
+                    tables.len() != before
+                })
+                .await?;
+            if removed {
+                format!("Removed random table {}", html::escape(&name))
+            } else {
+                format!("There is no random table named {}", html::escape(&name))
+            }
+        }
+        Some("upload") | Some("remove") => {
+            "Only chat administrators can change random tables.".to_string()
+        }
+        Some(name) => {
+            let result = store
+                .read(|storage| {
+                    let tables = storage
+                        .chat(chat_id)
+                        .map(|chat| chat.random_tables.as_slice())
+                        .unwrap_or_default();
+                    roll(tables, name)
+                })
+                .await;
+            match result {
+                Ok(text) => text,
+                Err(e) => html::escape(&format!("Could not roll on {}: {:#}", name, e)),
+            }
+        }
+    };
+
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_ranges_against_the_dice() {
+        let tables = parse_tables(
+            br#"{"name": "surge", "roll": "1d4", "entries": [
+                {"range": [1, 3], "text": "Nothing"},
+                {"range": 4, "text": "Boom"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(tables[0].entry_for(2).unwrap().text, "Nothing");
+        assert_eq!(tables[0].entry_for(4).unwrap().text, "Boom");
+
+        let error = parse_tables(
+            br#"{"name": "surge", "roll": "1d4", "entries": [{"range": [1, 3], "text": "Nothing"}]}"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "invalid table surge: no entry for a 4 on 1d4"
+        );
+        assert!(parse_tables(br#"{"name": "empty", "entries": []}"#).is_err());
+        // Totals like 17 cannot come up on a d66
+        assert!(parse_tables(
+            br#"{"name": "patron", "roll": "d66", "entries": [
+                {"range": [11, 36], "text": "Noble"},
+                {"range": [41, 66], "text": "Merchant"}
+            ]}"#
+        )
+        .is_ok());
+        for table in BUILT_IN.iter() {
+            table.validate().unwrap();
+        }
+    }
+
+    #[test]
+    fn expands_rolls_and_references() {
+        let tables = parse_tables(
+            br#"[
+                {"name": "hoard", "roll": "1d1", "entries": [{"range": 1, "text": "[[3d1 + 2]] gp and a {gem}"}]},
+                {"name": "gem", "entries": [{"text": "ruby <red>"}]},
+                {"name": "loop", "entries": [{"text": "{loop}"}]}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            roll(&tables, "HOARD").unwrap(),
+            "🎲 <b>hoard</b> (1d1: 1)\n<b>5</b> gp and a ruby &lt;red&gt;"
+        );
+        assert!(roll(&tables, "loop").is_err());
+        assert!(roll(&tables, "missing").is_err());
+
+        let error = parse_tables(
+            br#"{"name": "hoard", "entries": [{"text": "[[1d20*9223372036854775807]] gp"}]}"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "invalid table hoard: [[1d20*9223372036854775807]] can total more than the bot can count"
+        );
+    }
+}
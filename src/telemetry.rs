@@ -0,0 +1,195 @@
+//! Logging, tracing, and Prometheus metrics about handled commands.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::Context;
+use metrics::{counter, histogram};
+use metrics_exporter_prometheus::PrometheusBuilder;
+use teloxide::prelude::*;
+use teloxide::RequestError;
+
+use crate::cli::{Cli, LogFormat};
+
+/// Flushes the remaining spans to the trace exporter, and the remaining events to Sentry, when dropped
+#[derive(Default)]
+pub struct Guard {
+    #[cfg(feature = "otel")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+    #[cfg(feature = "sentry")]
+    sentry: Option<sentry::ClientInitGuard>,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                log::error!("Error shutting down trace exporter: {}", e);
+            }
+        }
+    }
+}
+
+pub fn init_logging(cli: &Cli) -> anyhow::Result<Guard> {
+    #[allow(unused_mut)]
+    let mut guard = init_subscriber(cli)?;
+    #[cfg(feature = "sentry")]
+    if let Some(ref dsn) = cli.sentry_dsn {
+        guard.sentry = Some(init_sentry(dsn)?);
+    }
+    Ok(guard)
+}
+
+fn init_subscriber(cli: &Cli) -> anyhow::Result<Guard> {
+    #[cfg(feature = "otel")]
+    if let Some(ref endpoint) = cli.otel_endpoint {
+        return init_otel(cli.log_format, endpoint);
+    }
+    match cli.log_format {
+        LogFormat::Pretty => pretty_env_logger::formatted_timed_builder()
+            .filter_level(log::LevelFilter::Info)
+            .init(),
+        // Also picks up the records of the `log` macros
+        LogFormat::Json => tracing_subscriber::fmt()
+            .json()
+            .with_max_level(tracing::Level::INFO)
+            .init(),
+    }
+    Ok(Guard::default())
+}
+
+/// Log with `tracing`, and export spans to an OTLP collector
+#[cfg(feature = "otel")]
+fn init_otel(format: LogFormat, endpoint: &str) -> anyhow::Result<Guard> {
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::WithExportConfig;
+    use tracing_subscriber::filter::LevelFilter;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()
+        .context("error creating the trace exporter")?;
+    let resource = opentelemetry_sdk::Resource::builder()
+        .with_service_name(env!("CARGO_PKG_NAME"))
+        .build();
+    let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+
+    let registry = tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(tracing_opentelemetry::layer().with_tracer(tracer));
+    let logs = tracing_subscriber::fmt::layer();
+    match format {
+        LogFormat::Pretty => registry.with(logs).init(),
+        LogFormat::Json => registry.with(logs.json()).init(),
+    }
+    let mut guard = Guard::default();
+    guard.provider = Some(provider);
+    Ok(guard)
+}
+
+/// Capture panics, and the errors passed to [`record_command`]
+#[cfg(feature = "sentry")]
+fn init_sentry(dsn: &str) -> anyhow::Result<sentry::ClientInitGuard> {
+    let dsn: sentry::types::Dsn = dsn.parse().context("error parsing the Sentry DSN")?;
+    let mut options = sentry::ClientOptions::new();
+    options.dsn = Some(dsn);
+    options.release = sentry::release_name!();
+    Ok(sentry::init(options))
+}
+
+#[cfg(feature = "sentry")]
+fn tag(scope: &mut sentry::Scope, msg: &Message) {
+    scope.set_tag("chat_id", msg.chat.id.0);
+    scope.set_tag("command", command_name(msg));
+}
+
+/// Tag what is reported to Sentry while handling the message, panics included, with the chat and command
+#[cfg(feature = "sentry")]
+pub fn with_context<F: Future>(msg: &Message, future: F) -> impl Future<Output = F::Output> {
+    use sentry::SentryFutureExt;
+
+    let hub = std::sync::Arc::new(sentry::Hub::new_from_top(sentry::Hub::current()));
+    hub.configure_scope(|scope| tag(scope, msg));
+    future.bind_hub(hub)
+}
+
+#[cfg(not(feature = "sentry"))]
+pub fn with_context<F: Future>(_msg: &Message, future: F) -> F {
+    future
+}
+
+/// Serve the metrics over HTTP, such as `http://address/metrics`
+pub fn install_metrics(address: SocketAddr) -> anyhow::Result<()> {
+    PrometheusBuilder::new()
+        .with_http_listener(address)
+        .install()
+        .context("error starting the metrics endpoint")
+}
+
+/// The command as written, without the leading `/` and the bot name, such as `roll`
+pub fn command_name(msg: &Message) -> String {
+    msg.text()
+        .and_then(|text| text.split_whitespace().next())
+        .map(|command| command.trim_start_matches('/'))
+        .and_then(|command| command.split('@').next())
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+fn chat_type(chat: &teloxide::types::Chat) -> &'static str {
+    if chat.is_private() {
+        "private"
+    } else if chat.is_group() {
+        "group"
+    } else if chat.is_supergroup() {
+        "supergroup"
+    } else {
+        "channel"
+    }
+}
+
+pub fn record_command(msg: &Message, duration: Duration, result: &anyhow::Result<()>) {
+    let command = command_name(msg);
+    tracing::info!(
+        chat_id = msg.chat.id.0,
+        user_id = msg.from().map(|user| user.id.0),
+        command = command.as_str(),
+        duration_ms = duration.as_secs_f64() * 1000.0,
+        "Handled command"
+    );
+    counter!("dice_maestro_commands_total", "command" => command.clone()).increment(1);
+    histogram!("dice_maestro_command_duration_seconds", "command" => command).record(duration);
+    // By the type of chat rather than by chat, to keep to a few series
+    counter!("dice_maestro_chat_messages_total", "chat_type" => chat_type(&msg.chat)).increment(1);
+    if let Err(e) = result {
+        if e.downcast_ref::<RequestError>().is_some() {
+            counter!("dice_maestro_telegram_errors_total").increment(1);
+        }
+        #[cfg(feature = "sentry")]
+        sentry::with_scope(
+            |scope| tag(scope, msg),
+            || sentry::integrations::anyhow::capture_anyhow(e),
+        );
+    }
+}
+
+/// Time taken to parse and roll the dice of a roll, without answering it
+pub fn record_roll(duration: Duration) {
+    histogram!("dice_maestro_roll_duration_seconds").record(duration);
+}
+
+pub fn record_parse_failure() {
+    counter!("dice_maestro_parse_failures_total").increment(1);
+    // One issue for all of them, so that Sentry shows how often rolls fail to parse
+    #[cfg(feature = "sentry")]
+    sentry::capture_message("Could not parse a roll", sentry::Level::Info);
+}
@@ -0,0 +1,506 @@
+//! Fakes of the chat platform and the storage, so that handlers answering through
+//! [`ChatTransport`] can be tested without a bot token. [`MockBotApi`] goes further, standing in
+//! for Telegram so that whole updates can be run through the dispatcher.
+
+use std::collections::{BTreeSet, HashMap};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming as Body};
+use hyper::{Request, Response};
+use hyper_util::rt::TokioIo;
+use serde_json::json;
+use teloxide::types::Update;
+use teloxide::update_listeners::UpdateListener;
+
+use crate::dnd::Character;
+use crate::history::RollRecord;
+use crate::reaction::Celebration;
+use crate::storage::{Storage, StorageBackend, Store};
+use crate::transport::{Button, ChatTransport, Incoming, Press, Sender};
+
+/// Chat of the messages from [`incoming`]
+pub(crate) const CHAT_ID: i64 = -100;
+
+/// What a handler sent, in order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Sent {
+    Reply {
+        to: Incoming,
+        text: String,
+        buttons: Vec<Button>,
+    },
+    Message {
+        chat_id: i64,
+        text: String,
+    },
+    Edit {
+        chat_id: i64,
+        message_id: i32,
+        text: String,
+        buttons: Vec<Button>,
+    },
+    Delete {
+        chat_id: i64,
+        message_id: i32,
+    },
+    Pin {
+        chat_id: i64,
+        message_id: i32,
+    },
+    PressAnswer {
+        id: String,
+        notice: Option<String>,
+    },
+    Left {
+        chat_id: i64,
+    },
+    File {
+        chat_id: i64,
+        message_id: i32,
+        name: String,
+        contents: Vec<u8>,
+        caption: Option<String>,
+    },
+    NativeDie {
+        to: Incoming,
+    },
+    /// A special result was celebrated with [`ChatTransport::react`]
+    Reaction {
+        to: Incoming,
+        celebration: Celebration,
+    },
+}
+
+/// A transport that keeps what is sent to it. Replies get IDs counting up from 1000.
+#[derive(Debug, Default)]
+pub(crate) struct FakeTransport {
+    sent: Mutex<Vec<Sent>>,
+    admins: BTreeSet<i64>,
+    files: HashMap<String, Vec<u8>>,
+}
+
+impl FakeTransport {
+    /// Make the user an administrator of every chat
+    pub(crate) fn with_admin(mut self, user_id: i64) -> Self {
+        self.admins.insert(user_id);
+        self
+    }
+
+    /// Offer the contents for download under the file ID
+    pub(crate) fn with_file(mut self, file_id: &str, contents: &[u8]) -> Self {
+        self.files.insert(file_id.to_string(), contents.to_vec());
+        self
+    }
+
+    pub(crate) fn sent(&self) -> Vec<Sent> {
+        self.sent.lock().expect("to not be poisoned").clone()
+    }
+
+    /// The texts of the replies
+    pub(crate) fn replies(&self) -> Vec<String> {
+        self.sent()
+            .into_iter()
+            .filter_map(|sent| match sent {
+                Sent::Reply { text, .. } => Some(text),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Keep what was sent, returning its message ID
+    fn push(&self, sent: Sent) -> i32 {
+        let mut all = self.sent.lock().expect("to not be poisoned");
+        all.push(sent);
+        1000 + all.len() as i32 - 1
+    }
+}
+
+#[async_trait]
+impl ChatTransport for FakeTransport {
+    async fn reply(
+        &self,
+        to: &Incoming,
+        text: String,
+        buttons: Vec<Button>,
+    ) -> anyhow::Result<i32> {
+        Ok(self.push(Sent::Reply {
+            to: to.clone(),
+            text,
+            buttons,
+        }))
+    }
+
+    async fn send(&self, chat_id: i64, text: String) -> anyhow::Result<i32> {
+        Ok(self.push(Sent::Message { chat_id, text }))
+    }
+
+    async fn edit(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+        text: String,
+        buttons: Vec<Button>,
+    ) -> anyhow::Result<()> {
+        self.push(Sent::Edit {
+            chat_id,
+            message_id,
+            text,
+            buttons,
+        });
+        Ok(())
+    }
+
+    async fn delete(&self, chat_id: i64, message_id: i32) -> anyhow::Result<()> {
+        self.push(Sent::Delete {
+            chat_id,
+            message_id,
+        });
+        Ok(())
+    }
+
+    async fn pin(&self, chat_id: i64, message_id: i32) -> anyhow::Result<()> {
+        self.push(Sent::Pin {
+            chat_id,
+            message_id,
+        });
+        Ok(())
+    }
+
+    async fn answer_press(&self, press: &Press, notice: Option<String>) -> anyhow::Result<()> {
+        self.push(Sent::PressAnswer {
+            id: press.id.clone(),
+            notice,
+        });
+        Ok(())
+    }
+
+    async fn reply_with_file(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+        name: &str,
+        contents: Vec<u8>,
+        caption: Option<String>,
+    ) -> anyhow::Result<()> {
+        self.push(Sent::File {
+            chat_id,
+            message_id,
+            name: name.to_string(),
+            contents,
+            caption,
+        });
+        Ok(())
+    }
+
+    async fn roll_native_die(&self, to: &Incoming) -> anyhow::Result<()> {
+        self.push(Sent::NativeDie { to: to.clone() });
+        Ok(())
+    }
+
+    async fn react(&self, to: &Incoming, celebration: &Celebration) -> anyhow::Result<()> {
+        self.push(Sent::Reaction {
+            to: to.clone(),
+            celebration: celebration.clone(),
+        });
+        Ok(())
+    }
+
+    async fn is_chat_admin(&self, _: i64, user_id: i64) -> anyhow::Result<bool> {
+        Ok(self.admins.contains(&user_id))
+    }
+
+    async fn leave(&self, chat_id: i64) -> anyhow::Result<()> {
+        self.push(Sent::Left { chat_id });
+        Ok(())
+    }
+
+    async fn download(&self, file_id: &str) -> anyhow::Result<Vec<u8>> {
+        self.files
+            .get(file_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("no file {}", file_id))
+    }
+}
+
+/// Storage that is never written anywhere
+#[derive(Debug, Default)]
+pub(crate) struct Memory {
+    storage: Mutex<Storage>,
+    rolls: Mutex<Vec<RollRecord>>,
+}
+
+#[async_trait]
+impl StorageBackend for Memory {
+    async fn load(&self) -> anyhow::Result<Storage> {
+        Ok(self.storage.lock().expect("to not be poisoned").clone())
+    }
+
+    async fn save(&self, storage: &Storage) -> anyhow::Result<()> {
+        *self.storage.lock().expect("to not be poisoned") = storage.clone();
+        Ok(())
+    }
+
+    async fn record_roll(&self, roll: &RollRecord) -> anyhow::Result<()> {
+        self.rolls
+            .lock()
+            .expect("to not be poisoned")
+            .push(roll.clone());
+        Ok(())
+    }
+
+    async fn rolls(&self, chat_id: i64, since: DateTime<Utc>) -> anyhow::Result<Vec<RollRecord>> {
+        let mut rolls = self.all_rolls().await?;
+        rolls.retain(|roll| roll.chat_id == chat_id && roll.timestamp >= since);
+        Ok(rolls)
+    }
+
+    async fn all_rolls(&self) -> anyhow::Result<Vec<RollRecord>> {
+        Ok(self.rolls.lock().expect("to not be poisoned").clone())
+    }
+
+    async fn count_rolls(&self) -> anyhow::Result<usize> {
+        Ok(self.rolls.lock().expect("to not be poisoned").len())
+    }
+
+    async fn void_last_roll(&self, chat_id: i64, user_id: i64) -> anyhow::Result<bool> {
+        let mut rolls = self.rolls.lock().expect("to not be poisoned");
+        let roll = rolls
+            .iter_mut()
+            .rev()
+            .find(|roll| roll.chat_id == chat_id && roll.user_id == user_id && !roll.voided);
+        Ok(roll.map(|roll| roll.voided = true).is_some())
+    }
+
+    async fn replace_rolls(&self, rolls: &[RollRecord]) -> anyhow::Result<()> {
+        *self.rolls.lock().expect("to not be poisoned") = rolls.to_vec();
+        Ok(())
+    }
+}
+
+/// An empty store, kept in memory
+pub(crate) async fn store() -> Store {
+    Store::open(Box::new(Memory::default()))
+        .await
+        .expect("memory to load")
+}
+
+/// A user called User 7 for the ID 7
+pub(crate) fn sender(user_id: i64) -> Sender {
+    Sender {
+        id: user_id,
+        name: format!("User {}", user_id),
+        username: Some(format!("user{}", user_id)),
+    }
+}
+
+/// A message from the user in the chat [`CHAT_ID`]
+pub(crate) fn incoming(user_id: i64) -> Incoming {
+    Incoming {
+        chat_id: CHAT_ID,
+        message_id: 1,
+        from: Some(sender(user_id)),
+        chat_title: Some("The Party".to_string()),
+        date: Utc::now(),
+        ..Default::default()
+    }
+}
+
+/// A press by the user of a button with the data, under the message 1000 in [`CHAT_ID`]
+pub(crate) fn press(user_id: i64, data: &str) -> Press {
+    Press {
+        id: "press".to_string(),
+        from: sender(user_id),
+        message: Some((CHAT_ID, 1000)),
+        data: data.to_string(),
+    }
+}
+
+/// A character with every modifier at 0 and no proficiencies. Set the fields a test needs on it,
+/// or build on it with `Character { ..character(name) }`.
+pub(crate) fn character(name: &str) -> Character {
+    serde_json::from_value(json!({
+        "name": name,
+        "initiative_modifier": 0,
+        "attribute_modifiers": {"str": 0, "dex": 0, "con": 0, "int": 0, "wis": 0, "cha": 0},
+        "saving_throw_modifiers": {"str": 0, "dex": 0, "con": 0, "int": 0, "wis": 0, "cha": 0},
+        "skill_modifiers": {"proficient": []}
+    }))
+    .expect("the character to be valid")
+}
+
+/// ID of the bot that [`MockBotApi`] answers as
+const BOT_ID: u64 = 1;
+
+/// A request the bot made of the Bot API
+#[derive(Debug, Clone)]
+pub(crate) struct Call {
+    pub method: String,
+    /// JSON, except for the multipart requests with files
+    pub body: Vec<u8>,
+}
+
+impl Call {
+    pub(crate) fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body).expect("a JSON request")
+    }
+}
+
+/// A Bot API server on localhost. Messages that are sent get IDs counting up from 1000, and
+/// methods that do not return a message return `true`.
+#[derive(Debug, Clone)]
+pub(crate) struct MockBotApi {
+    pub url: reqwest::Url,
+    calls: Arc<Mutex<Vec<Call>>>,
+}
+
+impl MockBotApi {
+    pub(crate) async fn start() -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("to bind to localhost");
+        let url = format!("http://{}", listener.local_addr().expect("an address"))
+            .parse()
+            .expect("a URL");
+        let api = MockBotApi {
+            url,
+            calls: Default::default(),
+        };
+        let calls = api.calls.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let calls = calls.clone();
+                let service =
+                    hyper::service::service_fn(move |request| answer(calls.clone(), request));
+                tokio::spawn(
+                    hyper::server::conn::http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service),
+                );
+            }
+        });
+        api
+    }
+
+    /// A bot that talks to this server
+    pub(crate) fn bot(&self) -> teloxide::Bot {
+        teloxide::Bot::new("1:TOKEN").set_api_url(self.url.clone())
+    }
+
+    pub(crate) fn calls(&self) -> Vec<Call> {
+        self.calls.lock().expect("to not be poisoned").clone()
+    }
+
+    /// The calls of a method, in order
+    pub(crate) fn calls_of(&self, method: &str) -> Vec<Call> {
+        self.calls()
+            .into_iter()
+            .filter(|call| call.method == method)
+            .collect()
+    }
+}
+
+async fn answer(
+    calls: Arc<Mutex<Vec<Call>>>,
+    request: Request<Body>,
+) -> Result<Response<Full<Bytes>>, hyper::Error> {
+    // teloxide asks for `SendMessage`, where the documentation of Telegram has `sendMessage`
+    let path = request.uri().path().rsplit('/').next().unwrap_or_default();
+    let mut chars = path.chars();
+    let method = match chars.next() {
+        Some(first) => first.to_ascii_lowercase().to_string() + chars.as_str(),
+        None => String::new(),
+    };
+    let body = request.into_body().collect().await?.to_bytes().to_vec();
+    let message_id = {
+        let mut calls = calls.lock().expect("to not be poisoned");
+        calls.push(Call {
+            method: method.clone(),
+            body,
+        });
+        1000 + calls.len() as i32 - 1
+    };
+    let result = match method.as_str() {
+        "getMe" => json!({
+            "id": BOT_ID,
+            "is_bot": true,
+            "first_name": "Dice Maestro",
+            "username": "dice_maestro_bot",
+            "can_join_groups": true,
+            "can_read_all_group_messages": false,
+            "supports_inline_queries": false,
+        }),
+        "sendMessage" | "editMessageText" | "sendDocument" | "sendDice" | "sendSticker" => {
+            json!({
+                "message_id": message_id,
+                "date": 0,
+                "chat": chat(),
+                "from": {"id": BOT_ID, "is_bot": true, "first_name": "Dice Maestro"},
+                "text": "",
+            })
+        }
+        _ => json!(true),
+    };
+    let body = json!({"ok": true, "result": result}).to_string();
+    Ok(Response::new(Full::new(Bytes::from(body))))
+}
+
+fn chat() -> serde_json::Value {
+    json!({"id": CHAT_ID, "type": "supergroup", "title": "Dungeon"})
+}
+
+fn user(user_id: i64) -> serde_json::Value {
+    json!({"id": user_id, "is_bot": false, "first_name": "Player"})
+}
+
+/// Updates only parse from text, as they borrow from it
+fn update(json: serde_json::Value) -> Update {
+    serde_json::from_str(&json.to_string()).expect("a valid update")
+}
+
+/// The user sending text to the chat [`CHAT_ID`]
+pub(crate) fn text_update(update_id: i32, user_id: i64, text: &str) -> Update {
+    update(json!({
+        "update_id": update_id,
+        "message": {
+            "message_id": update_id,
+            "date": 0,
+            "chat": chat(),
+            "from": user(user_id),
+            "text": text,
+        },
+    }))
+}
+
+/// The user pressing a button with `data` under the message of the bot
+pub(crate) fn button_update(update_id: i32, user_id: i64, message_id: i32, data: &str) -> Update {
+    update(json!({
+        "update_id": update_id,
+        "callback_query": {
+            "id": update_id.to_string(),
+            "from": user(user_id),
+            "chat_instance": "dungeon",
+            "data": data,
+            "message": {
+                "message_id": message_id,
+                "date": 0,
+                "chat": chat(),
+                "from": {"id": BOT_ID, "is_bot": true, "first_name": "Dice Maestro"},
+                "text": "",
+            },
+        },
+    }))
+}
+
+/// A source of the updates given, which stops once they have all been handed out
+pub(crate) fn updates(updates: Vec<Update>) -> impl UpdateListener<Err = Infallible> {
+    teloxide::update_listeners::StatefulListener::new(
+        updates,
+        |updates: &mut Vec<Update>| {
+            futures::stream::iter(std::mem::take(updates).into_iter().map(Ok))
+        },
+        |_: &mut Vec<Update>| teloxide::stop::mk_stop_token().0,
+    )
+}
@@ -0,0 +1,201 @@
+//! Countdown timers, such as `/timer 10m short rest`, run by the scheduler like reminders.
+
+use chrono::{DateTime, TimeDelta, Utc};
+use teloxide::utils::html;
+
+use crate::scheduler::{Action, Job};
+use crate::storage::{Storage, Store};
+use crate::transport::{ChatTransport, Incoming};
+
+/// Upper bound on how long a timer runs
+const MAX_DURATION: TimeDelta = TimeDelta::hours(24);
+
+/// Upper bound on the running timers of a chat
+const MAX_TIMERS: usize = 10;
+
+/// Durations like `90s`, `10m` or `1h30m`. A number alone is in minutes.
+fn parse_duration(input: &str) -> Option<TimeDelta> {
+    if let Ok(minutes) = input.parse::<u32>() {
+        return Some(TimeDelta::minutes(minutes as i64));
+    }
+    let mut total = TimeDelta::zero();
+    let mut number = String::new();
+    for c in input.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'h' | 'm' | 's' => {
+                let value: i64 = number.parse().ok()?;
+                number.clear();
+                let part = match c {
+                    'h' => TimeDelta::try_hours(value)?,
+                    'm' => TimeDelta::try_minutes(value)?,
+                    _ => TimeDelta::try_seconds(value)?,
+                };
+                total = total.checked_add(&part)?;
+            }
+            _ => return None,
+        }
+    }
+    match number.is_empty() {
+        true => Some(total),
+        false => None,
+    }
+}
+
+fn format_duration(duration: TimeDelta) -> String {
+    let seconds = duration.num_seconds().max(0);
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    let parts: Vec<_> = [(hours, "h"), (minutes, "m"), (seconds, "s")]
+        .iter()
+        .filter(|(value, _)| *value > 0)
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect();
+    match parts.is_empty() {
+        true => "0s".to_string(),
+        false => parts.join(" "),
+    }
+}
+
+fn timers(storage: &Storage, chat_id: i64) -> Vec<&Job> {
+    storage
+        .jobs
+        .for_chat(chat_id)
+        .into_iter()
+        .filter(|job| matches!(job.action, Action::Timer { .. }))
+        .collect()
+}
+
+const USAGE: &str = "<code>/timer 10m short rest</code> posts a message when the time is up
+<code>/timer list</code> shows the running timers of this chat
+<code>/timer cancel 3</code> stops a timer
+Durations can be like 90s, 10m or 1h30m, up to 24h.";
+
+fn timer(
+    storage: &mut Storage,
+    chat_id: i64,
+    message_id: i32,
+    input: &str,
+    now: DateTime<Utc>,
+) -> String {
+    let mut args = input.split_whitespace();
+    match args.next() {
+        None => USAGE.to_string(),
+        Some("list") => {
+            let lines: Vec<_> = timers(storage, chat_id)
+                .iter()
+                .map(|job| {
+                    let Action::Timer { ref label, .. } = job.action else {
+                        unreachable!("only timers are listed")
+                    };
+                    format!(
+                        "#{} {} · {} left",
+                        job.id,
+                        html::escape(label),
+                        format_duration(job.due - now)
+                    )
+                })
+                .collect();
+            if lines.is_empty() {
+                return "This chat has no running timers.".to_string();
+            }
+            format!("⌛ Timers\n{}", lines.join("\n"))
+        }
+        Some("cancel") => {
+            let id = args
+                .next()
+                .and_then(|id| id.trim_start_matches('#').parse::<u64>().ok());
+            match id {
+                Some(id) if timers(storage, chat_id).iter().any(|job| job.id == id) => {
+                    storage.jobs.cancel_in_chat(chat_id, id);
+                    format!("Stopped timer #{}", id)
+                }
+                _ => "There is no timer with that number. See /timer list.".to_string(),
+            }
+        }
+        Some(duration) => {
+            let Some(duration) = parse_duration(duration) else {
+                return USAGE.to_string();
+            };
+            if duration <= TimeDelta::zero() || duration > MAX_DURATION {
+                return USAGE.to_string();
+            }
+            if timers(storage, chat_id).len() >= MAX_TIMERS {
+                return format!("This chat already has {} running timers.", MAX_TIMERS);
+            }
+            let label = match args.collect::<Vec<_>>().join(" ") {
+                label if label.is_empty() => "Timer".to_string(),
+                label => label,
+            };
+            let timezone = crate::localtime::timezone(storage, chat_id);
+            let due = now + duration;
+            let id = storage.jobs.schedule(
+                due,
+                Action::Timer {
+                    chat_id,
+                    message_id,
+                    label: label.clone(),
+                },
+            );
+            format!(
+                "⌛ Timer #{} for {}: {}, until {}",
+                id,
+                format_duration(duration),
+                html::escape(&label),
+                crate::localtime::clock(due, timezone)
+            )
+        }
+    }
+}
+
+pub(crate) async fn handle_timer(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let (chat_id, message_id) = (msg.chat_id, msg.message_id);
+    let text = store
+        .update(|storage| timer(storage, chat_id, message_id, input, Utc::now()))
+        .await?;
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("10"), Some(TimeDelta::minutes(10)));
+        assert_eq!(parse_duration("90s"), Some(TimeDelta::seconds(90)));
+        assert_eq!(parse_duration("1h30m"), Some(TimeDelta::minutes(90)));
+        assert!(parse_duration("1h30").is_none());
+        assert!(parse_duration("soon").is_none());
+        assert!(parse_duration("99999999999999h").is_none());
+        assert_eq!(format_duration(TimeDelta::seconds(3725)), "1h 2m 5s");
+        assert_eq!(format_duration(TimeDelta::seconds(-5)), "0s");
+    }
+
+    #[test]
+    fn runs_timers() {
+        let mut storage = Storage::default();
+        let now = Utc.with_ymd_and_hms(2024, 7, 3, 12, 0, 0).unwrap();
+        assert_eq!(
+            timer(&mut storage, 1, 7, "10m short rest", now),
+            "⌛ Timer #0 for 10m: short rest, until 12:10:00"
+        );
+        assert!(timer(&mut storage, 1, 7, "25h", now).starts_with("<code>/timer"));
+        let later = now + TimeDelta::minutes(4);
+        assert_eq!(
+            timer(&mut storage, 1, 8, "list", later),
+            "⌛ Timers\n#0 short rest · 6m left"
+        );
+        assert!(timer(&mut storage, 2, 9, "cancel 0", now).starts_with("There is no"));
+        assert_eq!(
+            timer(&mut storage, 1, 9, "cancel 0", now),
+            "Stopped timer #0"
+        );
+    }
+}
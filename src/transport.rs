@@ -0,0 +1,319 @@
+//! Sending messages without the types of a chat platform, so that the rolling, characters and
+//! campaigns can one day answer on Discord or Matrix too. Telegram is the only transport so far, and
+//! the dispatcher in `main` is the only place that turns its updates into [`Incoming`] and [`Press`].
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use teloxide::net::Download;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, InputFile, MessageId};
+use teloxide::{ApiError, RequestError};
+
+use crate::reaction::Celebration;
+use crate::AdaptedBot;
+
+/// Who sent a message or pressed a button
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Sender {
+    pub id: i64,
+    /// First and last name
+    pub name: String,
+    /// To find the user by an @mention
+    pub username: Option<String>,
+}
+
+impl From<&teloxide::types::User> for Sender {
+    fn from(user: &teloxide::types::User) -> Self {
+        Sender {
+            id: user.id.0 as i64,
+            name: user.full_name(),
+            username: user.username.clone(),
+        }
+    }
+}
+
+/// A file sent with a message, which the platform keeps for the bot to use again
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Attachment {
+    Sticker {
+        file_id: String,
+        pack: Option<String>,
+    },
+    /// A GIF
+    Animation {
+        file_id: String,
+    },
+    Document {
+        file_id: String,
+        size: u32,
+    },
+}
+
+/// The message that a message answers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Replied {
+    pub from: Option<Sender>,
+    pub attachment: Option<Attachment>,
+}
+
+/// The message being answered
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct Incoming {
+    pub chat_id: i64,
+    pub message_id: i32,
+    pub from: Option<Sender>,
+    pub chat_title: Option<String>,
+    /// Whether it is the private chat of the sender with the bot
+    pub private: bool,
+    pub date: DateTime<Utc>,
+    pub reply_to: Option<Replied>,
+}
+
+impl Incoming {
+    pub fn user_id(&self) -> Option<i64> {
+        self.from.as_ref().map(|from| from.id)
+    }
+}
+
+fn attachment(msg: &Message) -> Option<Attachment> {
+    if let Some(sticker) = msg.sticker() {
+        return Some(Attachment::Sticker {
+            file_id: sticker.file.id.clone(),
+            pack: sticker.set_name.clone(),
+        });
+    }
+    if let Some(animation) = msg.animation() {
+        return Some(Attachment::Animation {
+            file_id: animation.file.id.clone(),
+        });
+    }
+    msg.document().map(|document| Attachment::Document {
+        file_id: document.file.id.clone(),
+        size: document.file.size,
+    })
+}
+
+impl From<&Message> for Incoming {
+    fn from(msg: &Message) -> Self {
+        Incoming {
+            chat_id: msg.chat.id.0,
+            message_id: msg.id.0,
+            from: msg.from().map(Sender::from),
+            chat_title: msg.chat.title().map(str::to_string),
+            private: msg.chat.is_private(),
+            date: msg.date,
+            reply_to: msg.reply_to_message().map(|replied| Replied {
+                from: replied.from().map(Sender::from),
+                attachment: attachment(replied),
+            }),
+        }
+    }
+}
+
+/// A button under a message, which sends `data` back when pressed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Button {
+    pub label: String,
+    pub data: String,
+}
+
+/// A press of a [`Button`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Press {
+    /// To answer the press with
+    pub id: String,
+    pub from: Sender,
+    /// Chat and ID of the message with the button, unless it is too old for the platform to say
+    pub message: Option<(i64, i32)>,
+    pub data: String,
+}
+
+impl From<&CallbackQuery> for Press {
+    fn from(query: &CallbackQuery) -> Self {
+        Press {
+            id: query.id.clone(),
+            from: Sender::from(&query.from),
+            message: query
+                .message
+                .as_ref()
+                .map(|message| (message.chat.id.0, message.id.0)),
+            data: query.data.clone().unwrap_or_default(),
+        }
+    }
+}
+
+#[async_trait]
+pub(crate) trait ChatTransport: Send + Sync {
+    /// Answer the message with formatted text, returning the ID of the answer. Buttons are shown one
+    /// above the other.
+    async fn reply(&self, to: &Incoming, text: String, buttons: Vec<Button>)
+        -> anyhow::Result<i32>;
+
+    /// Send formatted text to a chat, such as the private chat of a user
+    async fn send(&self, chat_id: i64, text: String) -> anyhow::Result<i32>;
+
+    /// Replace the text and buttons of a message of the bot. Editing to the same text is not an
+    /// error, as pressing a button twice in quick succession can do that.
+    async fn edit(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+        text: String,
+        buttons: Vec<Button>,
+    ) -> anyhow::Result<()>;
+
+    async fn delete(&self, chat_id: i64, message_id: i32) -> anyhow::Result<()>;
+
+    async fn pin(&self, chat_id: i64, message_id: i32) -> anyhow::Result<()>;
+
+    /// Let whoever pressed the button know that it worked, with a short notice if there is one
+    async fn answer_press(&self, press: &Press, notice: Option<String>) -> anyhow::Result<()>;
+
+    /// Attach a file to a message of the chat, with a caption if there is one
+    async fn reply_with_file(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+        name: &str,
+        contents: Vec<u8>,
+        caption: Option<String>,
+    ) -> anyhow::Result<()>;
+
+    /// Answer with a die of the platform itself, such as the animated one of Telegram
+    async fn roll_native_die(&self, to: &Incoming) -> anyhow::Result<()>;
+
+    /// Celebrate a special result, such as with the reactions and stickers of Telegram
+    async fn react(&self, to: &Incoming, celebration: &Celebration) -> anyhow::Result<()>;
+
+    /// Whether the user may manage the chat
+    async fn is_chat_admin(&self, chat_id: i64, user_id: i64) -> anyhow::Result<bool>;
+
+    async fn leave(&self, chat_id: i64) -> anyhow::Result<()>;
+
+    /// Contents of an attachment that a user sent
+    async fn download(&self, file_id: &str) -> anyhow::Result<Vec<u8>>;
+}
+
+fn keyboard(buttons: Vec<Button>) -> Option<InlineKeyboardMarkup> {
+    match buttons.is_empty() {
+        true => None,
+        false => Some(InlineKeyboardMarkup::new(buttons.into_iter().map(
+            |button| [InlineKeyboardButton::callback(button.label, button.data)],
+        ))),
+    }
+}
+
+#[async_trait]
+impl ChatTransport for AdaptedBot {
+    async fn reply(
+        &self,
+        to: &Incoming,
+        text: String,
+        buttons: Vec<Button>,
+    ) -> anyhow::Result<i32> {
+        // The message may be gone already, such as one with a secret in it. Links are to the
+        // source code or donations, which need no preview.
+        let mut request = self
+            .send_message(ChatId(to.chat_id), text)
+            .reply_to_message_id(MessageId(to.message_id))
+            .allow_sending_without_reply(true)
+            .disable_web_page_preview(true);
+        if let Some(keyboard) = keyboard(buttons) {
+            request = request.reply_markup(keyboard);
+        }
+        Ok(request.await?.id.0)
+    }
+
+    async fn send(&self, chat_id: i64, text: String) -> anyhow::Result<i32> {
+        Ok(self.send_message(ChatId(chat_id), text).await?.id.0)
+    }
+
+    async fn edit(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+        text: String,
+        buttons: Vec<Button>,
+    ) -> anyhow::Result<()> {
+        let mut request = self.edit_message_text(ChatId(chat_id), MessageId(message_id), text);
+        if let Some(keyboard) = keyboard(buttons) {
+            request = request.reply_markup(keyboard);
+        }
+        match request.await {
+            Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, chat_id: i64, message_id: i32) -> anyhow::Result<()> {
+        self.delete_message(ChatId(chat_id), MessageId(message_id))
+            .await?;
+        Ok(())
+    }
+
+    async fn pin(&self, chat_id: i64, message_id: i32) -> anyhow::Result<()> {
+        self.pin_chat_message(ChatId(chat_id), MessageId(message_id))
+            .await?;
+        Ok(())
+    }
+
+    async fn answer_press(&self, press: &Press, notice: Option<String>) -> anyhow::Result<()> {
+        let mut request = self.answer_callback_query(press.id.clone());
+        if let Some(notice) = notice {
+            request = request.text(notice);
+        }
+        request.await?;
+        Ok(())
+    }
+
+    async fn reply_with_file(
+        &self,
+        chat_id: i64,
+        message_id: i32,
+        name: &str,
+        contents: Vec<u8>,
+        caption: Option<String>,
+    ) -> anyhow::Result<()> {
+        let mut request = self
+            .send_document(
+                ChatId(chat_id),
+                InputFile::memory(contents).file_name(name.to_string()),
+            )
+            .reply_to_message_id(MessageId(message_id));
+        if let Some(caption) = caption {
+            request = request.caption(caption);
+        }
+        request.await?;
+        Ok(())
+    }
+
+    async fn roll_native_die(&self, to: &Incoming) -> anyhow::Result<()> {
+        self.send_dice(ChatId(to.chat_id))
+            .reply_to_message_id(MessageId(to.message_id))
+            .await?;
+        Ok(())
+    }
+
+    async fn react(&self, to: &Incoming, celebration: &Celebration) -> anyhow::Result<()> {
+        crate::reaction::celebrate(self, to, celebration).await
+    }
+
+    async fn is_chat_admin(&self, chat_id: i64, user_id: i64) -> anyhow::Result<bool> {
+        let member = self
+            .get_chat_member(ChatId(chat_id), UserId(user_id as u64))
+            .await?;
+        Ok(member.is_privileged())
+    }
+
+    async fn leave(&self, chat_id: i64) -> anyhow::Result<()> {
+        self.leave_chat(ChatId(chat_id)).await?;
+        Ok(())
+    }
+
+    async fn download(&self, file_id: &str) -> anyhow::Result<Vec<u8>> {
+        let file = self.get_file(file_id).await?;
+        let mut contents = Vec::with_capacity(file.size as usize);
+        self.download_file(&file.path, &mut contents).await?;
+        Ok(contents)
+    }
+}
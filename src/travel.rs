@@ -0,0 +1,167 @@
+//! Overland travel, with an encounter check every watch and the weather of every day.
+
+use chrono::Datelike;
+use teloxide::utils::html;
+
+use crate::dice::{Flags, RollResults, RollSettings, RollType};
+use crate::storage::{Storage, Store};
+use crate::transport::{ChatTransport, Incoming};
+
+/// Most days a single journey can cover, keeping the log within one message
+const MAX_DAYS: u32 = 10;
+
+/// Watches of the day, each with its own encounter check
+const WATCHES: [&str; 3] = ["Morning", "Afternoon", "Night"];
+
+/// Lowest d20 of an encounter check that calls for an encounter
+const ENCOUNTER_ON: i64 = 18;
+
+/// A journey like `3 days forest 5`, with the number of days, the terrain and the party level
+fn parse(input: &str) -> Option<(u32, &str, Option<u8>)> {
+    let mut args = input
+        .split_whitespace()
+        .filter(|arg| !matches!(*arg, "day" | "days"));
+    let days = args
+        .next()?
+        .parse()
+        .ok()
+        .filter(|days| (1..=MAX_DAYS).contains(days))?;
+    let terrain = args.next()?;
+    let level = match args.next() {
+        Some(level) => Some(level.parse().ok()?),
+        None => None,
+    };
+    match args.next() {
+        Some(_) => None,
+        None => Some((days, terrain, level)),
+    }
+}
+
+/// The weather of a day, from a random table named `weather_forest` or `weather`,
+/// or else the /weather of a temperate climate in the season
+fn weather(tables: &[crate::table::RandomTable], terrain: &str, season: &str) -> String {
+    let table = [format!("weather_{}", terrain), "weather".to_string()]
+        .into_iter()
+        .find(|name| crate::table::find(tables, name).is_some());
+    let Some(name) = table else {
+        return crate::weather::weather(tables, "temperate", season).replace('\n', " · ");
+    };
+    match crate::table::roll_entry(tables, &name) {
+        Ok(text) => format!("🌦 {}", text),
+        Err(e) => format!("<code>{}</code>", html::escape(&format!("{:#}", e))),
+    }
+}
+
+/// What happens in a watch, if anything
+fn watch(storage: &Storage, chat_id: i64, terrain: &str, level: Option<u8>) -> Option<String> {
+    let settings = RollSettings {
+        number: 1,
+        sides: 20,
+        modifier: None,
+        label: None,
+        target: None,
+        keep: None,
+        explode: None,
+        success: None,
+        composite: false,
+        multiplier: None,
+        flags: Flags::NONE,
+    };
+    let check = RollResults::new(&settings, &RollType::Straight)
+        .result
+        .total;
+    if check < ENCOUNTER_ON {
+        return None;
+    }
+    let tables = storage
+        .chat(chat_id)
+        .map(|chat| chat.encounter_tables.as_slice())
+        .unwrap_or_default();
+    let mut rng = rand::thread_rng();
+    let Some((_, encounter)) = crate::encounter::pick(tables, terrain, level, &mut rng) else {
+        return Some(format!(
+            "an encounter (d20: {}), but no table matches",
+            check
+        ));
+    };
+    let mut text = format!("{} (d20: {})", html::escape(&encounter.description), check);
+    for group in &encounter.monsters {
+        text.push_str(&format!(
+            "\n    • {} × {}",
+            group.count.roll(),
+            html::escape(&group.name)
+        ));
+    }
+    Some(text)
+}
+
+/// The travel log of the journey, day by day
+fn travel(storage: &Storage, chat_id: i64, days: u32, terrain: &str, level: Option<u8>) -> String {
+    let random_tables = storage
+        .chat(chat_id)
+        .map(|chat| chat.random_tables.as_slice())
+        .unwrap_or_default();
+    let season = crate::weather::season(chrono::Utc::now().month());
+    let mut text = format!(
+        "🧭 <b>{} days of travel through {}</b>",
+        days,
+        html::escape(terrain)
+    );
+    for day in 1..=days {
+        text.push_str(&format!("\n\n<b>Day {}</b>", day));
+        text.push_str(&format!("\n{}", weather(random_tables, terrain, season)));
+        for name in WATCHES {
+            match watch(storage, chat_id, terrain, level) {
+                Some(encounter) => text.push_str(&format!("\n⚔️ {}: {}", name, encounter)),
+                None => text.push_str(&format!("\n{}: uneventful", name)),
+            }
+        }
+    }
+    text
+}
+
+const USAGE: &str = "<code>/travel 3 days forest</code> rolls an encounter check every watch of every day, on the /encounter tables for the terrain
+<code>/travel 3 days forest 5</code> only uses tables for a party of level 5
+The weather of every day is that of /weather, unless you upload random tables named like weather_forest or weather with /table.";
+
+pub(crate) async fn handle_travel(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let text = match parse(input) {
+        Some((days, terrain, level)) => {
+            store
+                .read(|storage| travel(storage, chat_id, days, terrain, level))
+                .await
+        }
+        None => USAGE.to_string(),
+    };
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logs_every_day() {
+        assert_eq!(parse("3 days forest"), Some((3, "forest", None)));
+        assert_eq!(parse("1 swamp 4"), Some((1, "swamp", Some(4))));
+        assert_eq!(parse("30 days forest"), None);
+        assert_eq!(parse("forest"), None);
+
+        let mut storage = Storage::default();
+        storage.chat_mut(1).random_tables = serde_json::from_str(
+            r#"[{"name": "weather_forest", "entries": [{"text": "Drizzle"}]}]"#,
+        )
+        .unwrap();
+        let text = travel(&storage, 1, 2, "forest", None);
+        assert_eq!(text.matches("🌦 Drizzle").count(), 2);
+        assert_eq!(text.matches("Night: ").count(), 2);
+        assert!(travel(&storage, 1, 1, "desert", None).contains("<b>Day 1</b>\n🌡 "));
+    }
+}
@@ -0,0 +1,127 @@
+//! Taking back or repeating the latest roll of a user, with `/undo` and `/reroll`.
+
+use serde::{Deserialize, Serialize};
+use teloxide::utils::html;
+
+use crate::dice::{RollResults, RollType};
+use crate::storage::Store;
+use crate::transport::{ChatTransport, Incoming};
+
+/// The latest roll of a user in a chat
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct LastRoll {
+    pub expression: String,
+    pub roll_type: RollType,
+    /// The message of the bot with the results
+    pub message_id: i32,
+    pub total: i64,
+}
+
+pub(crate) async fn remember(
+    store: &Store,
+    chat_id: i64,
+    user_id: i64,
+    reply: i32,
+    expression: &str,
+    results: &RollResults<'_>,
+) -> anyhow::Result<()> {
+    let last = LastRoll {
+        expression: expression.to_string(),
+        roll_type: results.roll_type.clone(),
+        message_id: reply,
+        total: results.result.total,
+    };
+    store
+        .update(|storage| storage.chat_mut(chat_id).last_rolls.insert(user_id, last))
+        .await?;
+    Ok(())
+}
+
+pub(crate) async fn last_roll(store: &Store, msg: &Incoming) -> Option<LastRoll> {
+    let user_id = msg.user_id()?;
+    let chat_id = msg.chat_id;
+    store
+        .read(|storage| {
+            storage
+                .chat(chat_id)
+                .and_then(|chat| chat.last_rolls.get(&user_id).cloned())
+        })
+        .await
+}
+
+/// What the results of a voided roll are replaced with
+fn voided_text(last: &LastRoll) -> String {
+    format!(
+        "🚫 <s>{} = {}</s>\nVoided with /undo",
+        html::escape(&last.expression),
+        last.total
+    )
+}
+
+pub(crate) async fn handle_undo(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+) -> anyhow::Result<()> {
+    let Some(user) = &msg.from else {
+        return Ok(());
+    };
+    let (chat_id, user_id) = (msg.chat_id, user.id);
+    let last = store
+        .update(|storage| storage.chat_mut(chat_id).last_rolls.remove(&user_id))
+        .await?;
+    let Some(last) = last else {
+        bot.reply(
+            msg,
+            "You have no roll to undo in this chat.".to_string(),
+            vec![],
+        )
+        .await?;
+        return Ok(());
+    };
+    if !store.void_last_roll(chat_id, user_id).await? {
+        bot.reply(
+            msg,
+            "There is nothing to undo in this chat.".to_string(),
+            vec![],
+        )
+        .await?;
+        return Ok(());
+    }
+    if let Err(e) = bot
+        .edit(chat_id, last.message_id, voided_text(&last), vec![])
+        .await
+    {
+        log::warn!("Unable to edit the voided roll: {:#}", e);
+    }
+    bot.reply(
+        msg,
+        format!(
+            "Voided your roll of <code>{}</code>.",
+            html::escape(&last.expression)
+        ),
+        vec![],
+    )
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strikes_out_voided_rolls() {
+        let last = LastRoll {
+            expression: "1d20 + 5 <sneaky>".to_string(),
+            roll_type: RollType::Straight,
+            message_id: 3,
+            total: 17,
+        };
+        assert_eq!(
+            voided_text(&last),
+            "🚫 <s>1d20 + 5 &lt;sneaky&gt; = 17</s>\nVoided with /undo"
+        );
+    }
+}
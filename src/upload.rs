@@ -0,0 +1,33 @@
+use thiserror::Error;
+
+use crate::transport::{Attachment, ChatTransport, Incoming};
+
+/// Files larger than this are refused instead of downloaded
+const MAX_UPLOAD_SIZE: u32 = 1024 * 1024;
+
+#[derive(Error, Debug, PartialEq)]
+pub(crate) enum UploadError {
+    #[error("Reply to a JSON file with this command to upload it.")]
+    Missing,
+    #[error("That file is {0} bytes, which is more than the {MAX_UPLOAD_SIZE} bytes I accept.")]
+    TooBig(u32),
+}
+
+/// Download the document that the command message replies to.
+pub(crate) async fn replied_document(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+) -> anyhow::Result<Result<Vec<u8>, UploadError>> {
+    let document = msg
+        .reply_to
+        .as_ref()
+        .and_then(|replied| replied.attachment.as_ref());
+    let Some(Attachment::Document { file_id, size }) = document else {
+        return Ok(Err(UploadError::Missing));
+    };
+    if *size > MAX_UPLOAD_SIZE {
+        return Ok(Err(UploadError::TooBig(*size)));
+    }
+
+    Ok(Ok(bot.download(file_id).await?))
+}
@@ -0,0 +1,154 @@
+//! Checks the settings of `run` without starting the bot, reporting every problem at once.
+
+use std::fmt;
+
+use anyhow::Context;
+use clap::ArgMatches;
+
+use crate::cli::RunArgs;
+use crate::config::Config;
+
+/// Outcome of one check, with what was found when it passed
+pub struct Check {
+    pub name: &'static str,
+    pub result: anyhow::Result<String>,
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.result {
+            Ok(ref found) => write!(f, "✓ {}: {}", self.name, found),
+            Err(ref e) => write!(f, "✗ {}: {:#}", self.name, e),
+        }
+    }
+}
+
+async fn check_storage(args: &RunArgs) -> anyhow::Result<String> {
+    let backend = crate::open_backend(&args.storage).await?;
+    backend.load().await?;
+    let rolls = backend.count_rolls().await?;
+    Ok(format!("readable, with {} rolls in the history", rolls))
+}
+
+fn check_character_data(pattern: &str) -> anyhow::Result<String> {
+    let reports = crate::diagnostics::check_pattern(pattern)?;
+    let invalid: Vec<_> = reports
+        .iter()
+        .filter(|report| report.result.is_err())
+        .map(ToString::to_string)
+        .collect();
+    if !invalid.is_empty() {
+        anyhow::bail!(
+            "{} of {} files are invalid\n{}",
+            invalid.len(),
+            reports.len(),
+            invalid.join("\n")
+        );
+    }
+    Ok(format!("{} valid files", reports.len()))
+}
+
+fn check_backup_dir(directory: &str) -> anyhow::Result<String> {
+    match std::fs::metadata(directory) {
+        Ok(metadata) if metadata.is_dir() => Ok(format!("{} exists", directory)),
+        Ok(_) => anyhow::bail!("{} is not a directory", directory),
+        // The first backup creates it
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Ok(format!("{} will be created", directory))
+        }
+        Err(e) => Err(e).with_context(|| format!("error reading {}", directory)),
+    }
+}
+
+/// Apply the config file if there is one, then check everything that `run` would use
+pub async fn check(mut args: RunArgs, matches: &ArgMatches) -> Vec<Check> {
+    let mut checks = vec![];
+    if let Some(path) = args.config.clone() {
+        let result = Config::load(&path).map(|config| {
+            config.apply(&mut args, matches);
+            format!("{} parsed", path)
+        });
+        checks.push(Check {
+            name: "config",
+            result,
+        });
+    }
+
+    let token =
+        crate::get_token(args.bot_token.as_ref(), args.bot_token_file.as_ref()).and_then(|token| {
+            match token.is_empty() {
+                true => anyhow::bail!("the token is empty"),
+                false => Ok("readable".to_string()),
+            }
+        });
+    checks.push(Check {
+        name: "bot token",
+        result: token,
+    });
+
+    checks.push(Check {
+        name: "storage",
+        result: check_storage(&args).await,
+    });
+
+    if args.proxy.is_some() {
+        checks.push(Check {
+            name: "proxy",
+            result: crate::http_client(args.proxy.as_deref()).map(|_| "valid".to_string()),
+        });
+    }
+    if let Some(ref pattern) = args.character_data {
+        checks.push(Check {
+            name: "character data",
+            result: check_character_data(pattern),
+        });
+    }
+    if let Some(ref path) = args.secret_key_file {
+        checks.push(Check {
+            name: "secret key",
+            result: crate::secret::SecretKey::read(path).map(|_| "valid".to_string()),
+        });
+    }
+    if let Some(ref directory) = args.backup_dir {
+        checks.push(Check {
+            name: "backup directory",
+            result: check_backup_dir(directory),
+        });
+    }
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    #[tokio::test]
+    async fn reports_every_problem() {
+        let directory = std::env::temp_dir().join(format!("validate-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let config = directory.join("bot.toml");
+        std::fs::write(&config, "bot_token_file = \"missing\"\nrate_limt = 1").unwrap();
+
+        let matches = crate::cli::Cli::command().get_matches_from([
+            "maestro",
+            "run",
+            "--config",
+            config.to_str().unwrap(),
+            "--storage-path",
+            directory.join("storage.json").to_str().unwrap(),
+            "--backup-dir",
+            config.to_str().unwrap(),
+        ]);
+        let matches = matches.subcommand_matches("run").unwrap();
+        let args = RunArgs::from_arg_matches(matches).unwrap();
+        let checks = check(args, matches).await;
+        let failed: Vec<_> = checks
+            .iter()
+            .filter(|check| check.result.is_err())
+            .map(|check| check.name)
+            .collect();
+        assert_eq!(failed, ["config", "bot token", "backup directory"]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,166 @@
+//! Keeps the characters of players in sync with character data files managed by the operator.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+
+use crate::diagnostics;
+use crate::dnd::Character;
+use crate::storage::Store;
+
+/// How often the files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Modification time of every file matching the pattern. Files that cannot be read are left out,
+/// with a warning, so that the others are still reloaded.
+fn snapshot(pattern: &str) -> anyhow::Result<HashMap<PathBuf, SystemTime>> {
+    let entries =
+        glob::glob(pattern).with_context(|| format!("error figuring out path {}", pattern))?;
+    Ok(entries
+        .filter_map(|entry| {
+            let path = match entry {
+                Ok(path) => path,
+                Err(e) => {
+                    log::warn!(
+                        "Error looking at character data {}: {}",
+                        e.path().display(),
+                        e
+                    );
+                    return None;
+                }
+            };
+            match path.metadata().and_then(|metadata| metadata.modified()) {
+                Ok(modified) => Some((path, modified)),
+                Err(e) => {
+                    log::warn!("Error looking at character data {}: {}", path.display(), e);
+                    None
+                }
+            }
+        })
+        .collect())
+}
+
+/// Characters in the changed files, logging the ones that cannot be loaded
+fn load(changed: &[PathBuf]) -> Vec<(i64, Character)> {
+    let mut characters = vec![];
+    for path in changed {
+        let report = diagnostics::check_file(path);
+        match report.result {
+            Err(_) => log::error!("{}", report),
+            Ok(character) => match character.player {
+                None => log::warn!(
+                    "{}: no player is set, so the character is not given to anyone",
+                    path.display()
+                ),
+                Some(player) => {
+                    log::info!("Loaded {} for player {}", character.name, player);
+                    characters.push((player, character));
+                }
+            },
+        }
+    }
+    characters
+}
+
+/// Reload the files that changed since they were `seen`
+async fn poll(store: &Store, pattern: &str, seen: &mut HashMap<PathBuf, SystemTime>) {
+    let current = match snapshot(pattern) {
+        Ok(current) => current,
+        Err(e) => {
+            log::error!("Error looking for character data: {:#}", e);
+            return;
+        }
+    };
+    let changed: Vec<_> = current
+        .iter()
+        .filter(|(path, modified)| seen.get(*path) != Some(*modified))
+        .map(|(path, _)| path.clone())
+        .collect();
+    *seen = current;
+
+    let characters = load(&changed);
+    if characters.is_empty() {
+        return;
+    }
+    let result = store
+        .update(|storage| {
+            for (player, character) in characters {
+                let user = storage.user_mut(player);
+                user.default_character
+                    .get_or_insert_with(|| character.name.clone());
+                user.characters.insert(character.name.clone(), character);
+            }
+        })
+        .await;
+    if let Err(e) = result {
+        log::error!("Error saving character data: {:#}", e);
+    }
+}
+
+pub async fn run(store: Store, pattern: String) {
+    let mut seen = HashMap::new();
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        poll(&store, &pattern, &mut seen).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn character(name: &str) -> String {
+        let character = Character {
+            player: Some(7),
+            proficiency_bonus: Some(2),
+            ..crate::testing::character(name)
+        };
+        serde_json::to_string(&character).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reloads_changed_files() {
+        let directory = std::env::temp_dir().join(format!("watched-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("aria.json");
+        std::fs::write(&path, character("Aria")).unwrap();
+        std::fs::write(directory.join("broken.json"), "{").unwrap();
+        let pattern = directory.join("*.json").display().to_string();
+        let store = crate::testing::store().await;
+        let names = || async {
+            store
+                .read(|storage| {
+                    let user = storage.user(7)?;
+                    Some(user.characters.keys().cloned().collect::<Vec<_>>())
+                })
+                .await
+        };
+
+        let mut seen = HashMap::new();
+        poll(&store, &pattern, &mut seen).await;
+        assert_eq!(names().await, Some(vec!["Aria".to_string()]));
+
+        // Nothing changed, so nothing is written
+        store
+            .update(|storage| storage.user_mut(7).characters.clear())
+            .await
+            .unwrap();
+        poll(&store, &pattern, &mut seen).await;
+        assert_eq!(names().await, Some(vec![]));
+
+        std::fs::write(&path, character("Aria")).unwrap();
+        let later = SystemTime::now() + Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        poll(&store, &pattern, &mut seen).await;
+        assert_eq!(names().await, Some(vec!["Aria".to_string()]));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
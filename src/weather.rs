@@ -0,0 +1,149 @@
+//! The weather of the day, rolled on the `weather_` random tables of the chat or the built in ones.
+
+use chrono::Datelike;
+use teloxide::utils::html;
+
+use crate::storage::Store;
+use crate::transport::{ChatTransport, Incoming};
+
+const CLIMATES: [&str; 4] = ["temperate", "arctic", "desert", "tropical"];
+
+const SEASONS: [&str; 4] = ["spring", "summer", "autumn", "winter"];
+
+/// The season of the northern hemisphere in the month, from 1 to 12
+pub(crate) fn season(month: u32) -> &'static str {
+    match month {
+        3..=5 => "spring",
+        6..=8 => "summer",
+        9..=11 => "autumn",
+        _ => "winter",
+    }
+}
+
+/// Roll on the most specific table of the aspect, like `weather_wind_arctic_winter`,
+/// `weather_wind_arctic` or `weather_wind`
+fn aspect(
+    tables: &[crate::table::RandomTable],
+    aspect: &str,
+    climate: &str,
+    season: &str,
+) -> String {
+    let names = [
+        format!("weather_{}_{}_{}", aspect, climate, season),
+        format!("weather_{}_{}", aspect, climate),
+        format!("weather_{}", aspect),
+    ];
+    let Some(name) = names
+        .iter()
+        .find(|name| crate::table::find(tables, name).is_some())
+    else {
+        return "unknown".to_string();
+    };
+    match crate::table::roll_entry(tables, name) {
+        Ok(text) => text,
+        Err(e) => format!("<code>{}</code>", html::escape(&format!("{:#}", e))),
+    }
+}
+
+/// Temperature, wind and precipitation of a day, as HTML
+pub(crate) fn weather(tables: &[crate::table::RandomTable], climate: &str, season: &str) -> String {
+    format!(
+        "🌡 {}\n💨 {}\n🌧 {}",
+        aspect(tables, "temperature", climate, season),
+        aspect(tables, "wind", climate, season),
+        aspect(tables, "precipitation", climate, season)
+    )
+}
+
+/// A climate and a season in any order, defaulting to a temperate climate in the current season
+fn parse(input: &str) -> Option<(String, String)> {
+    let mut climate = None;
+    let mut season = None;
+    for arg in input.split_whitespace() {
+        let arg = arg.to_lowercase();
+        let arg = match arg.as_str() {
+            "fall" => "autumn".to_string(),
+            _ => arg,
+        };
+        match SEASONS.contains(&arg.as_str()) {
+            true if season.is_none() => season = Some(arg),
+            false if climate.is_none() => climate = Some(arg),
+            _ => return None,
+        }
+    }
+    Some((
+        climate.unwrap_or_else(|| CLIMATES[0].to_string()),
+        season.unwrap_or_else(|| season_now().to_string()),
+    ))
+}
+
+fn season_now() -> &'static str {
+    season(chrono::Utc::now().month())
+}
+
+const USAGE: &str = "<code>/weather</code> rolls the weather of a temperate climate in the current season
+<code>/weather arctic winter</code> picks the climate and the season
+The built in climates are temperate, arctic, desert and tropical. Upload random tables like weather_temperature_jungle_summer, weather_wind_jungle or weather_precipitation with /table to use your own.";
+
+pub(crate) async fn handle_weather(
+    bot: &impl ChatTransport,
+    msg: &Incoming,
+    store: Store,
+    input: &str,
+) -> anyhow::Result<()> {
+    let chat_id = msg.chat_id;
+    let text = match parse(input) {
+        Some((climate, season)) if input.trim() != "help" => {
+            store
+                .read(|storage| {
+                    let tables = storage
+                        .chat(chat_id)
+                        .map(|chat| chat.random_tables.as_slice())
+                        .unwrap_or_default();
+                    format!(
+                        "🌦 <b>{} {}</b>\n{}",
+                        html::escape(&climate),
+                        season,
+                        weather(tables, &climate, &season)
+                    )
+                })
+                .await
+        }
+        _ => USAGE.to_string(),
+    };
+    bot.reply(msg, text, vec![]).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolls_the_weather() {
+        assert_eq!(
+            (1..=12).map(season).collect::<Vec<_>>(),
+            [
+                "winter", "winter", "spring", "spring", "spring", "summer", "summer", "summer",
+                "autumn", "autumn", "autumn", "winter"
+            ]
+        );
+        assert_eq!(
+            parse("Winter arctic"),
+            Some(("arctic".to_string(), "winter".to_string()))
+        );
+        assert_eq!(parse("summer winter"), None);
+
+        for climate in CLIMATES {
+            for season in SEASONS {
+                assert!(!weather(&[], climate, season).contains("unknown"));
+            }
+        }
+        let tables: Vec<crate::table::RandomTable> = serde_json::from_str(
+            r#"[{"name": "weather_wind_desert", "entries": [{"text": "Scouring sand"}]}]"#,
+        )
+        .unwrap();
+        assert!(weather(&tables, "desert", "summer").contains("💨 Scouring sand\n"));
+        assert!(weather(&tables, "jungle", "summer").starts_with("🌡 unknown"));
+    }
+}
@@ -0,0 +1,251 @@
+//! Rolls of a campaign posted to a webhook of the GM, such as a bridge to a virtual tabletop. Each
+//! request is signed with a secret that only the GM is told, so the receiver can tell it came from us.
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::output::RollOutput;
+
+/// Header with the hex HMAC-SHA256 of the body, as `sha256=...`
+pub(crate) const SIGNATURE_HEADER: &str = "X-Dice-Maestro-Signature";
+
+/// Waits between attempts to deliver an event. Client errors are not retried.
+const RETRY_DELAYS: [Duration; 3] = [
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+    Duration::from_secs(30),
+];
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct Webhook {
+    pub url: reqwest::Url,
+    /// Key of the signatures
+    pub secret: String,
+}
+
+impl Webhook {
+    pub(crate) fn new(url: reqwest::Url) -> Self {
+        Webhook {
+            url,
+            secret: format!("{:032x}", rand::random::<u128>()),
+        }
+    }
+}
+
+/// What a webhook receives for a roll
+#[derive(Serialize, Debug)]
+pub(crate) struct RollEvent {
+    /// Always `roll`, for receivers that may one day get other events
+    pub event: &'static str,
+    pub chat_id: i64,
+    pub user_id: Option<i64>,
+    pub character: Option<String>,
+    pub roll: RollOutput,
+}
+
+impl RollEvent {
+    pub(crate) fn new(
+        chat_id: i64,
+        user_id: Option<i64>,
+        character: Option<String>,
+        roll: RollOutput,
+    ) -> Self {
+        RollEvent {
+            event: "roll",
+            chat_id,
+            user_id,
+            character,
+            roll,
+        }
+    }
+}
+
+pub(crate) fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("any key to do");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Whether the bot may post to an address. Any GM can set a webhook, so it must not reach the host
+/// of the bot or the network it runs in.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let shared = ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64;
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || shared)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let unique_local = ip.segments()[0] & 0xfe00 == 0xfc00;
+                let link_local = ip.segments()[0] & 0xffc0 == 0xfe80;
+                !(ip.is_loopback() || ip.is_unspecified() || unique_local || link_local)
+            }
+        },
+    }
+}
+
+/// Resolve the host of a webhook, refusing those that are not on the public internet. Returns the
+/// address to post to, so that the host cannot resolve elsewhere in between.
+pub(crate) async fn check_destination(url: &reqwest::Url) -> Result<SocketAddr, &'static str> {
+    let Some(host) = url.host_str() else {
+        return Err("That URL has no host.");
+    };
+    // IPv6 hosts keep their brackets
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addresses: Vec<_> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|_| "I could not find the host of that URL.")?
+        .collect();
+    if addresses.is_empty() || !addresses.iter().all(|address| is_public(address.ip())) {
+        return Err("That URL is not on the public internet.");
+    }
+    Ok(addresses[0])
+}
+
+/// A client that only reaches the checked address. It follows no redirects, as they could lead
+/// anywhere, and goes around the proxy of the bot, which would resolve the host again.
+fn client(url: &reqwest::Url, address: SocketAddr) -> reqwest::Result<reqwest::Client> {
+    let mut builder = teloxide::net::default_reqwest_settings()
+        .redirect(reqwest::redirect::Policy::none())
+        .no_proxy();
+    if let Some(host) = url.domain() {
+        builder = builder.resolve(host, address);
+    }
+    builder.build()
+}
+
+/// Post the event, returning the status of a success or a redirect
+async fn post(
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    body: &str,
+) -> reqwest::Result<reqwest::StatusCode> {
+    let response = client
+        .post(webhook.url.clone())
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, sign(&webhook.secret, body.as_bytes()))
+        .body(body.to_string())
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(response.status())
+}
+
+/// Post the event, trying again a few times when the receiver is down
+pub(crate) async fn deliver(webhook: Webhook, body: String) {
+    let mut delays = RETRY_DELAYS.iter();
+    loop {
+        // The host may resolve elsewhere since the webhook was set
+        let address = match check_destination(&webhook.url).await {
+            Ok(address) => address,
+            Err(reason) => {
+                log::warn!("Not delivering a roll to a webhook: {}", reason);
+                return;
+            }
+        };
+        let posted = match client(&webhook.url, address) {
+            Ok(client) => post(&client, &webhook, &body).await,
+            Err(e) => Err(e),
+        };
+        let e = match posted {
+            Ok(status) if status.is_redirection() => {
+                log::warn!("Not following a redirect of a webhook ({})", status);
+                return;
+            }
+            Ok(_) => return,
+            Err(e) => e,
+        };
+        let client_error = e.status().is_some_and(|status| status.is_client_error());
+        match delays.next() {
+            Some(delay) if !client_error => tokio::time::sleep(*delay).await,
+            _ => {
+                log::warn!("Could not deliver a roll to a webhook: {}", e.without_url());
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_the_body() {
+        // From the test vectors of RFC 4231
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        let webhook = Webhook::new("https://example.com/hook".parse().unwrap());
+        assert_eq!(webhook.secret.len(), 32);
+    }
+
+    #[tokio::test]
+    async fn refuses_private_destinations() {
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://localhost:8080/hook",
+            "http://[::1]/hook",
+            "http://[::ffff:192.168.1.1]/hook",
+            "http://10.0.0.7/hook",
+            "http://172.16.0.1/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[fe80::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://0.0.0.0/hook",
+        ] {
+            let url = url.parse().unwrap();
+            assert!(check_destination(&url).await.is_err(), "{}", url);
+        }
+        let url = "https://93.184.216.34/hook".parse().unwrap();
+        assert_eq!(
+            check_destination(&url).await,
+            Ok("93.184.216.34:443".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn does_not_follow_redirects() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let metadata = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let receiver = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = receiver.local_addr().unwrap();
+        let location = format!("http://{}/latest/meta-data", metadata.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = receiver.accept().await.unwrap();
+            let mut request = [0; 4096];
+            let read = stream.read(&mut request).await.unwrap();
+            assert!(request[..read].starts_with(b"POST /hook"));
+            let response = format!(
+                "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\n\r\n",
+                location
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        // The host does not exist, so only the pinned address can be reached
+        let url = format!("http://hooks.invalid:{}/hook", address.port());
+        let webhook = Webhook::new(url.parse().unwrap());
+        let client = client(&webhook.url, address).unwrap();
+        let status = post(&client, &webhook, "{}").await.unwrap();
+        assert_eq!(status, reqwest::StatusCode::FOUND);
+        let followed = tokio::time::timeout(Duration::from_millis(200), metadata.accept()).await;
+        assert!(followed.is_err());
+    }
+}